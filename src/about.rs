@@ -0,0 +1,83 @@
+//! What the About window reports: the installed ssh client and whether a
+//! newer release is out.
+//!
+//! The update check only runs when asked for. It uses `curl` rather than an
+//! HTTP library, the same way tunnels rely on the system's `ssh`.
+
+use serde::Deserialize;
+use std::process::Command;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/DeltaManiac/onigiri/releases/latest";
+pub const RELEASES_PAGE: &str = "https://github.com/DeltaManiac/onigiri/releases";
+
+/// The first line of `ssh -V`, which OpenSSH prints to stderr.
+pub fn ssh_version() -> Option<String> {
+    let output = Command::new("ssh").arg("-V").output().ok()?;
+    let text = if output.stderr.is_empty() {
+        output.stdout
+    } else {
+        output.stderr
+    };
+    let text = String::from_utf8_lossy(&text);
+    text.lines()
+        .next()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+}
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+}
+
+/// The tag of the latest GitHub release. Runs on a worker thread.
+pub fn latest_release() -> Result<String, String> {
+    let output = Command::new("curl")
+        .args(["--silent", "--fail", "--max-time", "10", "--location"])
+        .args(["--header", "Accept: application/vnd.github+json"])
+        .arg(LATEST_RELEASE_URL)
+        .output()
+        .map_err(|e| format!("Failed to run curl: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("curl exited with {}", output.status));
+    }
+    parse_release(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn parse_release(json: &str) -> Result<String, String> {
+    serde_json::from_str::<Release>(json)
+        .map(|release| release.tag_name)
+        .map_err(|e| format!("Unexpected response: {}", e))
+}
+
+/// Whether release `tag` (like "v0.2.0") is newer than `current`.
+pub fn is_newer(tag: &str, current: &str) -> bool {
+    let parts = |version: &str| -> Vec<u64> {
+        version
+            .trim()
+            .trim_start_matches('v')
+            .split(['.', '-'])
+            .map_while(|part| part.parse().ok())
+            .collect()
+    };
+    parts(tag) > parts(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn release_tags_compare_by_number() {
+        assert_eq!(
+            parse_release(r#"{"tag_name": "v0.10.0", "name": "x"}"#).unwrap(),
+            "v0.10.0"
+        );
+        assert!(is_newer("v0.10.0", "0.9.3"));
+        assert!(is_newer("0.1.1", "0.1.0"));
+        assert!(!is_newer("v0.1.0", "0.1.0"));
+        assert!(!is_newer("v0.0.9", "0.1.0"));
+    }
+}
@@ -0,0 +1,87 @@
+//! Choosing the SSH agent a tunnel's ssh talks to, for people with several:
+//! a personal one, a password manager's, one forwarded from elsewhere. The
+//! chosen socket is given to ssh as `SSH_AUTH_SOCK`.
+
+use std::path::Path;
+
+/// Where agents commonly listen, relative to the home directory.
+const KNOWN_SOCKETS: [(&str, &str); 5] = [
+    (
+        "1Password",
+        "Library/Group Containers/2BUA8C4S2C.com.1password/t/agent.sock",
+    ),
+    ("1Password", ".1password/agent.sock"),
+    (
+        "Secretive",
+        "Library/Containers/com.maxgoedjen.Secretive.SecretAgent/Data/socket.ssh",
+    ),
+    ("Bitwarden", ".bitwarden-ssh-agent.sock"),
+    ("GnuPG", ".gnupg/S.gpg-agent.ssh"),
+];
+
+/// OpenSSH for Windows' agent, a named pipe rather than a file.
+const WINDOWS_PIPE: &str = r"\\.\pipe\openssh-ssh-agent";
+
+/// The agents found on this machine, as a label and the path to store. Paths
+/// under the home directory are given with `~`, so they survive an export.
+pub fn candidates() -> Vec<(String, String)> {
+    let mut found = Vec::new();
+    if let Ok(current) = std::env::var("SSH_AUTH_SOCK") {
+        if !current.is_empty() {
+            found.push(("Current ($SSH_AUTH_SOCK)".to_string(), current));
+        }
+    }
+    if let Some(home) = dirs::home_dir() {
+        for (label, relative) in KNOWN_SOCKETS {
+            if home.join(relative).exists() {
+                found.push((label.to_string(), format!("~/{}", relative)));
+            }
+        }
+    }
+    if cfg!(windows) {
+        found.push(("OpenSSH for Windows".to_string(), WINDOWS_PIPE.to_string()));
+    }
+    found
+}
+
+/// Refuses a socket that isn't there, so the start fails with the reason
+/// rather than ssh falling back to asking for a password.
+pub fn check(path: &Path) -> Result<(), String> {
+    // Named pipes can't be looked up like files
+    if path.to_string_lossy().starts_with(r"\\.\pipe\") {
+        return Ok(());
+    }
+    let metadata = std::fs::metadata(path)
+        .map_err(|_| format!("SSH agent socket {} does not exist", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if !metadata.file_type().is_socket() {
+            return Err(format!("{} is not an SSH agent socket", path.display()));
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = metadata;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn only_existing_sockets_pass() {
+        let dir = std::env::temp_dir().join(format!("onigiri-agent-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("agent.sock");
+        let _ = std::fs::remove_file(&socket);
+        let _listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+
+        assert!(check(&socket).is_ok());
+        let missing = check(&dir.join("gone.sock")).unwrap_err();
+        assert!(missing.contains("does not exist"), "{}", missing);
+        assert!(check(&dir).unwrap_err().contains("not an SSH agent socket"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
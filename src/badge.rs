@@ -0,0 +1,40 @@
+//! The number of running tunnels on the macOS Dock icon, or in the window
+//! title on platforms without icon badges.
+
+/// Puts `count` on the Dock icon, removing the badge for zero. Returns false
+/// when the badge can't be set from this thread.
+#[cfg(target_os = "macos")]
+pub fn set_dock_badge(count: usize) -> bool {
+    use objc2::MainThreadMarker;
+    use objc2_app_kit::NSApplication;
+    use objc2_foundation::NSString;
+
+    let Some(mtm) = MainThreadMarker::new() else {
+        return false;
+    };
+    let label = (count > 0).then(|| NSString::from_str(&count.to_string()));
+    NSApplication::sharedApplication(mtm)
+        .dockTile()
+        .setBadgeLabel(label.as_deref());
+    true
+}
+
+/// The window title with the running count, like "Onigiri (3)".
+pub fn window_title(app_name: &str, count: usize) -> String {
+    if count == 0 {
+        app_name.to_string()
+    } else {
+        format!("{} ({})", app_name, count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn title_shows_running_count() {
+        assert_eq!(window_title("Onigiri", 0), "Onigiri");
+        assert_eq!(window_title("Onigiri", 3), "Onigiri (3)");
+    }
+}
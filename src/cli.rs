@@ -0,0 +1,449 @@
+//! `onigiri list`, `start`, `stop` and `toggle` from a terminal.
+//!
+//! A running Onigiri gets the request over the control socket. Without one
+//! the command runs here against the same store, and `start` keeps its
+//! tunnels up until Ctrl-C while answering other terminals the same way.
+
+use crate::control::{self, Reply, Request, TunnelStatus};
+use crate::storage::{self, EventKind, SqliteStore, TomlStore, TunnelStore};
+use crate::{unix_now, Cli, StorageBackend, Tunnel, TunnelId, TunnelInfo, RUNNING};
+use log::error;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+// How often a headless run checks for requests and exited tunnels
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Finds each of `names` by id or by name, ignoring case when no name
+/// matches exactly. Deleted tunnels are left out.
+pub fn find_tunnels<'a>(
+    tunnels: &'a [Tunnel],
+    names: &[String],
+) -> Result<Vec<&'a Tunnel>, String> {
+    let live = || tunnels.iter().filter(|t| !t.deleted);
+    names
+        .iter()
+        .map(|name| {
+            if let Some(tunnel) = name
+                .parse()
+                .ok()
+                .and_then(|id: TunnelId| live().find(|t| t.id == id))
+            {
+                return Ok(tunnel);
+            }
+            let mut matches: Vec<&Tunnel> = live().filter(|t| t.name == *name).collect();
+            if matches.is_empty() {
+                matches = live()
+                    .filter(|t| t.name.eq_ignore_ascii_case(name))
+                    .collect();
+            }
+            match matches.as_slice() {
+                [tunnel] => Ok(*tunnel),
+                [] => Err(format!("No tunnel named '{}'", name)),
+                _ => Err(format!("'{}' matches several tunnels; use its id", name)),
+            }
+        })
+        .collect()
+}
+
+/// What `list` and `status` report for a tunnel, with its run if it has one.
+pub fn tunnel_status(tunnel: &Tunnel, running: Option<&TunnelInfo>, queued: bool) -> TunnelStatus {
+    let status = match running {
+        Some(info) if info.is_starting() => "starting",
+        Some(_) => "running",
+        None if queued => "starting",
+        None => "stopped",
+    };
+    TunnelStatus {
+        id: tunnel.id,
+        name: tunnel.name.clone(),
+        status: status.to_string(),
+        local: match running {
+            Some(info) if info.auto_port => info.local_address(),
+            _ => tunnel.local_address(),
+        },
+        pid: running.and_then(TunnelInfo::pid),
+        started_at: running.map(|info| info.started_at),
+    }
+}
+
+/// Answers `list` or `status` with a table and the same rows as data.
+pub fn status_reply(tunnels: Vec<TunnelStatus>) -> Reply {
+    Reply {
+        output: status_table(&tunnels),
+        tunnels,
+        ..Reply::ok(String::new())
+    }
+}
+
+fn status_table(tunnels: &[TunnelStatus]) -> String {
+    let mut rows = vec![["ID", "NAME", "STATUS", "LOCAL"].map(String::from)];
+    rows.extend(tunnels.iter().map(|t| {
+        [
+            t.id.to_string(),
+            t.name.clone(),
+            t.status.clone(),
+            t.local.clone(),
+        ]
+    }));
+    let widths: Vec<usize> = (0..4)
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    rows.iter()
+        .map(|row| {
+            let line: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                .collect();
+            line.join("  ").trim_end().to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Joins what happened to each tunnel into one reply, failed if any did.
+pub fn reply_from(results: Vec<Result<String, String>>) -> Reply {
+    let ok = results.iter().all(Result::is_ok);
+    let lines: Vec<String> = results
+        .into_iter()
+        .map(|result| result.unwrap_or_else(|e| e))
+        .collect();
+    Reply {
+        ok,
+        ..Reply::ok(lines.join("\n"))
+    }
+}
+
+/// Runs `request` and returns the process exit code.
+pub fn run(cli: &Cli, request: Request) -> i32 {
+    let path = match control::socket_path() {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("Failed to find the data directory: {:#}", e);
+            return 1;
+        }
+    };
+    match control::send(&path, &request) {
+        Ok(Some(reply)) => return print_reply(&reply),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("Failed to reach the running Onigiri: {}", e);
+            return 1;
+        }
+    }
+
+    let store = match open_store(cli) {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Failed to open tunnel storage: {}", e);
+            return 1;
+        }
+    };
+    let mut headless = Headless {
+        store,
+        running: BTreeMap::new(),
+    };
+    let code = print_reply(&headless.answer(&request));
+    if !headless.running.is_empty() {
+        headless.autostart();
+        headless.serve(&path);
+    }
+    code
+}
+
+/// Prints the names of the tunnels that aren't deleted, for the shell
+/// completion scripts.
+pub fn print_names(cli: &Cli) -> i32 {
+    let tunnels =
+        open_store(cli).and_then(|mut store| store.load_tunnels().map_err(|e| format!("{:#}", e)));
+    match tunnels {
+        Ok(tunnels) => {
+            let names: BTreeSet<&str> = tunnels
+                .iter()
+                .filter(|t| !t.deleted)
+                .map(|t| t.name.as_str())
+                .collect();
+            for name in names {
+                println!("{}", name);
+            }
+            0
+        }
+        Err(e) => {
+            eprintln!("Failed to load tunnels: {}", e);
+            1
+        }
+    }
+}
+
+fn print_reply(reply: &Reply) -> i32 {
+    if reply.ok {
+        if !reply.output.is_empty() {
+            println!("{}", reply.output);
+        }
+        0
+    } else {
+        eprintln!("{}", reply.output);
+        1
+    }
+}
+
+fn open_store(cli: &Cli) -> Result<Box<dyn TunnelStore>, String> {
+    match cli.storage {
+        StorageBackend::Sqlite => {
+            let store = storage::database_path()
+                .and_then(|path| SqliteStore::open(&path))
+                .map_err(|e| format!("{:#}", e))?;
+            Ok(Box::new(store))
+        }
+        StorageBackend::Toml => {
+            let path = cli
+                .tunnels_file
+                .clone()
+                .or_else(TomlStore::default_path)
+                .ok_or("Could not determine where tunnels.toml is")?;
+            Ok(Box::new(TomlStore::new(path)))
+        }
+    }
+}
+
+/// Tunnels run by this process when no window is open.
+struct Headless {
+    store: Box<dyn TunnelStore>,
+    running: BTreeMap<TunnelId, TunnelInfo>,
+}
+
+impl Headless {
+    fn answer(&mut self, request: &Request) -> Reply {
+        let tunnels = match self.store.load_tunnels() {
+            Ok(tunnels) => tunnels,
+            Err(e) => return Reply::failed(format!("Failed to load tunnels: {:#}", e)),
+        };
+        let status = |t: &Tunnel| tunnel_status(t, self.running.get(&t.id), false);
+        let names = match request {
+            Request::List => {
+                return status_reply(tunnels.iter().filter(|t| !t.deleted).map(status).collect());
+            }
+            Request::Status { tunnels: names } => {
+                return match find_tunnels(&tunnels, names) {
+                    Ok(found) => status_reply(found.into_iter().map(status).collect()),
+                    Err(e) => Reply::failed(e),
+                };
+            }
+            // Every request reads the store afresh
+            Request::Reload => {
+                let count = tunnels.iter().filter(|t| !t.deleted).count();
+                return Reply::ok(format!("Reloaded {} tunnels", count));
+            }
+            Request::Stop { all: true, .. } => {
+                if self.running.is_empty() {
+                    return Reply::ok("No tunnels are running".to_string());
+                }
+                let ids: Vec<TunnelId> = self.running.keys().copied().collect();
+                return reply_from(ids.into_iter().map(|id| Ok(self.stop(id))).collect());
+            }
+            Request::Start { tunnels: names }
+            | Request::Stop { tunnels: names, .. }
+            | Request::Toggle { tunnels: names } => names,
+        };
+        let targets: Vec<Tunnel> = match find_tunnels(&tunnels, names) {
+            Ok(targets) => targets.into_iter().cloned().collect(),
+            Err(e) => return Reply::failed(e),
+        };
+        let results = targets
+            .into_iter()
+            .map(|tunnel| {
+                let id = tunnel.id;
+                let running = self.running.contains_key(&id);
+                match request {
+                    Request::Start { .. } if running => {
+                        Ok(format!("{} is already running", tunnel.name))
+                    }
+                    Request::Stop { .. } if !running => {
+                        Ok(format!("{} is not running", tunnel.name))
+                    }
+                    Request::Stop { .. } | Request::Toggle { .. } if running => Ok(self.stop(id)),
+                    _ => self.start(tunnel),
+                }
+            })
+            .collect();
+        reply_from(results)
+    }
+
+    fn start(&mut self, tunnel: Tunnel) -> Result<String, String> {
+        let id = tunnel.id;
+        let mut info = TunnelInfo::from(tunnel);
+        let started = info
+            .run_pre_start()
+            .and_then(|()| info.start_tunnel())
+            .and_then(|()| info.wait_until_up());
+        if let Err(e) = started {
+            self.record_event(id, EventKind::Status, &format!("Failed to start: {}", e));
+            return Err(format!("Failed to start {}: {}", info.name, e));
+        }
+        if let Err(e) = self.store.record_start(id, unix_now()) {
+            error!("Failed to record start of tunnel {}: {:#}", id, e);
+        }
+        self.set_active(id, true);
+        self.record_event(id, EventKind::Started, "");
+        let line = format!(
+            "Started {} on {}:{}",
+            info.name, info.local_ip, info.local_port
+        );
+        self.running.insert(id, info);
+        Ok(line)
+    }
+
+    /// Starts the tunnels marked to start with Onigiri alongside the ones
+    /// asked for, as opening the window would.
+    fn autostart(&mut self) {
+        let tunnels = match self.store.load_tunnels() {
+            Ok(tunnels) => tunnels,
+            Err(e) => {
+                error!("Failed to load tunnels to autostart: {:#}", e);
+                return;
+            }
+        };
+        let flagged: Vec<Tunnel> = tunnels
+            .into_iter()
+            .filter(|t| t.autostart && !t.deleted && !self.running.contains_key(&t.id))
+            .collect();
+        if !flagged.is_empty() {
+            let results = flagged
+                .into_iter()
+                .map(|tunnel| self.start(tunnel))
+                .collect();
+            print_reply(&reply_from(results));
+        }
+    }
+
+    fn stop(&mut self, id: TunnelId) -> String {
+        let Some(mut info) = self.running.remove(&id) else {
+            return format!("Tunnel {} is not running", id);
+        };
+        info.stop_tunnel();
+        self.set_active(id, false);
+        self.record_event(id, EventKind::Stopped, "");
+        format!("Stopped {}", info.name)
+    }
+
+    /// Drops tunnels whose ssh has ended on its own.
+    fn reap(&mut self) {
+        let exited: Vec<TunnelId> = self
+            .running
+            .iter_mut()
+            .filter_map(|(id, info)| (!info.is_active()).then_some(*id))
+            .collect();
+        for id in exited {
+            let info = self.running.remove(&id).unwrap();
+            let status = info.exit_status.clone().unwrap_or_default();
+            eprintln!("{} exited: {}", info.name, status);
+            self.set_active(id, false);
+            self.record_event(id, EventKind::Exited, &status);
+        }
+    }
+
+    /// Keeps the started tunnels up until Ctrl-C or until they have all
+    /// ended, answering requests from other terminals meanwhile.
+    fn serve(&mut self, path: &Path) {
+        if let Err(e) = ctrlc::set_handler(|| RUNNING.store(false, Ordering::SeqCst)) {
+            error!("Failed to handle Ctrl-C: {}", e);
+        }
+        let server = control::Server::start(path, || {})
+            .map_err(|e| error!("Not listening for other commands: {}", e))
+            .ok();
+        eprintln!("Press Ctrl-C to stop");
+        while RUNNING.load(Ordering::SeqCst) && !self.running.is_empty() {
+            for incoming in server.iter().flat_map(control::Server::requests) {
+                let reply = self.answer(&incoming.request);
+                incoming.answer(reply);
+            }
+            self.reap();
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        let ids: Vec<TunnelId> = self.running.keys().copied().collect();
+        for id in ids {
+            println!("{}", self.stop(id));
+        }
+    }
+
+    fn set_active(&mut self, id: TunnelId, active: bool) {
+        if let Err(e) = self.store.set_active(id, active) {
+            error!("Failed to save state of tunnel {}: {:#}", id, e);
+        }
+    }
+
+    fn record_event(&mut self, id: TunnelId, kind: EventKind, detail: &str) {
+        if let Err(e) = self.store.record_event(id, unix_now(), kind, detail) {
+            error!(
+                "Failed to record {} event for tunnel {}: {:#}",
+                kind.key(),
+                id,
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tunnel(id: i64, name: &str) -> Tunnel {
+        crate::NewTunnelForm {
+            name: name.to_string(),
+            ssh_server: "bastion".to_string(),
+            local_ip: "127.0.0.1".to_string(),
+            local_port: (5000 + id).to_string(),
+            remote_ip: "db.internal".to_string(),
+            remote_port: "5432".to_string(),
+            ..Default::default()
+        }
+        .to_tunnel(TunnelId(id))
+    }
+
+    #[test]
+    fn tunnels_are_found_by_id_or_name() {
+        let tunnels = vec![
+            tunnel(1, "Prod DB"),
+            tunnel(2, "prod db"),
+            tunnel(3, "Cache"),
+            tunnel(4, "12"),
+        ];
+        let names = |found: Vec<&Tunnel>| found.iter().map(|t| t.id).collect::<Vec<_>>();
+        let find = |names: &[&str]| {
+            let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+            find_tunnels(&tunnels, &names)
+        };
+
+        assert_eq!(
+            names(find(&["Prod DB", "cache", "3", "12"]).unwrap()),
+            [1, 3, 3, 4].map(TunnelId)
+        );
+        assert!(find(&["PROD DB"]).unwrap_err().contains("several"));
+        assert_eq!(find(&["Web"]).unwrap_err(), "No tunnel named 'Web'");
+    }
+
+    #[test]
+    fn list_lines_up_columns() {
+        let statuses = vec![
+            tunnel_status(&tunnel(1, "Prod DB"), None, true),
+            tunnel_status(&tunnel(12, "Cache"), None, false),
+        ];
+        let reply = status_reply(statuses);
+        assert_eq!(reply.tunnels[0].status, "starting");
+        assert_eq!(
+            reply.output,
+            "ID  NAME     STATUS    LOCAL\n\
+             1   Prod DB  starting  127.0.0.1:5001\n\
+             12  Cache    stopped   127.0.0.1:5012"
+        );
+    }
+}
@@ -0,0 +1,88 @@
+//! `onigiri completions <shell>`: clap's completion script, plus completion
+//! of tunnel names for bash, zsh and fish. The scripts read the names from
+//! `onigiri __complete-names` each time, so they stay current.
+
+use clap_complete::Shell;
+
+pub const NAMES_COMMAND: &str = "__complete-names";
+
+// Called when the word being completed follows start, stop, status or toggle
+const BASH_NAMES: &str = r#"
+# Tunnel names after start, stop, status and toggle, quoted so that names
+# with spaces complete as one word
+_onigiri_tunnel_names() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" word name quoted
+    for word in "${COMP_WORDS[@]:1:COMP_CWORD-1}"; do
+        case "$word" in
+            start|stop|status|toggle)
+                if [[ $cur != -* ]]; then
+                    COMPREPLY=()
+                    while IFS= read -r name; do
+                        printf -v quoted '%q' "$name"
+                        [[ $quoted == "$cur"* ]] && COMPREPLY+=("$quoted")
+                    done < <(onigiri __complete-names 2>/dev/null)
+                    return 0
+                fi
+                ;;
+        esac
+    done
+    _onigiri "$@"
+}
+
+complete -F _onigiri_tunnel_names -o bashdefault -o default onigiri
+"#;
+
+const ZSH_NAMES: &str = r#"(( $+functions[_onigiri_tunnel_names] )) ||
+_onigiri_tunnel_names() {
+    local -a names
+    names=("${(@f)$(onigiri __complete-names 2>/dev/null)}")
+    compadd -a names
+}
+
+"#;
+
+const FISH_NAMES: &str = r#"
+complete -c onigiri -n "__fish_onigiri_using_subcommand start stop status toggle" -f -a "(onigiri __complete-names)"
+"#;
+
+/// The completion script for `shell`. Elvish and PowerShell only complete
+/// subcommands and options.
+pub fn script(shell: Shell, command: &mut clap::Command) -> String {
+    let mut out = Vec::new();
+    clap_complete::generate(shell, command, "onigiri", &mut out);
+    let script = String::from_utf8_lossy(&out).into_owned();
+    match shell {
+        Shell::Bash => script + BASH_NAMES,
+        // The tunnel arguments are completed like files unless told otherwise
+        Shell::Zsh => script
+            .replace(
+                "'*::tunnels:_default'",
+                "'*::tunnels:_onigiri_tunnel_names'",
+            )
+            .replacen(
+                "\nif [ \"$funcstack[1]\"",
+                &format!("\n{}if [ \"$funcstack[1]\"", ZSH_NAMES),
+                1,
+            ),
+        Shell::Fish => script + FISH_NAMES,
+        _ => script,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn scripts_complete_tunnel_names() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let script = script(shell, &mut crate::Cli::command());
+            assert!(script.contains(NAMES_COMMAND), "{}", shell);
+        }
+        let zsh = script(Shell::Zsh, &mut crate::Cli::command());
+        assert_eq!(zsh.matches("'*::tunnels:_onigiri_tunnel_names'").count(), 4);
+        assert!(!zsh.contains("'*::tunnels:_default'"));
+        assert!(zsh.find("_onigiri_tunnel_names() {") < zsh.find("if [ \"$funcstack[1]\""));
+    }
+}
@@ -0,0 +1,555 @@
+//! The socket a running Onigiri listens on for `onigiri start` and the other
+//! subcommands, so the terminal and other tools drive the tunnels that
+//! instance already owns instead of spawning a second ssh for them.
+//!
+//! The socket is `onigiri.sock` in the data directory, readable and
+//! writable by its owner only. Each connection carries one JSON request
+//! line and gets one JSON reply line:
+//!
+//! ```text
+//! {"command": "list"}
+//! {"command": "status", "tunnels": ["Prod DB"]}
+//! {"command": "start", "tunnels": ["Prod DB", "3"]}
+//! {"command": "stop", "tunnels": ["Prod DB"]}
+//! {"command": "stop", "all": true}
+//! {"command": "toggle", "tunnels": ["cache"]}
+//! {"command": "reload"}
+//!
+//! {"ok": true, "output": "Starting Prod DB"}
+//! {"ok": true, "output": "ID  NAME ...", "tunnels": [{"id": 1, "name": "Prod DB",
+//!  "status": "running", "local": "127.0.0.1:15432", "pid": 4242, "started_at": 1709993100}]}
+//! ```
+//!
+//! Tunnels are named by id or name. `output` is what the CLI prints; `list`
+//! and `status` also fill `tunnels`, whose `status` is "running",
+//! "starting" or "stopped". On Windows the same lines go over a named pipe
+//! whose name is derived from that path.
+
+use crate::TunnelId;
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+
+const SOCKET_FILE: &str = "onigiri.sock";
+// How long a request may wait for the instance to act on it
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+// How long a client may take to send its request line
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A subcommand, as parsed from the command line and sent over the socket.
+#[derive(Subcommand, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum Request {
+    /// List tunnels with their status and local address
+    List,
+    /// Show the status, pid and local address of tunnels by name or id
+    Status {
+        #[arg(required = true, value_name = "TUNNEL")]
+        #[serde(default)]
+        tunnels: Vec<String>,
+    },
+    /// Start tunnels by name or id; without a running Onigiri they stay up until Ctrl-C
+    Start {
+        #[arg(required = true, value_name = "TUNNEL")]
+        #[serde(default)]
+        tunnels: Vec<String>,
+    },
+    /// Stop tunnels by name or id
+    Stop {
+        #[arg(
+            value_name = "TUNNEL",
+            required_unless_present = "all",
+            conflicts_with = "all"
+        )]
+        #[serde(default)]
+        tunnels: Vec<String>,
+        /// Stop every running tunnel
+        #[arg(long)]
+        #[serde(default)]
+        all: bool,
+    },
+    /// Start the given tunnels that are stopped and stop the running ones
+    Toggle {
+        #[arg(required = true, value_name = "TUNNEL")]
+        #[serde(default)]
+        tunnels: Vec<String>,
+    },
+    /// Read the tunnels again after the database or tunnel file changed
+    Reload,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Reply {
+    pub ok: bool,
+    pub output: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tunnels: Vec<TunnelStatus>,
+}
+
+impl Reply {
+    pub fn ok(output: String) -> Self {
+        Self {
+            ok: true,
+            output,
+            tunnels: Vec::new(),
+        }
+    }
+
+    pub fn failed(output: String) -> Self {
+        Self {
+            ok: false,
+            output,
+            tunnels: Vec::new(),
+        }
+    }
+}
+
+/// One tunnel in a `list` or `status` reply.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TunnelStatus {
+    pub id: TunnelId,
+    pub name: String,
+    pub status: String,
+    pub local: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    /// Unix time the running ssh was started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<i64>,
+}
+
+/// A request waiting for the instance's answer.
+pub struct Incoming {
+    pub request: Request,
+    reply: Sender<Reply>,
+}
+
+impl Incoming {
+    pub fn answer(self, reply: Reply) {
+        // The client may have given up waiting
+        let _ = self.reply.send(reply);
+    }
+}
+
+pub fn socket_path() -> anyhow::Result<PathBuf> {
+    Ok(crate::storage::data_dir()?.join(SOCKET_FILE))
+}
+
+/// Where the socket for `path` is bound before it is moved there.
+#[cfg(unix)]
+fn staging_dir(path: &Path) -> PathBuf {
+    path.with_file_name(format!(".sock-{}", std::process::id()))
+}
+
+/// Accepts requests on a worker thread and hands them over through
+/// `requests`.
+pub struct Server {
+    path: PathBuf,
+    rx: Receiver<Incoming>,
+}
+
+impl Server {
+    /// Listens at `path`, calling `wake` whenever a request comes in. Fails
+    /// when another instance is already listening there.
+    #[cfg(unix)]
+    pub fn start(path: &Path, wake: impl Fn() + Send + Sync + 'static) -> io::Result<Self> {
+        use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+        use std::os::unix::net::{UnixListener, UnixStream};
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another Onigiri is already listening",
+            ));
+        }
+        // Left behind by an instance that didn't exit cleanly
+        let _ = std::fs::remove_file(path);
+        let dir = path.parent().unwrap_or(Path::new("."));
+        std::fs::create_dir_all(dir)?;
+        // Anyone who can connect can start and stop tunnels, so the socket is
+        // bound in a directory only its owner can enter and moved into place
+        // once it is private
+        let private = staging_dir(path);
+        let _ = std::fs::remove_dir_all(&private);
+        std::fs::DirBuilder::new().mode(0o700).create(&private)?;
+        let staged = private.join(SOCKET_FILE);
+        let bound = UnixListener::bind(&staged).and_then(|listener| {
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+            std::fs::rename(&staged, path)?;
+            Ok(listener)
+        });
+        let _ = std::fs::remove_dir_all(&private);
+        let listener = bound?;
+        let (tx, rx) = mpsc::channel();
+        let wake = Arc::new(wake);
+        let closed = Arc::new(AtomicBool::new(false));
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                // The instance has stopped taking requests
+                if closed.load(Ordering::Relaxed) {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log::debug!("Control connection failed: {}", e);
+                        continue;
+                    }
+                };
+                // Each connection gets its own thread so a client that never
+                // finishes its request can't hold up the others
+                let (tx, wake, closed) = (tx.clone(), Arc::clone(&wake), Arc::clone(&closed));
+                std::thread::spawn(move || {
+                    let result = stream
+                        .set_read_timeout(Some(REQUEST_TIMEOUT))
+                        .and_then(|()| serve(&stream, &tx, &*wake));
+                    match result {
+                        Ok(true) => {}
+                        Ok(false) => closed.store(true, Ordering::Relaxed),
+                        Err(e) => log::debug!("Control connection failed: {}", e),
+                    }
+                });
+            }
+        });
+        Ok(Self {
+            path: path.to_path_buf(),
+            rx,
+        })
+    }
+
+    /// Listens on the named pipe for `path`, calling `wake` whenever a
+    /// request comes in. Fails when another instance already owns the pipe.
+    #[cfg(windows)]
+    pub fn start(path: &Path, wake: impl Fn() + Send + Sync + 'static) -> io::Result<Self> {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use tokio::net::windows::named_pipe::ServerOptions;
+
+        let name = pipe_name(path);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let first = {
+            let _entered = runtime.enter();
+            ServerOptions::new()
+                .first_pipe_instance(true)
+                .create(&name)
+                .map_err(|e| match e.kind() {
+                    io::ErrorKind::PermissionDenied => io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        "another Onigiri is already listening",
+                    ),
+                    _ => e,
+                })?
+        };
+        let (tx, rx) = mpsc::channel();
+        let wake = Arc::new(wake);
+        let closed = Arc::new(AtomicBool::new(false));
+        std::thread::spawn(move || {
+            runtime.block_on(async move {
+                let mut pipe = first;
+                loop {
+                    let connected = pipe.connect().await;
+                    // The next client needs a new instance of the pipe
+                    let next = match ServerOptions::new().create(&name) {
+                        Ok(next) => next,
+                        Err(e) => {
+                            log::warn!("Stopped listening on {}: {}", name, e);
+                            return;
+                        }
+                    };
+                    let current = std::mem::replace(&mut pipe, next);
+                    // The instance has stopped taking requests
+                    if closed.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    if let Err(e) = connected {
+                        log::debug!("Control connection failed: {}", e);
+                        continue;
+                    }
+                    let (tx, wake, closed) = (tx.clone(), Arc::clone(&wake), Arc::clone(&closed));
+                    tokio::spawn(async move {
+                        match serve_pipe(current, tx, wake).await {
+                            Ok(true) => {}
+                            Ok(false) => closed.store(true, Ordering::Relaxed),
+                            Err(e) => log::debug!("Control connection failed: {}", e),
+                        }
+                    });
+                }
+            });
+        });
+        Ok(Self {
+            path: path.to_path_buf(),
+            rx,
+        })
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    pub fn start(_path: &Path, _wake: impl Fn() + Send + Sync + 'static) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the control socket needs Unix sockets or named pipes",
+        ))
+    }
+
+    pub fn requests(&self) -> Vec<Incoming> {
+        self.rx.try_iter().collect()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Hands a request line to the instance, or returns `None` once nobody
+/// takes requests any more.
+fn hand_over(
+    line: &str,
+    tx: &Sender<Incoming>,
+    wake: &impl Fn(),
+) -> io::Result<Option<Receiver<Reply>>> {
+    let request: Request = serde_json::from_str(line)?;
+    let (reply_tx, reply_rx) = mpsc::channel();
+    if tx
+        .send(Incoming {
+            request,
+            reply: reply_tx,
+        })
+        .is_err()
+    {
+        return Ok(None);
+    }
+    wake();
+    Ok(Some(reply_rx))
+}
+
+/// Waits for the instance's answer and returns it as a reply line.
+fn reply_line(reply_rx: Receiver<Reply>) -> io::Result<String> {
+    let reply = reply_rx
+        .recv_timeout(REPLY_TIMEOUT)
+        .unwrap_or_else(|_| Reply::failed("Onigiri did not answer in time".to_string()));
+    let mut line = serde_json::to_string(&reply)?;
+    line.push('\n');
+    Ok(line)
+}
+
+/// Passes one connection's request on and writes back the answer. Returns
+/// false once nobody takes requests any more.
+#[cfg(unix)]
+fn serve(
+    stream: &std::os::unix::net::UnixStream,
+    tx: &Sender<Incoming>,
+    wake: &impl Fn(),
+) -> io::Result<bool> {
+    use std::io::{BufRead, BufReader, Write};
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let Some(reply_rx) = hand_over(&line, tx, wake)? else {
+        return Ok(false);
+    };
+    let mut stream = stream;
+    stream.write_all(reply_line(reply_rx)?.as_bytes())?;
+    Ok(true)
+}
+
+/// `serve` for a client connected to the named pipe.
+#[cfg(windows)]
+async fn serve_pipe(
+    pipe: tokio::net::windows::named_pipe::NamedPipeServer,
+    tx: Sender<Incoming>,
+    wake: Arc<impl Fn() + Send + Sync + 'static>,
+) -> io::Result<bool> {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    let mut pipe = BufReader::new(pipe);
+    let mut line = String::new();
+    tokio::time::timeout(REQUEST_TIMEOUT, pipe.read_line(&mut line))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    let Some(reply_rx) = hand_over(&line, &tx, &*wake)? else {
+        return Ok(false);
+    };
+    let line = tokio::task::spawn_blocking(move || reply_line(reply_rx)).await??;
+    pipe.get_mut().write_all(line.as_bytes()).await?;
+    Ok(true)
+}
+
+/// The pipe standing in for the socket at `path`, so each data directory
+/// still gets its own.
+#[cfg(any(windows, test))]
+fn pipe_name(path: &Path) -> String {
+    let flat: String = path
+        .to_string_lossy()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!(r"\\.\pipe\onigiri-{}", flat.trim_matches('-'))
+}
+
+/// Hands `request` to the instance listening at `path`, or returns `None`
+/// when there is none.
+#[cfg(unix)]
+pub fn send(path: &Path, request: &Request) -> io::Result<Option<Reply>> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+
+    let mut stream = match UnixStream::connect(path) {
+        Ok(stream) => stream,
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Ok(None)
+        }
+        Err(e) => return Err(e),
+    };
+    stream.set_read_timeout(Some(REPLY_TIMEOUT + Duration::from_secs(1)))?;
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    Ok(Some(serde_json::from_str(&reply)?))
+}
+
+#[cfg(windows)]
+pub fn send(path: &Path, request: &Request) -> io::Result<Option<Reply>> {
+    use std::io::{BufRead, BufReader, Write};
+
+    let mut pipe = match std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(pipe_name(path))
+    {
+        Ok(pipe) => pipe,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    pipe.write_all(line.as_bytes())?;
+    let mut reply = String::new();
+    BufReader::new(pipe).read_line(&mut reply)?;
+    Ok(Some(serde_json::from_str(&reply)?))
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn send(_path: &Path, _request: &Request) -> io::Result<Option<Reply>> {
+    Ok(None)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn wire_format_is_tagged_by_command() {
+        let request: Request = serde_json::from_str(r#"{"command": "stop", "all": true}"#).unwrap();
+        assert_eq!(
+            request,
+            Request::Stop {
+                tunnels: Vec::new(),
+                all: true
+            }
+        );
+        let request = Request::Status {
+            tunnels: vec!["Prod DB".to_string()],
+        };
+        assert_eq!(
+            serde_json::to_string(&request).unwrap(),
+            r#"{"command":"status","tunnels":["Prod DB"]}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Reply::ok("Started".to_string())).unwrap(),
+            r#"{"ok":true,"output":"Started"}"#
+        );
+    }
+
+    #[test]
+    fn requests_reach_the_server_and_back() {
+        let path =
+            std::env::temp_dir().join(format!("onigiri-control-{}.sock", std::process::id()));
+        assert_eq!(send(&path, &Request::List).unwrap(), None);
+
+        let server = Server::start(&path, || {}).unwrap();
+        assert!(Server::start(&path, || {}).is_err());
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(!staging_dir(&path).exists());
+        let answering = std::thread::spawn(move || loop {
+            if let Some(incoming) = server.requests().pop() {
+                assert_eq!(
+                    incoming.request,
+                    Request::Stop {
+                        tunnels: Vec::new(),
+                        all: true
+                    }
+                );
+                incoming.answer(Reply::ok("Stopped 2 tunnels".to_string()));
+                return server;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        });
+
+        let request = Request::Stop {
+            tunnels: Vec::new(),
+            all: true,
+        };
+        let reply = send(&path, &request).unwrap().unwrap();
+        assert_eq!(reply, Reply::ok("Stopped 2 tunnels".to_string()));
+        drop(answering.join().unwrap());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn silent_clients_do_not_hold_up_others() {
+        let path =
+            std::env::temp_dir().join(format!("onigiri-control-slow-{}.sock", std::process::id()));
+        let server = Server::start(&path, || {}).unwrap();
+        // Connects but never sends its request
+        let _silent = std::os::unix::net::UnixStream::connect(&path).unwrap();
+        let answering = std::thread::spawn(move || loop {
+            if let Some(incoming) = server.requests().pop() {
+                incoming.answer(Reply::ok("ok".to_string()));
+                return server;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        });
+
+        let started = std::time::Instant::now();
+        let reply = send(&path, &Request::List).unwrap().unwrap();
+        assert_eq!(reply, Reply::ok("ok".to_string()));
+        assert!(started.elapsed() < REQUEST_TIMEOUT);
+        drop(answering.join().unwrap());
+    }
+
+    #[test]
+    fn each_data_directory_gets_its_own_pipe() {
+        assert_eq!(
+            pipe_name(Path::new(
+                r"C:\Users\me\AppData\Roaming\Onigiri\onigiri.sock"
+            )),
+            r"\\.\pipe\onigiri-C--Users-me-AppData-Roaming-Onigiri-onigiri.sock"
+        );
+        assert_ne!(
+            pipe_name(Path::new("/home/me/work/onigiri.sock")),
+            pipe_name(Path::new("/home/me/personal/onigiri.sock"))
+        );
+    }
+}
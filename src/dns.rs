@@ -0,0 +1,243 @@
+//! Resolving a tunnel's SSH server before ssh is started, so a typo or a VPN
+//! that's down fails the start at once with a clear reason instead of as
+//! ssh's exit a while later.
+//!
+//! The host ssh would connect to comes from `ssh -G`, which applies the SSH
+//! config and the tunnel's own options, so aliases are looked up by their
+//! `HostName`. Servers reached through a `ProxyJump` or `ProxyCommand`, from
+//! the config or from `-J` and `-o`, are resolved by the proxy and not
+//! checked. Answers are kept for `CACHE_FOR`, so starting ten tunnels on one
+//! bastion looks it up once.
+
+use log::debug;
+use std::collections::HashMap;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a lookup may take before the server counts as unresolvable.
+const TIMEOUT: Duration = Duration::from_secs(2);
+const CACHE_FOR: Duration = Duration::from_secs(10);
+
+/// Recent answers by server and options, with when they were looked up.
+type Answers = HashMap<(String, Vec<String>), (Instant, Result<(), String>)>;
+
+static CACHE: Mutex<Option<Answers>> = Mutex::new(None);
+
+/// Checks that `server`, as given to ssh with `options`, resolves. The
+/// error is the host name that didn't.
+pub fn check(server: &str, options: &[String]) -> Result<(), String> {
+    let now = Instant::now();
+    let key = (server.to_string(), options.to_vec());
+    if let Some((at, result)) = CACHE
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .get(&key)
+    {
+        if now.duration_since(*at) < CACHE_FOR {
+            return result.clone();
+        }
+    }
+    let result = check_with(server, options, TIMEOUT, ssh_target, resolves);
+    CACHE
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(key, (now, result.clone()));
+    result
+}
+
+/// `check` without the cache, with the config lookup and resolver given.
+/// Both run on a thread of their own so a hanging resolver only costs
+/// `timeout`.
+fn check_with(
+    server: &str,
+    options: &[String],
+    timeout: Duration,
+    target: fn(&str, &[String]) -> Option<Target>,
+    resolve: fn(&str) -> bool,
+) -> Result<(), String> {
+    let (tx, rx) = mpsc::channel();
+    let (owned, options) = (server.to_string(), options.to_vec());
+    std::thread::spawn(move || {
+        let host = match target(&owned, &options) {
+            Some(Target::Host(host)) => host,
+            Some(Target::Proxied) => return tx.send(Ok(())),
+            None if proxied(&options) => return tx.send(Ok(())),
+            None => host_of(&owned).to_string(),
+        };
+        let found = host.parse::<IpAddr>().is_ok() || resolve(&host);
+        tx.send(if found { Ok(()) } else { Err(host) })
+    });
+    rx.recv_timeout(timeout).unwrap_or_else(|_| {
+        debug!("Looking up {} took over {:?}", server, timeout);
+        Err(host_of(server).to_string())
+    })
+}
+
+/// Where ssh would connect to for a server.
+#[derive(Debug, PartialEq)]
+enum Target {
+    Host(String),
+    /// A proxy makes the connection, and resolves the name itself.
+    Proxied,
+}
+
+/// Asks ssh where it would connect for `server` with `options`, `None` when
+/// it can't say, e.g. an ssh too old for `-G`.
+fn ssh_target(server: &str, options: &[String]) -> Option<Target> {
+    let output = crate::background_command("ssh")
+        .arg("-G")
+        .args(options)
+        .arg(server)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    parse_target(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Reads the host, or that a proxy is used, from `ssh -G` output.
+fn parse_target(config: &str) -> Option<Target> {
+    let mut host = None;
+    for line in config.lines() {
+        match line.split_once(' ') {
+            Some(("proxyjump" | "proxycommand", value)) if value != "none" => {
+                return Some(Target::Proxied)
+            }
+            Some(("hostname", value)) => host = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    host.map(Target::Host)
+}
+
+/// Whether ssh `options` send it through a proxy, for when `ssh -G` can't
+/// tell.
+fn proxied(options: &[String]) -> bool {
+    if crate::ssh_command::has_flag(options, 'J') {
+        return true;
+    }
+    let mut words = options.iter();
+    while let Some(word) = words.next() {
+        let option = match word.strip_prefix("-o") {
+            Some("") => words.next().map_or("", String::as_str),
+            Some(option) => option,
+            None => continue,
+        };
+        let name = option.split(['=', ' ', '\t']).next().unwrap_or_default();
+        if name.eq_ignore_ascii_case("proxyjump") || name.eq_ignore_ascii_case("proxycommand") {
+            return true;
+        }
+    }
+    false
+}
+
+/// The host part of `[ssh://][user@]host[:port]`.
+fn host_of(server: &str) -> &str {
+    let server = server.strip_prefix("ssh://").unwrap_or(server);
+    let host = server.split_once('@').map_or(server, |(_, host)| host);
+    if let Some(bracketed) = host.strip_prefix('[') {
+        return bracketed.split(']').next().unwrap_or(bracketed);
+    }
+    match host.rsplit_once(':') {
+        Some((name, _)) if !name.contains(':') => name,
+        _ => host,
+    }
+}
+
+fn resolves(host: &str) -> bool {
+    (host, 22)
+        .to_socket_addrs()
+        .is_ok_and(|mut addrs| addrs.next().is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_configured_host_is_looked_up() {
+        let config = "user deploy\nhostname db1.example.com\nport 2222\n";
+        assert_eq!(
+            parse_target(config),
+            Some(Target::Host("db1.example.com".to_string()))
+        );
+        let jumped = "hostname db1.internal\nproxyjump bastion\n";
+        assert_eq!(parse_target(jumped), Some(Target::Proxied));
+        assert_eq!(parse_target(""), None);
+
+        assert_eq!(host_of("ssh://deploy@bastion:2222"), "bastion");
+        assert_eq!(host_of("deploy@[2001:db8::1]:22"), "2001:db8::1");
+        assert_eq!(host_of("::1"), "::1");
+        assert_eq!(host_of("bastion"), "bastion");
+    }
+
+    #[test]
+    fn unresolvable_and_slow_lookups_fail() {
+        let unknown = |_: &str, _: &[String]| None;
+        let known = |host: &str| host == "bastion.example.com";
+        let second = Duration::from_secs(1);
+        assert!(check_with("deploy@bastion.example.com", &[], second, unknown, known).is_ok());
+        assert_eq!(
+            check_with("db-serverr:22", &[], second, unknown, known),
+            Err("db-serverr".to_string())
+        );
+        // Addresses and proxied servers need no lookup
+        assert!(check_with("10.0.0.1", &[], second, unknown, |_| false).is_ok());
+        let proxied = |_: &str, _: &[String]| Some(Target::Proxied);
+        assert!(check_with("db-serverr", &[], second, proxied, |_| false).is_ok());
+
+        let hanging = |_: &str| {
+            std::thread::sleep(Duration::from_secs(5));
+            true
+        };
+        let started = Instant::now();
+        assert!(check_with("slow", &[], Duration::from_millis(100), unknown, hanging).is_err());
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn the_tunnels_options_reach_ssh() {
+        let words = |line: &str| crate::ssh_command::split(line).unwrap();
+        // ssh -G sees the options, so a jump host given there counts
+        let jumps = |_: &str, options: &[String]| {
+            options
+                .iter()
+                .any(|option| option == "-J")
+                .then_some(Target::Proxied)
+        };
+        let second = Duration::from_secs(1);
+        assert!(
+            check_with("db.internal", &words("-J bastion"), second, jumps, |_| {
+                false
+            })
+            .is_ok()
+        );
+        assert!(check_with("db.internal", &[], second, jumps, |_| false).is_err());
+
+        // Without ssh -G the options are read here
+        let unknown = |_: &str, _: &[String]| None;
+        for options in [
+            "-J bastion",
+            "-CJbastion",
+            "-o ProxyCommand='nc %h %p'",
+            "-oProxyJump=bastion",
+        ] {
+            assert!(
+                check_with("db.internal", &words(options), second, unknown, |_| false).is_ok(),
+                "{}",
+                options
+            );
+        }
+        assert!(check_with(
+            "db.internal",
+            &words("-i ~/.ssh/work -o ServerAliveInterval=30"),
+            second,
+            unknown,
+            |_| false
+        )
+        .is_err());
+    }
+}
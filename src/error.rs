@@ -0,0 +1,163 @@
+//! What can go wrong starting, saving or deleting a tunnel, told apart so
+//! the window can show each failure where it belongs: a bad field in the
+//! form, a failed start on the tunnel's row, a storage failure in a dialog.
+
+use crate::{FormField, PortHolder, TestOutcome, TunnelId};
+
+/// ssh's ways of saying the server turned the login down.
+pub const AUTH_FAILURES: [&str; 3] = [
+    "permission denied",
+    "host key verification failed",
+    "too many authentication failures",
+];
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum OnigiriError {
+    /// ssh couldn't be run, or exited before the forward came up.
+    #[error("{0}")]
+    Spawn(String),
+    /// The server refused the login.
+    #[error("ssh login failed: {0}")]
+    Auth(String),
+    /// The SSH server's host name doesn't resolve.
+    #[error("could not resolve '{0}' — check the hostname or your VPN")]
+    Unresolved(String),
+    /// The pre-start hook failed, with its stderr or how it ended.
+    #[error("pre-start hook failed: {0}")]
+    PreStart(String),
+    /// The local port can't be listened on.
+    #[error("{}", bind_message(*.port, .detail, .holder.as_ref()))]
+    Bind {
+        port: u16,
+        detail: String,
+        holder: Option<PortHolder>,
+    },
+    /// The tunnel store couldn't be read or written.
+    #[error("{0}")]
+    Database(String),
+    /// A setting is unusable, in `field` of the form when it's one of them.
+    #[error("{message}")]
+    Validation {
+        field: Option<FormField>,
+        message: String,
+    },
+    #[error("tunnel {0} no longer exists")]
+    NotFound(TunnelId),
+}
+
+fn bind_message(port: u16, detail: &str, holder: Option<&PortHolder>) -> String {
+    match holder {
+        Some(holder) => format!(
+            "port {} is used by {} (pid {})",
+            port, holder.command, holder.pid
+        ),
+        None => format!("local port {} is unavailable: {}", port, detail),
+    }
+}
+
+impl OnigiriError {
+    /// `program` failed to run at all.
+    pub fn spawn(program: &str, error: &std::io::Error) -> Self {
+        if error.kind() == std::io::ErrorKind::NotFound {
+            OnigiriError::Spawn(format!("{} was not found; is it installed?", program))
+        } else {
+            OnigiriError::Spawn(format!("could not run {}: {}", program, error))
+        }
+    }
+
+    /// Why ssh exited with `status` before the forward came up, going by
+    /// the last lines it wrote to stderr.
+    pub fn ssh_exited<S: AsRef<str>>(lines: &[S], status: &str) -> Self {
+        let refused = lines.iter().rev().find(|line| {
+            let lower = line.as_ref().to_lowercase();
+            AUTH_FAILURES.iter().any(|failure| lower.contains(failure))
+        });
+        match (refused, lines.last()) {
+            (Some(line), _) => OnigiriError::Auth(line.as_ref().to_string()),
+            (None, Some(line)) => OnigiriError::Spawn(line.as_ref().to_string()),
+            (None, None) => OnigiriError::Spawn(format!("ssh exited with {}", status)),
+        }
+    }
+}
+
+impl From<anyhow::Error> for OnigiriError {
+    fn from(error: anyhow::Error) -> Self {
+        OnigiriError::Database(format!("{:#}", error))
+    }
+}
+
+/// A start that failed while testing a tunnel.
+impl From<OnigiriError> for TestOutcome {
+    fn from(error: OnigiriError) -> Self {
+        match error {
+            OnigiriError::Bind { .. } => TestOutcome::BindError(error.to_string()),
+            OnigiriError::Auth(line) => TestOutcome::AuthError(line),
+            error => TestOutcome::Failed(error.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ssh_exits_are_told_apart() {
+        let refused = [
+            "debug1: Offering public key",
+            "Permission denied (publickey).",
+        ];
+        let error = OnigiriError::ssh_exited(&refused, "exit status: 255");
+        assert!(matches!(error, OnigiriError::Auth(_)));
+        assert_eq!(
+            error.to_string(),
+            "ssh login failed: Permission denied (publickey)."
+        );
+
+        let unreachable = ["ssh: connect to host db port 22: Connection refused"];
+        let error = OnigiriError::ssh_exited(&unreachable, "exit status: 255");
+        assert!(matches!(error, OnigiriError::Spawn(_)));
+        assert_eq!(error.to_string(), unreachable[0]);
+
+        let silent: [&str; 0] = [];
+        assert_eq!(
+            OnigiriError::ssh_exited(&silent, "exit status: 1").to_string(),
+            "ssh exited with exit status: 1"
+        );
+    }
+
+    #[test]
+    fn conversions_keep_the_message() {
+        let missing = std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert_eq!(
+            OnigiriError::spawn("ssh", &missing).to_string(),
+            "ssh was not found; is it installed?"
+        );
+
+        let locked = anyhow::anyhow!("database is locked").context("Failed to delete tunnel");
+        let error = OnigiriError::from(locked);
+        assert!(matches!(error, OnigiriError::Database(_)));
+        assert_eq!(
+            error.to_string(),
+            "Failed to delete tunnel: database is locked"
+        );
+
+        let bind = OnigiriError::Bind {
+            port: 5432,
+            detail: "Address already in use".to_string(),
+            holder: Some(PortHolder {
+                command: "postgres".to_string(),
+                pid: 42,
+            }),
+        };
+        assert_eq!(bind.to_string(), "port 5432 is used by postgres (pid 42)");
+        assert!(matches!(
+            TestOutcome::from(bind),
+            TestOutcome::BindError(message) if message.contains("postgres")
+        ));
+        assert!(matches!(
+            TestOutcome::from(OnigiriError::Auth("Permission denied".to_string())),
+            TestOutcome::AuthError(line) if line == "Permission denied"
+        ));
+    }
+}
@@ -0,0 +1,114 @@
+//! `~`, `$VAR` and `${VAR}` in tunnel fields, so shared and exported tunnels
+//! can leave out what differs between machines. Tunnels are stored as typed
+//! and only expanded when ssh is started.
+
+use std::path::Path;
+
+/// Whether `value` has anything to expand.
+pub fn has_references(value: &str) -> bool {
+    value.starts_with('~') || value.contains('$')
+}
+
+/// Expands `value` with this process's environment and home directory.
+pub fn expand(value: &str) -> Result<String, String> {
+    expand_with(value, dirs::home_dir().as_deref(), |name| {
+        std::env::var(name).ok()
+    })
+}
+
+/// Reports malformed references such as an unclosed `${`, whatever the
+/// environment holds.
+pub fn check(value: &str) -> Result<(), String> {
+    expand_with(value, Some(Path::new("~")), |_| Some(String::new())).map(|_| ())
+}
+
+/// Expands a leading `~` or `~/` (or `~\` on Windows) to `home` and each
+/// `$VAR` or `${VAR}` to what `var` returns for it. `$$` is a literal `$`.
+pub fn expand_with(
+    value: &str,
+    home: Option<&Path>,
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = value;
+    if rest == "~" || rest.starts_with("~/") || (cfg!(windows) && rest.starts_with("~\\")) {
+        let home = home.ok_or("~ is used but there is no home directory")?;
+        out.push_str(&home.display().to_string());
+        rest = &rest[1..];
+    }
+    while let Some(at) = rest.find('$') {
+        out.push_str(&rest[..at]);
+        rest = &rest[at + 1..];
+        let (name, after) = if let Some(braced) = rest.strip_prefix('{') {
+            let end = braced
+                .find('}')
+                .ok_or_else(|| format!("'${{' without a closing '}}' in {}", value))?;
+            (&braced[..end], &braced[end + 1..])
+        } else if let Some(after) = rest.strip_prefix('$') {
+            out.push('$');
+            rest = after;
+            continue;
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(format!("'${}' is not a variable name in {}", name, value));
+        }
+        let expanded =
+            var(name).ok_or_else(|| format!("environment variable {} is not set", name))?;
+        out.push_str(&expanded);
+        rest = after;
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_test(value: &str) -> Result<String, String> {
+        expand_with(value, Some(Path::new("/home/me")), |name| match name {
+            "BASTION_HOST" => Some("bastion.example.com".to_string()),
+            "USER" => Some("deploy".to_string()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn references_are_expanded() {
+        assert_eq!(
+            expand_test("~/.ssh/work_ed25519").unwrap(),
+            "/home/me/.ssh/work_ed25519"
+        );
+        assert_eq!(
+            expand_test("$USER@${BASTION_HOST}").unwrap(),
+            "deploy@bastion.example.com"
+        );
+        assert_eq!(expand_test("cost$$").unwrap(), "cost$");
+        // Only a leading ~ is a home directory
+        assert_eq!(expand_test("a~b").unwrap(), "a~b");
+        assert_eq!(expand_test("db.internal").unwrap(), "db.internal");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows_home_may_use_backslashes() {
+        let home = Path::new(r"C:\Users\me");
+        let expanded = expand_with(r"~\.ssh\work", Some(home), |_| None).unwrap();
+        assert_eq!(expanded, r"C:\Users\me\.ssh\work");
+    }
+
+    #[test]
+    fn unset_and_malformed_references_are_errors() {
+        let unset = expand_test("$PROD_HOST").unwrap_err();
+        assert!(unset.contains("PROD_HOST"), "{}", unset);
+        assert!(expand_test("${BASTION_HOST").is_err());
+        assert!(expand_test("host$").is_err());
+        assert!(check("${OTHER}:22").is_ok());
+        assert!(check("${OTHER").is_err());
+    }
+}
@@ -0,0 +1,223 @@
+//! Commands a tunnel runs around its start: before ssh, such as connecting a
+//! VPN or `tsh login`, and once it is up, such as opening a database client.
+//!
+//! Hooks run in the system shell (`sh -c`, or `cmd /C` on Windows) with the
+//! tunnel's details in `ONIGIRI_*` variables, and are killed along with
+//! whatever they started when they run past their timeout or are cancelled.
+
+use log::debug;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a pre-start hook may run when its tunnel doesn't say.
+pub const PRE_START_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long a post-start hook may run before it is stopped.
+pub const POST_START_TIMEOUT: Duration = Duration::from_secs(60);
+/// Lines kept of each of a hook's stdout and stderr.
+const MAX_LINES: usize = 200;
+/// Lines of stderr given as the reason a hook failed.
+const REASON_LINES: usize = 5;
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// How long to wait for output after the hook exits. Something it left
+/// running in the background may hold its pipes open for much longer.
+const OUTPUT_GRACE: Duration = Duration::from_millis(500);
+
+/// How a hook went.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HookRun {
+    /// What the hook wrote, stdout before stderr.
+    pub output: Vec<String>,
+    /// Why it failed: its stderr, or how it ended when it wrote none.
+    pub result: Result<(), String>,
+}
+
+/// Runs `command` with `env` added to Onigiri's environment, for at most
+/// `timeout` or until `cancel` is set. Blocks, so the app calls it off the
+/// UI thread.
+pub fn run(
+    command: &str,
+    env: &[(&str, String)],
+    timeout: Duration,
+    cancel: &AtomicBool,
+) -> HookRun {
+    let mut process = shell(command);
+    process
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // Its own process group, so a timeout can end what it started too
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut process, 0);
+    let mut child = match process.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            return HookRun {
+                output: Vec::new(),
+                result: Err(format!("could not run the hook: {}", e)),
+            }
+        }
+    };
+
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let stderr = Arc::new(Mutex::new(Vec::new()));
+    let (done_tx, done_rx) = mpsc::channel();
+    if let Some(pipe) = child.stdout.take() {
+        read_lines(pipe, Arc::clone(&stdout), done_tx.clone());
+    }
+    if let Some(pipe) = child.stderr.take() {
+        read_lines(pipe, Arc::clone(&stderr), done_tx.clone());
+    }
+    drop(done_tx);
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Some(status),
+            Ok(None) if Instant::now() < deadline && !cancel.load(Ordering::Relaxed) => {
+                std::thread::sleep(POLL_INTERVAL)
+            }
+            _ => {
+                debug!("Stopping hook '{}'", command);
+                kill(&mut child);
+                let _ = child.wait();
+                break None;
+            }
+        }
+    };
+    let grace = Instant::now() + OUTPUT_GRACE;
+    while done_rx
+        .recv_timeout(grace.saturating_duration_since(Instant::now()))
+        .is_ok()
+    {}
+
+    let stdout = std::mem::take(&mut *stdout.lock().unwrap());
+    let stderr = std::mem::take(&mut *stderr.lock().unwrap());
+    let ended = match status {
+        Some(status) if status.success() => None,
+        Some(status) => Some(format!("exited with {}", status)),
+        None if cancel.load(Ordering::Relaxed) => Some("stopped".to_string()),
+        None => Some(format!("timed out after {:?}", timeout)),
+    };
+    let result = match ended {
+        None => Ok(()),
+        Some(ended) if stderr.is_empty() => Err(ended),
+        // A timeout or stop is the reason even when the hook said something first
+        Some(ended) if status.is_none() => Err(format!("{}: {}", ended, reason(&stderr))),
+        Some(_) => Err(reason(&stderr)),
+    };
+    HookRun {
+        output: stdout.into_iter().chain(stderr).collect(),
+        result,
+    }
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut shell = crate::background_command("sh");
+    shell.args(["-c", command]);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = crate::background_command("cmd");
+    shell.args(["/C", command]);
+    shell
+}
+
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    let _ = Command::new("kill")
+        .args(["-s", "KILL", "--", &format!("-{}", child.id())])
+        .status();
+    let _ = crate::kill_child(child);
+}
+
+fn read_lines(pipe: impl Read + Send + 'static, into: Arc<Mutex<Vec<String>>>, done: Sender<()>) {
+    std::thread::spawn(move || {
+        for line in BufReader::new(pipe).lines().map_while(Result::ok) {
+            let mut lines = into.lock().unwrap();
+            if lines.len() == MAX_LINES {
+                lines.remove(0);
+            }
+            lines.push(line);
+        }
+        let _ = done.send(());
+    });
+}
+
+/// The last lines of stderr, which usually say what went wrong.
+fn reason(stderr: &[String]) -> String {
+    let start = stderr.len().saturating_sub(REASON_LINES);
+    stderr[start..].join("\n")
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hooks_get_the_tunnel_environment() {
+        let env = [
+            ("ONIGIRI_TUNNEL_NAME", "Prod DB".to_string()),
+            ("ONIGIRI_LOCAL_PORT", "15432".to_string()),
+        ];
+        let run = run(
+            "echo \"$ONIGIRI_TUNNEL_NAME on $ONIGIRI_LOCAL_PORT\"",
+            &env,
+            Duration::from_secs(5),
+            &AtomicBool::new(false),
+        );
+        assert_eq!(run.result, Ok(()));
+        assert_eq!(run.output, ["Prod DB on 15432"]);
+    }
+
+    #[test]
+    fn failures_say_why() {
+        let (timeout, running) = (Duration::from_secs(5), AtomicBool::new(false));
+        let failed = run(
+            "echo checking; echo 'no such profile' >&2; exit 3",
+            &[],
+            timeout,
+            &running,
+        );
+        assert_eq!(failed.result, Err("no such profile".to_string()));
+        assert_eq!(failed.output, ["checking", "no such profile"]);
+
+        let silent = run("exit 2", &[], timeout, &running);
+        assert!(silent.result.unwrap_err().contains("exited with"));
+    }
+
+    #[test]
+    fn slow_hooks_are_killed_with_what_they_started() {
+        let started = Instant::now();
+        let run = run(
+            "echo waking >&2; sleep 30 & sleep 30",
+            &[],
+            Duration::from_millis(300),
+            &AtomicBool::new(false),
+        );
+        assert_eq!(run.result, Err("timed out after 300ms: waking".to_string()));
+        // The background sleep was killed too, or its pipes would be held
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+
+    #[test]
+    fn cancelled_hooks_stop_early() {
+        let cancel = std::sync::Arc::new(AtomicBool::new(false));
+        let flag = std::sync::Arc::clone(&cancel);
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            flag.store(true, Ordering::Relaxed);
+        });
+        let started = Instant::now();
+        let run = run("sleep 30", &[], Duration::from_secs(60), &cancel);
+        assert_eq!(run.result, Err("stopped".to_string()));
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+}
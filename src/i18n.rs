@@ -0,0 +1,703 @@
+//! Translations of the UI text.
+//!
+//! The English text is the key, so a string without a translation still
+//! reads fine. Log messages stay in English and don't go through `tr`.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Write};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    English,
+    German,
+}
+
+impl Language {
+    pub const ALL: [Language; 2] = [Language::English, Language::German];
+
+    /// The language's own name, so it can be found whatever is selected.
+    pub fn label(self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::German => "Deutsch",
+        }
+    }
+
+    pub fn key(self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::German => "de",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|language| language.key() == key)
+    }
+
+    fn catalog(self) -> Option<&'static HashMap<&'static str, &'static str>> {
+        static GERMAN_CATALOG: OnceLock<HashMap<&str, &str>> = OnceLock::new();
+        match self {
+            Language::English => None,
+            Language::German => {
+                Some(GERMAN_CATALOG.get_or_init(|| GERMAN.iter().copied().collect()))
+            }
+        }
+    }
+}
+
+static LANGUAGE: AtomicU8 = AtomicU8::new(0);
+
+pub fn language() -> Language {
+    Language::ALL[LANGUAGE.load(Ordering::Relaxed) as usize]
+}
+
+pub fn set_language(language: Language) {
+    let index = Language::ALL
+        .iter()
+        .position(|l| *l == language)
+        .unwrap_or(0);
+    LANGUAGE.store(index as u8, Ordering::Relaxed);
+}
+
+/// `text` in the selected language.
+pub fn tr(text: &'static str) -> &'static str {
+    translate(language(), text)
+}
+
+/// `tr` for text built with `format!`: each `{}` in the translation is
+/// replaced by the next of `args`.
+pub fn trf(text: &'static str, args: &[&dyn Display]) -> String {
+    fill(tr(text), args)
+}
+
+fn fill(template: &str, args: &[&dyn Display]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut args = args.iter();
+    let mut pieces = template.split("{}");
+    out.push_str(pieces.next().unwrap_or_default());
+    for piece in pieces {
+        if let Some(arg) = args.next() {
+            let _ = write!(out, "{}", arg);
+        }
+        out.push_str(piece);
+    }
+    out
+}
+
+fn translate(language: Language, text: &'static str) -> &'static str {
+    language
+        .catalog()
+        .and_then(|catalog| catalog.get(text).copied())
+        .unwrap_or(text)
+}
+
+const GERMAN: &[(&str, &str)] = &[
+    // Header and menus
+    ("SSH Tunnel Manager", "SSH-Tunnel-Manager"),
+    ("Import…", "Importieren…"),
+    ("Export…", "Exportieren…"),
+    (
+        "Import from SSH config…",
+        "Aus SSH-Konfiguration importieren…",
+    ),
+    (
+        "Import from SSH config",
+        "Aus SSH-Konfiguration importieren",
+    ),
+    ("Import PuTTY sessions…", "PuTTY-Sitzungen importieren…"),
+    ("Templates…", "Vorlagen…"),
+    ("Templates", "Vorlagen"),
+    ("Empty trash…", "Papierkorb leeren…"),
+    ("Empty trash", "Papierkorb leeren"),
+    ("Back up database…", "Datenbank sichern…"),
+    ("Restore from backup…", "Aus Sicherung wiederherstellen…"),
+    ("Restore from backup", "Aus Sicherung wiederherstellen"),
+    ("Theme", "Design"),
+    ("Follow system", "Wie das System"),
+    ("Light", "Hell"),
+    ("Dark", "Dunkel"),
+    ("Density", "Dichte"),
+    ("Comfortable", "Bequem"),
+    ("Compact", "Kompakt"),
+    ("Language", "Sprache"),
+    (
+        "Close window keeps tunnels running",
+        "Schließen des Fensters lässt Tunnel laufen",
+    ),
+    (
+        "Closing the window hides it; use Quit to exit",
+        "Schließen blendet das Fenster aus; mit Beenden wird die App geschlossen",
+    ),
+    (
+        "Tunnels keep running when this window is closed",
+        "Tunnel laufen weiter, wenn dieses Fenster geschlossen wird",
+    ),
+    ("Terminal command…", "Terminal-Befehl…"),
+    ("Terminal command", "Terminal-Befehl"),
+    ("Quit", "Beenden"),
+    ("New workspace…", "Neuer Arbeitsbereich…"),
+    ("Rename…", "Umbenennen…"),
+    (
+        "The default workspace can't be deleted",
+        "Der Standard-Arbeitsbereich kann nicht gelöscht werden",
+    ),
+    ("Show them again", "Erneut anzeigen"),
+    ("Clear", "Leeren"),
+    // List
+    ("Search:", "Suche:"),
+    ("Clear search", "Suche leeren"),
+    ("Running only", "Nur laufende"),
+    ("Tags:", "Tags:"),
+    ("Start All", "Alle starten"),
+    ("Show server under names", "Server unter den Namen zeigen"),
+    ("Not shown in the compact density", "In der kompakten Darstellung ausgeblendet"),
+    ("About Onigiri", "Über Onigiri"),
+    ("Onigiri {}", "Onigiri {}"),
+    ("A SSH tunnel manager", "Ein Verwalter für SSH-Tunnel"),
+    ("ssh:", "ssh:"),
+    ("not found", "nicht gefunden"),
+    ("Tunnels:", "Tunnel:"),
+    ("Open folder", "Ordner öffnen"),
+    ("not saved", "nicht gespeichert"),
+    ("Check for updates", "Nach Updates suchen"),
+    ("Releases", "Versionen"),
+    ("You have the latest version", "Du hast die neueste Version"),
+    ("Couldn't check for updates", "Suche nach Updates fehlgeschlagen"),
+    ("No tunnels match the filters", "Keine Tunnel passen zu den Filtern"),
+    ("Clear filter", "Filter zurücksetzen"),
+    ("Never started", "Nie gestartet"),
+    (
+        "Saving will restart this tunnel and drop existing connections.",
+        "Beim Speichern wird dieser Tunnel neu gestartet und bestehende Verbindungen werden getrennt.",
+    ),
+    ("Save and restart", "Speichern und neu starten"),
+    ("Save without restarting", "Speichern ohne Neustart"),
+    ("restart pending", "Neustart ausstehend"),
+    ("Restart", "Neu starten"),
+    ("Restart with the saved settings", "Mit den gespeicherten Einstellungen neu starten"),
+    ("Start when Onigiri opens", "Beim Öffnen von Onigiri starten"),
+    ("When quitting", "Beim Beenden"),
+    ("Stop tunnels", "Tunnel stoppen"),
+    ("Leave tunnels running", "Tunnel weiterlaufen lassen"),
+    ("Ask", "Nachfragen"),
+    ("These tunnels are still running:", "Diese Tunnel laufen noch:"),
+    ("Keep them running and quit", "Weiterlaufen lassen und beenden"),
+    ("Stop tunnels and quit", "Tunnel stoppen und beenden"),
+    ("Data folder:", "Datenordner:"),
+    ("Log level", "Protokollstufe"),
+    ("Set by RUST_LOG", "Durch RUST_LOG festgelegt"),
+    ("Errors", "Fehler"),
+    ("Warnings", "Warnungen"),
+    ("Info", "Info"),
+    ("Debug", "Debug"),
+    ("Trace", "Trace"),
+    ("Open log folder", "Protokollordner öffnen"),
+    ("Logs:", "Protokolle:"),
+    ("Resolved:", "Aufgelöst:"),
+    ("Can't resolve:", "Nicht auflösbar:"),
+    ("Copy share link", "Freigabelink kopieren"),
+    ("One line to paste into another Onigiri", "Eine Zeile zum Einfügen in ein anderes Onigiri"),
+    ("Paste tunnel", "Tunnel einfügen"),
+    ("Paste tunnel…", "Tunnel einfügen…"),
+    ("Add a tunnel from a share link", "Einen Tunnel aus einem Freigabelink hinzufügen"),
+    ("Paste a link made with Copy share link.", "Einen mit „Freigabelink kopieren“ erstellten Link einfügen."),
+    ("Forward:", "Weiterleitung:"),
+    ("Edit first…", "Erst bearbeiten…"),
+    ("Couldn't save the change", "Die Änderung konnte nicht gespeichert werden"),
+    ("Another program may be using the tunnel database. Try again in a moment.", "Möglicherweise verwendet ein anderes Programm die Tunnel-Datenbank. Versuche es gleich noch einmal."),
+    ("OK", "OK"),
+    ("'{}' is not an IP address, such as 127.0.0.1 or ::1", "'{}' ist keine IP-Adresse wie 127.0.0.1 oder ::1"),
+    ("'{}' is not an IP address or hostname", "'{}' ist weder IP-Adresse noch Hostname"),
+    ("Put a user name before the @, or leave both out", "Gib vor dem @ einen Benutzernamen an oder lass beides weg"),
+    ("'[' without a closing ']'", "'[' ohne schließende ']'"),
+    ("'{}' is not an IPv6 address", "'{}' ist keine IPv6-Adresse"),
+    ("Only a :port may follow the address", "Nach der Adresse darf nur ein :Port folgen"),
+    ("The SSH port must be between 1 and 65535", "Der SSH-Port muss zwischen 1 und 65535 liegen"),
+    ("Remove the control characters from this field", "Entferne die Steuerzeichen aus diesem Feld"),
+    ("Show logs…", "Protokoll anzeigen…"),
+    ("Logs", "Protokoll"),
+    ("Search logs", "Protokoll durchsuchen"),
+    ("Autoscroll", "Automatisch scrollen"),
+    ("Copy all", "Alles kopieren"),
+    ("Copies the records shown", "Kopiert die angezeigten Einträge"),
+    ("Nothing logged at this level yet", "Auf dieser Stufe wurde noch nichts protokolliert"),
+    ("0 picks a free port at each start", "0 wählt bei jedem Start einen freien Port"),
+    ("Paste command:", "Befehl einfügen:"),
+    ("Fill in", "Übernehmen"),
+    ("SSH options:", "SSH-Optionen:"),
+    ("More ssh options, such as -i ~/.ssh/work or -J jump@edge", "Weitere ssh-Optionen, etwa -i ~/.ssh/work oder -J jump@edge"),
+    ("Leave out -f: Onigiri keeps ssh running itself", "Lass -f weg: Onigiri hält ssh selbst am Laufen"),
+    ("Kept as SSH options: {}", "Als SSH-Optionen übernommen: {}"),
+    ("Left out the remote command: {}", "Entfernten Befehl weggelassen: {}"),
+    ("SSH agent:", "SSH-Agent:"),
+    ("inherited", "geerbt"),
+    ("The agent socket given to ssh as SSH_AUTH_SOCK", "Der Agent-Socket, den ssh als SSH_AUTH_SOCK bekommt"),
+    ("No agents found", "Keine Agents gefunden"),
+    ("Inherited from Onigiri", "Von Onigiri geerbt"),
+    ("After start:", "Nach dem Start:"),
+    ("A shell command run once the tunnel is up, with ONIGIRI_LOCAL_PORT, ONIGIRI_LOCAL_IP and ONIGIRI_TUNNEL_NAME set", "Ein Shell-Befehl, der läuft, sobald der Tunnel steht, mit ONIGIRI_LOCAL_PORT, ONIGIRI_LOCAL_IP und ONIGIRI_TUNNEL_NAME"),
+    ("Before start:", "Vor dem Start:"),
+    ("A shell command run before ssh, such as tsh login; the start fails if it does", "Ein Shell-Befehl, der vor ssh läuft, etwa tsh login; schlägt er fehl, startet der Tunnel nicht"),
+    ("Timeout (s):", "Zeitlimit (s):"),
+    ("Timeout must be a whole number of seconds", "Das Zeitlimit muss eine ganze Zahl von Sekunden sein"),
+    ("running; restart to apply", "läuft; zum Übernehmen neu starten"),
+    ("The tunnel keeps its old settings until it is restarted", "Der Tunnel behält seine alten Einstellungen, bis er neu gestartet wird"),
+    // Text with values filled in by trf
+    ("Start all", "Alle starten"),
+    ("Stop all", "Alle stoppen"),
+    ("Start all matching", "Alle Treffer starten"),
+    ("Stop all matching", "Alle Treffer stoppen"),
+    ("Exited", "Beendet"),
+    ("Stopped", "Gestoppt"),
+    (" (approximate)", " (ungefähr)"),
+    ("Saved template '{}'", "Vorlage '{}' gespeichert"),
+    ("History: {}", "Verlauf: {}"),
+    ("Start {}", "{} starten"),
+    ("Stopped {} tunnel", "{} Tunnel gestoppt"),
+    ("Stopped {} tunnels", "{} Tunnel gestoppt"),
+    ("Stopped {} tunnel. Failed: {}", "{} Tunnel gestoppt. Fehlgeschlagen: {}"),
+    ("Stopped {} tunnels. Failed: {}", "{} Tunnel gestoppt. Fehlgeschlagen: {}"),
+    ("These tunnels were running when Onigiri last closed: {}", "Diese Tunnel liefen, als Onigiri zuletzt geschlossen wurde: {}"),
+    ("Exported {} tunnels to {}", "{} Tunnel nach {} exportiert"),
+    ("Export failed: {}", "Export fehlgeschlagen: {}"),
+    ("Import failed: {}", "Import fehlgeschlagen: {}"),
+    ("Templates: {}", "Vorlagen: {}"),
+    ("{} entries will not be imported:", "{} Einträge werden nicht importiert:"),
+    ("Import {}", "{} importieren"),
+    ("Imported {} tunnels", "{} Tunnel importiert"),
+    ("Imported {} tunnels; set the secret again for {} of them from Edit", "{} Tunnel importiert; setze das Geheimnis für {} davon unter Bearbeiten neu"),
+    ("Imported {} tunnels from the SSH config", "{} Tunnel aus der SSH-Konfiguration importiert"),
+    ("Run by Open terminal; {} is replaced by the ssh command.", "Wird von Terminal öffnen ausgeführt; {} wird durch den ssh-Befehl ersetzt."),
+    ("Quit {}", "{} beenden"),
+    ("Permanently remove {} deleted tunnel? This cannot be undone.", "{} gelöschten Tunnel endgültig entfernen? Das kann nicht rückgängig gemacht werden."),
+    ("Permanently remove {} deleted tunnels? This cannot be undone.", "{} gelöschte Tunnel endgültig entfernen? Das kann nicht rückgängig gemacht werden."),
+    ("Remove {}", "{} entfernen"),
+    ("Removed {} deleted tunnels", "{} gelöschte Tunnel entfernt"),
+    ("Backed up to {}", "Gesichert unter {}"),
+    ("Backup failed: {}", "Sicherung fehlgeschlagen: {}"),
+    ("Replace all tunnels with the 1 tunnel in {}? Running tunnels will be stopped.", "Alle Tunnel durch den einen Tunnel in {} ersetzen? Laufende Tunnel werden gestoppt."),
+    ("Replace all tunnels with the {} tunnels in {}? Running tunnels will be stopped.", "Alle Tunnel durch die {} Tunnel in {} ersetzen? Laufende Tunnel werden gestoppt."),
+    ("Restored tunnels from {}", "Tunnel aus {} wiederhergestellt"),
+    ("Restore failed: {}", "Wiederherstellung fehlgeschlagen: {}"),
+    ("Move {} tunnel to the trash?", "{} Tunnel in den Papierkorb verschieben?"),
+    ("Move {} tunnels to the trash?", "{} Tunnel in den Papierkorb verschieben?"),
+    ("Delete {}", "{} löschen"),
+    ("The database has {} tunnels. Copy them into the new tunnel file?", "Die Datenbank enthält {} Tunnel. In die neue Tunneldatei kopieren?"),
+    ("Copy {} tunnels", "{} Tunnel kopieren"),
+    ("{} active", "{} aktiv"),
+    ("/ {} tunnel", "/ {} Tunnel"),
+    ("/ {} tunnels", "/ {} Tunnel"),
+    ("(showing {})", "({} angezeigt)"),
+    ("Sort: {}", "Sortierung: {}"),
+    ("{} tunnel hidden by filters", "{} Tunnel durch Filter ausgeblendet"),
+    ("{} tunnels hidden by filters", "{} Tunnel durch Filter ausgeblendet"),
+    ("{} selected", "{} ausgewählt"),
+    ("No tunnels match '{}'", "Keine Tunnel passen zu '{}'"),
+    ("Up for {}", "Läuft seit {}"),
+    ("Failed {}: {}", "Fehlgeschlagen {}: {}"),
+    ("stopped after {} idle", "nach {} Leerlauf gestoppt"),
+    ("{} · local port {} (picked)", "{} · lokaler Port {} (gewählt)"),
+    ("{} · any free local port", "{} · beliebiger freier lokaler Port"),
+    ("{} · local port {}", "{} · lokaler Port {}"),
+    ("Died unexpectedly {}: {}", "Unerwartet beendet {}: {}"),
+    ("Post-start hook failed: {}", "Befehl nach dem Start fehlgeschlagen: {}"),
+    ("Port {} is used by Onigiri tunnel '{}'", "Port {} wird vom Onigiri-Tunnel '{}' belegt"),
+    ("Stop '{}'", "'{}' stoppen"),
+    ("Started {} ({})", "Gestartet {} ({})"),
+    ("Last check: {}", "Letzte Prüfung: {}"),
+    ("Last connection seen {} ago", "Letzte Verbindung vor {}"),
+    ("Last exit: {}", "Letztes Ende: {}"),
+    ("Local: {} -> Remote: {}:{}", "Lokal: {} -> Entfernt: {}:{}"),
+    ("SSH agent: {}", "SSH-Agent: {}"),
+    ("Idle timeout: stops in {}", "Leerlauf-Timeout: stoppt in {}"),
+    ("Last used {}", "Zuletzt benutzt {}"),
+    ("Created {}", "Erstellt {}"),
+    ("Updated {}", "Geändert {}"),
+    ("Try {} of {} in {}", "Versuch {} von {} in {}"),
+    ("reconnecting", "verbindet neu"),
+    ("Active", "Aktiv"),
+    ("Inactive", "Inaktiv"),
+    ("Ungrouped", "Ohne Gruppe"),
+    ("Stop All", "Alle stoppen"),
+    ("★ Pinned", "★ Angeheftet"),
+    ("No tunnels yet", "Noch keine Tunnel"),
+    (
+        "Add one, or import the forwards you already have.",
+        "Lege einen an oder importiere vorhandene Weiterleitungen.",
+    ),
+    ("Add tunnel", "Tunnel hinzufügen"),
+    ("Import from file…", "Aus Datei importieren…"),
+    ("Create examples", "Beispiele anlegen"),
+    (
+        "Add three made-up tunnels to try things out",
+        "Drei erfundene Tunnel zum Ausprobieren anlegen",
+    ),
+    ("Move up", "Nach oben"),
+    ("Move down", "Nach unten"),
+    ("Drag to reorder", "Zum Sortieren ziehen"),
+    ("Switch to Manual sort to drag", "Zum Ziehen auf manuelle Sortierung wechseln"),
+    ("Select; shift-click selects a range", "Auswählen; Umschalt-Klick wählt einen Bereich"),
+    ("Start", "Starten"),
+    ("Stop", "Stoppen"),
+    ("Delete…", "Löschen…"),
+    ("Add tag", "Tag hinzufügen"),
+    ("tag", "Tag"),
+    ("Move to group", "In Gruppe verschieben"),
+    ("No group", "Keine Gruppe"),
+    ("new group", "neue Gruppe"),
+    ("Move", "Verschieben"),
+    ("Clear selection", "Auswahl aufheben"),
+    ("Delete tunnels", "Tunnel löschen"),
+    ("Open", "Öffnen"),
+    ("Test", "Testen"),
+    ("Testing…", "Teste…"),
+    ("Edit", "Bearbeiten"),
+    ("Duplicate", "Duplizieren"),
+    ("Hide", "Ausblenden"),
+    ("Export", "Exportieren"),
+    ("Delete", "Löschen"),
+    ("Dismiss", "Ausblenden"),
+    ("queued", "wartet"),
+    ("Copy command", "Befehl kopieren"),
+    ("Copy as ssh_config", "Als ssh_config kopieren"),
+    (
+        "Append ssh_config to file…",
+        "ssh_config an Datei anhängen…",
+    ),
+    ("Export as service…", "Als Dienst exportieren…"),
+    ("Export as service", "Als Dienst exportieren"),
+    ("Save as template…", "Als Vorlage speichern…"),
+    ("Save as template", "Als Vorlage speichern"),
+    ("Open terminal", "Terminal öffnen"),
+    (
+        "ssh to the server in a terminal",
+        "Per ssh im Terminal mit dem Server verbinden",
+    ),
+    ("Copy local address", "Lokale Adresse kopieren"),
+    ("Copy as URL", "Als URL kopieren"),
+    ("Copy", "Kopieren"),
+    ("Copied", "Kopiert"),
+    ("History", "Verlauf"),
+    ("Edit here", "Hier bearbeiten"),
+    ("ssh output:", "ssh-Ausgabe:"),
+    ("Never used", "Nie benutzt"),
+    // Forms
+    ("Add New Tunnel", "Neuer Tunnel"),
+    ("Edit Tunnel", "Tunnel bearbeiten"),
+    ("Add Tunnel", "Tunnel hinzufügen"),
+    ("Preset:", "Vorgabe:"),
+    ("Save as preset", "Als Vorgabe speichern"),
+    (
+        "Save the name and ports for next time",
+        "Name und Ports für das nächste Mal speichern",
+    ),
+    ("Name:", "Name:"),
+    ("SSH Server:", "SSH-Server:"),
+    ("Local IP:", "Lokale IP:"),
+    ("Local Port:", "Lokaler Port:"),
+    ("Remote IP:", "Entfernte IP:"),
+    ("Remote Port:", "Entfernter Port:"),
+    ("Idle timeout (min):", "Leerlauf-Timeout (min):"),
+    ("Group:", "Gruppe:"),
+    ("Color:", "Farbe:"),
+    ("No color", "Keine Farbe"),
+    ("Open as:", "Öffnen als:"),
+    ("Notes:", "Notizen:"),
+    ("Secret:", "Geheimnis:"),
+    ("Set secret", "Geheimnis setzen"),
+    ("Clear secret", "Geheimnis entfernen"),
+    ("in system keychain", "im Schlüsselbund des Systems"),
+    ("Existing:", "Vorhanden:"),
+    ("add tag", "Tag hinzufügen"),
+    ("none", "keine"),
+    ("off", "aus"),
+    ("Add", "Hinzufügen"),
+    ("Save", "Speichern"),
+    ("Cancel", "Abbrechen"),
+    ("Browse…", "Durchsuchen…"),
+    ("Rename", "Umbenennen"),
+    ("New from template", "Neu aus Vorlage"),
+    (
+        "No templates yet. Use a tunnel's Export → Save as template… to add one.",
+        "Noch keine Vorlagen. Lege eine über Exportieren → Als Vorlage speichern… an.",
+    ),
+    (
+        "Leave blank in new tunnels:",
+        "In neuen Tunneln leer lassen:",
+    ),
+    ("SSH server", "SSH-Server"),
+    ("Local port", "Lokaler Port"),
+    ("Remote IP", "Entfernte IP"),
+    ("Remote port", "Entfernter Port"),
+    ("Reset to default", "Zurücksetzen"),
+    // Validation
+    ("Name is required", "Name ist erforderlich"),
+    ("SSH Server is required", "SSH-Server ist erforderlich"),
+    ("Local IP is required", "Lokale IP ist erforderlich"),
+    ("Remote IP is required", "Entfernte IP ist erforderlich"),
+    (
+        "Port must be between 1 and 65535",
+        "Port muss zwischen 1 und 65535 liegen",
+    ),
+    ("Invalid port number", "Ungültige Portnummer"),
+    (
+        "Idle timeout must be a whole number of minutes",
+        "Leerlauf-Timeout muss eine ganze Zahl von Minuten sein",
+    ),
+    (
+        "'{}' is running on this address",
+        "'{}' läuft bereits auf dieser Adresse",
+    ),
+    (
+        "'{}' also uses this address; only one can run at a time",
+        "'{}' nutzt dieselbe Adresse; nur einer kann gleichzeitig laufen",
+    ),
+    // Dialogs
+    ("Import Tunnels", "Tunnel importieren"),
+    ("new", "neu"),
+    (
+        "Replaces templates with the same name",
+        "Ersetzt Vorlagen mit gleichem Namen",
+    ),
+    (
+        "Runs this tunnel in the background, even when Onigiri is closed.",
+        "Führt diesen Tunnel im Hintergrund aus, auch wenn Onigiri geschlossen ist.",
+    ),
+    ("Load and start it now", "Jetzt laden und starten"),
+    ("Restore", "Wiederherstellen"),
+    ("Restore tunnels", "Tunnel wiederherstellen"),
+    ("Not now", "Nicht jetzt"),
+    ("New tunnel file", "Neue Tunneldatei"),
+    ("Start empty", "Leer beginnen"),
+    ("Storage error", "Speicherfehler"),
+    (
+        "Check that the data folder exists and is writable, then retry.",
+        "Prüfe, ob der Datenordner existiert und beschreibbar ist, und versuche es erneut.",
+    ),
+    ("Retry", "Erneut versuchen"),
+    ("Open data folder", "Datenordner öffnen"),
+    ("Continue", "Fortfahren"),
+    (
+        "Use an empty tunnel list; nothing will be saved",
+        "Mit leerer Tunnelliste fortfahren; nichts wird gespeichert",
+    ),
+    ("Rename workspace", "Arbeitsbereich umbenennen"),
+    ("New workspace", "Neuer Arbeitsbereich"),
+    ("Default", "Standard"),
+    ("Name", "Name"),
+    ("Nothing has happened yet", "Bisher ist nichts passiert"),
+    (
+        "History is only kept with database storage",
+        "Der Verlauf wird nur mit Datenbank-Speicherung geführt",
+    ),
+    ("Started", "Gestartet"),
+    ("Status", "Status"),
+    ("Deleted", "Gelöscht"),
+    ("Tagged", "Getaggt"),
+    ("Moved", "Verschoben"),
+    (
+        "Secrets aren't exported; set it again from Edit after importing",
+        "Geheimnisse werden nicht exportiert; setze es nach dem Import unter Bearbeiten neu",
+    ),
+    ("name exists", "Name vorhanden"),
+    ("duplicate", "doppelt"),
+    ("Skip", "Überspringen"),
+    ("Overwrite", "Überschreiben"),
+    ("Keep both", "Beide behalten"),
+    ("{} forwards can't be imported:", "{} Weiterleitungen können nicht importiert werden:"),
+    ("Choose…", "Auswählen…"),
+    ("Fill in the highlighted fields", "Fülle die markierten Felder aus"),
+    ("Auto", "Automatisch"),
+    // Sorting
+    ("Manual", "Manuell"),
+    ("Running first", "Laufende zuerst"),
+    ("Recently used", "Zuletzt benutzt"),
+    ("Newest first", "Neueste zuerst"),
+    // Rows
+    ("Running, checking the local port…", "Läuft, der lokale Port wird geprüft…"),
+    ("Running", "Läuft"),
+    ("Running but unreachable", "Läuft, aber nicht erreichbar"),
+    ("Running the pre-start command…", "Befehl vor dem Start läuft…"),
+    ("Starting…", "Startet…"),
+    ("Queued", "Wartet"),
+    ("Reconnecting…", "Verbindet neu…"),
+    ("Failed", "Fehlgeschlagen"),
+    ("Pin to top", "Oben anheften"),
+    ("Unpin", "Lösen"),
+    ("Save or cancel the edit first", "Speichere oder verwirf zuerst die Änderung"),
+    ("Open in the browser", "Im Browser öffnen"),
+    ("Start the tunnel first", "Starte zuerst den Tunnel"),
+    ("Show all tunnels", "Alle Tunnel zeigen"),
+    ("Show running tunnels only", "Nur laufende Tunnel zeigen"),
+    ("Esc", "Esc"),
+    ("PID {}", "PID {}"),
+    ("PID: {}", "PID: {}"),
+    ("{}/{} running", "{}/{} laufen"),
+    ("{} running in other workspaces", "{} laufen in anderen Arbeitsbereichen"),
+    ("{} recent error", "{} aktueller Fehler"),
+    ("{} recent errors", "{} aktuelle Fehler"),
+    // Status line
+    ("{} {} tunnel", "{}: {} Tunnel"),
+    ("{} {} tunnels", "{}: {} Tunnel"),
+    ("{} {} of {} tunnel. Failed: {}", "{}: {} von {} Tunnel. Fehlgeschlagen: {}"),
+    ("{} {} of {} tunnels. Failed: {}", "{}: {} von {} Tunneln. Fehlgeschlagen: {}"),
+    ("Copied ssh command", "ssh-Befehl kopiert"),
+    ("Copied '{}' as ssh_config", "'{}' als ssh_config kopiert"),
+    ("Copied a share link for '{}'", "Freigabelink für '{}' kopiert"),
+    ("Appended '{}' to {}", "'{}' an {} angehängt"),
+    ("Failed to append to {}: {}", "Anhängen an {} fehlgeschlagen: {}"),
+    ("'{}' now runs as a service", "'{}' läuft jetzt als Dienst"),
+    ("Wrote service for '{}' to {}", "Dienst für '{}' nach {} geschrieben"),
+    ("Template '{}': {}", "Vorlage '{}': {}"),
+    ("Failed to load history: {}", "Verlauf konnte nicht geladen werden: {}"),
+    ("Failed to add example tunnels: {}", "Beispieltunnel konnten nicht angelegt werden: {}"),
+    ("No PuTTY sessions with tunnels found", "Keine PuTTY-Sitzungen mit Tunneln gefunden"),
+    ("Could not find the home directory", "Das Benutzerverzeichnis wurde nicht gefunden"),
+    ("No SSH config at {}", "Keine SSH-Konfiguration unter {}"),
+    ("No forwards found in {}", "Keine Weiterleitungen in {} gefunden"),
+    ("Trash is already empty", "Der Papierkorb ist schon leer"),
+    ("Failed to count deleted tunnels: {}", "Gelöschte Tunnel konnten nicht gezählt werden: {}"),
+    ("Failed to empty trash: {}", "Papierkorb konnte nicht geleert werden: {}"),
+    (
+        "The terminal command is only kept until Onigiri closes: {}",
+        "Der Terminal-Befehl bleibt nur bis zum Schließen von Onigiri erhalten: {}",
+    ),
+    ("Settings are unavailable", "Die Einstellungen sind nicht verfügbar"),
+    ("Saved preset '{}'", "Vorgabe '{}' gespeichert"),
+    (
+        "Preset '{}' is only kept until Onigiri closes: {}",
+        "Vorgabe '{}' bleibt nur bis zum Schließen von Onigiri erhalten: {}",
+    ),
+    // Pasted commands
+    (
+        "Paste an ssh command, such as ssh -L 8443:internal:443 deploy@bastion",
+        "Füge einen ssh-Befehl ein, etwa ssh -L 8443:internal:443 deploy@bastion",
+    ),
+    ("Not an ssh command: it starts with '{}'", "Kein ssh-Befehl: er beginnt mit '{}'"),
+    ("-{} needs a value", "-{} braucht einen Wert"),
+    (
+        "No server in the command; it needs one such as deploy@bastion",
+        "Kein Server im Befehl; er braucht einen wie deploy@bastion",
+    ),
+    (
+        "No -L forward in the command; a tunnel forwards a local port, as in -L 8443:internal:443",
+        "Keine -L-Weiterleitung im Befehl; ein Tunnel leitet einen lokalen Port weiter, wie bei -L 8443:internal:443",
+    ),
+    ("A ' quote isn't closed", "Ein '-Anführungszeichen ist nicht geschlossen"),
+    ("A \" quote isn't closed", "Ein \"-Anführungszeichen ist nicht geschlossen"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources(dir: &std::path::Path, files: &mut Vec<std::path::PathBuf>) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                sources(&path, files);
+            } else if path.extension().is_some_and(|ext| ext == "rs") {
+                files.push(path);
+            }
+        }
+    }
+
+    /// Where UI text ends up; a literal handed straight to one of these
+    /// skipped `tr`.
+    const SETTERS: &[&str] = &[
+        "label(",
+        "button(",
+        "on_hover_text(",
+        "on_disabled_hover_text(",
+        "weak(",
+        "heading(",
+        "status_message = Some(",
+    ];
+
+    /// The string literal `code` starts with, unescaped.
+    fn leading_literal(code: &str) -> Option<String> {
+        let mut chars = code.strip_prefix('"')?.chars();
+        let mut literal = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return Some(literal),
+                '\\' => literal.extend(chars.next()),
+                c => literal.push(c),
+            }
+        }
+        None
+    }
+
+    /// Every literal passed to `tr` or `trf` anywhere under src needs a
+    /// translation, with as many `{}` as the English, and no words may
+    /// reach a label, button, hover text or the status line without them.
+    #[test]
+    fn every_referenced_text_is_translated() {
+        let mut files = Vec::new();
+        sources(
+            &std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src"),
+            &mut files,
+        );
+        let mut missing = Vec::new();
+        let mut raw = Vec::new();
+        for path in files {
+            let source = std::fs::read_to_string(&path).unwrap();
+            let code = source.split("#[cfg(test)]").next().unwrap_or_default();
+            for setter in SETTERS {
+                for call in code.split(setter).skip(1) {
+                    let call = call.trim_start();
+                    let call = call.strip_prefix("format!(").map_or(call, str::trim_start);
+                    if let Some(literal) = leading_literal(call) {
+                        if literal.chars().any(|c| c.is_ascii_alphabetic()) {
+                            raw.push(literal);
+                        }
+                    }
+                }
+            }
+            // Longer texts start on the line after the call
+            let calls = ["tr", "trf"].into_iter().flat_map(|function| {
+                source
+                    .split(&format!("{}(", function))
+                    .skip(1)
+                    .filter_map(|call| call.trim_start().strip_prefix('"'))
+                    .collect::<Vec<_>>()
+            });
+            for call in calls {
+                let end = call.find("\",").into_iter().chain(call.find("\")")).min();
+                let key = call[..end.unwrap_or(0)].replace("\\\"", "\"");
+                if key.is_empty() {
+                    continue;
+                }
+                match GERMAN.iter().find(|(english, _)| *english == key) {
+                    Some((_, german))
+                        if german.matches("{}").count() == key.matches("{}").count() => {}
+                    _ => missing.push(key),
+                }
+            }
+        }
+        assert!(missing.is_empty(), "untranslated: {:?}", missing);
+        assert!(raw.is_empty(), "not passed through tr: {:?}", raw);
+    }
+
+    #[test]
+    fn arguments_fill_the_translation() {
+        assert_eq!(fill("Up for {}", &[&"3m"]), "Up for 3m");
+        assert_eq!(
+            fill("Stopped {}. Failed: {}", &[&2, &"boom"]),
+            "Stopped 2. Failed: boom"
+        );
+        assert_eq!(fill("{} and {}", &[&1]), "1 and ");
+    }
+
+    #[test]
+    fn untranslated_text_falls_back_to_english() {
+        assert_eq!(translate(Language::German, "Cancel"), "Abbrechen");
+        assert_eq!(
+            translate(Language::German, "Not in the catalog"),
+            "Not in the catalog"
+        );
+        assert_eq!(translate(Language::English, "Cancel"), "Cancel");
+    }
+}
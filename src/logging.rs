@@ -0,0 +1,332 @@
+//! Logging to stderr and to a file in the data directory, since a GUI app
+//! opened from the Finder or a desktop launcher has nowhere to show stderr.
+//!
+//! The file is `logs/onigiri.log`. Once it grows past `MAX_FILE_SIZE` it is
+//! renamed to `onigiri.log.1`, shifting older files up, and only
+//! `KEPT_FILES` old files are kept. An externally set `RUST_LOG` decides
+//! what gets logged; otherwise the level picked in the app does.
+//!
+//! The last `RECENT_RECORDS` records are also kept in memory for the log
+//! window.
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+/// Settings key the chosen level is stored under.
+pub const SETTING_KEY: &str = "log.level";
+pub const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+/// Levels offered in the app, quietest first.
+pub const LEVELS: [LevelFilter; 5] = [
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace,
+];
+
+const LOG_DIR: &str = "logs";
+const LOG_FILE: &str = "onigiri.log";
+const MAX_FILE_SIZE: u64 = 2 * 1024 * 1024;
+const KEPT_FILES: usize = 5;
+pub const RECENT_RECORDS: usize = 5_000;
+
+// Other crates stay at Info even when Onigiri's own logs are more verbose
+const DEPENDENCY_LEVEL: LevelFilter = LevelFilter::Info;
+
+static LEVEL: AtomicUsize = AtomicUsize::new(DEFAULT_LEVEL as usize);
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+static RECENT: Mutex<VecDeque<LogRecord>> = Mutex::new(VecDeque::new());
+
+/// A logged record, as kept for the log window.
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    /// Unix time it was logged at.
+    pub at: i64,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+impl LogRecord {
+    /// The record as a line of the log file, without the newline.
+    pub fn line(&self) -> String {
+        let (year, month, day, hour, minute, second) = crate::storage::utc_date_time(self.at);
+        format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} {:<5} {}: {}",
+            year, month, day, hour, minute, second, self.level, self.target, self.message
+        )
+    }
+}
+
+/// Keeps `record`, dropping the oldest once `RECENT_RECORDS` are kept.
+fn remember(record: LogRecord) {
+    let mut recent = RECENT.lock().unwrap();
+    if recent.len() == RECENT_RECORDS {
+        recent.pop_front();
+    }
+    recent.push_back(record);
+}
+
+/// Calls `f` with the records kept in memory, oldest first.
+pub fn with_recent<R>(f: impl FnOnce(&VecDeque<LogRecord>) -> R) -> R {
+    f(&RECENT.lock().unwrap())
+}
+
+struct Logger {
+    stderr: Box<dyn Log>,
+    // Set when RUST_LOG was, and then decides instead of `LEVEL`
+    env_filter: Option<Box<dyn Log>>,
+    file: Mutex<Option<LogFile>>,
+}
+
+/// The log file being appended to.
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl LogFile {
+    fn open(path: PathBuf) -> std::io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path, file, size })
+    }
+
+    fn write(&mut self, line: &str) -> std::io::Result<()> {
+        if self.size + line.len() as u64 > MAX_FILE_SIZE && self.size > 0 {
+            rotate(&self.path, KEPT_FILES)?;
+            *self = Self::open(self.path.clone())?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Shifts `path` to `path.1`, `path.1` to `path.2` and so on, dropping the
+/// file that would become `path.{keep + 1}`.
+fn rotate(path: &Path, keep: usize) -> std::io::Result<()> {
+    let numbered = |n: usize| PathBuf::from(format!("{}.{}", path.display(), n));
+    let _ = std::fs::remove_file(numbered(keep));
+    for n in (1..keep).rev() {
+        let from = numbered(n);
+        if from.exists() {
+            std::fs::rename(&from, numbered(n + 1))?;
+        }
+    }
+    std::fs::rename(path, numbered(1))
+}
+
+/// The level picked in the app.
+pub fn level() -> LevelFilter {
+    LEVELS
+        .into_iter()
+        .find(|level| *level as usize == LEVEL.load(Ordering::Relaxed))
+        .unwrap_or(LevelFilter::Off)
+}
+
+impl Logger {
+    fn enabled_for(&self, metadata: &Metadata) -> bool {
+        if let Some(filter) = &self.env_filter {
+            return filter.enabled(metadata);
+        }
+        let own = metadata.target() == "onigiri" || metadata.target().starts_with("onigiri::");
+        let limit = if own {
+            level()
+        } else {
+            level().min(DEPENDENCY_LEVEL)
+        };
+        metadata.level() <= limit
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.enabled_for(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled_for(record.metadata()) {
+            return;
+        }
+        self.stderr.log(record);
+        let kept = LogRecord {
+            at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs() as i64)
+                .unwrap_or(0),
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        };
+        let mut file = self.file.lock().unwrap();
+        if let Some(log_file) = file.as_mut() {
+            if let Err(e) = log_file.write(&format!("{}\n", kept.line())) {
+                // Logging the failure would come straight back here
+                eprintln!("Stopped writing {}: {}", log_file.path.display(), e);
+                *file = None;
+            }
+        }
+        drop(file);
+        remember(kept);
+    }
+
+    fn flush(&self) {
+        self.stderr.flush();
+        if let Some(log_file) = self.file.lock().unwrap().as_mut() {
+            let _ = log_file.file.flush();
+        }
+    }
+}
+
+/// The folder log files are written to.
+pub fn log_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join(LOG_DIR)
+}
+
+/// Whether `RUST_LOG` overrides the level picked in the app.
+pub fn level_from_env() -> bool {
+    LOGGER
+        .get()
+        .is_some_and(|logger| logger.env_filter.is_some())
+}
+
+/// Logs to stderr and, when `dir` is given, to a rotated file in it.
+pub fn init(dir: Option<&Path>) {
+    let env_filter = std::env::var("RUST_LOG")
+        .ok()
+        .filter(|filters| !filters.is_empty())
+        .map(|filters| {
+            Box::new(
+                pretty_env_logger::formatted_timed_builder()
+                    .parse_filters(&filters)
+                    .build(),
+            ) as Box<dyn Log>
+        });
+    let stderr = Box::new(
+        pretty_env_logger::formatted_timed_builder()
+            .filter_level(LevelFilter::Trace)
+            .build(),
+    );
+    let file = dir.and_then(|dir| match LogFile::open(dir.join(LOG_FILE)) {
+        Ok(file) => Some(file),
+        Err(e) => {
+            eprintln!("Not logging to {}: {}", dir.display(), e);
+            None
+        }
+    });
+    let max_level = if env_filter.is_some() {
+        LevelFilter::Trace
+    } else {
+        level()
+    };
+    let logger = LOGGER.get_or_init(|| Logger {
+        stderr,
+        env_filter,
+        file: Mutex::new(file),
+    });
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+/// Changes the level from now on. Has no effect while `RUST_LOG` is set.
+pub fn set_level(level: LevelFilter) {
+    LEVEL.store(level as usize, Ordering::Relaxed);
+    if !level_from_env() {
+        log::set_max_level(level);
+    }
+}
+
+pub fn level_label(level: LevelFilter) -> &'static str {
+    match level {
+        LevelFilter::Off => "Off",
+        LevelFilter::Error => "Errors",
+        LevelFilter::Warn => "Warnings",
+        LevelFilter::Info => "Info",
+        LevelFilter::Debug => "Debug",
+        LevelFilter::Trace => "Trace",
+    }
+}
+
+/// The level saved under `SETTING_KEY`.
+pub fn parse_level(key: &str) -> Option<LevelFilter> {
+    LEVELS
+        .into_iter()
+        .find(|level| level.as_str().eq_ignore_ascii_case(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation_keeps_the_newest_files() {
+        let dir = std::env::temp_dir().join(format!("onigiri-logs-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut file = LogFile::open(dir.join(LOG_FILE)).unwrap();
+        let line = "x".repeat(MAX_FILE_SIZE as usize / 2 + 1);
+        for _ in 0..KEPT_FILES + 3 {
+            file.write(&line).unwrap();
+        }
+
+        let mut names: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        let mut expected = vec![LOG_FILE.to_string()];
+        expected.extend((1..=KEPT_FILES).map(|n| format!("{}.{}", LOG_FILE, n)));
+        assert_eq!(names, expected);
+        assert_eq!(
+            std::fs::metadata(dir.join(LOG_FILE)).unwrap().len(),
+            line.len() as u64
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recent_records_are_capped() {
+        let record = |n: usize| LogRecord {
+            at: 1_700_000_000,
+            level: Level::Info,
+            target: "onigiri::logging::test".to_string(),
+            message: n.to_string(),
+        };
+        for n in 0..RECENT_RECORDS + 10 {
+            remember(record(n));
+        }
+        // Other tests may log meanwhile, so only this test's records count
+        let kept: Vec<String> = with_recent(|recent| {
+            assert_eq!(recent.len(), RECENT_RECORDS);
+            recent
+                .iter()
+                .filter(|r| r.target == "onigiri::logging::test")
+                .map(|r| r.message.clone())
+                .collect()
+        });
+        assert_eq!(kept.last().unwrap(), &(RECENT_RECORDS + 9).to_string());
+        assert!(!kept.contains(&"9".to_string()));
+        assert_eq!(
+            record(1).line(),
+            "2023-11-14 22:13:20 INFO  onigiri::logging::test: 1"
+        );
+    }
+
+    #[test]
+    fn levels_round_trip_through_the_setting() {
+        for level in LEVELS {
+            assert_eq!(parse_level(level.as_str()), Some(level));
+        }
+        assert_eq!(parse_level("loud"), None);
+    }
+}
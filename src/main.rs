@@ -1,96 +1,771 @@
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use eframe::egui;
-use log::{debug, error, info, trace};
-use rusqlite::{params, Connection, OptionalExtension};
-use std::collections::{HashMap, HashSet};
-use std::process::{Child, Command};
-use std::sync::atomic::AtomicBool;
-use std::sync::Arc;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::io::{BufRead, BufReader, ErrorKind, Read};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+mod about;
+mod agent;
+mod badge;
+mod cli;
+mod completions;
+mod control;
+mod dns;
+mod error;
+mod expand;
+mod hooks;
+mod i18n;
+mod logging;
+mod presets;
+#[cfg(any(windows, test))]
+mod putty;
+mod secrets;
+mod service;
+mod ssh_command;
+mod ssh_config;
+mod storage;
+mod terminal;
+mod transfer;
+#[cfg(any(target_os = "macos", windows, test))]
+mod tray;
+mod window;
+
+use error::OnigiriError;
+use storage::{
+    EventKind, LeftRunning, Settings, SqliteStore, Template, TomlStore, TunnelChange, TunnelEvent, TunnelStore,
+    Workspace,
+};
+use i18n::{tr, trf, Language};
+use window::WindowGeometry;
 
 const WINDOW_HEIGHT: f32 = 500.0;
 const WINDOW_WIDTH: f32 = 400.0;
+const MIN_WINDOW_WIDTH: f32 = 280.0;
+// Below this the row buttons no longer leave room for the name
+const COMPACT_ROW_WIDTH: f32 = 350.0;
 const APP_NAME: &str = "Onigiri";
+const DEFAULT_START_DELAY_MS: u64 = 300;
+const DEFAULT_START_CONCURRENCY: u16 = 2;
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(15);
+const STDERR_LINES: usize = 200;
+// Marks the pre-start hook's lines in a tunnel's log
+const PRE_START_PREFIX: &str = "[pre-start] ";
+const TEST_TIMEOUT: Duration = Duration::from_secs(10);
+// How long a start without the window waits for the forward to come up
+const START_GRACE: Duration = Duration::from_secs(2);
+// How long an error toast stays up, and how many errors are kept after that
+const TOAST_DURATION: Duration = Duration::from_secs(5);
+const RECENT_ERRORS: usize = 20;
+// How long "Copied" shows next to a copy button
+const COPIED_FEEDBACK: Duration = Duration::from_millis(1500);
+// Form errors are shown once typing pauses for this long
+const LIVE_VALIDATION_DELAY: Duration = Duration::from_millis(400);
+// Window moves and resizes are saved once they settle for this long
+const GEOMETRY_SAVE_DELAY: Duration = Duration::from_millis(500);
+const WORKSPACE_SETTING: &str = "workspace.active";
+const KEEP_RUNNING_SETTING: &str = "window.keep_running";
+const THEME_SETTING: &str = "appearance.theme";
+const DENSITY_SETTING: &str = "appearance.density";
+const SUBTITLE_SETTING: &str = "appearance.server_subtitle";
+const LANGUAGE_SETTING: &str = "appearance.language";
+const QUIT_SETTING: &str = "quit.tunnels";
+// Events listed in a tunnel's history window
+const HISTORY_LIMIT: usize = 100;
+// How often a tunnel taken over from the last session is checked on
+const ADOPTED_POLL_INTERVAL: Duration = Duration::from_secs(2);
+// How often the window redraws with nothing happening, for times shown
+const CLOCK_REPAINT_INTERVAL: Duration = Duration::from_secs(30);
+// How long an ssh whose stderr closed is watched for its exit
+const EXIT_REAP_WAIT: Duration = Duration::from_secs(1);
+// How long quitting waits for ssh to exit after SIGTERM before killing it
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+// How often a tunnel whose ssh died is started again before it counts as
+// failed, and the wait before the first try, doubled for each one after
+const RECONNECT_ATTEMPTS: u32 = 3;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+// A tunnel up for longer than this before dying starts its tries afresh
+const RECONNECT_STABLE_SECS: i64 = 60;
+// Cleared by Ctrl-C and SIGTERM
 static RUNNING: AtomicBool = AtomicBool::new(true);
 
+#[derive(Parser, Debug)]
+#[command(name = "onigiri", version, about)]
+struct Cli {
+    /// Delay in milliseconds between ssh spawns when several tunnels start together
+    #[arg(long, default_value_t = DEFAULT_START_DELAY_MS)]
+    start_delay_ms: u64,
+
+    /// Maximum number of ssh processes spawned per delay interval
+    #[arg(long, default_value_t = DEFAULT_START_CONCURRENCY, value_parser = clap::value_parser!(u16).range(1..))]
+    start_concurrency: u16,
+
+    /// Where tunnel definitions are kept
+    #[arg(long, value_enum, default_value_t = StorageBackend::Sqlite)]
+    storage: StorageBackend,
+
+    /// Tunnel file for the TOML backend [default: ~/.config/onigiri/tunnels.toml]
+    #[arg(long, value_name = "PATH")]
+    tunnels_file: Option<PathBuf>,
+
+    /// At startup, permanently remove tunnels deleted more than this many days ago
+    #[arg(long, value_name = "DAYS")]
+    purge_deleted_after_days: Option<u32>,
+
+    /// Keep the database, control socket and logs in DIR instead of the
+    /// platform's data directory; overrides ONIGIRI_DATA_DIR
+    #[arg(long, global = true, value_name = "DIR")]
+    data_dir: Option<PathBuf>,
+
+    /// Whether to start the tunnels that were running when Onigiri last closed
+    #[arg(long, value_enum, default_value_t = RestoreMode::Ask)]
+    restore_tunnels: RestoreMode,
+
+    /// Run a command instead of opening the window
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+enum CliCommand {
+    #[command(flatten)]
+    Tunnels(control::Request),
+    /// Print a completion script for bash, zsh, fish, elvish or PowerShell
+    Completions { shell: clap_complete::Shell },
+    /// Print tunnel names for the completion scripts, one per line
+    #[command(name = completions::NAMES_COMMAND, hide = true)]
+    CompleteNames,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum RestoreMode {
+    Ask,
+    Always,
+    Never,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum StorageBackend {
+    Sqlite,
+    Toml,
+}
+
 #[derive(Debug)]
 struct TunnelInfo {
-    id: i64,
+    id: TunnelId,
     name: String,
-    ssh_server: String,
     local_ip: String,
     local_port: u16,
     remote_ip: String,
     remote_port: u16,
+    // Stored as local port 0: a free port is picked into `local_port` at
+    // every start
+    auto_port: bool,
+    ssh_server: String,
+    // The tunnel's extra options in `command`, which may reach the server
+    // through a proxy
+    ssh_options: Vec<String>,
+    command: Vec<String>,
+    // Given to ssh as SSH_AUTH_SOCK, in place of Onigiri's own
+    agent_socket: Option<PathBuf>,
+    // Run in the shell before ssh, which only starts if it succeeds
+    pre_start: Option<String>,
+    pre_start_timeout: Duration,
+    // What the pre-start hook wrote, shown ahead of ssh's lines
+    pre_start_log: Vec<String>,
+    // Set while the pre-start hook or the server check runs; stopping the
+    // tunnel sets the flag
+    pre_start_cancel: Option<Arc<AtomicBool>>,
+    // The server resolved on the preparing thread, so ssh starts without
+    // asking again
+    server_checked: bool,
+    // Run in the shell once the tunnel is up
+    post_start: Option<String>,
+    // Why the fields couldn't be expanded, refusing the start
+    unresolved: Option<OnigiriError>,
     process: Option<Child>,
+    stderr: Arc<Mutex<VecDeque<String>>>,
+    idle_timeout: Option<Duration>,
+    last_activity: Instant,
+    started: Instant,
+    // Unix time of `started`, for showing when the run began
+    started_at: i64,
+    // How the ssh process ended, once it has
+    exit_status: Option<String>,
+    // Spawned, but the forward hasn't been seen to work or fail yet
+    starting: bool,
+    // Whether the local port was probed after starting
+    probing: bool,
+    // An ssh left running by an earlier Onigiri, in place of `process`
+    adopted: Option<AdoptedProcess>,
+    // Told when ssh exits, so the window needn't poll for it
+    exit_watch: Option<ExitWatch>,
+    runner: Arc<dyn TunnelRunner>,
+}
+
+/// Spawns the process behind a tunnel from its built command. The app runs
+/// ssh; tests run a stand-in so starting and stopping can be checked
+/// without a server.
+trait TunnelRunner: std::fmt::Debug + Send + Sync {
+    /// Refuses a server that can't be reached with the tunnel's extra ssh
+    /// `options` before anything is spawned.
+    fn check_server(&self, _server: &str, _options: &[String]) -> Result<(), OnigiriError> {
+        Ok(())
+    }
+
+    /// Whether `check_server` asks the network, so the window runs it on a
+    /// thread rather than its own.
+    fn checks_server(&self) -> bool {
+        false
+    }
+
+    /// Starts `command` with its stderr piped, using the agent at
+    /// `agent_socket` when one is given.
+    fn spawn(&self, command: &[String], agent_socket: Option<&Path>) -> std::io::Result<Child>;
+}
+
+#[derive(Debug)]
+struct SshRunner;
+
+impl TunnelRunner for SshRunner {
+    fn check_server(&self, server: &str, options: &[String]) -> Result<(), OnigiriError> {
+        dns::check(server, options).map_err(OnigiriError::Unresolved)
+    }
+
+    fn checks_server(&self) -> bool {
+        true
+    }
+
+    fn spawn(&self, command: &[String], agent_socket: Option<&Path>) -> std::io::Result<Child> {
+        ssh_process(command, agent_socket).spawn()
+    }
+}
+
+fn ssh_process(command: &[String], agent_socket: Option<&Path>) -> Command {
+    let mut process = background_command(&command[0]);
+    process.args(&command[1..]).stderr(Stdio::piped());
+    if let Some(socket) = agent_socket {
+        process.env("SSH_AUTH_SOCK", socket);
+    }
+    process
+}
+
+/// Where a tunnel's stderr reader reports that ssh has exited. ssh's
+/// stderr closes when it does, so this costs nothing while it runs.
+#[derive(Debug, Clone)]
+struct ExitWatch {
+    tx: Sender<TunnelId>,
+    ctx: egui::Context,
+}
+
+/// A tunnel's ssh that Onigiri didn't spawn this time. It isn't our child,
+/// so there is no handle or stderr and `ps` is polled to see it's still up.
+#[derive(Debug)]
+struct AdoptedProcess {
+    pid: u32,
+    last_check: Instant,
+}
+
+/// What came of getting a tunnel ready off the window's thread: the
+/// pre-start hook's run, if it has one, and whether its server resolved.
+#[derive(Debug)]
+struct Prepared {
+    hook: Option<hooks::HookRun>,
+    server: Result<(), OnigiriError>,
+}
+
+/// What is left of a run that ended on its own, for the details view.
+#[derive(Debug, Clone)]
+struct TunnelExit {
+    status: String,
+    log: Vec<String>,
+    /// Unix time of an unexpected exit, until the next start or a dismiss.
+    died_at: Option<i64>,
+}
+
+impl TunnelExit {
+    /// ssh's last line, or the exit status when it said nothing.
+    fn reason(&self) -> &str {
+        self.log.iter().rev().find(|line| !line.starts_with(PRE_START_PREFIX)).unwrap_or(&self.status)
+    }
+}
+
+/// The process listening on a port we wanted to bind.
+#[derive(Debug, Clone)]
+struct PortHolder {
+    command: String,
+    pid: u32,
+}
+
+/// Finds the process listening on a local TCP port.
+#[cfg(not(windows))]
+fn find_port_holder(port: u16) -> Option<PortHolder> {
+    let output = Command::new("lsof")
+        .args(["-nP", "+c", "0", "-Fpc", "-sTCP:LISTEN"])
+        .arg(format!("-iTCP:{}", port))
+        .output()
+        .map_err(|e| debug!("Could not run lsof: {}", e))
+        .ok()?;
+
+    // -F output is one field per line: p<pid> followed by c<command>
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut pid = None;
+    for line in stdout.lines() {
+        if let Some(value) = line.strip_prefix('p') {
+            pid = value.parse().ok();
+        } else if let (Some(command), Some(pid)) = (line.strip_prefix('c'), pid) {
+            return Some(PortHolder {
+                command: command.to_string(),
+                pid,
+            });
+        }
+    }
+    None
+}
+
+/// Finds the process listening on a local TCP port.
+#[cfg(windows)]
+fn find_port_holder(port: u16) -> Option<PortHolder> {
+    let output = background_command("netstat").args(["-ano", "-p", "TCP"]).output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let suffix = format!(":{}", port);
+    let pid: u32 = stdout.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [_, local, _, "LISTENING", pid] if local.ends_with(&suffix) => pid.parse().ok(),
+            _ => None,
+        }
+    })?;
+
+    let output = background_command("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/FO", "CSV", "/NH"])
+        .output()
+        .ok()?;
+    let command = String::from_utf8_lossy(&output.stdout)
+        .split(',')
+        .next()
+        .map(|name| name.trim_matches('"').to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown process".to_string());
+    Some(PortHolder { command, pid })
+}
+
+/// Whether `pid` is still an ssh process, for tunnels taken over from the
+/// last session.
+#[cfg(not(windows))]
+fn is_ssh_process(pid: u32) -> bool {
+    Command::new("ps")
+        .args(["-p", &pid.to_string(), "-o", "comm="])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().ends_with("ssh"))
+        .unwrap_or(false)
+}
+
+/// Whether `pid` is still an ssh process, for tunnels taken over from the
+/// last session.
+#[cfg(windows)]
+fn is_ssh_process(pid: u32) -> bool {
+    background_command("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/FO", "CSV", "/NH"])
+        .output()
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .trim_start_matches('"')
+                .to_ascii_lowercase()
+                .starts_with("ssh")
+        })
+        .unwrap_or(false)
+}
+
+/// A command for a helper program run in the background. On Windows it
+/// gets no console window, which would otherwise flash up for every call.
+fn background_command(program: &str) -> Command {
+    let mut command = Command::new(program);
+    hide_console(&mut command);
+    command
+}
+
+#[cfg(windows)]
+fn hide_console(command: &mut Command) {
+    use std::os::windows::process::CommandExt;
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+    command.creation_flags(CREATE_NO_WINDOW);
+}
+
+#[cfg(not(windows))]
+fn hide_console(_command: &mut Command) {}
+
+/// Sends SIGTERM to a process, or SIGKILL when `force` is set. Windows has
+/// no SIGTERM, so there the process is always ended outright, along with
+/// any it started, such as a ProxyCommand.
+fn kill_process(pid: u32, force: bool) -> Result<(), String> {
+    let status = if cfg!(windows) {
+        background_command("taskkill").args(["/PID", &pid.to_string(), "/T", "/F"]).status()
+    } else {
+        let signal = if force { "KILL" } else { "TERM" };
+        Command::new("kill").args(["-s", signal, &pid.to_string()]).status()
+    };
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("kill exited with {}", status)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Kills a spawned ssh. On Windows that alone would leave the processes it
+/// started running, so the whole tree is ended.
+fn kill_child(child: &mut Child) -> Result<(), String> {
+    if cfg!(windows) && kill_process(child.id(), true).is_ok() {
+        return Ok(());
+    }
+    child.kill().map_err(|e| e.to_string())
+}
+
+impl From<Tunnel> for TunnelInfo {
+    fn from(tunnel: Tunnel) -> Self {
+        let (tunnel, unresolved) = match tunnel.expanded() {
+            Ok(expanded) => (expanded, None),
+            Err(e) => (tunnel, Some(e)),
+        };
+        let command = build_command(&tunnel);
+        let ssh_options = tunnel.extra_args_words();
+        Self {
+            id: tunnel.id,
+            name: tunnel.name,
+            local_ip: tunnel.local_ip,
+            local_port: tunnel.local_port,
+            remote_ip: tunnel.remote_ip,
+            remote_port: tunnel.remote_port,
+            auto_port: tunnel.local_port == 0,
+            ssh_server: tunnel.ssh_server,
+            ssh_options,
+            command,
+            agent_socket: tunnel.agent_socket.map(PathBuf::from),
+            pre_start: Some(tunnel.pre_start).filter(|hook| !hook.is_empty()),
+            pre_start_timeout: tunnel
+                .pre_start_timeout_secs
+                .map_or(hooks::PRE_START_TIMEOUT, |secs| Duration::from_secs(u64::from(secs))),
+            pre_start_log: Vec::new(),
+            pre_start_cancel: None,
+            server_checked: false,
+            post_start: Some(tunnel.post_start).filter(|hook| !hook.is_empty()),
+            unresolved,
+            process: None,
+            stderr: Arc::new(Mutex::new(VecDeque::new())),
+            idle_timeout: tunnel
+                .idle_timeout_minutes
+                .map(|minutes| Duration::from_secs(u64::from(minutes) * 60)),
+            last_activity: Instant::now(),
+            started: Instant::now(),
+            started_at: unix_now(),
+            exit_status: None,
+            starting: false,
+            probing: false,
+            adopted: None,
+            exit_watch: None,
+            runner: Arc::new(SshRunner),
+        }
+    }
 }
 
 impl TunnelInfo {
-    fn start_tunnel(&mut self) -> Result<(), String> {
-        if self.process.is_some() {
+    fn start_tunnel(&mut self) -> Result<(), OnigiriError> {
+        if self.has_process() {
             debug!("Tunnel {} is already running", self.name);
             return Ok(());
         }
+        if let Some(e) = &self.unresolved {
+            error!("Tunnel {} cannot be expanded: {}", self.name, e);
+            return Err(e.clone());
+        }
 
         debug!(
             "Starting tunnel: {} ({}:{}<-{}:{})",
             self.name, self.local_ip, self.local_port, self.remote_ip, self.remote_port
         );
 
-        let ssh_command = Command::new("ssh")
-            .args([
-                "-N",
-                "-p",
-                "22",
-                &self.ssh_server,
-                "-L",
-                &format!(
-                    "{}:{}:{}:{}",
-                    self.local_ip, self.local_port, self.remote_ip, self.remote_port
-                ),
-            ])
-            .spawn();
+        if self.auto_port {
+            self.pick_local_port()?;
+        }
+
+        // ssh only warns when it can't bind the local side, so check up front
+        if let Err(e) = TcpListener::bind((self.local_ip.as_str(), self.local_port)) {
+            error!("Tunnel {} cannot bind {}:{}: {}", self.name, self.local_ip, self.local_port, e);
+            return Err(OnigiriError::Bind {
+                port: self.local_port,
+                detail: e.to_string(),
+                holder: find_port_holder(self.local_port),
+            });
+        }
+
+        if let Some(socket) = &self.agent_socket {
+            if let Err(message) = agent::check(socket) {
+                error!("Tunnel {} has no agent: {}", self.name, message);
+                return Err(OnigiriError::Validation {
+                    field: Some(FormField::AgentSocket),
+                    message,
+                });
+            }
+        }
+
+        if !std::mem::take(&mut self.server_checked) {
+            if let Err(e) = self.runner.check_server(&self.ssh_server, &self.ssh_options) {
+                error!("Tunnel {} cannot reach its server: {}", self.name, e);
+                return Err(e);
+            }
+        }
+
+        let ssh_command = self.runner.spawn(&self.command, self.agent_socket.as_deref());
 
+        // Whether ssh gets through is only known once it exits or the
+        // forward answers, so the tunnel stays starting until then
         match ssh_command {
-            Ok(mut child) => match child.try_wait() {
-                Ok(Some(status)) => {
-                    error!("Tunnel {} failed to start (status: {})", self.name, status);
-                    Err(format!(
-                        "SSH process exited immediately with status {}",
-                        status
-                    ))
-                }
-                Ok(None) => {
-                    info!("Tunnel {} started successfully", self.name);
-                    self.process = Some(child);
-                    Ok(())
+            Ok(mut child) => {
+                info!("Tunnel {} spawned ssh (pid {})", self.name, child.id());
+                if let Some(stderr) = child.stderr.take() {
+                    self.capture_stderr(stderr);
                 }
-                Err(e) => {
-                    error!("Error checking tunnel {} status: {}", self.name, e);
-                    Err(format!("Error checking tunnel process: {}", e))
-                }
-            },
+                self.process = Some(child);
+                self.last_activity = Instant::now();
+                self.started = Instant::now();
+                self.started_at = unix_now();
+                self.starting = true;
+                self.probing = false;
+                Ok(())
+            }
             Err(e) => {
                 error!("Failed to start tunnel {}: {}", self.name, e);
-                Err(format!("Failed to start tunnel: {}", e))
+                Err(OnigiriError::spawn(&self.command[0], &e))
+            }
+        }
+    }
+
+    /// Lets the system pick a free local port and forwards that one.
+    fn pick_local_port(&mut self) -> Result<(), OnigiriError> {
+        let picked = TcpListener::bind((self.local_ip.as_str(), 0)).and_then(|listener| listener.local_addr());
+        let port = picked
+            .map_err(|e| OnigiriError::Bind {
+                port: 0,
+                detail: e.to_string(),
+                holder: None,
+            })?
+            .port();
+        debug!("Tunnel {} got local port {}", self.name, port);
+        self.use_local_port(port);
+        Ok(())
+    }
+
+    /// Forwards `port` instead of the configured local port.
+    fn use_local_port(&mut self, port: u16) {
+        self.local_port = port;
+        // The tunnel's own forward comes after any in the extra options
+        if let Some(at) = self.command.iter().rposition(|arg| arg == "-L") {
+            self.command[at + 1] = forward_spec(&self.local_ip, port, &self.remote_ip, self.remote_port);
+        }
+    }
+
+    /// The pre-start hook, ready to run on a thread of its own, or None when
+    /// the tunnel has none. `finish_pre_start` takes what came of it.
+    fn pre_start_hook(&mut self) -> Option<impl FnOnce() -> hooks::HookRun + Send + 'static> {
+        // A tunnel that can't start anyway fails without running it
+        if self.unresolved.is_some() || self.has_process() {
+            return None;
+        }
+        let hook = self.pre_start.clone()?;
+        let (env, timeout) = (self.hook_env(), self.pre_start_timeout);
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.pre_start_cancel = Some(Arc::clone(&cancel));
+        info!("Running pre-start hook of tunnel {}", self.name);
+        Some(move || hooks::run(&hook, &env, timeout, &cancel))
+    }
+
+    /// Keeps the pre-start hook's output for the log, and refuses the start
+    /// when the hook failed.
+    fn finish_pre_start(&mut self, run: hooks::HookRun) -> Result<(), OnigiriError> {
+        self.pre_start_cancel = None;
+        for line in &run.output {
+            debug!("[{}] pre-start: {}", self.name, line);
+        }
+        self.pre_start_log = run.output.into_iter().map(|line| format!("{}{}", PRE_START_PREFIX, line)).collect();
+        run.result.map_err(|reason| {
+            error!("Pre-start hook of tunnel {} failed: {}", self.name, reason);
+            OnigiriError::PreStart(reason)
+        })
+    }
+
+    /// The pre-start hook and the server check, ready to run on a thread of
+    /// their own, or None when there is nothing to wait for. The server is
+    /// only asked once the hook succeeded, as the hook may bring up the VPN
+    /// it is behind. `finish_preparing` takes what came of them.
+    fn preparation(&mut self) -> Option<impl FnOnce() -> Prepared + Send + 'static> {
+        let hook = self.pre_start_hook();
+        if hook.is_none() && (!self.runner.checks_server() || self.unresolved.is_some() || self.has_process()) {
+            return None;
+        }
+        if hook.is_none() {
+            self.pre_start_cancel = Some(Arc::new(AtomicBool::new(false)));
+        }
+        let (runner, server, options) = (Arc::clone(&self.runner), self.ssh_server.clone(), self.ssh_options.clone());
+        Some(move || {
+            let hook = hook.map(|hook| hook());
+            let server = match &hook {
+                Some(run) if run.result.is_err() => Ok(()),
+                _ => runner.check_server(&server, &options),
+            };
+            Prepared { hook, server }
+        })
+    }
+
+    /// Takes what came of `preparation`, refusing the start when the hook
+    /// failed or the server can't be reached.
+    fn finish_preparing(&mut self, prepared: Prepared) -> Result<(), OnigiriError> {
+        self.pre_start_cancel = None;
+        if let Some(run) = prepared.hook {
+            self.finish_pre_start(run)?;
+        }
+        if let Err(e) = prepared.server {
+            error!("Tunnel {} cannot reach its server: {}", self.name, e);
+            return Err(e);
+        }
+        self.server_checked = true;
+        Ok(())
+    }
+
+    /// Runs the pre-start hook here and now, for starts without the window.
+    fn run_pre_start(&mut self) -> Result<(), OnigiriError> {
+        match self.pre_start_hook() {
+            Some(hook) => {
+                let run = hook();
+                self.finish_pre_start(run)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// The pre-start hook's output followed by ssh's, as the details show it.
+    fn log(&self) -> Vec<String> {
+        let ssh = self.stderr.lock().unwrap();
+        self.pre_start_log.iter().chain(ssh.iter()).cloned().collect()
+    }
+
+    /// Whether ssh is waiting for the pre-start hook or the server check.
+    fn is_preparing(&self) -> bool {
+        self.pre_start_cancel.is_some()
+    }
+
+    /// What a hook is told about the tunnel, as `ONIGIRI_*` variables.
+    fn hook_env(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("ONIGIRI_TUNNEL_NAME", self.name.clone()),
+            ("ONIGIRI_LOCAL_IP", self.local_ip.clone()),
+            ("ONIGIRI_LOCAL_PORT", self.local_port.to_string()),
+            ("ONIGIRI_REMOTE_HOST", self.remote_ip.clone()),
+            ("ONIGIRI_REMOTE_PORT", self.remote_port.to_string()),
+        ]
+    }
+
+    /// `local_ip:local_port` as listened on in this run.
+    fn local_address(&self) -> String {
+        socket_address(&self.local_ip, self.local_port)
+    }
+
+    /// Keeps the most recent ssh stderr lines so failures can be explained.
+    fn capture_stderr(&self, stderr: std::process::ChildStderr) {
+        let lines = Arc::clone(&self.stderr);
+        let name = self.name.clone();
+        let (id, exit_watch) = (self.id, self.exit_watch.clone());
+        lines.lock().unwrap().clear();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                debug!("[{}] ssh: {}", name, line);
+                let mut lines = lines.lock().unwrap();
+                if lines.len() == STDERR_LINES {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
             }
+            if let Some(watch) = exit_watch {
+                let _ = watch.tx.send(id);
+                watch.ctx.request_repaint();
+            }
+        });
+    }
+
+    /// Takes over an ssh that was left running when Onigiri last quit.
+    fn adopt(&mut self, pid: u32, started_at: i64, local_port: u16) {
+        // A picked port is only known from what was saved at quit
+        if local_port != 0 {
+            self.use_local_port(local_port);
+        }
+        let age = Duration::from_secs(unix_now().saturating_sub(started_at).max(0) as u64);
+        self.started = Instant::now().checked_sub(age).unwrap_or_else(Instant::now);
+        self.started_at = started_at;
+        self.last_activity = Instant::now();
+        self.adopted = Some(AdoptedProcess {
+            pid,
+            last_check: Instant::now(),
+        });
+    }
+
+    fn has_process(&self) -> bool {
+        self.process.is_some() || self.adopted.is_some()
+    }
+
+    fn pid(&self) -> Option<u32> {
+        match (&self.process, &self.adopted) {
+            (Some(child), _) => Some(child.id()),
+            (None, Some(adopted)) => Some(adopted.pid),
+            (None, None) => None,
         }
     }
 
     fn stop_tunnel(&mut self) {
+        self.cancel_pre_start();
+        if let Some(adopted) = self.adopted.take() {
+            debug!("Stopping adopted tunnel {} (pid {})", self.name, adopted.pid);
+            if let Err(e) = kill_process(adopted.pid, false) {
+                error!("Failed to stop tunnel {}: {}", self.name, e);
+            }
+        }
         if let Some(mut child) = self.process.take() {
             debug!("Stopping tunnel: {}", self.name);
-            if let Err(e) = child.kill() {
+            if let Err(e) = kill_child(&mut child) {
                 error!("Failed to stop tunnel {}: {}", self.name, e);
             } else {
                 info!("Tunnel {} stopped successfully", self.name);
             }
+            // Reaped, or it would linger as a zombie until Onigiri exits
+            let _ = child.wait();
         }
     }
 
     fn is_active(&mut self) -> bool {
+        if let Some(adopted) = &mut self.adopted {
+            if adopted.last_check.elapsed() < ADOPTED_POLL_INTERVAL {
+                return true;
+            }
+            adopted.last_check = Instant::now();
+            if is_ssh_process(adopted.pid) {
+                return true;
+            }
+            debug!("Adopted tunnel {} process has exited", self.name);
+            // Only a parent learns the exit status
+            self.exit_status = Some("exited".to_string());
+            self.adopted = None;
+            return false;
+        }
         if let Some(child) = &mut self.process {
             match child.try_wait() {
-                Ok(Some(_)) => {
+                Ok(Some(status)) => {
                     debug!("Tunnel {} process has exited", self.name);
+                    self.exit_status = Some(status.to_string());
                     self.process = None;
                     false
                 }
@@ -105,833 +780,9399 @@ impl TunnelInfo {
             false
         }
     }
+
+    /// Stops a pre-start hook that is still running, so ssh never starts.
+    fn cancel_pre_start(&mut self) {
+        if let Some(cancel) = self.pre_start_cancel.take() {
+            debug!("Stopping pre-start hook of tunnel {}", self.name);
+            cancel.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Asks ssh to exit without waiting for it.
+    fn terminate(&mut self) {
+        self.cancel_pre_start();
+        let Some(pid) = self.pid() else {
+            return;
+        };
+        debug!("Sending SIGTERM to tunnel {} (pid {})", self.name, pid);
+        if let Err(e) = kill_process(pid, false) {
+            error!("Failed to stop tunnel {}: {}", self.name, e);
+        }
+    }
+
+    /// Whether the ssh process is gone, reaping it if it's our child.
+    fn has_exited(&mut self) -> bool {
+        match (&mut self.process, &self.adopted) {
+            (Some(child), _) => !matches!(child.try_wait(), Ok(None)),
+            (None, Some(adopted)) => !is_ssh_process(adopted.pid),
+            (None, None) => true,
+        }
+    }
+
+    /// Kills ssh outright, for when it ignored `terminate`.
+    fn kill_now(&mut self) {
+        if let Some(child) = &mut self.process {
+            if let Err(e) = kill_child(child) {
+                error!("Failed to kill tunnel {}: {}", self.name, e);
+            }
+            let _ = child.wait();
+        } else if let Some(adopted) = &self.adopted {
+            if let Err(e) = kill_process(adopted.pid, true) {
+                error!("Failed to kill tunnel {}: {}", self.name, e);
+            }
+        }
+    }
+
+    /// Spawned, and neither exited nor been probed yet.
+    fn is_starting(&self) -> bool {
+        self.starting && self.process.is_some()
+    }
+
+    /// Waits up to `START_GRACE` for the forward to accept a connection,
+    /// failing with ssh's last words if it exits first. For starts without
+    /// the window, where nobody would see a later failure.
+    fn wait_until_up(&mut self) -> Result<(), OnigiriError> {
+        let addr = (self.local_ip.as_str(), self.local_port)
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next());
+        while self.is_active() {
+            let up = addr.is_some_and(|addr| TcpStream::connect_timeout(&addr, Duration::from_millis(200)).is_ok());
+            if up || self.started.elapsed() >= START_GRACE {
+                self.starting = false;
+                return Ok(());
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        // Give the stderr reader a moment to catch the last lines
+        std::thread::sleep(Duration::from_millis(100));
+        let status = self.exit_status.clone().unwrap_or_else(|| "unknown status".to_string());
+        let lines: Vec<String> = self.stderr.lock().unwrap().iter().cloned().collect();
+        error!("Tunnel {} failed to start (status: {})", self.name, status);
+        Err(OnigiriError::ssh_exited(&lines, &status))
+    }
+
+    /// Time left before the idle timeout stops this tunnel, if one is set.
+    fn idle_remaining(&self, now: Instant) -> Option<Duration> {
+        let timeout = self.idle_timeout?;
+        Some(timeout.saturating_sub(now.duration_since(self.last_activity)))
+    }
 }
 
-#[derive(Debug, Clone)]
-struct Tunnel {
-    id: i32,
-    name: String,
-    command: String,
-    ssh_server: String,
-    local_ip: String,
-    local_port: u16,
-    remote_ip: String,
-    remote_port: u16,
-    active: bool,
-    deleted: bool,
+/// Stops all `tunnels` together: SIGTERM to each, up to `timeout` for them
+/// to exit, then SIGKILL for any still up. Returns how many were killed.
+fn stop_tunnels(tunnels: &mut [TunnelInfo], timeout: Duration) -> usize {
+    for tunnel in tunnels.iter_mut() {
+        tunnel.terminate();
+    }
+    let deadline = Instant::now() + timeout;
+    let mut waiting: Vec<&mut TunnelInfo> = tunnels.iter_mut().collect();
+    loop {
+        waiting.retain_mut(|tunnel| !tunnel.has_exited());
+        if waiting.is_empty() || Instant::now() >= deadline {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    for tunnel in &mut waiting {
+        warn!("Tunnel {} ignored SIGTERM for {}, killing it", tunnel.name, format_duration(timeout));
+        tunnel.kill_now();
+    }
+    waiting.len()
 }
 
-struct Tunneler {
-    tunnels: Vec<Tunnel>,
-    active_tunnels: HashMap<i64, TunnelInfo>,
-    expanded_tunnels: HashSet<i64>,
-    search_query: String,
-    show_new_tunnel_window: bool,
-    show_edit_tunnel_window: bool,
-    new_tunnel: NewTunnelForm,
-    edit_tunnel: Option<(i32, NewTunnelForm)>,
+/// Checks whether the ssh process has any established connection on its
+/// forwarded local port. `None` means the check itself could not run.
+fn has_established_connection(pid: u32, local_port: u16) -> Option<bool> {
+    let output = Command::new("lsof")
+        .args([
+            "-nP",
+            "-t",
+            "-a",
+            "-p",
+            &pid.to_string(),
+            &format!("-iTCP:{}", local_port),
+            "-sTCP:ESTABLISHED",
+        ])
+        .output();
+
+    match output {
+        // lsof exits with 1 when nothing matched
+        Ok(output) => Some(!output.stdout.is_empty()),
+        Err(e) => {
+            debug!("Could not run lsof for pid {}: {}", pid, e);
+            None
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
-struct NewTunnelForm {
-    name: String,
-    ssh_server: String,
-    local_ip: String,
-    local_port: String,
-    remote_ip: String,
-    remote_port: String,
-    name_error: Option<String>,
-    ssh_server_error: Option<String>,
-    local_ip_error: Option<String>,
-    local_port_error: Option<String>,
-    remote_ip_error: Option<String>,
-    remote_port_error: Option<String>,
+enum TestOutcome {
+    Reachable(Duration),
+    BindError(String),
+    AuthError(String),
+    RemoteRefused(String),
+    Failed(String),
 }
 
-impl Default for NewTunnelForm {
-    fn default() -> Self {
-        Self {
-            name: String::new(),
-            ssh_server: String::new(),
-            local_ip: "127.0.0.1".to_string(),
-            local_port: String::new(),
-            remote_ip: "127.0.0.1".to_string(),
-            remote_port: String::new(),
-            name_error: None,
-            ssh_server_error: None,
-            local_ip_error: None,
-            local_port_error: None,
-            remote_ip_error: None,
-            remote_port_error: None,
+impl TestOutcome {
+    fn is_success(&self) -> bool {
+        matches!(self, TestOutcome::Reachable(_))
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            TestOutcome::Reachable(elapsed) => {
+                format!("✓ reachable in {} ms", elapsed.as_millis())
+            }
+            TestOutcome::BindError(detail) => format!("✗ local bind error: {}", detail),
+            TestOutcome::AuthError(detail) => format!("✗ ssh auth error: {}", detail),
+            TestOutcome::RemoteRefused(detail) => {
+                format!("✗ remote connection refused: {}", detail)
+            }
+            TestOutcome::Failed(detail) => format!("✗ {}", detail),
         }
     }
 }
 
-impl NewTunnelForm {
-    fn clear_errors(&mut self) {
-        self.name_error = None;
-        self.ssh_server_error = None;
-        self.local_ip_error = None;
-        self.local_port_error = None;
-        self.remote_ip_error = None;
-        self.remote_port_error = None;
-    }
-
-    fn validate(&mut self) -> bool {
-        self.clear_errors();
-        let mut is_valid = true;
-
-        // Required fields
-        if self.name.trim().is_empty() {
-            self.name_error = Some("Name is required".to_string());
-            is_valid = false;
+/// Maps known ssh stderr messages onto test failures.
+fn classify_ssh_stderr(lines: &VecDeque<String>) -> Option<TestOutcome> {
+    for line in lines.iter().rev() {
+        let lower = line.to_lowercase();
+        if lower.contains("address already in use")
+            || lower.contains("cannot listen to port")
+            || lower.contains("could not request local forwarding")
+        {
+            return Some(TestOutcome::BindError(line.clone()));
         }
-        if self.ssh_server.trim().is_empty() {
-            self.ssh_server_error = Some("SSH Server is required".to_string());
-            is_valid = false;
+        if error::AUTH_FAILURES.iter().any(|failure| lower.contains(failure)) {
+            return Some(TestOutcome::AuthError(line.clone()));
         }
-        if self.local_ip.trim().is_empty() {
-            self.local_ip_error = Some("Local IP is required".to_string());
-            is_valid = false;
+        if lower.contains("open failed") || lower.contains("connect failed") {
+            return Some(TestOutcome::RemoteRefused(line.clone()));
         }
-        if self.remote_ip.trim().is_empty() {
-            self.remote_ip_error = Some("Remote IP is required".to_string());
-            is_valid = false;
+        if lower.contains("could not resolve hostname")
+            || lower.contains("connection refused")
+            || lower.contains("connection timed out")
+            || lower.contains("no route to host")
+        {
+            return Some(TestOutcome::Failed(line.clone()));
         }
+    }
+    None
+}
 
-        // Port validation
-        self.local_port_error = match Self::validate_port(&self.local_port) {
-            Ok(_) => None,
-            Err(e) => {
-                is_valid = false;
-                Some(e)
-            }
-        };
+/// Connects through the forwarded local port and waits to see whether ssh
+/// keeps the connection open. Runs on a worker thread.
+fn probe_tunnel(addr: SocketAddr, stderr: Arc<Mutex<VecDeque<String>>>) -> TestOutcome {
+    let started = Instant::now();
+    while started.elapsed() < TEST_TIMEOUT {
+        if let Some(failure) = classify_ssh_stderr(&stderr.lock().unwrap()) {
+            return failure;
+        }
 
-        self.remote_port_error = match Self::validate_port(&self.remote_port) {
-            Ok(_) => None,
-            Err(e) => {
-                is_valid = false;
-                Some(e)
+        match TcpStream::connect_timeout(&addr, Duration::from_millis(500)) {
+            Ok(mut stream) => {
+                let elapsed = started.elapsed();
+                // ssh accepts locally first and closes the socket if the
+                // remote side refuses, so give it a moment to do that.
+                let _ = stream.set_read_timeout(Some(Duration::from_millis(1500)));
+                let mut buf = [0u8; 1];
+                return match stream.read(&mut buf) {
+                    Ok(0) => {
+                        std::thread::sleep(Duration::from_millis(100));
+                        classify_ssh_stderr(&stderr.lock().unwrap()).unwrap_or_else(|| {
+                            TestOutcome::RemoteRefused("connection closed by ssh".to_string())
+                        })
+                    }
+                    // Data, or a connection held open with nothing to say yet
+                    Ok(_) => TestOutcome::Reachable(elapsed),
+                    Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                        TestOutcome::Reachable(elapsed)
+                    }
+                    Err(e) if matches!(e.kind(), ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted) => {
+                        std::thread::sleep(Duration::from_millis(100));
+                        classify_ssh_stderr(&stderr.lock().unwrap()).unwrap_or_else(|| {
+                            TestOutcome::RemoteRefused(format!("connection reset by ssh: {}", e))
+                        })
+                    }
+                    Err(e) => TestOutcome::Failed(format!("reading from {} failed: {}", addr, e)),
+                };
             }
-        };
-
-        is_valid
+            Err(_) => std::thread::sleep(Duration::from_millis(200)),
+        }
     }
 
-    fn validate_port(port: &str) -> Result<u16, String> {
-        match port.parse::<u16>() {
-            Ok(p) if p > 0 => Ok(p),
-            Ok(_) => Err("Port must be between 1 and 65535".to_string()),
-            Err(_) => Err("Invalid port number".to_string()),
-        }
+    classify_ssh_stderr(&stderr.lock().unwrap()).unwrap_or_else(|| {
+        TestOutcome::Failed(format!(
+            "nothing accepted connections on {} within {}s",
+            addr,
+            TEST_TIMEOUT.as_secs()
+        ))
+    })
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, (secs % 3600) / 60, secs % 60);
+    if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 && seconds > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m", minutes)
+    } else {
+        format!("{}s", seconds)
     }
 }
 
-impl Tunneler {
-    fn new() -> Self {
-        debug!("Creating new Tunneler instance");
-        let mut app = Self {
-            tunnels: Vec::new(),
-            active_tunnels: HashMap::new(),
-            expanded_tunnels: HashSet::new(),
-            search_query: String::new(),
-            show_new_tunnel_window: false,
-            show_edit_tunnel_window: false,
-            new_tunnel: NewTunnelForm::default(),
-            edit_tunnel: None,
-        };
+/// "3 days ago" style age of a unix timestamp.
+fn format_ago(timestamp: i64, now: i64) -> String {
+    let secs = (now - timestamp).max(0);
+    let (count, unit) = match secs {
+        0..=59 => return "just now".to_string(),
+        60..=3599 => (secs / 60, "minute"),
+        3600..=86399 => (secs / 3600, "hour"),
+        _ => (secs / 86400, "day"),
+    };
+    format!("{} {}{} ago", count, unit, if count == 1 { "" } else { "s" })
+}
 
-        // Initialize database and load tunnels
-        Self::db();
-        app.load_tunnels();
-        info!("Application initialized with {} tunnels", app.tunnels.len());
-        app
-    }
+/// Unix time as a UTC date and time, like "2024-03-09 14:05 UTC".
+fn format_utc(timestamp: i64) -> String {
+    let (year, month, day, hour, minute, _) = storage::utc_date_time(timestamp);
+    format!("{:04}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, hour, minute)
+}
 
-    fn start_edit_tunnel(&mut self, id: i32) {
-        if let Some(tunnel) = self.tunnels.iter().find(|t| t.id == id) {
-            let form = NewTunnelForm {
-                name: tunnel.name.clone(),
-                ssh_server: tunnel.ssh_server.clone(),
-                local_ip: tunnel.local_ip.clone(),
-                local_port: tunnel.local_port.to_string(),
-                remote_ip: tunnel.remote_ip.clone(),
-                remote_port: tunnel.remote_port.to_string(),
-                name_error: None,
-                ssh_server_error: None,
-                local_ip_error: None,
-                local_port_error: None,
-                remote_ip_error: None,
-                remote_port_error: None,
-            };
-            self.edit_tunnel = Some((id, form));
-            self.show_edit_tunnel_window = true;
-        }
+/// Opens a URL in the default browser.
+fn open_url(url: &str) {
+    if let Err(e) = open::that(url) {
+        error!("Failed to open {}: {}", url, e);
     }
+}
 
-    fn save_edited_tunnel(&mut self) -> Result<(), String> {
-        if let Some((id, form)) = &self.edit_tunnel {
-            let local_port: u16 = form.local_port.parse().unwrap_or(0);
-            let remote_port: u16 = form.remote_port.parse().unwrap_or(0);
+/// Shows a folder in the platform's file manager.
+fn open_folder(path: &Path) {
+    if let Err(e) = open::that(path) {
+        error!("Failed to open {}: {}", path.display(), e);
+    }
+}
 
-            let command = format!(
-                "ssh -L {}:{}:{} {}",
-                local_port, form.remote_ip, remote_port, form.ssh_server
-            );
+fn default_workspace() -> i32 {
+    storage::DEFAULT_WORKSPACE
+}
 
-            let conn = Self::db();
-            if let Err(e) = conn.execute(
-                "UPDATE tunnels SET name = ?1, command = ?2, ssh_server = ?3, local_ip = ?4, local_port = ?5, remote_ip = ?6, remote_port = ?7 WHERE id = ?8",
-                params![
-                    form.name.trim(),
-                    command,
-                    form.ssh_server.trim(),
-                    form.local_ip.trim(),
-                    local_port,
-                    form.remote_ip.trim(),
-                    remote_port,
-                    id,
-                ],
-            ) {
-                return Err(format!("Failed to update tunnel: {}", e));
-            }
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
 
-            // If the tunnel is active, restart it with new settings
-            if let Some(tunnel) = self.active_tunnels.get_mut(&(*id as i64)) {
-                tunnel.stop_tunnel();
-                self.active_tunnels.remove(&(*id as i64));
-                if let Err(e) = self.toggle_tunnel(*id as i64) {
-                    return Err(format!("Failed to restart tunnel: {}", e));
-                }
-            }
+/// Periodically looks for connections on running tunnels that have an idle
+/// timeout. The `lsof` calls happen on a worker thread so the UI never waits.
+struct IdlePoller {
+    last_poll: Option<Instant>,
+    in_flight: bool,
+    tx: Sender<Vec<(TunnelId, Option<bool>)>>,
+    rx: Receiver<Vec<(TunnelId, Option<bool>)>>,
+}
 
-            self.load_tunnels();
-            self.show_edit_tunnel_window = false;
-            self.edit_tunnel = None;
+impl IdlePoller {
+    fn new() -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            last_poll: None,
+            in_flight: false,
+            tx,
+            rx,
         }
-        Ok(())
     }
 
-    fn db() -> Connection {
-        debug!("Initializing database connection");
-        let home_dir = dirs::home_dir().ok_or_else(|| anyhow::anyhow!("No home dir")).unwrap();
-        let db_path = home_dir.join("Library").join("Application Support").join("Onigiri");
-        std::fs::create_dir_all(&db_path).unwrap();
-        let db_file = db_path.join("ssh_tunnels.db");
-    
-        let conn = Connection::open(db_file).unwrap();
-        trace!("Checking if tables are present");
-        let table_exists = conn
-            .query_row(
-                "SELECT name from sqlite_master WHERE type='table' and name='tunnels'",
-                [],
-                |row| row.get::<usize, String>(0),
-            )
-            .optional()
-            .unwrap();
-        if table_exists.is_none() {
-            info!("First time setup: Creating tunnels table");
-            conn.execute(
-                "CREATE TABLE IF NOT EXISTS tunnels (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                name TEXT NOT NULL,
-                command TEXT NOT NULL,
-                ssh_server TEXT NOT NULL,
-                local_ip TEXT NOT NULL,
-                local_port INTEGER NOT NULL,
-                remote_ip TEXT NOT NULL,
-                remote_port INTEGER NOT NULL,
-                active BOOLEAN NOT NULL DEFAULT 0,
-                deleted BOOLEAN NOT NULL DEFAULT 0
-            )",
-                [],
-            )
-            .unwrap();
-
-            debug!("Creating sample tunnels");
-            let sample_tunnels = vec![
-                (
-                    "Local MySQL",
-                    "ssh -L 3306:localhost:3306 user@db-server",
-                    "db-server",
-                    "127.0.0.1",
-                    3306,
-                    "localhost",
-                    3306,
-                    false,
-                    false,
-                ),
-                (
-                    "Dev MongoDB",
-                    "ssh -L 27017:mongodb:27017 user@dev-server",
-                    "dev-server",
-                    "127.0.0.1",
-                    27017,
-                    "mongodb",
-                    27017,
-                    false,
-                    false,
-                ),
-                (
-                    "Staging API",
-                    "ssh -L 8080:api-internal:80 user@staging",
-                    "staging",
-                    "127.0.0.1",
-                    8080,
-                    "api-internal",
-                    80,
-                    false,
-                    false,
-                ),
-            ];
-
-            for tunnel in &sample_tunnels {
-                debug!("Creating sample tunnel: {}", tunnel.0);
-                conn.execute(
-                    "INSERT INTO tunnels (name, command, ssh_server, local_ip, local_port, remote_ip, remote_port, active, deleted)
-                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
-                    params![
-                        tunnel.0,
-                        tunnel.1,
-                        tunnel.2,
-                        tunnel.3,
-                        tunnel.4,
-                        tunnel.5,
-                        tunnel.6,
-                        tunnel.7,
-                        tunnel.8,
-                    ],
-                )
-                .unwrap();
-            }
+    fn due(&self, now: Instant) -> bool {
+        !self.in_flight
+            && self
+                .last_poll
+                .is_none_or(|last| now.duration_since(last) >= IDLE_POLL_INTERVAL)
+    }
 
-            info!(
-                "Database initialized with {} sample tunnels",
-                sample_tunnels.len()
-            );
-        } else {
-            trace!("Database already initialized");
+    /// Checks `targets` off the UI thread, waking `ctx` with the results.
+    fn poll(&mut self, targets: Vec<(TunnelId, u32, u16)>, now: Instant, ctx: &egui::Context) {
+        self.last_poll = Some(now);
+        if targets.is_empty() {
+            return;
         }
-        conn
+        self.in_flight = true;
+        let tx = self.tx.clone();
+        let ctx = ctx.clone();
+        std::thread::spawn(move || {
+            let results = targets
+                .into_iter()
+                .map(|(id, pid, port)| (id, has_established_connection(pid, port)))
+                .collect();
+            let _ = tx.send(results);
+            ctx.request_repaint();
+        });
     }
+}
 
-    fn load_tunnels(&mut self) {
-        debug!("Loading tunnels from database");
-        let conn = Self::db();
-        let mut stmt = conn
-            .prepare("SELECT id, name, command, ssh_server, local_ip, local_port, remote_ip, remote_port, active, deleted FROM tunnels WHERE deleted = 0")
-            .unwrap();
-
-        let tunnel_iter = stmt
-            .query_map([], |row| {
-                Ok(Tunnel {
-                    id: row.get(0)?,
-                    name: row.get(1)?,
-                    command: row.get(2)?,
-                    ssh_server: row.get(3)?,
-                    local_ip: row.get(4)?,
-                    local_port: row.get(5)?,
-                    remote_ip: row.get(6)?,
-                    remote_port: row.get(7)?,
-                    active: row.get(8)?,
-                    deleted: row.get(9)?,
-                })
-            })
-            .unwrap();
-
-        self.tunnels = tunnel_iter.filter_map(Result::ok).collect();
-        info!("Loaded {} active tunnels", self.tunnels.len());
-    }
-
-    fn toggle_tunnel(&mut self, id: i64) -> Result<(), String> {
-        let conn = Self::db();
-        let mut tunnel = conn.query_row(
-            "SELECT id, name, ssh_server, local_ip, local_port, remote_ip, remote_port FROM tunnels WHERE id = ?",
-            [id],
-            |row| -> Result<TunnelInfo, rusqlite::Error> {
-                Ok(TunnelInfo {
-                    id: row.get(0)?,
-                    name: row.get(1)?,
-                    ssh_server: row.get(2)?,
-                    local_ip: row.get(3)?,
-                    local_port: row.get(4)?,
-                    remote_ip: row.get(5)?,
-                    remote_port: row.get(6)?,
-                    process: None,
-                })
-            },
-        ).map_err(|e| format!("Failed to load tunnel: {}", e))?;
+/// Tunnels waiting to be spawned. Starting many tunnels at once is spread out
+/// so a bastion doesn't see a burst of connections.
+#[derive(Debug)]
+struct StartQueue {
+    pending: VecDeque<TunnelId>,
+    delay: Duration,
+    concurrency: usize,
+    last_batch_at: Option<Instant>,
+}
 
-        if let Some(existing_tunnel) = self.active_tunnels.get_mut(&id) {
-            existing_tunnel.stop_tunnel();
-            self.active_tunnels.remove(&id);
-            debug!("Tunnel stopped: {}", tunnel.name);
-            Ok(())
-        } else {
-            tunnel.start_tunnel()?;
-            self.active_tunnels.insert(id, tunnel);
-            debug!(
-                "Tunnel started: {}",
-                self.active_tunnels.get(&id).unwrap().name
-            );
-            Ok(())
+impl StartQueue {
+    fn new(delay: Duration, concurrency: usize) -> Self {
+        Self {
+            pending: VecDeque::new(),
+            delay,
+            concurrency: concurrency.max(1),
+            last_batch_at: None,
         }
     }
 
-    fn update_tunnel_status(&mut self) {
-        let mut inactive_tunnels = Vec::new();
-
-        for (id, tunnel) in &mut self.active_tunnels {
-            if !tunnel.is_active() {
-                inactive_tunnels.push(*id);
-                error!("Tunnel {} is no longer active", tunnel.name);
-            }
+    fn push(&mut self, id: TunnelId) {
+        if !self.contains(id) {
+            self.pending.push_back(id);
         }
+    }
 
-        for id in inactive_tunnels {
-            if let Some(tunnel) = self.active_tunnels.remove(&id) {
-                error!("Tunnel {} died unexpectedly", tunnel.name);
-                if let Some(ui_tunnel) = self.tunnels.iter_mut().find(|t| t.id as i64 == id) {
-                    debug!("Updated UI state for tunnel {}", ui_tunnel.name);
-                }
-            }
+    fn cancel(&mut self, id: TunnelId) -> bool {
+        let before = self.pending.len();
+        self.pending.retain(|queued| *queued != id);
+        self.pending.len() != before
+    }
+
+    fn contains(&self, id: TunnelId) -> bool {
+        self.pending.contains(&id)
+    }
+
+    /// Returns the tunnels that may be spawned now. The delay only applies
+    /// between batches, so a single start goes out immediately.
+    fn next_batch(&mut self, now: Instant) -> Vec<TunnelId> {
+        if self.pending.is_empty() || self.time_until_next(now).is_some() {
+            return Vec::new();
         }
+        let count = self.concurrency.min(self.pending.len());
+        self.last_batch_at = Some(now);
+        self.pending.drain(..count).collect()
     }
 
-    fn add_new_tunnel(&mut self) -> Result<(), rusqlite::Error> {
-        debug!("Adding new tunnel: {}", self.new_tunnel.name);
-        let conn = Self::db();
-        let local_port: u16 = self.new_tunnel.local_port.parse().unwrap_or(0);
-        let remote_port: u16 = self.new_tunnel.remote_port.parse().unwrap_or(0);
+    fn time_until_next(&self, now: Instant) -> Option<Duration> {
+        let last = self.last_batch_at?;
+        let ready_at = last + self.delay;
+        (ready_at > now).then(|| ready_at - now)
+    }
+}
 
-        let command = format!(
-            "ssh -L {}:{}:{} {}",
-            local_port, self.new_tunnel.remote_ip, remote_port, self.new_tunnel.ssh_server
-        );
+/// Outcome of starting several tunnels at once, reported as one message
+/// when the last of them has been tried.
+#[derive(Debug, Default)]
+struct BulkStart {
+    pending: HashSet<TunnelId>,
+    started: usize,
+    failures: Vec<String>,
+}
 
-        conn.execute(
-            "INSERT INTO tunnels (name, command, ssh_server, local_ip, local_port, remote_ip, remote_port, active, deleted)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
-            params![
-                self.new_tunnel.name.trim(),
-                command,
-                self.new_tunnel.ssh_server.trim(),
-                self.new_tunnel.local_ip.trim(),
-                local_port,
-                self.new_tunnel.remote_ip.trim(),
-                remote_port,
-                false,
-                false,
-            ],
-        )?;
+impl BulkStart {
+    fn summary(&self) -> String {
+        bulk_summary(tr("Started"), self.started, &self.failures)
+    }
+}
 
-        info!("New tunnel '{}' added successfully", self.new_tunnel.name);
-        self.new_tunnel = NewTunnelForm::default();
-        self.load_tunnels();
-        Ok(())
+/// One message for an action on several tunnels, naming the failed ones.
+/// `verb` is already translated.
+fn bulk_summary(verb: &str, done: usize, failures: &[String]) -> String {
+    let tried = done + failures.len();
+    match (tried, failures.is_empty()) {
+        (1, true) => trf("{} {} tunnel", &[&verb, &done]),
+        (_, true) => trf("{} {} tunnels", &[&verb, &done]),
+        (1, false) => trf("{} {} of {} tunnel. Failed: {}", &[&verb, &done, &tried, &failures.join("; ")]),
+        (_, false) => trf("{} {} of {} tunnels. Failed: {}", &[&verb, &done, &tried, &failures.join("; ")]),
     }
+}
 
-    fn delete_tunnel(&mut self, id: i32) -> Result<(), String> {
-        debug!("Marking tunnel {} as deleted", id);
+/// What the bulk action bar was asked to do with the selected tunnels.
+enum BulkAction {
+    Start,
+    Stop,
+    Delete,
+    AddTag(String),
+    MoveToGroup(Option<String>),
+    Clear,
+}
 
-        if let Some(tunnel) = self.active_tunnels.get_mut(&(id as i64)) {
-            tunnel.stop_tunnel();
-            self.active_tunnels.remove(&(id as i64));
-        }
+/// The ids listed from `anchor` to `id`, inclusive and in either direction.
+fn selection_range(order: &[TunnelId], anchor: TunnelId, id: TunnelId) -> Vec<TunnelId> {
+    let position = |id| order.iter().position(|&listed| listed == id);
+    match (position(anchor), position(id)) {
+        (Some(a), Some(b)) => order[a.min(b)..=a.max(b)].to_vec(),
+        _ => vec![id],
+    }
+}
 
-        let conn = Self::db();
-        conn.execute(
-            "UPDATE tunnels SET deleted = TRUE WHERE id = ?1",
-            params![id],
-        )
-        .map_err(|e| format!("Failed to mark tunnel as deleted: {}", e))?;
+/// Where a tunnel is in its run. Kept in `Tunneler::states`, where a
+/// missing entry means stopped, and only changed by `Tunneler::transition`.
+#[derive(Debug, Clone)]
+enum TunnelState {
+    Stopped,
+    Queued,
+    /// ssh was spawned and the probe of its local port hasn't come back.
+    Starting,
+    /// Up since `since` (unix time). `check` is the probe of the local port,
+    /// None for a tunnel taken over from the last session until it's back.
+    Running { since: i64, check: Option<TestOutcome> },
+    /// ssh died while up and is started again at `next_retry`. `attempt`
+    /// counts from 1 up to `RECONNECT_ATTEMPTS`.
+    Reconnecting { attempt: u32, next_retry: Instant },
+    /// The start failed, or ssh exited on its own, at `at`.
+    Failed { reason: String, at: i64 },
+}
 
-        if let Some(tunnel) = self.tunnels.iter_mut().find(|t| t.id == id) {
-            tunnel.deleted = true;
-            debug!("Tunnel {} marked as deleted", id);
+impl TunnelState {
+    fn can_become(&self, to: &TunnelState) -> bool {
+        use TunnelState::*;
+        match (self, to) {
+            (Stopped | Failed { .. }, Queued) => true,
+            (Stopped | Queued | Reconnecting { .. } | Failed { .. }, Starting | Failed { .. }) => true,
+            // Taken over from the last session
+            (Stopped, Running { .. }) => true,
+            // Probed, or probed again
+            (Starting | Running { .. }, Running { .. }) => true,
+            (Starting | Running { .. }, Failed { .. }) => true,
+            // Died, or a try to reconnect didn't get going
+            (Starting | Running { .. }, Reconnecting { .. }) => true,
+            (Queued | Starting | Running { .. } | Reconnecting { .. } | Failed { .. }, Stopped) => true,
+            _ => false,
         }
+    }
 
-        Ok(())
+    fn is_active(&self) -> bool {
+        matches!(self, TunnelState::Starting | TunnelState::Running { .. })
     }
 
-    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
-        if ctx.input(|i| i.viewport().close_requested()) {
-            info!("Window close requested");
-            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
-            return;
-        }
-        
-        self.update_tunnel_status();
+    /// Waiting to start, whether in the start queue or to reconnect.
+    fn is_waiting(&self) -> bool {
+        matches!(self, TunnelState::Queued | TunnelState::Reconnecting { .. })
+    }
+}
 
-        // Collect all the data we need upfront
-        #[derive(Clone)]
-        struct TunnelDisplayData {
-            id: i32,
-            name: String,
-            is_active: bool,
-            is_expanded: bool,
-            local_ip: String,
-            local_port: u16,
-            remote_ip: String,
-            remote_port: u16,
-            pid: Option<u32>,
+/// Failures shown as toasts for a few seconds, then kept in a short list
+/// the header offers to reopen.
+#[derive(Debug, Default)]
+struct ErrorToasts {
+    // Newest first
+    recent: VecDeque<(Instant, String)>,
+    show_recent: bool,
+}
+
+impl ErrorToasts {
+    fn push(&mut self, message: String) {
+        if self.recent.len() == RECENT_ERRORS {
+            self.recent.pop_back();
         }
+        self.recent.push_front((Instant::now(), message));
+    }
 
-        let tunnel_data: Vec<TunnelDisplayData> = self.tunnels.iter()
-            .filter(|t| !t.deleted && (self.search_query.is_empty() || 
-                t.name.to_lowercase().contains(&self.search_query.to_lowercase())))
-            .map(|t| {
-                let is_active = self.active_tunnels.contains_key(&(t.id as i64));
-                let is_expanded = self.expanded_tunnels.contains(&(t.id as i64));
-                let pid = if is_active {
-                    self.active_tunnels.get(&(t.id as i64))
-                        .and_then(|info| info.process.as_ref())
-                        .map(|process| process.id())
-                } else {
-                    None
-                };
-                
-                TunnelDisplayData {
-                    id: t.id,
-                    name: t.name.clone(),
-                    is_active,
-                    is_expanded,
-                    local_ip: t.local_ip.clone(),
-                    local_port: t.local_port,
-                    remote_ip: t.remote_ip.clone(),
-                    remote_port: t.remote_port,
-                    pid,
-                }
-            })
-            .collect();
+    /// Errors still within their toast time.
+    fn fresh(&self, now: Instant) -> impl Iterator<Item = &(Instant, String)> {
+        self.recent
+            .iter()
+            .take_while(move |(at, _)| now.duration_since(*at) < TOAST_DURATION)
+    }
+}
 
-        let mut tunnel_to_toggle = None;
-        let mut tunnel_to_delete = None;
-        let mut tunnel_to_toggle_expand = None;
-        let mut tunnel_to_edit = None;
+/// A tunnel's row id. Every store, the control socket and the UI key
+/// tunnels by it; unsaved tunnels have the default, 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+struct TunnelId(i64);
 
-        egui::CentralPanel::default().show(ctx, |ui| {
-            ui.vertical(|ui| {
-                ui.horizontal(|ui| {
-                    ui.heading("SSH Tunnel Manager");
-                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
-                        if ui.button("Add Tunnel").clicked() {
-                            self.show_new_tunnel_window = true;
-                        }
-                    });
-                });
+impl std::fmt::Display for TunnelId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
 
-                // Search box
-                ui.horizontal(|ui| {
-                    ui.label("Search:");
-                    ui.text_edit_singleline(&mut self.search_query);
-                });
+impl std::str::FromStr for TunnelId {
+    type Err = std::num::ParseIntError;
 
-                ui.separator();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(TunnelId)
+    }
+}
 
-                // Tunnels list
-                egui::ScrollArea::vertical().show(ui, |ui| {
-                    for tunnel in &tunnel_data {
-                        ui.vertical(|ui| {
-                            ui.horizontal(|ui| {
-                                // Draw status circle
-                                let color = if tunnel.is_active {
-                                    egui::Color32::from_rgb(50, 205, 50) // Green
-                                } else {
-                                    egui::Color32::from_rgb(220, 50, 50) // Red
-                                };
-                                let circle_size = 8.0;
-                                let (rect, _response) = ui.allocate_exact_size(
-                                    egui::vec2(circle_size, circle_size),
-                                    egui::Sense::hover(),
-                                );
-                                ui.painter().circle_filled(rect.center(), circle_size / 2.0, color);
-                                ui.add_space(4.0); // Add a small gap between circle and name
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Tunnel {
+    #[serde(default)]
+    id: TunnelId,
+    name: String,
+    ssh_server: String,
+    local_ip: String,
+    local_port: u16,
+    remote_ip: String,
+    remote_port: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    idle_timeout_minutes: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    /// Unix time of the last successful start.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_started_at: Option<i64>,
+    /// Unix times set by the store on insert and on every update.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    updated_at: Option<i64>,
+    /// The timestamps were filled in when upgrading an older database.
+    #[serde(skip)]
+    timestamps_approximate: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pinned: bool,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    notes: String,
+    /// Credential store entry holding the tunnel's secret, never the value.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    secret_ref: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    color: Option<ColorLabel>,
+    /// "http" or "https" for the Open button, instead of guessing from the port.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    url_scheme: Option<String>,
+    /// Started whenever Onigiri opens, whatever ran in the last session.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    autostart: bool,
+    /// More ssh options, such as `-i` or `-J`, as shell words.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    extra_args: String,
+    /// The SSH agent socket to use instead of the inherited `SSH_AUTH_SOCK`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    agent_socket: Option<String>,
+    /// A shell command run before ssh; the start fails when it does.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pre_start: String,
+    /// How long the pre-start hook may run, when not the default minute.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pre_start_timeout_secs: Option<u32>,
+    /// A shell command run once the tunnel is up.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    post_start: String,
+    #[serde(skip, default = "default_workspace")]
+    workspace_id: i32,
+    #[serde(skip)]
+    active: bool,
+    #[serde(skip)]
+    deleted: bool,
+}
 
-                                ui.label(&tunnel.name);
-                                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
-                                    if ui.small_button("Delete").clicked() {
-                                        tunnel_to_delete = Some(tunnel.id);
-                                    }
-                                    let toggle_text = if tunnel.is_active { "Stop" } else { "Start" };
-                                    if ui.small_button(toggle_text).clicked() {
-                                        tunnel_to_toggle = Some(tunnel.id as i64);
-                                    }
-                                    let info_text = if tunnel.is_expanded { "Hide" } else { "Info" };
-                                    if ui.small_button(info_text).clicked() {
-                                        tunnel_to_toggle_expand = Some(tunnel.id as i64);
-                                    }
-                                    if ui.small_button("Edit").clicked() {
-                                        tunnel_to_edit = Some(tunnel.id);
-                                    }
-                                });
-                            });
+struct Tunneler {
+    store: Option<Box<dyn TunnelStore>>,
+    storage_backend: StorageBackend,
+    tunnels_file: Option<PathBuf>,
+    settings: Option<Settings>,
+    window_geometry: Option<WindowGeometry>,
+    saved_geometry: Option<WindowGeometry>,
+    geometry_changed_at: Instant,
+    // A restored position still has to be checked against the monitors
+    check_restored_geometry: bool,
+    // Tunnels offered for copying into a newly created tunnel file
+    store_migration: Option<Vec<Tunnel>>,
+    // Only the active workspace's tunnels are loaded into `tunnels`
+    workspace: i32,
+    workspaces: Vec<Workspace>,
+    workspace_dialog: Option<WorkspaceDialog>,
+    // Tunnel whose history window is open, with its recent events
+    history: Option<(TunnelId, Vec<TunnelEvent>)>,
+    #[cfg(any(target_os = "macos", windows))]
+    tray: Option<tray::Tray>,
+    // Where `onigiri start` and the other subcommands reach this window
+    control: Option<control::Server>,
+    // The running count last put on the Dock icon or window title
+    badge_count: Option<usize>,
+    theme: Theme,
+    density: Density,
+    // A second line under each name with the server and local port
+    show_subtitles: bool,
+    // Closing the window hides it instead of quitting
+    keep_running: bool,
+    // Set once the user really quits, so the close isn't intercepted
+    quitting: bool,
+    // Asking before a quit stops running tunnels
+    confirm_quit: bool,
+    quit_behavior: QuitBehavior,
+    // Set when quitting leaves the tunnels running for the next launch
+    leave_running: bool,
+    // Set once `shut_down` has dealt with the tunnels
+    shut_down: bool,
+    // Tunnel whose local address was just copied, for the inline feedback
+    copied: Option<(TunnelId, Instant)>,
+    tunnels: Vec<Tunnel>,
+    active_tunnels: HashMap<TunnelId, TunnelInfo>,
+    expanded_tunnels: HashSet<TunnelId>,
+    // The last stop or exit of tunnels, from their history; None once
+    // looked up and found to have none
+    last_stops: HashMap<TunnelId, Option<TunnelEvent>>,
+    // Rows ticked for the bulk action bar, and where a shift-click range starts
+    selected: BTreeSet<TunnelId>,
+    selection_anchor: Option<TunnelId>,
+    // Text typed into the bulk action bar's tag and group menus
+    bulk_tag: String,
+    bulk_group: String,
+    // Selected tunnels awaiting confirmation to delete
+    confirm_bulk_delete: Option<Vec<TunnelId>>,
+    search_query: String,
+    // Tags a tunnel must all have to be listed
+    tag_filter: BTreeSet<String>,
+    running_only: bool,
+    sort_mode: SortMode,
+    collapsed_sections: BTreeSet<String>,
+    saved_list_preferences: ListPreferences,
+    list_cache: ListCache,
+    // Set when a change was saved, to reload the tunnels at the end of the frame
+    tunnels_dirty: bool,
+    show_new_tunnel_window: bool,
+    show_edit_tunnel_window: bool,
+    new_tunnel: NewTunnelForm,
+    edit_tunnel: Option<(TunnelId, NewTunnelForm)>,
+    // Asking in the edit window whether saving should restart the running tunnel
+    confirm_restart: bool,
+    // Running tunnels whose saved settings differ from the running ones
+    restart_pending: HashSet<TunnelId>,
+    // Presets saved from the Add Tunnel window, after the built-in ones
+    custom_presets: Vec<presets::Preset>,
+    // Opens a shell on a tunnel's server; see terminal::PLACEHOLDER
+    terminal_template: String,
+    // The template being edited in the terminal command dialog
+    terminal_dialog: Option<String>,
+    // The share link in the Paste tunnel dialog
+    paste_dialog: Option<String>,
+    templates: Vec<Template>,
+    show_templates: bool,
+    show_logs: bool,
+    log_view: LogView,
+    // The About window, with the ssh version found when it was opened
+    show_about: bool,
+    ssh_version: Option<String>,
+    update_check: Option<UpdateCheck>,
+    // Template being renamed in the templates window
+    template_rename: Option<(i32, String)>,
+    template_dialog: Option<TemplateDialog>,
+    // The one expanded row being edited in place
+    inline_edit: Option<(TunnelId, NewTunnelForm)>,
+    start_queue: StartQueue,
+    // A "Start all" still working through the queue
+    bulk_start: Option<BulkStart>,
+    errors: ErrorToasts,
+    idle_poller: IdlePoller,
+    idle_stopped: HashMap<TunnelId, Duration>,
+    tests_running: HashSet<TunnelId>,
+    started_for_test: HashSet<TunnelId>,
+    test_results: HashMap<TunnelId, TestOutcome>,
+    test_tx: Sender<(TunnelId, TestOutcome)>,
+    test_rx: Receiver<(TunnelId, TestOutcome)>,
+    // Probe results after a start, tagged with the run they checked
+    verify_tx: Sender<(TunnelId, Instant, TestOutcome)>,
+    verify_rx: Receiver<(TunnelId, Instant, TestOutcome)>,
+    // Pre-start hooks that finished, tagged with the run they were for
+    pre_start_tx: Sender<(TunnelId, Instant, Prepared)>,
+    pre_start_rx: Receiver<(TunnelId, Instant, Prepared)>,
+    // Post-start hooks that finished, by tunnel
+    hook_tx: Sender<(TunnelId, hooks::HookRun)>,
+    hook_rx: Receiver<(TunnelId, hooks::HookRun)>,
+    // Why a tunnel's last post-start hook failed, until it is dismissed
+    hook_failures: HashMap<TunnelId, String>,
+    // Given to each started tunnel; its ctx is the window's once there is one
+    exit_watch: ExitWatch,
+    runner: Arc<dyn TunnelRunner>,
+    exit_rx: Receiver<TunnelId>,
+    // Tunnels whose stderr closed, and since when, until ssh can be reaped
+    exiting: HashMap<TunnelId, Instant>,
+    states: HashMap<TunnelId, TunnelState>,
+    // The last reconnect attempt of tunnels whose ssh died, until they are
+    // stopped or give up
+    reconnects: HashMap<TunnelId, u32>,
+    start_errors: HashMap<TunnelId, OnigiriError>,
+    // A storage failure during an action, shown in a dialog
+    database_error: Option<String>,
+    // The last unexpected exit of each tunnel, with its ssh output
+    exits: HashMap<TunnelId, TunnelExit>,
+    storage_error: Option<String>,
+    import_preview: Option<ImportPreview>,
+    ssh_config_import: Option<SshConfigImport>,
+    service_export: Option<ServiceExport>,
+    // Number of deleted tunnels awaiting confirmation to purge
+    confirm_empty_trash: Option<usize>,
+    // Backup file awaiting confirmation to restore, with its tunnel count
+    confirm_restore_backup: Option<(PathBuf, usize)>,
+    // Tunnels from the last session waiting for the user to restore them
+    pending_restore: Option<Vec<TunnelId>>,
+    status_message: Option<String>,
+}
 
-                            // Show expanded details
-                            if tunnel.is_expanded {
-                                ui.indent("details", |ui| {
-                                    if let Some(pid) = tunnel.pid {
-                                        ui.label(format!("PID: {}", pid));
-                                    }
-                                    ui.label(format!(
-                                        "Local: {}:{} -> Remote: {}:{}",
-                                        tunnel.local_ip, tunnel.local_port,
-                                        tunnel.remote_ip, tunnel.remote_port
-                                    ));
-                                });
-                            }
-                            ui.separator();
-                        });
-                    }
-                });
-            });
-        });
+/// The validated fields of a tunnel form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum FormField {
+    Name,
+    SshServer,
+    LocalIp,
+    LocalPort,
+    RemoteIp,
+    RemotePort,
+    IdleTimeout,
+    ExtraArgs,
+    AgentSocket,
+    PreStart,
+    PostStart,
+}
 
-        // Handle actions after UI
-        if let Some(id) = tunnel_to_toggle {
-            if let Err(e) = self.toggle_tunnel(id) {
-                error!("Failed to toggle tunnel: {}", e);
-            }
-        }
+impl FormField {
+    const ALL: [FormField; 11] = [
+        FormField::Name,
+        FormField::SshServer,
+        FormField::LocalIp,
+        FormField::LocalPort,
+        FormField::RemoteIp,
+        FormField::RemotePort,
+        FormField::IdleTimeout,
+        FormField::ExtraArgs,
+        FormField::AgentSocket,
+        FormField::PreStart,
+        FormField::PostStart,
+    ];
+}
 
-        if let Some(id) = tunnel_to_delete {
-            if let Err(e) = self.delete_tunnel(id) {
-                error!("Failed to delete tunnel: {}", e);
-            }
+#[derive(Debug, Clone)]
+struct NewTunnelForm {
+    name: String,
+    ssh_server: String,
+    local_ip: String,
+    local_port: String,
+    remote_ip: String,
+    remote_port: String,
+    idle_timeout: String,
+    tags: Vec<String>,
+    group: String,
+    notes: String,
+    color: Option<ColorLabel>,
+    url_scheme: Option<String>,
+    autostart: bool,
+    extra_args: String,
+    // Empty for the agent Onigiri was started with
+    agent_socket: String,
+    pre_start: String,
+    // Seconds, empty for the default
+    pre_start_timeout: String,
+    post_start: String,
+    // Not shown in the form, only carried through edits and imports
+    pinned: bool,
+    secret_ref: Option<String>,
+    // Imported from a tunnel whose secret was left out of the export
+    needs_secret: bool,
+    // Typed into the edit window before being stored with "Set secret"
+    secret_input: String,
+    // A duplicated tunnel is placed right after its original
+    insert_after: Option<TunnelId>,
+    // Opened from a template, so its empty fields are highlighted
+    from_template: bool,
+    // Text in the tag box that hasn't been added yet
+    tag_input: String,
+    // An ssh command pasted to fill the form, and what came of it
+    pasted_command: String,
+    paste_result: Option<Result<String, String>>,
+    // Fields edited so far; errors of the others wait for Add or Save
+    touched: HashSet<FormField>,
+    // Last edit not yet validated
+    edited_at: Option<Instant>,
+    name_error: Option<String>,
+    ssh_server_error: Option<String>,
+    local_ip_error: Option<String>,
+    local_port_error: Option<String>,
+    remote_ip_error: Option<String>,
+    remote_port_error: Option<String>,
+    idle_timeout_error: Option<String>,
+    extra_args_error: Option<String>,
+    agent_socket_error: Option<String>,
+    // Also says what's wrong with the hook's timeout
+    pre_start_error: Option<String>,
+    post_start_error: Option<String>,
+    // Why the last Add or Save failed, shown above the buttons
+    save_error: Option<String>,
+}
+
+impl Default for NewTunnelForm {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            ssh_server: String::new(),
+            local_ip: "127.0.0.1".to_string(),
+            local_port: String::new(),
+            remote_ip: "127.0.0.1".to_string(),
+            remote_port: String::new(),
+            idle_timeout: String::new(),
+            tags: Vec::new(),
+            group: String::new(),
+            notes: String::new(),
+            color: None,
+            url_scheme: None,
+            autostart: false,
+            extra_args: String::new(),
+            agent_socket: String::new(),
+            pre_start: String::new(),
+            pre_start_timeout: String::new(),
+            post_start: String::new(),
+            pinned: false,
+            secret_ref: None,
+            needs_secret: false,
+            secret_input: String::new(),
+            insert_after: None,
+            from_template: false,
+            tag_input: String::new(),
+            pasted_command: String::new(),
+            paste_result: None,
+            touched: HashSet::new(),
+            edited_at: None,
+            name_error: None,
+            ssh_server_error: None,
+            local_ip_error: None,
+            local_port_error: None,
+            remote_ip_error: None,
+            remote_port_error: None,
+            idle_timeout_error: None,
+            extra_args_error: None,
+            agent_socket_error: None,
+            pre_start_error: None,
+            post_start_error: None,
+            save_error: None,
+        }
+    }
+}
+
+/// Whether `name` is a hostname by RFC 1123: dot-separated labels of
+/// letters, digits and inner hyphens, up to 63 characters each and 253 in
+/// all. A trailing dot is allowed, an all-digit last label isn't, so that
+/// a mistyped IPv4 address such as 256.1.1.1 doesn't pass as a name.
+fn is_hostname(name: &str) -> bool {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    !name.is_empty()
+        && name.len() <= 253
+        && !name.rsplit('.').next().unwrap_or_default().chars().all(|c| c.is_ascii_digit())
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// `server` with its host lower-cased; the user name is left as typed.
+/// ssh only reads a port from `ssh://` destinations, so `[user@]host:port`
+/// gets the scheme put in front.
+fn normalize_server(server: &str) -> String {
+    if expand::has_references(server) {
+        return server.to_string();
+    }
+    let (scheme, rest) = match server.strip_prefix("ssh://") {
+        Some(rest) => ("ssh://", rest),
+        None => ("", server),
+    };
+    let (user, host) = match rest.split_once('@') {
+        Some((user, host)) => (format!("{}@", user), host.to_lowercase()),
+        None => (String::new(), rest.to_lowercase()),
+    };
+    let has_port = host.parse::<Ipv6Addr>().is_err()
+        && host
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()));
+    let scheme = if has_port { "ssh://" } else { scheme };
+    format!("{}{}{}", scheme, user, host)
+}
+
+impl NewTunnelForm {
+    fn clear_errors(&mut self) {
+        self.name_error = None;
+        self.ssh_server_error = None;
+        self.local_ip_error = None;
+        self.local_port_error = None;
+        self.remote_ip_error = None;
+        self.remote_port_error = None;
+        self.idle_timeout_error = None;
+        self.extra_args_error = None;
+        self.agent_socket_error = None;
+        self.pre_start_error = None;
+        self.post_start_error = None;
+    }
+
+    fn validate(&mut self) -> bool {
+        self.clear_errors();
+        self.touched.extend(FormField::ALL);
+        let mut is_valid = true;
+
+        // A tag typed but not confirmed with Enter still counts
+        self.add_tag();
+        // What's checked below is then exactly what's stored and run
+        self.normalize();
+
+        // Required fields
+        if self.name.trim().is_empty() {
+            self.name_error = Some(tr("Name is required").to_string());
+            is_valid = false;
+        }
+        if self.ssh_server.trim().is_empty() {
+            self.ssh_server_error = Some(tr("SSH Server is required").to_string());
+            is_valid = false;
+        }
+        if self.local_ip.trim().is_empty() {
+            self.local_ip_error = Some(tr("Local IP is required").to_string());
+            is_valid = false;
+        }
+        if self.remote_ip.trim().is_empty() {
+            self.remote_ip_error = Some(tr("Remote IP is required").to_string());
+            is_valid = false;
+        }
+        for (value, error) in [
+            (&self.name, &mut self.name_error),
+            (&self.ssh_server, &mut self.ssh_server_error),
+            (&self.local_ip, &mut self.local_ip_error),
+            (&self.remote_ip, &mut self.remote_ip_error),
+            (&self.extra_args, &mut self.extra_args_error),
+            (&self.agent_socket, &mut self.agent_socket_error),
+            (&self.pre_start, &mut self.pre_start_error),
+            (&self.post_start, &mut self.post_start_error),
+        ] {
+            if error.is_none() && value.contains(char::is_control) {
+                *error = Some(tr("Remove the control characters from this field").to_string());
+                is_valid = false;
+            }
+        }
+        // Unset variables are only an error when starting, where they may be set
+        for (value, error) in [
+            (&self.ssh_server, &mut self.ssh_server_error),
+            (&self.local_ip, &mut self.local_ip_error),
+            (&self.remote_ip, &mut self.remote_ip_error),
+            (&self.extra_args, &mut self.extra_args_error),
+            (&self.agent_socket, &mut self.agent_socket_error),
+        ] {
+            if let (None, Err(e)) = (&error, expand::check(value)) {
+                *error = Some(e);
+                is_valid = false;
+            }
+        }
+        // What ssh would only reject once started. Values with references
+        // are checked once expanded, at start.
+        for (value, error, check) in [
+            (&self.ssh_server, &mut self.ssh_server_error, Self::validate_ssh_server as fn(&str) -> Result<(), String>),
+            (&self.local_ip, &mut self.local_ip_error, Self::validate_local_ip),
+            (&self.remote_ip, &mut self.remote_ip_error, Self::validate_host),
+        ] {
+            if error.is_some() || expand::has_references(value) {
+                continue;
+            }
+            if let Err(e) = check(value) {
+                *error = Some(e);
+                is_valid = false;
+            }
+        }
+
+        // Port validation
+        self.local_port_error = match Self::validate_local_port(&self.local_port) {
+            Ok(_) => None,
+            Err(e) => {
+                is_valid = false;
+                Some(e)
+            }
+        };
+
+        self.remote_port_error = match Self::validate_port(&self.remote_port) {
+            Ok(_) => None,
+            Err(e) => {
+                is_valid = false;
+                Some(e)
+            }
+        };
+
+        self.idle_timeout_error = match Self::parse_idle_timeout(&self.idle_timeout) {
+            Ok(_) => None,
+            Err(e) => {
+                is_valid = false;
+                Some(e)
+            }
+        };
+
+        if self.extra_args_error.is_none() {
+            if let Err(e) = Self::validate_extra_args(&self.extra_args) {
+                self.extra_args_error = Some(e);
+                is_valid = false;
+            }
+        }
+
+        if self.pre_start_error.is_none() {
+            if let Err(e) = Self::parse_pre_start_timeout(&self.pre_start_timeout) {
+                self.pre_start_error = Some(e);
+                is_valid = false;
+            }
+        }
+
+        is_valid
+    }
+
+    /// `validate`, plus refusing a local address that a running tunnel
+    /// already listens on. `editing` is the tunnel the form belongs to.
+    fn validate_with(&mut self, tunnels: &[Tunnel], running: &HashMap<TunnelId, TunnelInfo>, editing: Option<TunnelId>) -> bool {
+        let mut is_valid = self.validate();
+        if let Some(other) = self.port_conflict(tunnels, editing) {
+            if running.contains_key(&other.id) {
+                self.local_port_error = Some(trf("'{}' is running on this address", &[&other.name]));
+                is_valid = false;
+            }
+        }
+        is_valid
+    }
+
+    /// What `validate_with` would report, without showing it in the form.
+    fn problems(&self, tunnels: &[Tunnel], running: &HashMap<TunnelId, TunnelInfo>, editing: Option<TunnelId>) -> Vec<String> {
+        let mut checked = self.clone();
+        checked.validate_with(tunnels, running, editing);
+        checked.errors()
+    }
+
+    fn error_mut(&mut self, field: FormField) -> &mut Option<String> {
+        match field {
+            FormField::Name => &mut self.name_error,
+            FormField::SshServer => &mut self.ssh_server_error,
+            FormField::LocalIp => &mut self.local_ip_error,
+            FormField::LocalPort => &mut self.local_port_error,
+            FormField::RemoteIp => &mut self.remote_ip_error,
+            FormField::RemotePort => &mut self.remote_port_error,
+            FormField::IdleTimeout => &mut self.idle_timeout_error,
+            FormField::ExtraArgs => &mut self.extra_args_error,
+            FormField::AgentSocket => &mut self.agent_socket_error,
+            FormField::PreStart => &mut self.pre_start_error,
+            FormField::PostStart => &mut self.post_start_error,
+        }
+    }
+
+    /// Marks `field` as touched once it is edited or left.
+    fn note_edit(&mut self, field: FormField, response: &egui::Response) {
+        if response.changed() || response.lost_focus() {
+            self.touched.insert(field);
+            self.edited_at = Some(Instant::now());
+        }
+    }
+
+    /// Shows the errors of touched fields once typing has paused.
+    fn live_validate(&mut self, ctx: &egui::Context) {
+        let Some(edited_at) = self.edited_at else {
+            return;
+        };
+        let due = edited_at + LIVE_VALIDATION_DELAY;
+        if let Some(left) = due.checked_duration_since(Instant::now()) {
+            ctx.request_repaint_after(left);
+            return;
+        }
+        self.edited_at = None;
+        let mut checked = self.clone();
+        checked.validate();
+        for field in FormField::ALL {
+            *self.error_mut(field) = if self.touched.contains(&field) {
+                checked.error_mut(field).take()
+            } else {
+                None
+            };
+        }
+    }
+
+    /// Another tunnel with the same local address and port.
+    fn port_conflict<'a>(&self, tunnels: &'a [Tunnel], editing: Option<TunnelId>) -> Option<&'a Tunnel> {
+        let port = self.local_port.trim().parse::<u16>().ok().filter(|port| *port != 0)?;
+        let ip = self.local_ip.trim();
+        tunnels.iter().find(|t| {
+            !t.deleted
+                && Some(t.id) != editing
+                && t.local_port == port
+                && (t.local_ip == ip || t.local_ip == "0.0.0.0" || ip == "0.0.0.0")
+        })
+    }
+
+    /// Moves the text in the tag box into the tag list.
+    fn add_tag(&mut self) {
+        let tag = self.tag_input.trim().to_string();
+        if !tag.is_empty() && !self.tags.contains(&tag) {
+            self.tags.push(tag);
+        }
+        self.tag_input.clear();
+    }
+
+    /// An empty idle timeout means the tunnel is never stopped for inactivity.
+    fn parse_idle_timeout(minutes: &str) -> Result<Option<u32>, String> {
+        let minutes = minutes.trim();
+        if minutes.is_empty() {
+            return Ok(None);
+        }
+        match minutes.parse::<u32>() {
+            Ok(m) if m > 0 => Ok(Some(m)),
+            _ => Err(tr("Idle timeout must be a whole number of minutes").to_string()),
+        }
+    }
+
+    /// Seconds the pre-start hook may run; empty for the default.
+    fn parse_pre_start_timeout(seconds: &str) -> Result<Option<u32>, String> {
+        let seconds = seconds.trim();
+        if seconds.is_empty() {
+            return Ok(None);
+        }
+        match seconds.parse::<u32>() {
+            Ok(s) if s > 0 => Ok(Some(s)),
+            _ => Err(tr("Timeout must be a whole number of seconds").to_string()),
+        }
+    }
+
+    /// Trims every field, runs of whitespace in names down to one space and
+    /// hosts to lower case, as ssh compares them. Control characters are
+    /// dropped from notes, except line breaks and tabs, and left in the
+    /// other fields for `validate` to refuse.
+    fn normalize(&mut self) {
+        let collapse = |value: &str| value.split_whitespace().collect::<Vec<_>>().join(" ");
+        self.name = collapse(&self.name);
+        self.group = collapse(&self.group);
+        for tag in &mut self.tags {
+            *tag = collapse(tag);
+        }
+        self.tags.retain(|tag| !tag.is_empty());
+        self.notes = self
+            .notes
+            .trim()
+            .chars()
+            .filter(|c| !c.is_control() || matches!(c, '\n' | '\t'))
+            .collect();
+        self.ssh_server = normalize_server(self.ssh_server.trim());
+        for host in [&mut self.local_ip, &mut self.remote_ip] {
+            *host = match host.trim() {
+                value if expand::has_references(value) => value.to_string(),
+                value => value.to_lowercase(),
+            };
+        }
+        for number in [&mut self.local_port, &mut self.remote_port, &mut self.idle_timeout, &mut self.pre_start_timeout] {
+            *number = number.trim().to_string();
+        }
+        self.extra_args = self.extra_args.trim().to_string();
+        self.agent_socket = self.agent_socket.trim().to_string();
+        self.pre_start = self.pre_start.trim().to_string();
+        self.post_start = self.post_start.trim().to_string();
+    }
+
+    /// Extra options must split into words, and can't send ssh to the
+    /// background, where Onigiri would lose it.
+    fn validate_extra_args(args: &str) -> Result<(), String> {
+        if ssh_command::has_flag(&ssh_command::split(args)?, 'f') {
+            return Err(tr("Leave out -f: Onigiri keeps ssh running itself").to_string());
+        }
+        Ok(())
+    }
+
+    /// Fills the form from the ssh command in `pasted_command`.
+    fn fill_from_command(&mut self) {
+        self.paste_result = Some(match ssh_command::parse(&self.pasted_command) {
+            Ok(pasted) => {
+                pasted.apply(self);
+                self.touched.extend(FormField::ALL);
+                self.edited_at = Some(Instant::now());
+                let mut notes = Vec::new();
+                if !pasted.extra_args.is_empty() {
+                    notes.push(trf("Kept as SSH options: {}", &[&self.extra_args]));
+                }
+                if !pasted.dropped.is_empty() {
+                    notes.push(trf("Left out the remote command: {}", &[&shell_words(&pasted.dropped)]));
+                }
+                Ok(notes.join("\n"))
+            }
+            Err(e) => Err(e),
+        });
+    }
+
+    /// Like `validate_port`, but 0 asks for any free port at each start.
+    fn validate_local_port(port: &str) -> Result<u16, String> {
+        match port.parse::<u16>() {
+            Ok(p) => Ok(p),
+            Err(_) => Err(tr("Invalid port number").to_string()),
+        }
+    }
+
+    fn validate_port(port: &str) -> Result<u16, String> {
+        match port.parse::<u16>() {
+            Ok(p) if p > 0 => Ok(p),
+            Ok(_) => Err(tr("Port must be between 1 and 65535").to_string()),
+            Err(_) => Err(tr("Invalid port number").to_string()),
+        }
+    }
+
+    /// ssh listens on the local address, so it has to be an IP.
+    fn validate_local_ip(ip: &str) -> Result<(), String> {
+        match ip.parse::<IpAddr>() {
+            Ok(_) => Ok(()),
+            Err(_) => Err(trf("'{}' is not an IP address, such as 127.0.0.1 or ::1", &[&ip])),
+        }
+    }
+
+    /// The remote end is an IP or a hostname the server resolves.
+    fn validate_host(host: &str) -> Result<(), String> {
+        if host.parse::<IpAddr>().is_ok() || is_hostname(host) {
+            Ok(())
+        } else {
+            Err(trf("'{}' is not an IP address or hostname", &[&host]))
+        }
+    }
+
+    /// `[ssh://][user@]host[:port]`, where the host is an IP (bracketed when
+    /// a port follows an IPv6 one), a hostname or an ssh config alias.
+    fn validate_ssh_server(server: &str) -> Result<(), String> {
+        let rest = server.strip_prefix("ssh://").unwrap_or(server);
+        let host = match rest.split_once('@') {
+            Some((user, _)) if user.is_empty() || user.contains(char::is_whitespace) => {
+                return Err(tr("Put a user name before the @, or leave both out").to_string());
+            }
+            Some((_, host)) => host,
+            None => rest,
+        };
+        let (host, port) = if let Some(bracketed) = host.strip_prefix('[') {
+            let (address, after) = bracketed.split_once(']').ok_or_else(|| tr("'[' without a closing ']'").to_string())?;
+            if address.parse::<Ipv6Addr>().is_err() {
+                return Err(trf("'{}' is not an IPv6 address", &[&address]));
+            }
+            match after {
+                "" => return Ok(()),
+                after => match after.strip_prefix(':') {
+                    Some(port) => (None, Some(port)),
+                    None => return Err(tr("Only a :port may follow the address").to_string()),
+                },
+            }
+        } else if host.parse::<Ipv6Addr>().is_ok() {
+            (None, None)
+        } else {
+            match host.rsplit_once(':') {
+                Some((name, port)) => (Some(name), Some(port)),
+                None => (Some(host), None),
+            }
+        };
+        if let Some(host) = host {
+            // ssh config aliases may use underscores, which hostnames can't
+            if host.parse::<IpAddr>().is_err() && !is_hostname(&host.replace('_', "-")) {
+                return Err(trf("'{}' is not an IP address or hostname", &[&host]));
+            }
+        }
+        match port.map(|port| port.parse::<u16>()) {
+            Some(Ok(0) | Err(_)) => Err(tr("The SSH port must be between 1 and 65535").to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    fn from_tunnel(tunnel: &Tunnel) -> Self {
+        Self {
+            name: tunnel.name.clone(),
+            ssh_server: tunnel.ssh_server.clone(),
+            local_ip: tunnel.local_ip.clone(),
+            local_port: tunnel.local_port.to_string(),
+            remote_ip: tunnel.remote_ip.clone(),
+            remote_port: tunnel.remote_port.to_string(),
+            idle_timeout: tunnel
+                .idle_timeout_minutes
+                .map(|m| m.to_string())
+                .unwrap_or_default(),
+            tags: tunnel.tags.clone(),
+            group: tunnel.group.clone().unwrap_or_default(),
+            notes: tunnel.notes.clone(),
+            color: tunnel.color,
+            url_scheme: tunnel.url_scheme.clone(),
+            autostart: tunnel.autostart,
+            extra_args: tunnel.extra_args.clone(),
+            agent_socket: tunnel.agent_socket.clone().unwrap_or_default(),
+            pre_start: tunnel.pre_start.clone(),
+            pre_start_timeout: tunnel
+                .pre_start_timeout_secs
+                .map(|s| s.to_string())
+                .unwrap_or_default(),
+            post_start: tunnel.post_start.clone(),
+            pinned: tunnel.pinned,
+            secret_ref: tunnel.secret_ref.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// Builds the stored tunnel from a validated form.
+    fn to_tunnel(&self, id: TunnelId) -> Tunnel {
+        let local_port: u16 = self.local_port.parse().unwrap_or(0);
+        let remote_port: u16 = self.remote_port.parse().unwrap_or(0);
+
+        Tunnel {
+            id,
+            name: self.name.trim().to_string(),
+            ssh_server: self.ssh_server.trim().to_string(),
+            local_ip: self.local_ip.trim().to_string(),
+            local_port,
+            remote_ip: self.remote_ip.trim().to_string(),
+            remote_port,
+            idle_timeout_minutes: Self::parse_idle_timeout(&self.idle_timeout).unwrap_or(None),
+            tags: self.tags.clone(),
+            group: Some(self.group.trim().to_string()).filter(|group| !group.is_empty()),
+            last_started_at: None,
+            created_at: None,
+            updated_at: None,
+            timestamps_approximate: false,
+            pinned: self.pinned,
+            notes: self.notes.trim().to_string(),
+            secret_ref: self.secret_ref.clone(),
+            color: self.color,
+            url_scheme: self.url_scheme.clone(),
+            autostart: self.autostart,
+            extra_args: self.extra_args.trim().to_string(),
+            agent_socket: Some(self.agent_socket.trim().to_string()).filter(|path| !path.is_empty()),
+            pre_start: self.pre_start.trim().to_string(),
+            pre_start_timeout_secs: Self::parse_pre_start_timeout(&self.pre_start_timeout).unwrap_or(None),
+            post_start: self.post_start.trim().to_string(),
+            workspace_id: storage::DEFAULT_WORKSPACE,
+            active: false,
+            deleted: false,
+        }
+    }
+
+    /// The messages set by the last `validate` call.
+    fn errors(&self) -> Vec<String> {
+        [
+            &self.name_error,
+            &self.ssh_server_error,
+            &self.local_ip_error,
+            &self.local_port_error,
+            &self.remote_ip_error,
+            &self.remote_port_error,
+            &self.idle_timeout_error,
+            &self.extra_args_error,
+            &self.agent_socket_error,
+            &self.pre_start_error,
+            &self.post_start_error,
+        ]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect()
+    }
+}
+
+/// The ssh command line that runs a tunnel, program first. Everything that
+/// starts, shows or exports a tunnel builds it here. ssh reads the port from
+/// `ssh://host:port` servers itself.
+fn build_command(tunnel: &Tunnel) -> Vec<String> {
+    let mut command = vec!["ssh".to_string(), "-N".to_string()];
+    command.extend(tunnel.extra_args_words());
+    command.extend([
+        tunnel.ssh_server.clone(),
+        "-L".to_string(),
+        forward_spec(&tunnel.local_ip, tunnel.local_port, &tunnel.remote_ip, tunnel.remote_port),
+    ]);
+    command
+}
+
+/// The `-L` argument forwarding `local_ip:local_port` to the remote end.
+/// ssh needs IPv6 addresses in brackets on either end.
+fn forward_spec(local_ip: &str, local_port: u16, remote_ip: &str, remote_port: u16) -> String {
+    format!("{}:{}", socket_address(local_ip, local_port), socket_address(remote_ip, remote_port))
+}
+
+/// `ip:port`, with an IPv6 address in brackets.
+fn socket_address(ip: &str, port: u16) -> String {
+    if ip.contains(':') {
+        format!("[{}]:{}", ip, port)
+    } else {
+        format!("{}:{}", ip, port)
+    }
+}
+
+/// `build_command` as one line for a shell, quoting where needed.
+fn command_line(tunnel: &Tunnel) -> String {
+    shell_words(&build_command(tunnel))
+}
+
+/// Arguments joined for a POSIX shell, quoting the ones that need it.
+fn shell_words(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| {
+            let plain = !arg.is_empty()
+                && arg.chars().all(|c| c.is_ascii_alphanumeric() || "@%+=:,./-_[]".contains(c));
+            if plain {
+                arg.clone()
+            } else {
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Tunnel {
+    /// Whether a field, or a word of the extra ssh options, uses `~` or
+    /// environment variables.
+    fn has_references(&self) -> bool {
+        [&self.ssh_server, &self.local_ip, &self.remote_ip]
+            .into_iter()
+            .chain(&self.agent_socket)
+            .any(|value| expand::has_references(value))
+            || self.extra_args_words().iter().any(|word| expand::has_references(word))
+    }
+
+    /// The extra ssh options as ssh gets them.
+    fn extra_args_words(&self) -> Vec<String> {
+        // Checked when saved
+        ssh_command::split(&self.extra_args).unwrap_or_default()
+    }
+
+    /// The tunnel with `~` and environment variables in its fields expanded,
+    /// as ssh is started with it.
+    fn expanded(&self) -> Result<Tunnel, OnigiriError> {
+        self.expanded_with(expand::expand)
+    }
+
+    /// `expanded` with `expand` in place of this process's environment.
+    fn expanded_with(&self, expand: impl Fn(&str) -> Result<String, String>) -> Result<Tunnel, OnigiriError> {
+        if !self.has_references() {
+            return Ok(self.clone());
+        }
+        // Expanded values get the checks the form skipped for them
+        let field = |field: FormField, label: &str, value: &str, check: fn(&str) -> Result<(), String>| {
+            expand(value)
+                .map(|expanded| expanded.trim().to_string())
+                .and_then(|expanded| check(&expanded).map(|_| expanded))
+                .map_err(|e| OnigiriError::Validation {
+                    field: Some(field),
+                    message: format!("{}: {}", label, e),
+                })
+        };
+        Ok(Tunnel {
+            ssh_server: normalize_server(&field(
+                FormField::SshServer,
+                "SSH Server",
+                &self.ssh_server,
+                NewTunnelForm::validate_ssh_server,
+            )?),
+            local_ip: field(FormField::LocalIp, "Local IP", &self.local_ip, NewTunnelForm::validate_local_ip)?,
+            remote_ip: field(FormField::RemoteIp, "Remote IP", &self.remote_ip, NewTunnelForm::validate_host)?,
+            agent_socket: self
+                .agent_socket
+                .as_deref()
+                .map(|path| field(FormField::AgentSocket, "SSH agent", path, |_| Ok(())))
+                .transpose()?,
+            // Word by word, as `-i ~/.ssh/work` only starts with `~` in its
+            // second word
+            extra_args: shell_words(
+                &self
+                    .extra_args_words()
+                    .iter()
+                    .map(|word| {
+                        expand(word).map_err(|e| OnigiriError::Validation {
+                            field: Some(FormField::ExtraArgs),
+                            message: format!("SSH options: {}", e),
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            ..self.clone()
+        })
+    }
+
+    /// `local_ip:local_port`, what a client on this machine connects to.
+    fn local_address(&self) -> String {
+        socket_address(&self.local_ip, self.local_port)
+    }
+
+    /// The local end as a URL, for tunnels to the usual web ports, tagged
+    /// "http", or given a scheme in the edit window.
+    fn local_url(&self) -> Option<String> {
+        let scheme = match (self.url_scheme.as_deref(), self.remote_port) {
+            (Some(scheme), _) => scheme,
+            (None, 443) => "https",
+            (None, 80 | 3000 | 8080) => "http",
+            _ if self.tags.iter().any(|tag| tag.eq_ignore_ascii_case("http")) => "http",
+            _ => return None,
+        };
+        Some(format!("{}://{}", scheme, self.local_address()))
+    }
+
+    /// Whether the search box matches any of the tunnel's fields, ignoring case
+    fn matches(&self, query: &str) -> bool {
+        self.search_rank(query).is_some()
+    }
+
+    /// How well the search box matches the tunnel's fields, ignoring case:
+    /// lower first, or None for no match.
+    /// A number ranks exact and then prefix port matches ahead of the rest.
+    fn search_rank(&self, query: &str) -> Option<u8> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return Some(0);
+        }
+        if query.chars().all(|c| c.is_ascii_digit()) {
+            let ports = [self.local_port, self.remote_port].map(|port| port.to_string());
+            if ports.contains(&query) {
+                return Some(0);
+            }
+            if ports.iter().any(|port| port.starts_with(&query)) {
+                return Some(1);
+            }
+        }
+        let text_match = [
+            &self.name,
+            &self.ssh_server,
+            &self.local_ip,
+            &self.remote_ip,
+            &self.notes,
+        ]
+        .into_iter()
+        .chain(&self.tags)
+        .any(|field| field.to_lowercase().contains(&query));
+        text_match.then_some(2)
+    }
+}
+
+/// A color label to tell tunnels apart at a glance, e.g. production in red.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ColorLabel {
+    Red,
+    Orange,
+    Yellow,
+    Green,
+    Blue,
+    Purple,
+}
+
+impl ColorLabel {
+    const ALL: [ColorLabel; 6] = [
+        ColorLabel::Red,
+        ColorLabel::Orange,
+        ColorLabel::Yellow,
+        ColorLabel::Green,
+        ColorLabel::Blue,
+        ColorLabel::Purple,
+    ];
+
+    /// Stable name used when saving the choice.
+    fn key(self) -> &'static str {
+        match self {
+            ColorLabel::Red => "red",
+            ColorLabel::Orange => "orange",
+            ColorLabel::Yellow => "yellow",
+            ColorLabel::Green => "green",
+            ColorLabel::Blue => "blue",
+            ColorLabel::Purple => "purple",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|color| color.key() == key)
+    }
+
+    fn color(self) -> egui::Color32 {
+        match self {
+            ColorLabel::Red => egui::Color32::from_rgb(215, 60, 60),
+            ColorLabel::Orange => egui::Color32::from_rgb(235, 140, 40),
+            ColorLabel::Yellow => egui::Color32::from_rgb(230, 200, 50),
+            ColorLabel::Green => egui::Color32::from_rgb(70, 170, 80),
+            ColorLabel::Blue => egui::Color32::from_rgb(60, 120, 215),
+            ColorLabel::Purple => egui::Color32::from_rgb(150, 90, 200),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortMode {
+    /// The stored order, rearranged with the ⬆/⬇ buttons.
+    Manual,
+    Name,
+    /// Running tunnels first, then queued ones.
+    Status,
+    Recent,
+    Newest,
+}
+
+impl SortMode {
+    const ALL: [SortMode; 5] = [
+        SortMode::Manual,
+        SortMode::Name,
+        SortMode::Status,
+        SortMode::Recent,
+        SortMode::Newest,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            SortMode::Manual => "Manual",
+            SortMode::Name => "Name",
+            SortMode::Status => "Running first",
+            SortMode::Recent => "Recently used",
+            SortMode::Newest => "Newest first",
+        }
+    }
+
+    /// Stable name used when saving the choice.
+    fn key(self) -> &'static str {
+        match self {
+            SortMode::Manual => "manual",
+            SortMode::Name => "name",
+            SortMode::Status => "status",
+            SortMode::Recent => "recent",
+            SortMode::Newest => "newest",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.key() == key)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Theme {
+    /// Light or dark as the OS is set, switching when it does.
+    System,
+    Light,
+    Dark,
+}
+
+impl Theme {
+    const ALL: [Theme; 3] = [Theme::System, Theme::Light, Theme::Dark];
+
+    fn label(self) -> &'static str {
+        match self {
+            Theme::System => "Follow system",
+            Theme::Light => "Light",
+            Theme::Dark => "Dark",
+        }
+    }
+
+    /// Stable name used when saving the choice.
+    fn key(self) -> &'static str {
+        match self {
+            Theme::System => "system",
+            Theme::Light => "light",
+            Theme::Dark => "dark",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|theme| theme.key() == key)
+    }
+
+    fn preference(self) -> egui::ThemePreference {
+        match self {
+            Theme::System => egui::ThemePreference::System,
+            Theme::Light => egui::ThemePreference::Light,
+            Theme::Dark => egui::ThemePreference::Dark,
+        }
+    }
+}
+
+/// How tightly the tunnel list is packed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Density {
+    Comfortable,
+    /// Tighter rows with every action in the ⋯ menu and no status text.
+    Compact,
+}
+
+impl Density {
+    const ALL: [Density; 2] = [Density::Comfortable, Density::Compact];
+
+    fn label(self) -> &'static str {
+        match self {
+            Density::Comfortable => "Comfortable",
+            Density::Compact => "Compact",
+        }
+    }
+
+    fn key(self) -> &'static str {
+        match self {
+            Density::Comfortable => "comfortable",
+            Density::Compact => "compact",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|density| density.key() == key)
+    }
+}
+
+/// What quitting does with tunnels that are still running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum QuitBehavior {
+    Stop,
+    /// The ssh processes outlive Onigiri and are taken over next launch.
+    Keep,
+    Ask,
+}
+
+impl QuitBehavior {
+    const ALL: [QuitBehavior; 3] = [QuitBehavior::Ask, QuitBehavior::Stop, QuitBehavior::Keep];
+
+    fn label(self) -> &'static str {
+        match self {
+            QuitBehavior::Stop => "Stop tunnels",
+            QuitBehavior::Keep => "Leave tunnels running",
+            QuitBehavior::Ask => "Ask",
+        }
+    }
+
+    fn key(self) -> &'static str {
+        match self {
+            QuitBehavior::Stop => "stop",
+            QuitBehavior::Keep => "keep",
+            QuitBehavior::Ask => "ask",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|behavior| behavior.key() == key)
+    }
+}
+
+/// Meaning of a status color. Each has a shade per theme that stays
+/// readable on that theme's background.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tone {
+    Good,
+    Bad,
+    Warning,
+    Pending,
+}
+
+impl Tone {
+    fn color(self, dark_mode: bool) -> egui::Color32 {
+        match (self, dark_mode) {
+            (Tone::Good, true) => egui::Color32::from_rgb(80, 210, 80),
+            (Tone::Good, false) => egui::Color32::from_rgb(20, 125, 40),
+            (Tone::Bad, true) => egui::Color32::from_rgb(255, 110, 110),
+            (Tone::Bad, false) => egui::Color32::from_rgb(190, 30, 30),
+            (Tone::Warning, true) => egui::Color32::from_rgb(240, 170, 50),
+            (Tone::Warning, false) => egui::Color32::from_rgb(155, 90, 0),
+            (Tone::Pending, true) => egui::Color32::from_rgb(230, 210, 60),
+            (Tone::Pending, false) => egui::Color32::from_rgb(125, 110, 0),
+        }
+    }
+}
+
+/// `tone`'s color for the theme `ui` is drawn with.
+fn tone(ui: &egui::Ui, tone: Tone) -> egui::Color32 {
+    tone.color(ui.visuals().dark_mode)
+}
+
+/// How the list is searched, filtered and sorted. Saved as settings so it
+/// survives restarts, and never exported with the tunnels.
+#[derive(Debug, Clone, PartialEq)]
+struct ListPreferences {
+    search_query: String,
+    running_only: bool,
+    sort_mode: SortMode,
+    tag_filter: BTreeSet<String>,
+    // Keys of the list sections folded shut
+    collapsed_sections: BTreeSet<String>,
+}
+
+impl Default for ListPreferences {
+    fn default() -> Self {
+        Self {
+            search_query: String::new(),
+            running_only: false,
+            sort_mode: SortMode::Manual,
+            tag_filter: BTreeSet::new(),
+            collapsed_sections: BTreeSet::new(),
+        }
+    }
+}
+
+/// The list's rows as indexes into `Tunneler::tunnels`, in the order shown,
+/// kept until the tunnels, their states or the list preferences change.
+#[derive(Debug, Default)]
+struct ListCache {
+    preferences: Option<ListPreferences>,
+    rows: Vec<usize>,
+}
+
+impl ListCache {
+    fn invalidate(&mut self) {
+        self.preferences = None;
+    }
+}
+
+/// The tunnels the list shows, in order: those matching the search and tag
+/// filter, and running when only running ones are wanted, plus the pinned
+/// ones. `status` says whether a tunnel is running and whether it is queued.
+fn list_rows(tunnels: &[Tunnel], preferences: &ListPreferences, status: impl Fn(TunnelId) -> (bool, bool)) -> Vec<usize> {
+    let mut rows: Vec<(usize, u8, (bool, bool))> = tunnels
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| !t.deleted)
+        .filter_map(|(index, t)| {
+            let status = status(t.id);
+            let listed = t.matches(&preferences.search_query)
+                && preferences.tag_filter.iter().all(|tag| t.tags.contains(tag))
+                && (!preferences.running_only || status.0);
+            // Pinned tunnels stay listed whatever the search and filters
+            (listed || t.pinned).then(|| {
+                let rank = t.search_rank(&preferences.search_query).unwrap_or(u8::MAX);
+                (index, rank, status)
+            })
+        })
+        .collect();
+    match preferences.sort_mode {
+        SortMode::Manual => {}
+        SortMode::Name => rows.sort_by_key(|(index, ..)| tunnels[*index].name.to_lowercase()),
+        SortMode::Status => rows.sort_by_key(|(_, _, (active, queued))| (!active, !queued)),
+        // Most recent first; never-used tunnels (None) sort last
+        SortMode::Recent => rows.sort_by_key(|(index, ..)| std::cmp::Reverse(tunnels[*index].last_started_at)),
+        SortMode::Newest => rows.sort_by_key(|(index, ..)| std::cmp::Reverse(tunnels[*index].created_at)),
+    }
+    // Stable, so the sort order still holds among equally good matches
+    rows.sort_by_key(|(_, rank, _)| *rank);
+    rows.into_iter().map(|(index, ..)| index).collect()
+}
+
+impl ListPreferences {
+    const SEARCH_KEY: &str = "list.search_query";
+    const RUNNING_ONLY_KEY: &str = "list.running_only";
+    const SORT_KEY: &str = "list.sort_mode";
+    const TAGS_KEY: &str = "list.tag_filter";
+    const COLLAPSED_KEY: &str = "list.collapsed_sections";
+
+    /// Missing or unreadable values fall back to the defaults.
+    fn load(settings: &Settings) -> Self {
+        let get = |key| settings.get(key).ok().flatten();
+        let defaults = Self::default();
+        Self {
+            search_query: get(Self::SEARCH_KEY).unwrap_or_default(),
+            running_only: get(Self::RUNNING_ONLY_KEY).is_some_and(|value| value == "true"),
+            sort_mode: get(Self::SORT_KEY)
+                .and_then(|key| SortMode::from_key(&key))
+                .unwrap_or(defaults.sort_mode),
+            tag_filter: get(Self::TAGS_KEY)
+                .and_then(|json| serde_json::from_str(&json).ok())
+                .unwrap_or_default(),
+            collapsed_sections: get(Self::COLLAPSED_KEY)
+                .and_then(|json| serde_json::from_str(&json).ok())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn save(&self, settings: &Settings) -> anyhow::Result<()> {
+        // An empty search is removed rather than stored
+        if self.search_query.is_empty() {
+            settings.remove(Self::SEARCH_KEY)?;
+        } else {
+            settings.set(Self::SEARCH_KEY, &self.search_query)?;
+        }
+        settings.set(Self::RUNNING_ONLY_KEY, &self.running_only.to_string())?;
+        settings.set(Self::SORT_KEY, self.sort_mode.key())?;
+        settings.set(Self::TAGS_KEY, &serde_json::to_string(&self.tag_filter)?)?;
+        settings.set(Self::COLLAPSED_KEY, &serde_json::to_string(&self.collapsed_sections)?)?;
+        Ok(())
+    }
+}
+
+/// The About window's check for a newer release, started by the user.
+enum UpdateCheck {
+    Running(Receiver<Result<String, String>>),
+    Done(Result<String, String>),
+}
+
+/// Saving a tunnel as a template: its name and the fields left blank.
+struct TemplateDialog {
+    tunnel_id: TunnelId,
+    name: String,
+    blanks: transfer::TemplateBlanks,
+    error: Option<String>,
+}
+
+/// The name prompt for creating (`id` is None) or renaming a workspace.
+struct WorkspaceDialog {
+    id: Option<i32>,
+    name: String,
+    error: Option<String>,
+}
+
+/// A choice from the workspace menu in the header.
+enum WorkspaceAction {
+    Switch(i32),
+    Create,
+    Rename,
+    Delete,
+}
+
+/// What the secret buttons in the edit window asked for.
+enum SecretAction {
+    Set(String),
+    Clear,
+    Copy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ConflictChoice {
+    Skip,
+    Overwrite,
+    KeepBoth,
+}
+
+impl ConflictChoice {
+    fn label(self) -> &'static str {
+        match self {
+            ConflictChoice::Skip => "Skip",
+            ConflictChoice::Overwrite => "Overwrite",
+            ConflictChoice::KeepBoth => "Keep both",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ImportCandidate {
+    form: NewTunnelForm,
+    // Existing tunnel with the same name
+    existing_id: Option<TunnelId>,
+    choice: ConflictChoice,
+}
+
+/// A parsed import file waiting for the user to resolve name conflicts.
+#[derive(Debug)]
+struct ImportPreview {
+    source: PathBuf,
+    candidates: Vec<ImportCandidate>,
+    invalid: Vec<String>,
+    // Templates in the file, as name and definition; saved over same-named ones
+    templates: Vec<(String, String)>,
+}
+
+/// Forwards found in the SSH config, waiting to be picked for import.
+struct SshConfigImport {
+    source: PathBuf,
+    candidates: Vec<SshConfigCandidate>,
+    // Forwards that can't become tunnels, with the reason
+    skipped: Vec<String>,
+}
+
+struct SshConfigCandidate {
+    form: NewTunnelForm,
+    // An existing tunnel already has the same server and ports
+    duplicate: bool,
+    selected: bool,
+}
+
+/// The log window's filters. Kept while it's closed, like the records.
+struct LogView {
+    level: log::LevelFilter,
+    search: String,
+    autoscroll: bool,
+}
+
+impl Default for LogView {
+    fn default() -> Self {
+        Self {
+            level: log::LevelFilter::Trace,
+            search: String::new(),
+            autoscroll: true,
+        }
+    }
+}
+
+impl LogView {
+    fn shows(&self, record: &logging::LogRecord, search: &str) -> bool {
+        record.level <= self.level
+            && (search.is_empty()
+                || record.message.to_lowercase().contains(search)
+                || record.target.to_lowercase().contains(search))
+    }
+}
+
+/// A tunnel about to be written out as a launchd agent or systemd unit.
+struct ServiceExport {
+    tunnel_id: TunnelId,
+    path: String,
+    activate: bool,
+}
+
+/// Appends " (2)", " (3)", ... until the name is not taken.
+fn unique_name(base: &str, taken: &HashSet<String>) -> String {
+    (2..)
+        .map(|n| format!("{} ({})", base, n))
+        .find(|name| !taken.contains(name))
+        .unwrap()
+}
+
+impl Tunneler {
+    /// The app before its settings and storage are loaded.
+    fn unopened(cli: &Cli, settings: Option<Settings>, restored: Option<WindowGeometry>) -> Self {
+        let (test_tx, test_rx) = mpsc::channel();
+        let (verify_tx, verify_rx) = mpsc::channel();
+        let (pre_start_tx, pre_start_rx) = mpsc::channel();
+        let (hook_tx, hook_rx) = mpsc::channel();
+        let (exit_tx, exit_rx) = mpsc::channel();
+        Self {
+            store: None,
+            storage_backend: cli.storage,
+            tunnels_file: cli.tunnels_file.clone(),
+            settings,
+            window_geometry: restored,
+            saved_geometry: restored,
+            geometry_changed_at: Instant::now(),
+            check_restored_geometry: restored.is_some(),
+            store_migration: None,
+            workspace: storage::DEFAULT_WORKSPACE,
+            workspaces: Vec::new(),
+            workspace_dialog: None,
+            history: None,
+            #[cfg(any(target_os = "macos", windows))]
+            tray: None,
+            control: None,
+            badge_count: None,
+            theme: Theme::System,
+            density: Density::Comfortable,
+            show_subtitles: true,
+            keep_running: false,
+            quitting: false,
+            confirm_quit: false,
+            quit_behavior: QuitBehavior::Ask,
+            leave_running: false,
+            shut_down: false,
+            tunnels: Vec::new(),
+            active_tunnels: HashMap::new(),
+            expanded_tunnels: HashSet::new(),
+            last_stops: HashMap::new(),
+            selected: BTreeSet::new(),
+            selection_anchor: None,
+            bulk_tag: String::new(),
+            bulk_group: String::new(),
+            confirm_bulk_delete: None,
+            search_query: String::new(),
+            tag_filter: BTreeSet::new(),
+            running_only: false,
+            sort_mode: SortMode::Manual,
+            collapsed_sections: BTreeSet::new(),
+            saved_list_preferences: ListPreferences::default(),
+            list_cache: ListCache::default(),
+            tunnels_dirty: false,
+            show_new_tunnel_window: false,
+            show_edit_tunnel_window: false,
+            new_tunnel: NewTunnelForm::default(),
+            edit_tunnel: None,
+            confirm_restart: false,
+            restart_pending: HashSet::new(),
+            custom_presets: Vec::new(),
+            terminal_template: terminal::default_template().to_string(),
+            terminal_dialog: None,
+            paste_dialog: None,
+            templates: Vec::new(),
+            show_templates: false,
+            show_logs: false,
+            log_view: LogView::default(),
+            show_about: false,
+            ssh_version: None,
+            update_check: None,
+            template_rename: None,
+            template_dialog: None,
+            inline_edit: None,
+            bulk_start: None,
+            errors: ErrorToasts::default(),
+            copied: None,
+            start_queue: StartQueue::new(
+                Duration::from_millis(cli.start_delay_ms),
+                cli.start_concurrency as usize,
+            ),
+            idle_poller: IdlePoller::new(),
+            idle_stopped: HashMap::new(),
+            tests_running: HashSet::new(),
+            started_for_test: HashSet::new(),
+            test_results: HashMap::new(),
+            test_tx,
+            test_rx,
+            verify_tx,
+            verify_rx,
+            pre_start_tx,
+            pre_start_rx,
+            hook_tx,
+            hook_rx,
+            hook_failures: HashMap::new(),
+            exit_watch: ExitWatch {
+                tx: exit_tx,
+                ctx: egui::Context::default(),
+            },
+            exit_rx,
+            runner: Arc::new(SshRunner),
+            exiting: HashMap::new(),
+            states: HashMap::new(),
+            reconnects: HashMap::new(),
+            start_errors: HashMap::new(),
+            database_error: None,
+            exits: HashMap::new(),
+            storage_error: None,
+            import_preview: None,
+            ssh_config_import: None,
+            service_export: None,
+            confirm_empty_trash: None,
+            confirm_restore_backup: None,
+            pending_restore: None,
+            status_message: None,
+        }
+    }
+
+    fn new(cli: &Cli, settings: Option<Settings>, restored: Option<WindowGeometry>) -> Self {
+        debug!("Creating new Tunneler instance");
+        let mut app = Self::unopened(cli, settings, restored);
+
+        if let Some(settings) = &app.settings {
+            match settings.get(WORKSPACE_SETTING) {
+                Ok(Some(id)) => app.workspace = id.parse().unwrap_or(storage::DEFAULT_WORKSPACE),
+                Ok(None) => {}
+                Err(e) => error!("Failed to read the active workspace: {:#}", e),
+            }
+            if let Ok(Some(key)) = settings.get(THEME_SETTING) {
+                app.theme = Theme::from_key(&key).unwrap_or(Theme::System);
+            }
+            if let Some(language) = settings.get(LANGUAGE_SETTING).ok().flatten().and_then(|key| Language::from_key(&key)) {
+                i18n::set_language(language);
+            }
+            if let Ok(Some(key)) = settings.get(DENSITY_SETTING) {
+                app.density = Density::from_key(&key).unwrap_or(Density::Comfortable);
+            }
+            app.keep_running = matches!(settings.get(KEEP_RUNNING_SETTING), Ok(Some(value)) if value == "true");
+            if let Ok(Some(key)) = settings.get(QUIT_SETTING) {
+                app.quit_behavior = QuitBehavior::from_key(&key).unwrap_or(QuitBehavior::Ask);
+            }
+            app.show_subtitles = !matches!(settings.get(SUBTITLE_SETTING), Ok(Some(value)) if value == "false");
+            if let Ok(Some(template)) = settings.get(terminal::SETTING_KEY) {
+                app.terminal_template = template;
+            }
+            match settings.get(presets::SETTING_KEY) {
+                Ok(Some(json)) => match serde_json::from_str(&json) {
+                    Ok(custom) => app.custom_presets = custom,
+                    Err(e) => error!("Ignoring unreadable presets: {}", e),
+                },
+                Ok(None) => {}
+                Err(e) => error!("Failed to read presets: {:#}", e),
+            }
+            let preferences = ListPreferences::load(settings);
+            app.apply_list_preferences(preferences.clone());
+            app.saved_list_preferences = preferences;
+        }
+
+        // Initialize storage and load tunnels
+        match app.open_store() {
+            Ok(mut store) => {
+                if let Some(days) = cli.purge_deleted_after_days {
+                    if let Err(e) = store.purge_deleted(Some(days)) {
+                        error!("Failed to purge deleted tunnels: {:#}", e);
+                    }
+                }
+                app.store = Some(store);
+                app.load_workspaces();
+                app.load_tunnels();
+                app.load_templates();
+                app.adopt_processes();
+                app.start_autostart_tunnels();
+                app.offer_restore(cli.restore_tunnels);
+            }
+            Err(e) => {
+                error!("Failed to initialize storage: {}", e);
+                app.storage_error = Some(e);
+            }
+        }
+        info!("Application initialized with {} tunnels", app.tunnels.len());
+        app
+    }
+
+    fn start_edit_tunnel(&mut self, id: TunnelId) {
+        if let Some(tunnel) = self.tunnels.iter().find(|t| t.id == id) {
+            self.edit_tunnel = Some((id, NewTunnelForm::from_tunnel(tunnel)));
+            self.show_edit_tunnel_window = true;
+            self.confirm_restart = false;
+        }
+    }
+
+    /// Opens the Add Tunnel window filled in from an existing tunnel.
+    fn start_duplicate_tunnel(&mut self, id: TunnelId) {
+        if let Some(tunnel) = self.tunnels.iter().find(|t| t.id == id) {
+            let mut form = NewTunnelForm::from_tunnel(tunnel);
+            form.name = format!("{} copy", tunnel.name);
+            form.pinned = false;
+            // The copy would share, and could clear, the original's entry
+            form.secret_ref = None;
+            form.insert_after = Some(id);
+            self.new_tunnel = form;
+            self.show_new_tunnel_window = true;
+        }
+    }
+
+    fn save_edited_tunnel(&mut self, restart: bool) -> Result<(), OnigiriError> {
+        if let Some((id, mut form)) = self.edit_tunnel.take() {
+            if let Err(e) = self.save_tunnel_form(id, &form, restart) {
+                // The window stays open with everything typed so far, and
+                // the error next to the field to change when there is one
+                match &e {
+                    OnigiriError::Validation { field: Some(field), message } => {
+                        *form.error_mut(*field) = Some(message.clone());
+                    }
+                    OnigiriError::Bind { .. } => form.local_port_error = Some(e.to_string()),
+                    _ => form.save_error = Some(e.to_string()),
+                }
+                self.edit_tunnel = Some((id, form));
+                return Err(e);
+            }
+            self.show_edit_tunnel_window = false;
+            self.confirm_restart = false;
+        }
+        Ok(())
+    }
+
+    /// Stores a validated edit form. A running tunnel is restarted with the
+    /// new settings, or with `restart` false, left running and marked as
+    /// having a restart pending.
+    fn save_tunnel_form(&mut self, id: TunnelId, form: &NewTunnelForm, restart: bool) -> Result<(), OnigiriError> {
+        let tunnel = self.keep_metadata(form.to_tunnel(id));
+        if let Err(e) = self.store().map_err(OnigiriError::Database)?.update(tunnel) {
+            return Err(e.context("Failed to update tunnel").into());
+        }
+
+        if self.active_tunnels.contains_key(&id) {
+            if restart {
+                if let Err(e) = self.restart_tunnel(id) {
+                    self.tunnels_dirty = true;
+                    return Err(e);
+                }
+            } else {
+                self.restart_pending.insert(id);
+            }
+        }
+
+        self.tunnels_dirty = true;
+        Ok(())
+    }
+
+    /// Stops a running tunnel and starts it again with its saved settings.
+    fn restart_tunnel(&mut self, id: TunnelId) -> Result<(), OnigiriError> {
+        if self.active_tunnels.contains_key(&id) {
+            self.stop_running(id, "Restarting with new settings");
+            self.toggle_tunnel(id)?;
+        }
+        Ok(())
+    }
+
+    fn start_inline_edit(&mut self, id: TunnelId) {
+        if let Some(tunnel) = self.tunnels.iter().find(|t| t.id == id) {
+            self.inline_edit = Some((id, NewTunnelForm::from_tunnel(tunnel)));
+        }
+    }
+
+    /// Fresh errors in the bottom corner, or all recent ones when reopened
+    /// from the header.
+    fn show_error_toasts(&mut self, ctx: &egui::Context) {
+        let now = Instant::now();
+        let shown: Vec<String> = if self.errors.show_recent {
+            self.errors.recent.iter().map(|(_, message)| message.clone()).collect()
+        } else {
+            self.errors.fresh(now).map(|(_, message)| message.clone()).collect()
+        };
+        if shown.is_empty() {
+            return;
+        }
+        if let Some((at, _)) = self.errors.fresh(now).last() {
+            ctx.request_repaint_after(TOAST_DURATION.saturating_sub(now.duration_since(*at)));
+        }
+
+        let mut close = false;
+        let mut clear = false;
+        egui::Area::new(egui::Id::new("error_toasts"))
+            .anchor(egui::Align2::RIGHT_BOTTOM, [-8.0, -8.0])
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.set_max_width(320.0);
+                    for message in &shown {
+                        ui.colored_label(tone(ui, Tone::Bad), message);
+                    }
+                    if self.errors.show_recent {
+                        ui.horizontal(|ui| {
+                            close = ui.small_button(tr("Hide")).clicked();
+                            clear = ui.small_button(tr("Clear")).clicked();
+                        });
+                    }
+                });
+            });
+        if close || clear {
+            self.errors.show_recent = false;
+        }
+        if clear {
+            self.errors.recent.clear();
+        }
+    }
+
+    fn save_inline_edit(&mut self) {
+        let Some((id, mut form)) = self.inline_edit.take() else {
+            return;
+        };
+        if !form.validate_with(&self.tunnels, &self.active_tunnels, Some(id)) {
+            self.inline_edit = Some((id, form));
+            return;
+        }
+        if let Err(e) = self.save_tunnel_form(id, &form, true) {
+            self.report_failure(id, "Failed to save", e);
+        }
+    }
+
+    fn open_store(&mut self) -> Result<Box<dyn TunnelStore>, String> {
+        let db_path = storage::database_path().map_err(|e| format!("{:#}", e))?;
+        match self.storage_backend {
+            StorageBackend::Sqlite => {
+                let store = SqliteStore::open(&db_path).map_err(|e| format!("{:#}", e))?;
+                Ok(Box::new(store))
+            }
+            StorageBackend::Toml => {
+                let path = self
+                    .tunnels_file
+                    .clone()
+                    .or_else(TomlStore::default_path)
+                    .ok_or("Could not determine where to keep tunnels.toml")?;
+                let store = TomlStore::new(path);
+                if !store.exists() && db_path.exists() {
+                    // First run with the file backend: offer the database's tunnels
+                    match SqliteStore::open(&db_path)
+                        .and_then(|mut db| db.load_tunnels())
+                    {
+                        Ok(tunnels) if !tunnels.is_empty() => self.store_migration = Some(tunnels),
+                        Ok(_) => {}
+                        Err(e) => debug!("Not offering migration from the database: {:#}", e),
+                    }
+                }
+                info!("Using tunnel file {}", store.describe());
+                Ok(Box::new(store))
+            }
+        }
+    }
+
+    /// Tries opening the store again after the storage error dialog.
+    fn retry_storage(&mut self) {
+        match self.open_store() {
+            Ok(store) => {
+                info!("Storage is available again");
+                self.store = Some(store);
+                self.storage_error = None;
+                self.load_workspaces();
+                self.load_tunnels();
+                self.load_templates();
+            }
+            Err(e) => {
+                error!("Failed to initialize storage: {}", e);
+                self.storage_error = Some(e);
+            }
+        }
+    }
+
+    fn store(&mut self) -> Result<&mut Box<dyn TunnelStore>, String> {
+        self.store
+            .as_mut()
+            .ok_or_else(|| "Tunnel storage is unavailable".to_string())
+    }
+
+    /// Copies the offered tunnels into the new store, or just creates the
+    /// empty store when `copy` is false, so the offer is only made once.
+    fn finish_store_migration(&mut self, copy: bool) -> Result<(), String> {
+        let tunnels = self.store_migration.take().unwrap_or_default();
+        let changes = if copy {
+            tunnels.into_iter().map(TunnelChange::Insert).collect()
+        } else {
+            Vec::new()
+        };
+        let count = changes.len();
+        self.store()?.apply(changes).map_err(|e| format!("{:#}", e))?;
+        info!("Copied {} tunnels into the new store", count);
+        self.tunnels_dirty = true;
+        Ok(())
+    }
+
+    /// Reloads the workspace list, falling back to the default workspace
+    /// if the active one is gone.
+    fn load_workspaces(&mut self) {
+        let Some(store) = self.store.as_mut() else {
+            return;
+        };
+        match store.workspaces() {
+            Ok(workspaces) => self.workspaces = workspaces,
+            Err(e) => error!("Failed to load workspaces: {:#}", e),
+        }
+        if !self.workspaces.iter().any(|w| w.id == self.workspace) {
+            self.workspace = storage::DEFAULT_WORKSPACE;
+        }
+    }
+
+    fn switch_workspace(&mut self, id: i32) {
+        if id == self.workspace {
+            return;
+        }
+        info!("Switching to workspace {}", id);
+        self.workspace = id;
+        if let Some(settings) = &self.settings {
+            if let Err(e) = settings.set(WORKSPACE_SETTING, &id.to_string()) {
+                error!("Failed to save the active workspace: {:#}", e);
+            }
+        }
+        self.expanded_tunnels.clear();
+        self.load_tunnels();
+    }
+
+    fn save_workspace_dialog(&mut self) -> Result<(), String> {
+        let Some(dialog) = &self.workspace_dialog else {
+            return Ok(());
+        };
+        let name = dialog.name.trim().to_string();
+        if name.is_empty() {
+            return Err(tr("Name is required").to_string());
+        }
+        if self.workspaces.iter().any(|w| w.name == name && Some(w.id) != dialog.id) {
+            return Err(format!("A workspace named '{}' already exists", name));
+        }
+        let id = dialog.id;
+        let store = self.store()?;
+        let created = match id {
+            Some(id) => store.rename_workspace(id, &name).map(|_| None),
+            None => store.create_workspace(&name).map(Some),
+        }
+        .map_err(|e| format!("{:#}", e))?;
+        self.workspace_dialog = None;
+        self.load_workspaces();
+        if let Some(id) = created {
+            self.switch_workspace(id);
+        }
+        Ok(())
+    }
+
+    fn delete_workspace(&mut self) {
+        let id = self.workspace;
+        let result = self
+            .store()
+            .and_then(|store| store.delete_workspace(id).map_err(|e| format!("{:#}", e)));
+        match result {
+            Ok(()) => {
+                info!("Deleted workspace {}", id);
+                self.load_workspaces();
+                self.switch_workspace(storage::DEFAULT_WORKSPACE);
+            }
+            Err(e) => {
+                error!("Failed to delete workspace {}: {}", id, e);
+                self.status_message = Some(e);
+            }
+        }
+    }
+
+    fn show_workspace_dialog(&mut self, ctx: &egui::Context) {
+        let Some(dialog) = &mut self.workspace_dialog else {
+            return;
+        };
+        let mut save = false;
+        let mut cancelled = false;
+
+        egui::Modal::new(egui::Id::new("workspace_dialog")).show(ctx, |ui| {
+            if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                cancelled = true;
+            }
+            ui.set_max_width(300.0);
+            ui.heading(if dialog.id.is_some() { tr("Rename workspace") } else { tr("New workspace") });
+            let response = ui.text_edit_singleline(&mut dialog.name);
+            if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                save = true;
+            }
+            if let Some(error) = &dialog.error {
+                ui.colored_label(tone(ui, Tone::Bad), error);
+            }
+            ui.add_space(8.0);
+            ui.horizontal(|ui| {
+                if ui.button(tr("Cancel")).clicked() {
+                    cancelled = true;
+                }
+                if ui.button(tr("Save")).clicked() {
+                    save = true;
+                }
+            });
+        });
+
+        if save {
+            if let Err(e) = self.save_workspace_dialog() {
+                if let Some(dialog) = &mut self.workspace_dialog {
+                    dialog.error = Some(e);
+                }
+            }
+        } else if cancelled {
+            self.workspace_dialog = None;
+        }
+    }
+
+    fn load_templates(&mut self) {
+        let Some(store) = self.store.as_mut() else {
+            return;
+        };
+        match store.templates() {
+            Ok(templates) => self.templates = templates,
+            Err(e) => error!("Failed to load templates: {:#}", e),
+        }
+    }
+
+    fn begin_save_template(&mut self, id: TunnelId) {
+        let Some(tunnel) = self.tunnels.iter().find(|t| t.id == id) else {
+            return;
+        };
+        self.template_dialog = Some(TemplateDialog {
+            tunnel_id: id,
+            name: tunnel.name.clone(),
+            blanks: transfer::TemplateBlanks {
+                ssh_server: true,
+                ..Default::default()
+            },
+            error: None,
+        });
+    }
+
+    fn save_template_dialog(&mut self) -> Result<(), String> {
+        let Some(dialog) = &self.template_dialog else {
+            return Ok(());
+        };
+        let name = dialog.name.trim().to_string();
+        if name.is_empty() {
+            return Err(tr("Name is required").to_string());
+        }
+        let tunnel = self
+            .tunnels
+            .iter()
+            .find(|t| t.id == dialog.tunnel_id)
+            .ok_or_else(|| "The tunnel no longer exists".to_string())?;
+        let definition =
+            transfer::template_definition(tunnel, dialog.blanks).map_err(|e| e.to_string())?;
+        self.store()?
+            .save_template(&name, &definition)
+            .map_err(|e| format!("{:#}", e))?;
+        info!("Saved template '{}'", name);
+        self.template_dialog = None;
+        self.load_templates();
+        self.status_message = Some(trf("Saved template '{}'", &[&name]));
+        Ok(())
+    }
+
+    fn show_template_dialog(&mut self, ctx: &egui::Context) {
+        let Some(dialog) = &mut self.template_dialog else {
+            return;
+        };
+        let mut save = false;
+        let mut cancelled = false;
+
+        egui::Modal::new(egui::Id::new("template_dialog")).show(ctx, |ui| {
+            if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                cancelled = true;
+            }
+            ui.set_max_width(300.0);
+            ui.heading(tr("Save as template"));
+            ui.horizontal(|ui| {
+                ui.label(tr("Name:"));
+                ui.text_edit_singleline(&mut dialog.name);
+            });
+            ui.label(tr("Leave blank in new tunnels:"));
+            ui.add_enabled(false, egui::Checkbox::new(&mut true, tr("Name")));
+            ui.checkbox(&mut dialog.blanks.ssh_server, tr("SSH server"));
+            ui.checkbox(&mut dialog.blanks.local_port, tr("Local port"));
+            ui.checkbox(&mut dialog.blanks.remote_ip, tr("Remote IP"));
+            ui.checkbox(&mut dialog.blanks.remote_port, tr("Remote port"));
+            if let Some(error) = &dialog.error {
+                ui.colored_label(tone(ui, Tone::Bad), error);
+            }
+            ui.add_space(8.0);
+            ui.horizontal(|ui| {
+                if ui.button(tr("Cancel")).clicked() {
+                    cancelled = true;
+                }
+                if ui.button(tr("Save")).clicked() {
+                    save = true;
+                }
+            });
+        });
+
+        if save {
+            if let Err(e) = self.save_template_dialog() {
+                error!("Failed to save template: {}", e);
+                if let Some(dialog) = &mut self.template_dialog {
+                    dialog.error = Some(e);
+                }
+            }
+        } else if cancelled {
+            self.template_dialog = None;
+        }
+    }
+
+    /// Opens the Add Tunnel window with a template's fields filled in.
+    fn start_from_template(&mut self, id: i32) {
+        let Some(template) = self.templates.iter().find(|t| t.id == id) else {
+            return;
+        };
+        match transfer::template_form(&template.definition) {
+            Ok(mut form) => {
+                form.from_template = true;
+                self.new_tunnel = form;
+                self.show_new_tunnel_window = true;
+            }
+            Err(e) => self.status_message = Some(trf("Template '{}': {}", &[&template.name, &e])),
+        }
+    }
+
+    fn open_about(&mut self) {
+        self.ssh_version = about::ssh_version();
+        self.show_about = true;
+    }
+
+    /// Asks GitHub for the latest release on a worker thread.
+    fn check_for_updates(&mut self, ctx: &egui::Context) {
+        let (tx, rx) = mpsc::channel();
+        let ctx = ctx.clone();
+        std::thread::spawn(move || {
+            let _ = tx.send(about::latest_release());
+            ctx.request_repaint();
+        });
+        self.update_check = Some(UpdateCheck::Running(rx));
+    }
+
+    fn show_about_window(&mut self, ctx: &egui::Context) {
+        if let Some(UpdateCheck::Running(rx)) = &self.update_check {
+            if let Ok(result) = rx.try_recv() {
+                if let Err(e) = &result {
+                    // Being offline is not worth more than a log line
+                    debug!("Update check failed: {}", e);
+                }
+                self.update_check = Some(UpdateCheck::Done(result));
+            }
+        }
+        let mut open = self.show_about;
+        let mut should_check = false;
+        let location = self.store.as_ref().map(|store| store.describe());
+        let data_dir = storage::data_dir().ok();
+
+        egui::Window::new(tr("About Onigiri"))
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.heading(trf("Onigiri {}", &[&about::VERSION]));
+                ui.label(tr("A SSH tunnel manager"));
+                ui.add_space(8.0);
+                egui::Grid::new("about_grid").num_columns(2).show(ui, |ui| {
+                    ui.label(tr("ssh:"));
+                    match &self.ssh_version {
+                        Some(version) => ui.label(version),
+                        None => ui.colored_label(tone(ui, Tone::Bad), tr("not found")),
+                    };
+                    ui.end_row();
+                    ui.label(tr("Tunnels:"));
+                    match &location {
+                        Some(path) => {
+                            ui.horizontal(|ui| {
+                                ui.add(egui::Label::new(egui::RichText::new(path).monospace()).truncate());
+                                if ui.small_button(tr("Open folder")).clicked() {
+                                    if let Some(folder) = Path::new(path).parent() {
+                                        open_folder(folder);
+                                    }
+                                }
+                            });
+                        }
+                        None => {
+                            ui.weak(tr("not saved"));
+                        }
+                    }
+                    ui.end_row();
+                    if let Some(dir) = &data_dir {
+                        ui.label(tr("Data folder:"));
+                        ui.horizontal(|ui| {
+                            let path = dir.display().to_string();
+                            ui.add(egui::Label::new(egui::RichText::new(path).monospace()).truncate());
+                            if ui.small_button(tr("Open folder")).clicked() {
+                                open_folder(dir);
+                            }
+                        });
+                        ui.end_row();
+                        let logs = logging::log_dir(dir);
+                        ui.label(tr("Logs:"));
+                        ui.horizontal(|ui| {
+                            let path = logs.display().to_string();
+                            ui.add(egui::Label::new(egui::RichText::new(path).monospace()).truncate());
+                            if ui.small_button(tr("Open log folder")).clicked() {
+                                open_folder(&logs);
+                            }
+                        });
+                        ui.end_row();
+                    }
+                });
+                ui.add_space(8.0);
+                ui.horizontal(|ui| {
+                    let checking = matches!(self.update_check, Some(UpdateCheck::Running(_)));
+                    if ui.add_enabled(!checking, egui::Button::new(tr("Check for updates"))).clicked() {
+                        should_check = true;
+                    }
+                    match &self.update_check {
+                        Some(UpdateCheck::Running(_)) => {
+                            ui.spinner();
+                        }
+                        Some(UpdateCheck::Done(Ok(tag))) if about::is_newer(tag, about::VERSION) => {
+                            ui.colored_label(tone(ui, Tone::Good), format!("{} is available", tag));
+                            ui.hyperlink_to(tr("Releases"), about::RELEASES_PAGE);
+                        }
+                        Some(UpdateCheck::Done(Ok(_))) => {
+                            ui.weak(tr("You have the latest version"));
+                        }
+                        Some(UpdateCheck::Done(Err(_))) => {
+                            ui.weak(tr("Couldn't check for updates"));
+                        }
+                        None => {}
+                    }
+                });
+            });
+
+        if should_check {
+            self.check_for_updates(ctx);
+        }
+        self.show_about = open;
+    }
+
+    /// What Onigiri logged lately, for users who didn't start it from a
+    /// terminal.
+    fn show_log_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_logs;
+        let view = &mut self.log_view;
+        egui::Window::new(tr("Logs"))
+            .open(&mut open)
+            .default_size([640.0, 360.0])
+            .show(ctx, |ui| {
+                let mut copy = false;
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_salt("log_level")
+                        .selected_text(tr(logging::level_label(view.level)))
+                        .show_ui(ui, |ui| {
+                            for level in logging::LEVELS {
+                                ui.selectable_value(&mut view.level, level, tr(logging::level_label(level)));
+                            }
+                        });
+                    ui.add(egui::TextEdit::singleline(&mut view.search).hint_text(tr("Search logs")).desired_width(180.0));
+                    ui.checkbox(&mut view.autoscroll, tr("Autoscroll"));
+                    copy = ui.button(tr("Copy all")).on_hover_text(tr("Copies the records shown")).clicked();
+                });
+                ui.separator();
+
+                let search = view.search.trim().to_lowercase();
+                logging::with_recent(|recent| {
+                    let shown: Vec<&logging::LogRecord> = recent.iter().filter(|r| view.shows(r, &search)).collect();
+                    if copy {
+                        let lines: Vec<String> = shown.iter().map(|r| r.line()).collect();
+                        ctx.copy_text(lines.join("\n"));
+                    }
+                    if shown.is_empty() {
+                        ui.weak(tr("Nothing logged at this level yet"));
+                        return;
+                    }
+                    let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+                    egui::ScrollArea::both()
+                        .auto_shrink(false)
+                        .stick_to_bottom(view.autoscroll)
+                        .show_rows(ui, row_height, shown.len(), |ui, rows| {
+                            for record in &shown[rows] {
+                                let color = match record.level {
+                                    log::Level::Error => ui.visuals().error_fg_color,
+                                    log::Level::Warn => ui.visuals().warn_fg_color,
+                                    _ => ui.visuals().text_color(),
+                                };
+                                ui.label(egui::RichText::new(record.line()).monospace().color(color));
+                            }
+                        });
+                });
+            });
+        self.show_logs = open;
+        // New records don't come with input, so look for them now and then
+        ctx.request_repaint_after(Duration::from_millis(500));
+    }
+
+    fn show_templates_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_templates;
+        let mut template_to_use = None;
+        let mut template_to_delete = None;
+        let mut rename_done = None;
+
+        egui::Window::new(tr("Templates"))
+            .open(&mut open)
+            .collapsible(false)
+            .default_width(320.0)
+            .show(ctx, |ui| {
+                if self.templates.is_empty() {
+                    ui.weak(tr("No templates yet. Use a tunnel's Export → Save as template… to add one."));
+                }
+                for template in &self.templates {
+                    ui.horizontal(|ui| {
+                        match &mut self.template_rename {
+                            Some((id, name)) if *id == template.id => {
+                                let response = ui.add(egui::TextEdit::singleline(name).desired_width(140.0));
+                                if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                                    rename_done = Some(true);
+                                }
+                                if ui.small_button(tr("Save")).clicked() {
+                                    rename_done = Some(true);
+                                }
+                                if ui.small_button(tr("Cancel")).clicked() {
+                                    rename_done = Some(false);
+                                }
+                            }
+                            _ => {
+                                ui.label(&template.name);
+                                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                                    if ui.small_button(tr("Delete")).clicked() {
+                                        template_to_delete = Some(template.id);
+                                    }
+                                    if ui.small_button(tr("Rename")).clicked() {
+                                        self.template_rename = Some((template.id, template.name.clone()));
+                                    }
+                                    if ui.small_button(tr("New from template")).clicked() {
+                                        template_to_use = Some(template.id);
+                                    }
+                                });
+                            }
+                        }
+                    });
+                }
+            });
+        self.show_templates = open;
+
+        if let Some(save) = rename_done {
+            let rename = self.template_rename.take();
+            if let (true, Some((id, name))) = (save, rename) {
+                let name = name.trim().to_string();
+                let result = if name.is_empty() {
+                    Err(tr("Name is required").to_string())
+                } else {
+                    self.store()
+                        .and_then(|store| store.rename_template(id, &name).map_err(|e| format!("{:#}", e)))
+                };
+                match result {
+                    Ok(()) => self.load_templates(),
+                    Err(e) => {
+                        error!("Failed to rename template {}: {}", id, e);
+                        self.status_message = Some(e);
+                        self.template_rename = Some((id, name));
+                    }
+                }
+            }
+        }
+
+        if let Some(id) = template_to_delete {
+            let result = self
+                .store()
+                .and_then(|store| store.delete_template(id).map_err(|e| format!("{:#}", e)));
+            match result {
+                Ok(()) => {
+                    info!("Deleted template {}", id);
+                    self.load_templates();
+                }
+                Err(e) => {
+                    error!("Failed to delete template {}: {}", id, e);
+                    self.status_message = Some(e);
+                }
+            }
+        }
+
+        if let Some(id) = template_to_use {
+            self.start_from_template(id);
+        }
+    }
+
+    /// Reloads the list, keeping the current one if the store can't be read.
+    fn load_tunnels(&mut self) {
+        // Without a store the user chose to carry on with an empty list
+        let Some(store) = self.store.as_mut() else {
+            return;
+        };
+        match store.load_tunnels() {
+            Ok(tunnels) => {
+                let workspace = self.workspace;
+                self.tunnels = tunnels
+                    .into_iter()
+                    .filter(|t| t.workspace_id == workspace)
+                    .collect();
+                self.list_cache.invalidate();
+                info!("Loaded {} active tunnels", self.tunnels.len());
+            }
+            Err(e) => {
+                error!("Failed to load tunnels: {:#}", e);
+                self.storage_error = Some(format!("Failed to load tunnels: {:#}", e));
+            }
+        }
+    }
+
+    fn toggle_tunnel(&mut self, id: TunnelId) -> Result<(), OnigiriError> {
+        // Either way the next run uses the saved settings
+        self.restart_pending.remove(&id);
+        if self.active_tunnels.contains_key(&id) {
+            self.stop_running(id, "");
+            return Ok(());
+        }
+        let stored = match self.store().map_err(OnigiriError::Database)?.get_tunnel(id) {
+            Ok(stored) => stored,
+            // Deleted elsewhere, e.g. by another Onigiri or in tunnels.toml
+            Err(_) if !self.tunnels.iter().any(|t| t.id == id) => return Err(OnigiriError::NotFound(id)),
+            Err(e) => return Err(e.context("Failed to load tunnel").into()),
+        };
+        if stored.workspace_id != self.workspace {
+            return Err(OnigiriError::Validation {
+                field: None,
+                message: format!("Tunnel {} is in another workspace; switch to it first", stored.name),
+            });
+        }
+        let mut tunnel = TunnelInfo::from(stored);
+        tunnel.exit_watch = Some(self.exit_watch.clone());
+        tunnel.runner = Arc::clone(&self.runner);
+        self.launch(id, tunnel)
+    }
+
+    /// Spawns ssh for a tunnel. Every start ends up here, so a tunnel that
+    /// is already starting or running keeps its ssh and the start is
+    /// ignored, rather than a second ssh replacing it untracked. A tunnel
+    /// is starting while its pre-start hook and server check run on a
+    /// thread of their own, and ssh is spawned by `collect_pre_start_results`.
+    fn launch(&mut self, id: TunnelId, mut tunnel: TunnelInfo) -> Result<(), OnigiriError> {
+        if self.active_tunnels.contains_key(&id) {
+            debug!("Tunnel {} is already {:?}, ignoring start", id, self.state(id));
+            return Ok(());
+        }
+        if let Some(prepare) = tunnel.preparation() {
+            let (started, tx) = (tunnel.started, self.pre_start_tx.clone());
+            let ctx = self.exit_watch.ctx.clone();
+            std::thread::spawn(move || {
+                let _ = tx.send((id, started, prepare()));
+                ctx.request_repaint();
+            });
+        } else if let Err(e) = tunnel.start_tunnel() {
+            self.fail_start(id, e.clone());
+            return Err(e);
+        }
+        self.start_errors.remove(&id);
+        self.dismiss_death(id);
+        self.idle_stopped.remove(&id);
+        debug!("Tunnel started: {}", tunnel.name);
+        self.active_tunnels.insert(id, tunnel);
+        self.transition(id, TunnelState::Starting, "");
+        Ok(())
+    }
+
+    fn fail_start(&mut self, id: TunnelId, error: OnigiriError) {
+        let failed = TunnelState::Failed { reason: error.to_string(), at: unix_now() };
+        self.transition(id, failed, "");
+        self.start_errors.insert(id, error);
+    }
+
+    /// Spawns ssh for tunnels that are ready, and fails the start of those
+    /// whose hook failed or whose server didn't resolve. The hook's output
+    /// stays in the log.
+    fn collect_pre_start_results(&mut self) {
+        while let Ok((id, started, prepared)) = self.pre_start_rx.try_recv() {
+            // A hook of a run that was stopped or restarted meanwhile is stale
+            let Some(tunnel) = self.active_tunnels.get_mut(&id).filter(|t| t.started == started && t.is_preparing()) else {
+                continue;
+            };
+            let Err(e) = tunnel.finish_preparing(prepared).and_then(|()| tunnel.start_tunnel()) else {
+                continue;
+            };
+            if let Some(tunnel) = self.active_tunnels.remove(&id) {
+                let status = if matches!(e, OnigiriError::PreStart(_)) { "pre-start hook failed" } else { "not started" };
+                self.exits.insert(id, TunnelExit { status: status.to_string(), log: tunnel.pre_start_log, died_at: None });
+            }
+            self.report_error(Some(id), format!("Failed to start: {}", e));
+            self.fail_start(id, e);
+        }
+    }
+
+    /// Stops a starting or running tunnel, killing ssh even if it is still
+    /// connecting. `detail` says why, for the history.
+    fn stop_running(&mut self, id: TunnelId, detail: &str) {
+        let Some(mut tunnel) = self.active_tunnels.remove(&id) else {
+            debug!("Tunnel {} is not running, ignoring stop", id);
+            return;
+        };
+        tunnel.stop_tunnel();
+        self.transition(id, TunnelState::Stopped, detail);
+        debug!("Tunnel stopped: {}", tunnel.name);
+    }
+
+    /// Shows a failed action on a tunnel where it fits: a storage failure
+    /// in a dialog, since the change didn't happen, a tunnel that is gone
+    /// by reloading the list, anything else as a toast. Failed starts also
+    /// stay on the tunnel's row through `start_errors`.
+    fn report_failure(&mut self, id: TunnelId, action: &str, error: OnigiriError) {
+        match error {
+            OnigiriError::Database(message) => {
+                error!("{} tunnel {}: {}", action, id, message);
+                self.database_error = Some(message);
+            }
+            OnigiriError::NotFound(_) => {
+                self.report_error(None, format!("{}: {}", action, error));
+                self.tunnels_dirty = true;
+            }
+            error => self.report_error(Some(id), format!("{}: {}", action, error)),
+        }
+    }
+
+    /// Logs a failure and shows it as a toast, naming the tunnel if given.
+    fn report_error(&mut self, id: Option<TunnelId>, message: String) {
+        let name = id.and_then(|id| self.tunnels.iter().find(|t| t.id == id));
+        let message = match name {
+            Some(tunnel) => format!("{}: {}", tunnel.name, message),
+            None => message,
+        };
+        error!("{}", message);
+        self.errors.push(message);
+    }
+
+    /// Clears the "died unexpectedly" marker, keeping the exit's details.
+    fn dismiss_death(&mut self, id: TunnelId) {
+        if let Some(exit) = self.exits.get_mut(&id) {
+            exit.died_at = None;
+        }
+        if matches!(self.state(id), TunnelState::Failed { .. }) {
+            self.transition(id, TunnelState::Stopped, "");
+        }
+    }
+
+    fn state(&self, id: TunnelId) -> &TunnelState {
+        self.states.get(&id).unwrap_or(&TunnelState::Stopped)
+    }
+
+    /// Moves a tunnel to `to`, saving whether it runs and recording the
+    /// change in its history. `detail` says why it stopped. A change that
+    /// makes no sense from where the tunnel is, like stopping a stopped
+    /// tunnel, is ignored and returns false.
+    fn transition(&mut self, id: TunnelId, to: TunnelState, detail: &str) -> bool {
+        let from = self.state(id).clone();
+        if !from.can_become(&to) {
+            debug!("Tunnel {} can't go from {:?} to {:?}, ignoring", id, from, to);
+            return false;
+        }
+        match (&from, &to) {
+            (_, TunnelState::Starting) => {
+                self.hook_failures.remove(&id);
+                self.record_start(id);
+                self.set_active(id, true);
+                self.record_event(id, EventKind::Started, "");
+            }
+            (TunnelState::Stopped, TunnelState::Running { .. }) => {
+                self.set_active(id, true);
+                self.record_event(id, EventKind::Status, "Still running from the last session");
+            }
+            (TunnelState::Running { .. }, TunnelState::Failed { reason, .. }) => {
+                self.set_active(id, false);
+                self.record_event(id, EventKind::Exited, reason);
+            }
+            (_, TunnelState::Failed { reason, .. }) => {
+                if from.is_active() || matches!(from, TunnelState::Reconnecting { .. }) {
+                    self.set_active(id, false);
+                }
+                self.record_event(id, EventKind::Status, &format!("Failed to start: {}", reason));
+            }
+            // Stays marked active, so a crash meanwhile still restores it
+            (TunnelState::Running { .. }, TunnelState::Reconnecting { .. }) => {
+                self.record_event(id, EventKind::Exited, detail);
+            }
+            (_, TunnelState::Reconnecting { attempt, .. }) => {
+                self.record_event(id, EventKind::Status, &format!("Reconnect {} failed: {}", attempt - 1, detail));
+            }
+            (TunnelState::Starting | TunnelState::Running { .. } | TunnelState::Reconnecting { .. }, TunnelState::Stopped) => {
+                self.set_active(id, false);
+                self.record_event(id, EventKind::Stopped, detail);
+            }
+            _ => {}
+        }
+        match &to {
+            TunnelState::Reconnecting { attempt, .. } => {
+                self.reconnects.insert(id, *attempt);
+            }
+            TunnelState::Stopped | TunnelState::Queued | TunnelState::Failed { .. } => {
+                self.reconnects.remove(&id);
+            }
+            TunnelState::Starting | TunnelState::Running { .. } => {}
+        }
+        debug!("Tunnel {}: {:?} -> {:?}", id, from, to);
+        // Running-only lists and the status sort depend on it
+        self.list_cache.invalidate();
+        match to {
+            TunnelState::Stopped => self.states.remove(&id),
+            to => self.states.insert(id, to),
+        };
+        true
+    }
+
+    fn record_start(&mut self, id: TunnelId) {
+        let now = unix_now();
+        let result = self.store().and_then(|store| {
+            store
+                .record_start(id, now)
+                .map_err(|e| format!("{:#}", e))
+        });
+        if let Err(e) = result {
+            error!("Failed to record start of tunnel {}: {}", id, e);
+        }
+        if let Some(tunnel) = self.tunnels.iter_mut().find(|t| t.id == id) {
+            tunnel.last_started_at = Some(now);
+            self.list_cache.invalidate();
+        }
+    }
+
+    fn list_preferences(&self) -> ListPreferences {
+        ListPreferences {
+            search_query: self.search_query.clone(),
+            running_only: self.running_only,
+            sort_mode: self.sort_mode,
+            tag_filter: self.tag_filter.clone(),
+            collapsed_sections: self.collapsed_sections.clone(),
+        }
+    }
+
+    fn apply_list_preferences(&mut self, preferences: ListPreferences) {
+        self.search_query = preferences.search_query;
+        self.running_only = preferences.running_only;
+        self.sort_mode = preferences.sort_mode;
+        self.tag_filter = preferences.tag_filter;
+        self.collapsed_sections = preferences.collapsed_sections;
+    }
+
+    /// Saves the search, filters, sort mode and folded sections whenever
+    /// they change.
+    fn save_list_preferences(&mut self) {
+        let preferences = self.list_preferences();
+        if preferences == self.saved_list_preferences {
+            return;
+        }
+        let Some(settings) = &self.settings else {
+            return;
+        };
+        if let Err(e) = preferences.save(settings) {
+            error!("Failed to save list preferences: {:#}", e);
+        }
+        // Not retried every frame if saving fails
+        self.saved_list_preferences = preferences;
+    }
+
+    /// Follows the window's position and size, saving them once they have
+    /// settled. On the first frame a restored position is checked, so a
+    /// window saved on a monitor that is gone doesn't open off-screen.
+    fn track_window(&mut self, ctx: &egui::Context) {
+        let (geometry, monitor) = ctx.input(|i| {
+            let viewport = i.viewport();
+            (WindowGeometry::from_viewport(viewport), viewport.monitor_size)
+        });
+        let Some(geometry) = geometry else {
+            return;
+        };
+
+        if std::mem::take(&mut self.check_restored_geometry) {
+            // egui only reports the monitor's size, not where it starts
+            let fitted = geometry.fit_to_monitor(monitor, None);
+            if fitted != geometry {
+                info!("Moving window back on screen: {:?}", fitted);
+                ctx.send_viewport_cmd(egui::ViewportCommand::OuterPosition(egui::pos2(fitted.x, fitted.y)));
+                ctx.send_viewport_cmd(egui::ViewportCommand::InnerSize(egui::vec2(fitted.width, fitted.height)));
+                return;
+            }
+        }
+
+        if self.window_geometry != Some(geometry) {
+            self.window_geometry = Some(geometry);
+            self.geometry_changed_at = Instant::now();
+        }
+        if self.window_geometry != self.saved_geometry {
+            if self.geometry_changed_at.elapsed() >= GEOMETRY_SAVE_DELAY {
+                self.save_window_geometry();
+            } else {
+                ctx.request_repaint_after(GEOMETRY_SAVE_DELAY);
+            }
+        }
+    }
+
+    fn save_window_geometry(&mut self) {
+        let (Some(settings), Some(geometry)) = (&self.settings, self.window_geometry) else {
+            return;
+        };
+        match settings.set(window::SETTING_KEY, &geometry.to_setting()) {
+            Ok(()) => self.saved_geometry = Some(geometry),
+            Err(e) => error!("Failed to save window position: {:#}", e),
+        }
+    }
+
+    /// Persists whether a tunnel is running so it can be restored next launch.
+    fn set_active(&mut self, id: TunnelId, active: bool) {
+        let result = self.store().and_then(|store| {
+            store
+                .set_active(id, active)
+                .map_err(|e| format!("{:#}", e))
+        });
+        if let Err(e) = result {
+            error!("Failed to save running state of tunnel {}: {}", id, e);
+        }
+        if let Some(tunnel) = self.tunnels.iter_mut().find(|t| t.id == id) {
+            tunnel.active = active;
+        }
+    }
+
+    fn record_event(&mut self, id: TunnelId, kind: EventKind, detail: &str) {
+        let result = self.store().and_then(|store| {
+            store
+                .record_event(id, unix_now(), kind, detail)
+                .map_err(|e| format!("{:#}", e))
+        });
+        if let Err(e) = result {
+            error!("Failed to record {} event for tunnel {}: {}", kind.key(), id, e);
+        }
+        if matches!(kind, EventKind::Stopped | EventKind::Exited) {
+            let event = TunnelEvent { at: unix_now(), kind, detail: detail.to_string() };
+            self.last_stops.insert(id, Some(event));
+        }
+        if matches!(&self.history, Some((history_id, _)) if *history_id == id) {
+            self.open_history(id);
+        }
+    }
+
+    /// Looks up how a tunnel last stopped, for its details and status tooltip.
+    fn load_last_stop(&mut self, id: TunnelId) {
+        let result = self.store().and_then(|store| {
+            store
+                .events(id, 50)
+                .map_err(|e| format!("{:#}", e))
+        });
+        match result {
+            Ok(events) => {
+                let last = events.into_iter().find(|e| matches!(e.kind, EventKind::Stopped | EventKind::Exited));
+                self.last_stops.insert(id, last);
+            }
+            Err(e) => error!("Failed to load history of tunnel {}: {}", id, e),
+        }
+    }
+
+    fn open_history(&mut self, id: TunnelId) {
+        let result = self.store().and_then(|store| {
+            store
+                .events(id, HISTORY_LIMIT)
+                .map_err(|e| format!("{:#}", e))
+        });
+        match result {
+            Ok(events) => self.history = Some((id, events)),
+            Err(e) => {
+                error!("Failed to load history of tunnel {}: {}", id, e);
+                self.status_message = Some(trf("Failed to load history: {}", &[&e]));
+            }
+        }
+    }
+
+    fn show_history_window(&mut self, ctx: &egui::Context) {
+        let Some((id, events)) = &self.history else {
+            return;
+        };
+        let name = self
+            .tunnels
+            .iter()
+            .find(|t| t.id == *id)
+            .map(|t| t.name.clone())
+            .unwrap_or_default();
+        let now = unix_now();
+        let mut open = true;
+
+        egui::Window::new(trf("History: {}", &[&name]))
+            .id(egui::Id::new("tunnel_history"))
+            .open(&mut open)
+            .collapsible(false)
+            .default_width(360.0)
+            .show(ctx, |ui| {
+                if events.is_empty() {
+                    ui.weak(if self.storage_backend == StorageBackend::Sqlite {
+                        tr("Nothing has happened yet")
+                    } else {
+                        tr("History is only kept with database storage")
+                    });
+                    return;
+                }
+                egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                    egui::Grid::new("history_grid").striped(true).show(ui, |ui| {
+                        for event in events {
+                            ui.weak(format_ago(event.at, now));
+                            let (label, color) = match event.kind {
+                                EventKind::Started => (tr("Started"), tone(ui, Tone::Good)),
+                                EventKind::Stopped => (tr("Stopped"), ui.visuals().text_color()),
+                                EventKind::Exited => (tr("Exited"), tone(ui, Tone::Bad)),
+                                EventKind::Status => (tr("Status"), tone(ui, Tone::Warning)),
+                            };
+                            ui.colored_label(color, label);
+                            ui.label(&event.detail);
+                            ui.end_row();
+                        }
+                    });
+                });
+            });
+
+        if !open {
+            self.history = None;
+        }
+    }
+
+    /// Starts, or offers to start, the tunnels left running last session.
+    fn offer_restore(&mut self, mode: RestoreMode) {
+        if mode == RestoreMode::Never {
+            return;
+        }
+        let ids: Vec<TunnelId> = match self.store().and_then(|store| {
+            store.tunnels_to_resume().map_err(|e| format!("{:#}", e))
+        }) {
+            // Tunnels from other workspaces can't start until switched to,
+            // and autostart and still running ones are already taken care of
+            Ok(ids) => ids
+                .into_iter()
+                .filter(|id| self.tunnels.iter().any(|t| t.id == *id && !t.autostart))
+                .filter(|id| !self.active_tunnels.contains_key(id))
+                .collect(),
+            Err(e) => {
+                error!("Failed to read tunnels from the last session: {}", e);
+                return;
+            }
+        };
+        if ids.is_empty() {
+            return;
+        }
+        info!("{} tunnels were running when Onigiri last closed", ids.len());
+        if mode == RestoreMode::Always {
+            self.restore_tunnels(ids);
+        } else {
+            self.pending_restore = Some(ids);
+        }
+    }
+
+    /// Takes over the ssh processes left running when Onigiri last quit, as
+    /// long as they are still there.
+    fn adopt_processes(&mut self) {
+        let processes = match self.store().and_then(|store| {
+            store.take_processes().map_err(|e| format!("{:#}", e))
+        }) {
+            Ok(processes) => processes,
+            Err(e) => {
+                error!("Failed to read tunnels left running: {}", e);
+                return;
+            }
+        };
+        for process in processes {
+            let Some(tunnel) = self.tunnels.iter().find(|t| t.id == process.tunnel_id && !t.deleted) else {
+                continue;
+            };
+            if !is_ssh_process(process.pid) {
+                info!("Tunnel {} (pid {}) stopped while Onigiri was closed", tunnel.name, process.pid);
+                continue;
+            }
+            info!("Taking over tunnel {} (pid {})", tunnel.name, process.pid);
+            let mut info = TunnelInfo::from(tunnel.clone());
+            info.adopt(process.pid, process.started_at, process.local_port);
+            self.active_tunnels.insert(process.tunnel_id, info);
+            let running = TunnelState::Running { since: process.started_at, check: None };
+            self.transition(process.tunnel_id, running, "");
+        }
+    }
+
+    /// Queues the workspace's tunnels flagged to start whenever Onigiri
+    /// opens. Failures are reported like those of any queued start.
+    fn start_autostart_tunnels(&mut self) {
+        let ids: Vec<TunnelId> = self
+            .tunnels
+            .iter()
+            .filter(|t| t.autostart && !t.deleted)
+            .map(|t| t.id)
+            .filter(|id| !self.active_tunnels.contains_key(id))
+            .collect();
+        if !ids.is_empty() {
+            info!("Starting {} tunnels marked to start at launch", ids.len());
+        }
+        for id in ids {
+            self.queue_start(id);
+        }
+    }
+
+    /// Queues the tunnels for starting; any that fail keep their error in
+    /// their row like a manual start would.
+    fn restore_tunnels(&mut self, ids: Vec<TunnelId>) {
+        for id in ids {
+            self.queue_start(id);
+        }
+    }
+
+    fn show_restore_dialog(&mut self, ctx: &egui::Context) {
+        let Some(ids) = &self.pending_restore else {
+            return;
+        };
+        let names: Vec<&str> = ids
+            .iter()
+            .filter_map(|id| self.tunnels.iter().find(|t| t.id == *id))
+            .map(|t| t.name.as_str())
+            .collect();
+        let mut choice = None;
+
+        egui::Modal::new(egui::Id::new("restore_tunnels")).show(ctx, |ui| {
+            if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                choice = Some(false);
+            }
+            ui.set_max_width(360.0);
+            ui.heading(tr("Restore tunnels"));
+            ui.label(trf(
+                "These tunnels were running when Onigiri last closed: {}",
+                &[&names.join(", ")],
+            ));
+            ui.add_space(8.0);
+            ui.horizontal(|ui| {
+                if ui.button(tr("Not now")).clicked() {
+                    choice = Some(false);
+                }
+                if ui.button(trf("Start {}", &[&names.len()])).clicked() {
+                    choice = Some(true);
+                }
+            });
+        });
+
+        if let Some(restore) = choice {
+            let ids = self.pending_restore.take().unwrap_or_default();
+            if restore {
+                self.restore_tunnels(ids);
+            }
+        }
+    }
+
+    /// Swaps a tunnel with its neighbour in the same section of the list
+    /// (same group and pinned state), then saves the new order.
+    fn move_tunnel(&mut self, id: TunnelId, up: bool) -> Result<(), String> {
+        let Some(index) = self.tunnels.iter().position(|t| t.id == id) else {
+            return Ok(());
+        };
+        let section = |t: &Tunnel| (t.pinned, t.group.clone());
+        let key = section(&self.tunnels[index]);
+        let neighbour = if up {
+            (0..index).rev().find(|&i| section(&self.tunnels[i]) == key)
+        } else {
+            (index + 1..self.tunnels.len()).find(|&i| section(&self.tunnels[i]) == key)
+        };
+        if let Some(neighbour) = neighbour {
+            self.tunnels.swap(index, neighbour);
+            self.save_order()?;
+        }
+        Ok(())
+    }
+
+    /// Moves a dragged tunnel next to the one it was dropped on.
+    fn drop_tunnel(&mut self, id: TunnelId, target: TunnelId, after: bool) -> Result<(), String> {
+        let Some(index) = self.tunnels.iter().position(|t| t.id == id) else {
+            return Ok(());
+        };
+        let tunnel = self.tunnels.remove(index);
+        let Some(target) = self.tunnels.iter().position(|t| t.id == target) else {
+            self.tunnels.insert(index, tunnel);
+            return Ok(());
+        };
+        self.tunnels.insert(if after { target + 1 } else { target }, tunnel);
+        self.save_order()
+    }
+
+    fn save_order(&mut self) -> Result<(), String> {
+        self.list_cache.invalidate();
+        let ids: Vec<TunnelId> = self.tunnels.iter().map(|t| t.id).collect();
+        self.store()?
+            .reorder(&ids)
+            .map_err(|e| format!("Failed to save tunnel order: {:#}", e))
+    }
+
+    fn set_pinned(&mut self, id: TunnelId, pinned: bool) -> Result<(), String> {
+        self.store()?
+            .set_pinned(id, pinned)
+            .map_err(|e| format!("{:#}", e))?;
+        if let Some(tunnel) = self.tunnels.iter_mut().find(|t| t.id == id) {
+            tunnel.pinned = pinned;
+            self.list_cache.invalidate();
+        }
+        Ok(())
+    }
+
+    /// Copies the fields the forms don't edit (usage, timestamps, the secret
+    /// reference and workspace) from the stored tunnel, so saving a form
+    /// doesn't reset them.
+    fn keep_metadata(&self, mut tunnel: Tunnel) -> Tunnel {
+        if let Some(stored) = self.tunnels.iter().find(|t| t.id == tunnel.id) {
+            tunnel.last_started_at = stored.last_started_at;
+            tunnel.created_at = stored.created_at;
+            tunnel.updated_at = stored.updated_at;
+            tunnel.timestamps_approximate = stored.timestamps_approximate;
+            tunnel.secret_ref = stored.secret_ref.clone();
+            tunnel.workspace_id = stored.workspace_id;
+        }
+        tunnel
+    }
+
+    /// What the Start/Stop button does: stops a running tunnel, or queues
+    /// it to start.
+    fn start_or_stop(&mut self, id: TunnelId, ctx: &egui::Context) {
+        if self.active_tunnels.contains_key(&id) {
+            if let Err(e) = self.toggle_tunnel(id) {
+                self.report_failure(id, "Failed to stop", e);
+            }
+        } else {
+            self.queue_start(id);
+            self.process_start_queue(ctx);
+        }
+    }
+
+    #[cfg(any(target_os = "macos", windows))]
+    fn handle_tray_actions(&mut self, ctx: &egui::Context) {
+        let Some(tray) = &mut self.tray else {
+            return;
+        };
+        for action in tray.actions() {
+            debug!("Tray action: {:?}", action);
+            match action {
+                tray::TrayAction::Toggle(id) => self.start_or_stop(id, ctx),
+                tray::TrayAction::ShowWindow => {
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Visible(true));
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(false));
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+                }
+                tray::TrayAction::StartAll => {
+                    let ids = self.tunnels.iter().filter(|t| !t.deleted).map(|t| t.id).collect();
+                    self.start_many(ids, ctx);
+                }
+                tray::TrayAction::StopAll => self.stop_all_tunnels(),
+                tray::TrayAction::Quit => self.request_quit(ctx),
+            }
+        }
+    }
+
+    /// Answers the subcommands sent from terminals over the control socket.
+    fn handle_control_requests(&mut self, ctx: &egui::Context) {
+        let Some(server) = &self.control else {
+            return;
+        };
+        for incoming in server.requests() {
+            debug!("Control request: {:?}", incoming.request);
+            let reply = self.answer_control_request(&incoming.request, ctx);
+            incoming.answer(reply);
+        }
+    }
+
+    /// Does what a subcommand asks the way the matching buttons would. Starts
+    /// are only queued, so their failures show up in the window.
+    fn answer_control_request(&mut self, request: &control::Request, ctx: &egui::Context) -> control::Reply {
+        use control::{Reply, Request};
+
+        let tunnels = match self
+            .store()
+            .and_then(|store| store.load_tunnels().map_err(|e| format!("Failed to load tunnels: {:#}", e)))
+        {
+            Ok(tunnels) => tunnels,
+            Err(e) => return Reply::failed(e),
+        };
+        let status = |t: &Tunnel| {
+            let id = t.id;
+            cli::tunnel_status(t, self.active_tunnels.get(&id), self.state(id).is_waiting())
+        };
+        let names = match request {
+            Request::List => {
+                return cli::status_reply(tunnels.iter().filter(|t| !t.deleted).map(status).collect());
+            }
+            Request::Status { tunnels: names } => {
+                return match cli::find_tunnels(&tunnels, names) {
+                    Ok(found) => cli::status_reply(found.into_iter().map(status).collect()),
+                    Err(e) => Reply::failed(e),
+                };
+            }
+            Request::Reload => {
+                // Running tunnels keep their ssh; edits apply on their next start
+                self.load_workspaces();
+                self.load_tunnels();
+                self.load_templates();
+                return match &self.storage_error {
+                    Some(e) => Reply::failed(e.clone()),
+                    None => Reply::ok(format!("Reloaded {} tunnels", self.tunnels.len())),
+                };
+            }
+            Request::Stop { all: true, .. } => {
+                if self.active_tunnels.is_empty() {
+                    return Reply::ok("No tunnels are running".to_string());
+                }
+                let count = self.active_tunnels.len();
+                self.stop_all_tunnels();
+                return Reply::ok(format!("Stopped {} tunnels", count));
+            }
+            Request::Start { tunnels: names }
+            | Request::Stop { tunnels: names, .. }
+            | Request::Toggle { tunnels: names } => names,
+        };
+        let targets = match cli::find_tunnels(&tunnels, names) {
+            Ok(targets) => targets,
+            Err(e) => return Reply::failed(e),
+        };
+        let mut results = Vec::new();
+        for tunnel in targets {
+            let id = tunnel.id;
+            let running = self.active_tunnels.contains_key(&id) || self.start_queue.contains(id);
+            let result = match request {
+                Request::Start { .. } if running => Ok(format!("{} is already running", tunnel.name)),
+                Request::Stop { .. } if !running => Ok(format!("{} is not running", tunnel.name)),
+                Request::Stop { .. } | Request::Toggle { .. } if running => {
+                    self.stop_many(vec![id]);
+                    Ok(format!("Stopped {}", tunnel.name))
+                }
+                _ if tunnel.workspace_id != self.workspace => Err(format!(
+                    "{} is in another workspace; switch to it first",
+                    tunnel.name
+                )),
+                _ => {
+                    self.start_many(vec![id], ctx);
+                    Ok(format!("Starting {}", tunnel.name))
+                }
+            };
+            results.push(result);
+        }
+        cli::reply_from(results)
+    }
+
+    /// Brings the tray menu in line with the tunnel list.
+    #[cfg(any(target_os = "macos", windows))]
+    fn sync_tray(&mut self) {
+        let Some(tray) = &mut self.tray else {
+            return;
+        };
+        let entries = self
+            .tunnels
+            .iter()
+            .filter(|t| !t.deleted)
+            .map(|t| tray::TrayEntry {
+                id: t.id,
+                name: t.name.clone(),
+                active: self.active_tunnels.contains_key(&t.id),
+            })
+            .collect();
+        tray.sync(entries);
+    }
+
+    /// Shows how many tunnels run on the Dock icon, or in the window title
+    /// where there is no badge, whenever the number changes.
+    fn sync_badge(&mut self, ctx: &egui::Context) {
+        let count = self.active_tunnels.len();
+        if self.badge_count == Some(count) {
+            return;
+        }
+        self.badge_count = Some(count);
+        #[cfg(target_os = "macos")]
+        if badge::set_dock_badge(count) {
+            return;
+        }
+        ctx.send_viewport_cmd(egui::ViewportCommand::Title(badge::window_title(APP_NAME, count)));
+    }
+
+    /// Queues every tunnel in `ids` that isn't running yet and reports how
+    /// they all went once the queue is through them.
+    fn start_many(&mut self, ids: Vec<TunnelId>, ctx: &egui::Context) {
+        let ids: Vec<TunnelId> = ids
+            .into_iter()
+            .filter(|id| !self.active_tunnels.contains_key(id) && !self.start_queue.contains(*id))
+            .collect();
+        if ids.is_empty() {
+            return;
+        }
+        let bulk = self.bulk_start.get_or_insert_with(BulkStart::default);
+        bulk.pending.extend(&ids);
+        for id in ids {
+            self.queue_start(id);
+        }
+        self.process_start_queue(ctx);
+    }
+
+    /// Stops every tunnel in `ids` that is running or waiting to start.
+    fn stop_many(&mut self, ids: Vec<TunnelId>) {
+        let mut stopped = 0;
+        let mut failures = Vec::new();
+        for id in ids {
+            if self.cancel_start(id) {
+                debug!("Removed tunnel {} from the start queue", id);
+            } else if self.active_tunnels.contains_key(&id) {
+                match self.toggle_tunnel(id) {
+                    Ok(()) => stopped += 1,
+                    Err(e) => {
+                        error!("Failed to stop tunnel {}: {}", id, e);
+                        failures.push(e.to_string());
+                    }
+                }
+            }
+        }
+        if stopped > 0 || !failures.is_empty() {
+            let failed = failures.join("; ");
+            self.status_message = Some(match (stopped, failures.is_empty()) {
+                (1, true) => trf("Stopped {} tunnel", &[&stopped]),
+                (_, true) => trf("Stopped {} tunnels", &[&stopped]),
+                (1, false) => trf("Stopped {} tunnel. Failed: {}", &[&stopped, &failed]),
+                (_, false) => trf("Stopped {} tunnels. Failed: {}", &[&stopped, &failed]),
+            });
+        }
+    }
+
+    /// Queues a tunnel for starting. Queued tunnels are spawned from
+    /// `process_start_queue` so bulk starts respect the configured stagger.
+    fn queue_start(&mut self, id: TunnelId) {
+        if self.active_tunnels.contains_key(&id) {
+            debug!("Tunnel {} is already running, not queueing", id);
+            return;
+        }
+        if self.transition(id, TunnelState::Queued, "") {
+            self.start_queue.push(id);
+        }
+    }
+
+    /// Takes a tunnel off the start queue, or stops it reconnecting, if
+    /// it's waiting for either.
+    fn cancel_start(&mut self, id: TunnelId) -> bool {
+        let cancelled = self.start_queue.cancel(id) || matches!(self.state(id), TunnelState::Reconnecting { .. });
+        if cancelled {
+            self.transition(id, TunnelState::Stopped, "");
+        }
+        cancelled
+    }
+
+    fn process_start_queue(&mut self, ctx: &egui::Context) {
+        let now = Instant::now();
+        for id in self.start_queue.next_batch(now) {
+            let result = if self.active_tunnels.contains_key(&id) {
+                Ok(())
+            } else {
+                self.toggle_tunnel(id)
+            };
+            let in_bulk = self.bulk_start.as_ref().is_some_and(|bulk| bulk.pending.contains(&id));
+            match &result {
+                // A bulk start sums its failures up in one message instead
+                Err(e) if in_bulk => error!("Failed to start queued tunnel {}: {}", id, e),
+                Err(e) => self.report_failure(id, "Failed to start", e.clone()),
+                Ok(()) => {}
+            }
+            if let Some(bulk) = &mut self.bulk_start {
+                if bulk.pending.remove(&id) {
+                    match result {
+                        Ok(()) => bulk.started += 1,
+                        Err(e) => {
+                            let name = self.tunnels.iter().find(|t| t.id == id);
+                            let name = name.map_or_else(|| id.to_string(), |t| t.name.clone());
+                            bulk.failures.push(format!("{} ({})", name, e));
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(bulk) = &mut self.bulk_start {
+            // Tunnels taken off the queue by a stop were never tried
+            bulk.pending.retain(|id| self.start_queue.contains(*id));
+            if bulk.pending.is_empty() {
+                self.status_message = Some(bulk.summary());
+                self.bulk_start = None;
+            }
+        }
+        if let Some(wait) = self.start_queue.time_until_next(Instant::now()) {
+            if !self.start_queue.pending.is_empty() {
+                ctx.request_repaint_after(wait);
+            }
+        }
+    }
+
+    /// Handles tunnels whose ssh has exited. Tunnels started here say so
+    /// over `exit_rx`; ones taken over from the last session are polled.
+    fn update_tunnel_status(&mut self, ctx: &egui::Context) {
+        let now = Instant::now();
+        for id in self.exit_rx.try_iter() {
+            self.exiting.entry(id).or_insert(now);
+        }
+        let adopted = self.active_tunnels.iter().filter(|(_, t)| t.adopted.is_some()).map(|(id, _)| *id);
+        let to_check: Vec<TunnelId> = self.exiting.keys().copied().chain(adopted).collect();
+        let mut inactive_tunnels = Vec::new();
+
+        for id in to_check {
+            let Some(tunnel) = self.active_tunnels.get_mut(&id) else {
+                self.exiting.remove(&id);
+                continue;
+            };
+            let state = self.states.get(&id).cloned().unwrap_or(TunnelState::Stopped);
+            // Known before is_active, which forgets an adopted ssh that ended
+            let adopted = tunnel.adopted.is_some();
+            if !tunnel.is_active() {
+                self.exiting.remove(&id);
+                inactive_tunnels.push((id, state, adopted));
+                error!("Tunnel {} is no longer active", tunnel.name);
+            }
+        }
+        // stderr closes a moment before ssh can be reaped, so look again
+        // shortly. A notice from an earlier run of a restarted tunnel, or
+        // an ssh that closed stderr and kept going, is dropped after that.
+        self.exiting.retain(|_, since| now.duration_since(*since) < EXIT_REAP_WAIT);
+        if !self.exiting.is_empty() {
+            ctx.request_repaint_after(Duration::from_millis(50));
+        }
+        if self.active_tunnels.values().any(|t| t.adopted.is_some()) {
+            ctx.request_repaint_after(ADOPTED_POLL_INTERVAL);
+        }
+
+        for (id, state, adopted) in inactive_tunnels {
+            if let Some(tunnel) = self.active_tunnels.remove(&id) {
+                // ssh's last words usually say why
+                let log: Vec<String> = tunnel.stderr.lock().unwrap().iter().cloned().collect();
+                let shown = tunnel.log();
+                let detail = log.last().cloned().unwrap_or_default();
+                let status = tunnel.exit_status.unwrap_or_else(|| "unknown status".to_string());
+                let reason = if detail.is_empty() { format!("ssh exited with {}", status) } else { detail };
+                if let Some(attempt) = self.next_reconnect(id, &state, adopted) {
+                    let delay = RECONNECT_DELAY * 2u32.pow(attempt - 1);
+                    let reconnecting = TunnelState::Reconnecting { attempt, next_retry: Instant::now() + delay };
+                    self.transition(id, reconnecting, &reason);
+                    self.report_error(Some(id), format!("Exited unexpectedly, reconnecting in {}: {}", format_duration(delay), reason));
+                    self.exits.insert(id, TunnelExit { status, log: shown, died_at: None });
+                    ctx.request_repaint_after(delay);
+                    continue;
+                }
+                let gave_up = self.reconnects.contains_key(&id);
+                self.transition(id, TunnelState::Failed { reason: reason.clone(), at: unix_now() }, "");
+                if gave_up {
+                    self.report_error(Some(id), format!("Gave up reconnecting after {} tries: {}", RECONNECT_ATTEMPTS, reason));
+                    self.exits.insert(id, TunnelExit { status, log: shown, died_at: Some(unix_now()) });
+                    continue;
+                }
+                if matches!(state, TunnelState::Starting) {
+                    // Never got going, e.g. a bad host or refused key
+                    self.report_error(Some(id), format!("Failed to start: {}", reason));
+                    self.start_errors.insert(id, OnigiriError::ssh_exited(&log, &status));
+                    self.exits.insert(id, TunnelExit { status, log: shown, died_at: None });
+                    continue;
+                }
+                self.report_error(Some(id), format!("Exited unexpectedly: {}", reason));
+                self.exits.insert(id, TunnelExit { status, log: shown, died_at: Some(unix_now()) });
+            }
+        }
+    }
+
+    /// The reconnect attempt a tunnel whose ssh just ended is on, or None
+    /// when it isn't started again: it never got going, its forward was
+    /// never seen working, or it has used its tries up. An `adopted` ssh
+    /// from the last session is started again whatever its check said.
+    fn next_reconnect(&self, id: TunnelId, state: &TunnelState, adopted: bool) -> Option<u32> {
+        let attempt = match state {
+            // A try that didn't get going
+            TunnelState::Starting => self.reconnects.get(&id)? + 1,
+            // e.g. a refused login the probe read before the exit was reaped
+            TunnelState::Running { check, .. } if !adopted && !check.as_ref().is_some_and(TestOutcome::is_success) => {
+                return None
+            }
+            TunnelState::Running { since, .. } => match self.reconnects.get(&id) {
+                Some(attempt) if unix_now() - since < RECONNECT_STABLE_SECS => attempt + 1,
+                _ => 1,
+            },
+            _ => return None,
+        };
+        (attempt <= RECONNECT_ATTEMPTS).then_some(attempt)
+    }
+
+    /// Starts the tunnels whose wait to reconnect is over.
+    fn retry_reconnects(&mut self, ctx: &egui::Context) {
+        let now = Instant::now();
+        let mut next = None;
+        let mut due = Vec::new();
+        for (id, state) in &self.states {
+            if let TunnelState::Reconnecting { next_retry, .. } = state {
+                if *next_retry <= now {
+                    due.push(*id);
+                } else {
+                    next = Some(next.map_or(*next_retry, |next: Instant| next.min(*next_retry)));
+                }
+            }
+        }
+        for id in due {
+            if let Err(e) = self.toggle_tunnel(id) {
+                // Not tried again, e.g. because it was deleted meanwhile
+                if matches!(self.state(id), TunnelState::Reconnecting { .. }) {
+                    self.transition(id, TunnelState::Failed { reason: e.to_string(), at: unix_now() }, "");
+                }
+                self.report_failure(id, "Failed to reconnect", e);
+            }
+        }
+        if let Some(next) = next {
+            ctx.request_repaint_after(next.saturating_duration_since(now));
+        }
+    }
+
+    /// Stops running tunnels whose local port has seen no connections for
+    /// longer than their idle timeout.
+    fn check_idle_tunnels(&mut self, ctx: &egui::Context) {
+        while let Ok(results) = self.idle_poller.rx.try_recv() {
+            self.idle_poller.in_flight = false;
+            let now = Instant::now();
+            for (id, connected) in results {
+                // When lsof is unavailable, assume activity rather than
+                // stopping tunnels we can't observe.
+                if connected != Some(false) {
+                    if let Some(tunnel) = self.active_tunnels.get_mut(&id) {
+                        tunnel.last_activity = now;
+                    }
+                }
+            }
+        }
+
+        let now = Instant::now();
+        let idle: Vec<(TunnelId, Duration)> = self
+            .active_tunnels
+            .iter()
+            .filter(|(_, t)| t.idle_remaining(now) == Some(Duration::ZERO))
+            .filter_map(|(id, t)| t.idle_timeout.map(|timeout| (*id, timeout)))
+            .collect();
+        for (id, timeout) in idle {
+            info!("Stopping tunnel {} after {} idle", id, format_duration(timeout));
+            self.stop_running(id, &format!("Idle for {}", format_duration(timeout)));
+            self.idle_stopped.insert(id, timeout);
+        }
+
+        if self.idle_poller.due(now) {
+            let targets = self
+                .active_tunnels
+                .iter()
+                .filter(|(_, t)| t.idle_timeout.is_some())
+                .filter_map(|(id, t)| t.pid().map(|pid| (*id, pid, t.local_port)))
+                .collect();
+            self.idle_poller.poll(targets, now, ctx);
+        }
+
+        if self.active_tunnels.values().any(|t| t.idle_timeout.is_some()) {
+            ctx.request_repaint_after(Duration::from_secs(1));
+        }
+    }
+
+    /// Verifies a tunnel end to end, starting it just for the test when it
+    /// isn't already running.
+    fn test_tunnel(&mut self, id: TunnelId, ctx: &egui::Context) {
+        if self.tests_running.contains(&id) {
+            return;
+        }
+        self.test_results.remove(&id);
+
+        if !self.active_tunnels.contains_key(&id) {
+            self.cancel_start(id);
+            if let Err(e) = self.toggle_tunnel(id) {
+                self.test_results.insert(id, TestOutcome::from(e));
+                return;
+            }
+            self.started_for_test.insert(id);
+        }
+
+        let Some(tunnel) = self.active_tunnels.get(&id) else {
+            return;
+        };
+        let addr = match (tunnel.local_ip.as_str(), tunnel.local_port)
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+        {
+            Some(addr) => addr,
+            None => {
+                self.test_results.insert(
+                    id,
+                    TestOutcome::BindError(format!("cannot resolve {}", tunnel.local_ip)),
+                );
+                return;
+            }
+        };
+
+        info!("Testing tunnel {} via {}", tunnel.name, addr);
+        let stderr = Arc::clone(&tunnel.stderr);
+        let tx = self.test_tx.clone();
+        let ctx = ctx.clone();
+        self.tests_running.insert(id);
+        std::thread::spawn(move || {
+            let outcome = probe_tunnel(addr, stderr);
+            let _ = tx.send((id, outcome));
+            ctx.request_repaint();
+        });
+    }
+
+    fn collect_test_results(&mut self) {
+        while let Ok((id, outcome)) = self.test_rx.try_recv() {
+            self.tests_running.remove(&id);
+            debug!("Test of tunnel {} finished: {}", id, outcome.describe());
+            if self.started_for_test.remove(&id) && self.active_tunnels.contains_key(&id) {
+                if let Err(e) = self.toggle_tunnel(id) {
+                    error!("Failed to stop tunnel {} after test: {}", id, e);
+                }
+            }
+            self.test_results.insert(id, outcome);
+        }
+    }
+
+    /// Probes the local port of tunnels that just finished starting, once
+    /// per run, and collects the results.
+    fn verify_started_tunnels(&mut self, ctx: &egui::Context) {
+        let mut checked = Vec::new();
+        while let Ok((id, started, outcome)) = self.verify_rx.try_recv() {
+            // A result for an earlier run of a restarted tunnel is stale
+            if let Some(tunnel) = self.active_tunnels.get(&id).filter(|t| t.started == started) {
+                if !outcome.is_success() {
+                    warn!("Tunnel {} is running but unreachable: {}", tunnel.name, outcome.describe());
+                } else if matches!(self.states.get(&id), Some(TunnelState::Starting)) {
+                    info!("Tunnel {} is up", tunnel.name);
+                    self.run_post_start_hook(id, ctx);
+                }
+                checked.push((id, tunnel.started_at, outcome));
+            }
+        }
+
+        for (id, tunnel) in &mut self.active_tunnels {
+            if tunnel.probing || !tunnel.has_process() {
+                continue;
+            }
+            tunnel.probing = true;
+            let addr = (tunnel.local_ip.as_str(), tunnel.local_port)
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut addrs| addrs.next());
+            let Some(addr) = addr else {
+                let outcome = TestOutcome::BindError(format!("cannot resolve {}", tunnel.local_ip));
+                checked.push((*id, tunnel.started_at, outcome));
+                continue;
+            };
+            let (id, started) = (*id, tunnel.started);
+            let stderr = Arc::clone(&tunnel.stderr);
+            let tx = self.verify_tx.clone();
+            let ctx = ctx.clone();
+            std::thread::spawn(move || {
+                let outcome = probe_tunnel(addr, stderr);
+                let _ = tx.send((id, started, outcome));
+                ctx.request_repaint();
+            });
+        }
+
+        for (id, since, outcome) in checked {
+            let refused = matches!(outcome, TestOutcome::AuthError(_) | TestOutcome::BindError(_));
+            if refused && matches!(self.state(id), TunnelState::Starting) {
+                self.fail_refused_start(id, outcome);
+                continue;
+            }
+            self.transition(id, TunnelState::Running { since, check: Some(outcome) }, "");
+        }
+    }
+
+    /// Stops a starting tunnel whose probe found the login refused or the
+    /// local port taken, which trying again won't fix, and fails the start
+    /// with what ssh said.
+    fn fail_refused_start(&mut self, id: TunnelId, outcome: TestOutcome) {
+        let Some(mut tunnel) = self.active_tunnels.remove(&id) else {
+            return;
+        };
+        tunnel.stop_tunnel();
+        let error = match outcome {
+            TestOutcome::BindError(detail) => OnigiriError::Bind { port: tunnel.local_port, detail, holder: None },
+            TestOutcome::AuthError(line) => OnigiriError::Auth(line),
+            outcome => OnigiriError::Spawn(outcome.describe()),
+        };
+        let status = tunnel.exit_status.clone().unwrap_or_else(|| "stopped".to_string());
+        self.exits.insert(id, TunnelExit { status, log: tunnel.log(), died_at: None });
+        self.report_error(Some(id), format!("Failed to start: {}", error));
+        self.fail_start(id, error);
+    }
+
+    /// Runs a tunnel's post-start hook on a thread of its own. How it went
+    /// arrives through `hook_rx`.
+    fn run_post_start_hook(&self, id: TunnelId, ctx: &egui::Context) {
+        let Some(tunnel) = self.active_tunnels.get(&id) else {
+            return;
+        };
+        let Some(hook) = tunnel.post_start.clone() else {
+            return;
+        };
+        info!("Running post-start hook of tunnel {}", tunnel.name);
+        let env = tunnel.hook_env();
+        let tx = self.hook_tx.clone();
+        let ctx = ctx.clone();
+        std::thread::spawn(move || {
+            let run = hooks::run(&hook, &env, hooks::POST_START_TIMEOUT, &AtomicBool::new(false));
+            let _ = tx.send((id, run));
+            ctx.request_repaint();
+        });
+    }
+
+    /// Reports finished post-start hooks. A failure is kept in the tunnel's
+    /// history and shown on its row; the tunnel itself keeps running.
+    fn collect_hook_results(&mut self) {
+        while let Ok((id, run)) = self.hook_rx.try_recv() {
+            let name = self.tunnels.iter().find(|t| t.id == id).map_or_else(|| id.to_string(), |t| t.name.clone());
+            for line in &run.output {
+                debug!("Post-start hook of {}: {}", name, line);
+            }
+            match run.result {
+                Ok(()) => info!("Post-start hook of tunnel {} finished", name),
+                Err(reason) => {
+                    warn!("Post-start hook of tunnel {} failed: {}", name, reason);
+                    self.record_event(id, EventKind::Status, &format!("Post-start hook failed: {}", reason));
+                    self.hook_failures.insert(id, reason);
+                }
+            }
+        }
+    }
+
+    fn add_new_tunnel(&mut self) -> Result<(), String> {
+        debug!("Adding new tunnel: {}", self.new_tunnel.name);
+        let mut tunnel = self.new_tunnel.to_tunnel(TunnelId::default());
+        tunnel.workspace_id = self.workspace;
+        self.store()?
+            .insert(tunnel)
+            .map_err(|e| format!("{:#}", e))?;
+
+        info!("New tunnel '{}' added successfully", self.new_tunnel.name);
+        let insert_after = self.new_tunnel.insert_after;
+        self.load_tunnels();
+
+        // Stores append new tunnels, so it is the last one
+        if let (Some(after), Some(added)) = (insert_after, self.tunnels.pop()) {
+            let position = self
+                .tunnels
+                .iter()
+                .position(|t| t.id == after)
+                .map_or(self.tunnels.len(), |index| index + 1);
+            self.tunnels.insert(position, added);
+            // The tunnel is saved by now, only its place in the list is off
+            if let Err(e) = self.save_order() {
+                self.report_error(None, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the tunnel from the Add Tunnel window, closing it on success.
+    /// On failure the window stays open with the form intact and the error.
+    fn submit_new_tunnel(&mut self) {
+        match self.add_new_tunnel() {
+            Ok(()) => {
+                self.show_new_tunnel_window = false;
+                self.new_tunnel = NewTunnelForm::default();
+            }
+            Err(e) => {
+                error!("Failed to add '{}': {}", self.new_tunnel.name.trim(), e);
+                self.new_tunnel.save_error = Some(e);
+            }
+        }
+    }
+
+    fn export_tunnels(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .set_file_name("onigiri-tunnels.json")
+            .add_filter("JSON", &["json"])
+            .save_file()
+        else {
+            return;
+        };
+
+        let result = transfer::export_json(&self.tunnels, &self.templates)
+            .map_err(|e| e.to_string())
+            .and_then(|json| std::fs::write(&path, json).map_err(|e| e.to_string()));
+        self.status_message = Some(match result {
+            Ok(()) => {
+                info!("Exported {} tunnels to {}", self.tunnels.len(), path.display());
+                trf("Exported {} tunnels to {}", &[&self.tunnels.len(), &path.display()])
+            }
+            Err(e) => {
+                error!("Failed to export tunnels: {}", e);
+                trf("Export failed: {}", &[&e])
+            }
+        });
+    }
+
+    fn begin_import(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("JSON", &["json"])
+            .pick_file()
+        else {
+            return;
+        };
+
+        let (entries, templates) = match std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| Ok((transfer::parse_import(&json)?, transfer::parse_templates(&json))))
+        {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                error!("Failed to read import file {}: {}", path.display(), e);
+                self.status_message = Some(trf("Import failed: {}", &[&e]));
+                return;
+            }
+        };
+        self.preview_import(path, entries);
+        if let Some(preview) = &mut self.import_preview {
+            preview.templates = templates;
+        }
+    }
+
+    #[cfg(windows)]
+    fn begin_putty_import(&mut self) {
+        let entries = match putty::read_registry() {
+            Ok(output) => putty::parse_sessions(&output),
+            Err(e) => {
+                error!("Failed to read PuTTY sessions: {}", e);
+                self.status_message = Some(e);
+                return;
+            }
+        };
+        if entries.is_empty() {
+            self.status_message = Some(tr("No PuTTY sessions with tunnels found").to_string());
+            return;
+        }
+        self.preview_import(PathBuf::from(putty::SESSIONS_KEY), entries);
+    }
+
+    /// Opens the import window, matching entries to existing tunnels by name.
+    fn preview_import(&mut self, source: PathBuf, entries: Vec<transfer::ImportEntry>) {
+        let mut candidates = Vec::new();
+        let mut invalid = Vec::new();
+        for entry in entries {
+            match entry {
+                transfer::ImportEntry::Valid(form) => {
+                    let existing_id = self
+                        .tunnels
+                        .iter()
+                        .find(|t| !t.deleted && t.name == form.name.trim())
+                        .map(|t| t.id);
+                    candidates.push(ImportCandidate {
+                        form: *form,
+                        existing_id,
+                        choice: ConflictChoice::Skip,
+                    });
+                }
+                transfer::ImportEntry::Invalid { position, name, reasons } => {
+                    let label = name.unwrap_or_else(|| "unnamed".to_string());
+                    invalid.push(format!("#{} {}: {}", position, label, reasons.join(", ")));
+                }
+            }
+        }
+
+        debug!(
+            "Import preview: {} candidates, {} invalid",
+            candidates.len(),
+            invalid.len()
+        );
+        self.import_preview = Some(ImportPreview {
+            source,
+            candidates,
+            invalid,
+            templates: Vec::new(),
+        });
+    }
+
+    /// Writes the previewed import as one batch of store changes.
+    /// Returns how many tunnels were written and how many of them need
+    /// their secret entered again.
+    fn apply_import(&mut self) -> Result<(usize, usize), String> {
+        let Some(preview) = &self.import_preview else {
+            return Ok((0, 0));
+        };
+
+        let mut taken: HashSet<String> = self.tunnels.iter().map(|t| t.name.clone()).collect();
+        let mut changes = Vec::new();
+        let mut overwritten = Vec::new();
+        for candidate in &preview.candidates {
+            let change = match (candidate.existing_id, candidate.choice) {
+                (Some(_), ConflictChoice::Skip) => continue,
+                (Some(id), ConflictChoice::Overwrite) => {
+                    overwritten.push(id);
+                    TunnelChange::Update(self.keep_metadata(candidate.form.to_tunnel(id)))
+                }
+                // New names, and a name used twice in the file, are kept
+                // apart like Keep both
+                (None, _) | (Some(_), ConflictChoice::KeepBoth) => {
+                    let mut form = candidate.form.clone();
+                    form.name = form.name.trim().to_string();
+                    if taken.contains(&form.name) {
+                        form.name = unique_name(&form.name, &taken);
+                    }
+                    taken.insert(form.name.clone());
+                    let mut tunnel = form.to_tunnel(TunnelId::default());
+                    tunnel.workspace_id = self.workspace;
+                    TunnelChange::Insert(tunnel)
+                }
+            };
+            changes.push(change);
+        }
+        let written = changes.len();
+        // Overwritten tunnels keep their own secret
+        let missing_secrets = preview
+            .candidates
+            .iter()
+            .filter(|c| c.form.needs_secret)
+            .filter(|c| c.existing_id.is_none() || c.choice == ConflictChoice::KeepBoth)
+            .count();
+        let source = preview.source.clone();
+        let templates = preview.templates.clone();
+        let store = self.store()?;
+        store
+            .apply(changes)
+            .map_err(|e| format!("Failed to import tunnels: {:#}", e))?;
+        for (name, definition) in &templates {
+            store
+                .save_template(name, definition)
+                .map_err(|e| format!("Failed to import template '{}': {:#}", name, e))?;
+        }
+
+        // Running tunnels keep their old settings until restarted
+        for id in overwritten {
+            if self.active_tunnels.contains_key(&id) {
+                self.restart_pending.insert(id);
+            }
+        }
+        info!(
+            "Imported {} tunnels and {} templates from {}",
+            written,
+            templates.len(),
+            source.display()
+        );
+        self.import_preview = None;
+        self.tunnels_dirty = true;
+        self.load_templates();
+        Ok((written, missing_secrets))
+    }
+
+    fn show_import_window(&mut self, ctx: &egui::Context) {
+        let mut should_import = false;
+        let mut should_close = false;
+
+        let running = &self.active_tunnels;
+        if let Some(preview) = &mut self.import_preview {
+            egui::Window::new(tr("Import Tunnels"))
+                .collapsible(false)
+                .default_width(340.0)
+                .show(ctx, |ui| {
+                    ui.weak(preview.source.display().to_string());
+                    ui.add_space(4.0);
+                    egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                        for (index, candidate) in preview.candidates.iter_mut().enumerate() {
+                            ui.horizontal(|ui| {
+                                ui.label(candidate.form.name.trim());
+                                ui.weak(format!(
+                                    "{} {}:{}",
+                                    candidate.form.ssh_server.trim(),
+                                    candidate.form.remote_ip.trim(),
+                                    candidate.form.remote_port
+                                ));
+                                if candidate.form.needs_secret {
+                                    ui.label("🔒").on_hover_text(tr(
+                                        "Secrets aren't exported; set it again from Edit after importing",
+                                    ));
+                                }
+                                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                                    if let Some(id) = candidate.existing_id {
+                                        if candidate.choice == ConflictChoice::Overwrite && running.contains_key(&id) {
+                                            ui.weak(tr("running; restart to apply"))
+                                                .on_hover_text(tr("The tunnel keeps its old settings until it is restarted"));
+                                        }
+                                        egui::ComboBox::from_id_salt(("import_choice", index))
+                                            .selected_text(tr(candidate.choice.label()))
+                                            .show_ui(ui, |ui| {
+                                                for choice in [
+                                                    ConflictChoice::Skip,
+                                                    ConflictChoice::Overwrite,
+                                                    ConflictChoice::KeepBoth,
+                                                ] {
+                                                    ui.selectable_value(&mut candidate.choice, choice, tr(choice.label()));
+                                                }
+                                            });
+                                        ui.colored_label(tone(ui, Tone::Warning), tr("name exists"));
+                                    } else {
+                                        ui.weak(tr("new"));
+                                    }
+                                });
+                            });
+                        }
+
+                        if !preview.templates.is_empty() {
+                            ui.separator();
+                            let names: Vec<&str> = preview.templates.iter().map(|(name, _)| name.as_str()).collect();
+                            ui.label(trf("Templates: {}", &[&names.join(", ")]))
+                                .on_hover_text(tr("Replaces templates with the same name"));
+                        }
+
+                        if !preview.invalid.is_empty() {
+                            ui.separator();
+                            ui.label(trf("{} entries will not be imported:", &[&preview.invalid.len()]));
+                            for reason in &preview.invalid {
+                                ui.colored_label(tone(ui, Tone::Bad), reason);
+                            }
+                        }
+                    });
+
+                    ui.add_space(8.0);
+                    ui.horizontal(|ui| {
+                        if ui.button(tr("Cancel")).clicked() {
+                            should_close = true;
+                        }
+                        let importable = preview
+                            .candidates
+                            .iter()
+                            .filter(|c| c.existing_id.is_none() || c.choice != ConflictChoice::Skip)
+                            .count();
+                        let enabled = importable > 0 || !preview.templates.is_empty();
+                        if ui
+                            .add_enabled(enabled, egui::Button::new(trf("Import {}", &[&importable])))
+                            .clicked()
+                        {
+                            should_import = true;
+                        }
+                    });
+                });
+        }
+
+        if should_import {
+            self.status_message = Some(match self.apply_import() {
+                Ok((count, 0)) => trf("Imported {} tunnels", &[&count]),
+                Ok((count, missing)) => trf(
+                    "Imported {} tunnels; set the secret again for {} of them from Edit",
+                    &[&count, &missing],
+                ),
+                Err(e) => {
+                    error!("Import failed: {}", e);
+                    trf("Import failed: {}", &[&e])
+                }
+            });
+        } else if should_close {
+            self.import_preview = None;
+        }
+    }
+
+    /// Adds a few made-up tunnels to the workspace, from the empty list.
+    fn create_examples(&mut self) {
+        let examples = [
+            ("Local MySQL", "db-server", 3306, "localhost", 3306),
+            ("Dev MongoDB", "dev-server", 27017, "mongodb", 27017),
+            ("Staging API", "staging", 8080, "api-internal", 80),
+        ];
+        let changes = examples
+            .iter()
+            .map(|(name, server, local_port, remote_ip, remote_port)| {
+                let form = NewTunnelForm {
+                    name: name.to_string(),
+                    ssh_server: server.to_string(),
+                    local_port: local_port.to_string(),
+                    remote_ip: remote_ip.to_string(),
+                    remote_port: remote_port.to_string(),
+                    ..NewTunnelForm::default()
+                };
+                let mut tunnel = form.to_tunnel(TunnelId::default());
+                tunnel.workspace_id = self.workspace;
+                TunnelChange::Insert(tunnel)
+            })
+            .collect();
+        let result = self
+            .store()
+            .and_then(|store| store.apply(changes).map_err(|e| format!("{:#}", e)));
+        match result {
+            Ok(()) => {
+                info!("Added {} example tunnels", examples.len());
+                self.tunnels_dirty = true;
+            }
+            Err(e) => {
+                error!("Failed to add example tunnels: {}", e);
+                self.status_message = Some(trf("Failed to add example tunnels: {}", &[&e]));
+            }
+        }
+    }
+
+    fn begin_ssh_config_import(&mut self) {
+        let Some(path) = ssh_config::default_path() else {
+            self.status_message = Some(tr("Could not find the home directory").to_string());
+            return;
+        };
+        if !path.exists() {
+            self.status_message = Some(trf("No SSH config at {}", &[&path.display()]));
+            return;
+        }
+        let forwards = match ssh_config::read_forwards(&path) {
+            Ok(forwards) => forwards,
+            Err(e) => {
+                error!("{}", e);
+                self.status_message = Some(e);
+                return;
+            }
+        };
+
+        let mut candidates = Vec::new();
+        let mut skipped = Vec::new();
+        for forward in forwards {
+            let forward = match forward {
+                ssh_config::ConfigForward::Local(forward) => forward,
+                ssh_config::ConfigForward::Unsupported { host, reason } => {
+                    skipped.push(format!("{}: {}", host, reason));
+                    continue;
+                }
+            };
+            let mut form = NewTunnelForm {
+                name: format!("{} {}", forward.host, forward.local_port),
+                ssh_server: forward.host.clone(),
+                local_port: forward.local_port.to_string(),
+                remote_ip: forward.remote_host.clone(),
+                remote_port: forward.remote_port.to_string(),
+                ..NewTunnelForm::default()
+            };
+            if let Some(address) = &forward.bind_address {
+                form.local_ip = address.clone();
+            }
+            if !form.validate() {
+                skipped.push(format!("{}: {}", form.name, form.errors().join(", ")));
+                continue;
+            }
+            let duplicate = self.tunnels.iter().any(|t| {
+                t.ssh_server == form.ssh_server
+                    && t.local_port == forward.local_port
+                    && t.remote_ip == form.remote_ip
+                    && t.remote_port == forward.remote_port
+            });
+            candidates.push(SshConfigCandidate {
+                form,
+                duplicate,
+                selected: !duplicate,
+            });
+        }
+
+        if candidates.is_empty() && skipped.is_empty() {
+            self.status_message = Some(trf("No forwards found in {}", &[&path.display()]));
+            return;
+        }
+        debug!(
+            "SSH config import: {} candidates, {} skipped",
+            candidates.len(),
+            skipped.len()
+        );
+        self.ssh_config_import = Some(SshConfigImport {
+            source: path,
+            candidates,
+            skipped,
+        });
+    }
+
+    fn apply_ssh_config_import(&mut self) -> Result<usize, String> {
+        let Some(import) = self.ssh_config_import.take() else {
+            return Ok(0);
+        };
+        let mut taken: HashSet<String> = self.tunnels.iter().map(|t| t.name.clone()).collect();
+        let mut changes = Vec::new();
+        for candidate in import.candidates.into_iter().filter(|c| c.selected) {
+            let mut form = candidate.form;
+            if taken.contains(form.name.trim()) {
+                form.name = unique_name(form.name.trim(), &taken);
+            }
+            taken.insert(form.name.clone());
+            let mut tunnel = form.to_tunnel(TunnelId::default());
+            tunnel.workspace_id = self.workspace;
+            changes.push(TunnelChange::Insert(tunnel));
+        }
+        let count = changes.len();
+        self.store()?
+            .apply(changes)
+            .map_err(|e| format!("Failed to import tunnels: {:#}", e))?;
+        info!("Imported {} tunnels from {}", count, import.source.display());
+        self.tunnels_dirty = true;
+        Ok(count)
+    }
+
+    /// Copies a tunnel's `Host` block to the clipboard, or appends it to a
+    /// config file the user picks.
+    fn share_as_ssh_config(&mut self, ctx: &egui::Context, id: TunnelId, append: bool) {
+        let Some(tunnel) = self.tunnels.iter().find(|t| t.id == id) else {
+            return;
+        };
+        let block = ssh_config::render_host(tunnel);
+        if !append {
+            ctx.copy_text(block);
+            self.status_message = Some(trf("Copied '{}' as ssh_config", &[&tunnel.name]));
+            return;
+        }
+
+        let mut dialog = rfd::FileDialog::new();
+        if let Some(dir) = ssh_config::default_path().as_deref().and_then(Path::parent) {
+            dialog = dialog.set_directory(dir);
+        }
+        let Some(path) = dialog.pick_file() else {
+            return;
+        };
+        let result = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| {
+                use std::io::Write;
+                write!(file, "\n{}", block)
+            });
+        self.status_message = Some(match result {
+            Ok(()) => {
+                info!("Appended '{}' to {}", tunnel.name, path.display());
+                trf("Appended '{}' to {}", &[&tunnel.name, &path.display()])
+            }
+            Err(e) => {
+                error!("Failed to append to {}: {}", path.display(), e);
+                trf("Failed to append to {}: {}", &[&path.display(), &e])
+            }
+        });
+    }
+
+    fn begin_service_export(&mut self, id: TunnelId) {
+        let Some(tunnel) = self.tunnels.iter().find(|t| t.id == id) else {
+            return;
+        };
+        let path = service::default_path(tunnel)
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+        self.service_export = Some(ServiceExport {
+            tunnel_id: id,
+            path,
+            activate: true,
+        });
+    }
+
+    fn show_service_export_dialog(&mut self, ctx: &egui::Context) {
+        let Some(export) = &mut self.service_export else {
+            return;
+        };
+        let mut confirmed = false;
+        let mut cancelled = false;
+
+        egui::Modal::new(egui::Id::new("service_export")).show(ctx, |ui| {
+            if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                cancelled = true;
+            }
+            ui.set_max_width(420.0);
+            ui.heading(tr("Export as service"));
+            ui.label(tr("Runs this tunnel in the background, even when Onigiri is closed."));
+            ui.add_space(4.0);
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut export.path).desired_width(300.0));
+                if ui.button(tr("Browse…")).clicked() {
+                    let current = PathBuf::from(&export.path);
+                    let mut dialog = rfd::FileDialog::new();
+                    if let Some(dir) = current.parent() {
+                        dialog = dialog.set_directory(dir);
+                    }
+                    if let Some(name) = current.file_name() {
+                        dialog = dialog.set_file_name(name.to_string_lossy());
+                    }
+                    if let Some(path) = dialog.save_file() {
+                        export.path = path.display().to_string();
+                    }
+                }
+            });
+            ui.checkbox(&mut export.activate, tr("Load and start it now"));
+            ui.add_space(8.0);
+            ui.horizontal(|ui| {
+                if ui.button(tr("Cancel")).clicked() {
+                    cancelled = true;
+                }
+                let can_export = !export.path.trim().is_empty();
+                if ui.add_enabled(can_export, egui::Button::new(tr("Export"))).clicked() {
+                    confirmed = true;
+                }
+            });
+        });
+
+        if confirmed {
+            if let Some(export) = self.service_export.take() {
+                self.export_service(export);
+            }
+        } else if cancelled {
+            self.service_export = None;
+        }
+    }
+
+    fn export_service(&mut self, export: ServiceExport) {
+        let Some(tunnel) = self.tunnels.iter().find(|t| t.id == export.tunnel_id) else {
+            return;
+        };
+        let path = PathBuf::from(export.path.trim());
+        let result = service::render(tunnel)
+            .and_then(|service| {
+                path.parent()
+                    .map_or(Ok(()), std::fs::create_dir_all)
+                    .and_then(|()| std::fs::write(&path, service))
+                    .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+            })
+            .and_then(|()| {
+                if export.activate {
+                    service::activate(tunnel, &path)
+                } else {
+                    Ok(())
+                }
+            });
+        self.status_message = Some(match result {
+            Ok(()) => {
+                info!("Exported '{}' as a service to {}", tunnel.name, path.display());
+                if export.activate {
+                    trf("'{}' now runs as a service", &[&tunnel.name])
+                } else {
+                    trf("Wrote service for '{}' to {}", &[&tunnel.name, &path.display()])
+                }
+            }
+            Err(e) => {
+                error!("Service export for '{}' failed: {}", tunnel.name, e);
+                e
+            }
+        });
+    }
+
+    fn show_ssh_config_import_window(&mut self, ctx: &egui::Context) {
+        let mut should_import = false;
+        let mut should_close = false;
+
+        if let Some(import) = &mut self.ssh_config_import {
+            egui::Window::new(tr("Import from SSH config"))
+                .collapsible(false)
+                .default_width(340.0)
+                .show(ctx, |ui| {
+                    ui.weak(import.source.display().to_string());
+                    ui.add_space(4.0);
+                    egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                        for candidate in &mut import.candidates {
+                            ui.horizontal(|ui| {
+                                ui.checkbox(&mut candidate.selected, candidate.form.name.trim());
+                                ui.weak(format!(
+                                    "{}:{}",
+                                    candidate.form.remote_ip, candidate.form.remote_port
+                                ));
+                                if candidate.duplicate {
+                                    ui.colored_label(tone(ui, Tone::Warning), tr("duplicate"));
+                                }
+                            });
+                        }
+
+                        if !import.skipped.is_empty() {
+                            ui.separator();
+                            ui.label(trf("{} forwards can't be imported:", &[&import.skipped.len()]));
+                            for reason in &import.skipped {
+                                ui.colored_label(tone(ui, Tone::Bad), reason);
+                            }
+                        }
+                    });
+
+                    ui.add_space(8.0);
+                    ui.horizontal(|ui| {
+                        if ui.button(tr("Cancel")).clicked() {
+                            should_close = true;
+                        }
+                        let selected = import.candidates.iter().filter(|c| c.selected).count();
+                        if ui
+                            .add_enabled(selected > 0, egui::Button::new(trf("Import {}", &[&selected])))
+                            .clicked()
+                        {
+                            should_import = true;
+                        }
+                    });
+                });
+        }
+
+        if should_import {
+            self.status_message = Some(match self.apply_ssh_config_import() {
+                Ok(count) => trf("Imported {} tunnels from the SSH config", &[&count]),
+                Err(e) => {
+                    error!("SSH config import failed: {}", e);
+                    trf("Import failed: {}", &[&e])
+                }
+            });
+        } else if should_close {
+            self.ssh_config_import = None;
+        }
+    }
+
+    /// Counts the deleted tunnels and asks before purging them.
+    fn begin_empty_trash(&mut self) {
+        let count = self.store().and_then(|store| {
+            store
+                .deleted_count()
+                .map_err(|e| trf("Failed to count deleted tunnels: {}", &[&format!("{:#}", e)]))
+        });
+        match count {
+            Ok(0) => self.status_message = Some(tr("Trash is already empty").to_string()),
+            Ok(count) => self.confirm_empty_trash = Some(count),
+            Err(e) => {
+                error!("{}", e);
+                self.status_message = Some(e);
+            }
+        }
+    }
+
+    fn set_theme(&mut self, ctx: &egui::Context, theme: Theme) {
+        self.theme = theme;
+        ctx.set_theme(theme.preference());
+        if let Some(settings) = &self.settings {
+            if let Err(e) = settings.set(THEME_SETTING, theme.key()) {
+                error!("Failed to save the theme: {:#}", e);
+            }
+        }
+    }
+
+    fn set_language(&mut self, language: Language) {
+        i18n::set_language(language);
+        if let Some(settings) = &self.settings {
+            if let Err(e) = settings.set(LANGUAGE_SETTING, language.key()) {
+                error!("Failed to save the language: {:#}", e);
+            }
+        }
+    }
+
+    fn set_log_level(&mut self, level: log::LevelFilter) {
+        logging::set_level(level);
+        info!("Log level set to {}", level);
+        if let Some(settings) = &self.settings {
+            if let Err(e) = settings.set(logging::SETTING_KEY, &level.as_str().to_ascii_lowercase()) {
+                error!("Failed to save the log level: {:#}", e);
+            }
+        }
+    }
+
+    fn set_quit_behavior(&mut self, behavior: QuitBehavior) {
+        self.quit_behavior = behavior;
+        if let Some(settings) = &self.settings {
+            if let Err(e) = settings.set(QUIT_SETTING, behavior.key()) {
+                error!("Failed to save the quit setting: {:#}", e);
+            }
+        }
+    }
+
+    fn set_density(&mut self, density: Density) {
+        self.density = density;
+        if let Some(settings) = &self.settings {
+            if let Err(e) = settings.set(DENSITY_SETTING, density.key()) {
+                error!("Failed to save the list density: {:#}", e);
+            }
+        }
+    }
+
+    fn open_terminal(&mut self, id: TunnelId) {
+        let Some(tunnel) = self.tunnels.iter().find(|t| t.id == id) else {
+            return;
+        };
+        let tunnel = match tunnel.expanded() {
+            Ok(tunnel) => tunnel,
+            Err(e) => {
+                self.report_error(Some(id), e.to_string());
+                return;
+            }
+        };
+        info!("Opening a terminal on {}", tunnel.ssh_server);
+        if let Err(e) = terminal::open(&self.terminal_template, &tunnel) {
+            self.report_error(Some(id), e);
+        }
+    }
+
+    fn copy_share_link(&mut self, ctx: &egui::Context, id: TunnelId) {
+        let Some(tunnel) = self.tunnels.iter().find(|t| t.id == id) else {
+            return;
+        };
+        match transfer::share_link(tunnel) {
+            Ok(link) => {
+                ctx.copy_text(link);
+                self.status_message = Some(trf("Copied a share link for '{}'", &[&tunnel.name]));
+            }
+            Err(e) => {
+                let name = tunnel.name.clone();
+                self.report_error(Some(id), format!("Failed to make a share link for {}: {}", name, e));
+            }
+        }
+    }
+
+    /// Previews a pasted share link before adding it like the Add Tunnel
+    /// window would.
+    fn show_paste_dialog(&mut self, ctx: &egui::Context) {
+        let Some(text) = &mut self.paste_dialog else {
+            return;
+        };
+        let mut add = None;
+        let mut edit_first = None;
+        let mut cancelled = false;
+
+        egui::Modal::new(egui::Id::new("paste_dialog")).show(ctx, |ui| {
+            if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                cancelled = true;
+            }
+            ui.set_max_width(420.0);
+            ui.heading(tr("Paste tunnel"));
+            ui.label(tr("Paste a link made with Copy share link."));
+            ui.add(
+                egui::TextEdit::singleline(text)
+                    .hint_text(format!("{}v1;…", transfer::SHARE_PREFIX))
+                    .desired_width(400.0)
+                    .code_editor(),
+            );
+            let parsed = (!text.trim().is_empty()).then(|| transfer::parse_share_link(text));
+            let mut problems = Vec::new();
+            match &parsed {
+                Some(Ok(form)) => {
+                    problems = form.problems(&self.tunnels, &self.active_tunnels, None);
+                    ui.add_space(4.0);
+                    egui::Grid::new("paste_preview").num_columns(2).show(ui, |ui| {
+                        ui.label(tr("Name:"));
+                        ui.strong(&form.name);
+                        ui.end_row();
+                        ui.label(tr("SSH Server:"));
+                        ui.monospace(&form.ssh_server);
+                        ui.end_row();
+                        ui.label(tr("Forward:"));
+                        ui.monospace(format!(
+                            "{}:{} -> {}:{}",
+                            form.local_ip, form.local_port, form.remote_ip, form.remote_port
+                        ));
+                        ui.end_row();
+                        if !form.group.is_empty() {
+                            ui.label(tr("Group:"));
+                            ui.label(&form.group);
+                            ui.end_row();
+                        }
+                        if !form.tags.is_empty() {
+                            ui.label(tr("Tags:"));
+                            ui.label(form.tags.join(", "));
+                            ui.end_row();
+                        }
+                        if !form.notes.is_empty() {
+                            ui.label(tr("Notes:"));
+                            ui.label(&form.notes);
+                            ui.end_row();
+                        }
+                    });
+                    for problem in &problems {
+                        ui.colored_label(tone(ui, Tone::Warning), problem);
+                    }
+                }
+                Some(Err(e)) => {
+                    ui.colored_label(tone(ui, Tone::Bad), e);
+                }
+                None => {}
+            }
+            ui.add_space(8.0);
+            ui.horizontal(|ui| {
+                if ui.button(tr("Cancel")).clicked() {
+                    cancelled = true;
+                }
+                let form = parsed.and_then(Result::ok);
+                if ui.add_enabled(form.is_some(), egui::Button::new(tr("Edit first…"))).clicked() {
+                    edit_first = form.clone();
+                }
+                let button = egui::Button::new(tr("Add"));
+                let response = ui.add_enabled(form.is_some() && problems.is_empty(), button);
+                if response.on_disabled_hover_text(problems.join("\n")).clicked() {
+                    add = form;
+                }
+            });
+        });
+
+        if let Some(mut form) = add {
+            self.paste_dialog = None;
+            if form.validate_with(&self.tunnels, &self.active_tunnels, None) {
+                self.new_tunnel = form;
+                self.submit_new_tunnel();
+                // A failed save is shown where it can be retried
+                self.show_new_tunnel_window = self.new_tunnel.save_error.is_some();
+            }
+        } else if let Some(form) = edit_first {
+            self.paste_dialog = None;
+            self.new_tunnel = form;
+            self.show_new_tunnel_window = true;
+        } else if cancelled {
+            self.paste_dialog = None;
+        }
+    }
+
+    fn show_terminal_dialog(&mut self, ctx: &egui::Context) {
+        let Some(template) = &mut self.terminal_dialog else {
+            return;
+        };
+        let mut save = false;
+        let mut cancelled = false;
+
+        egui::Modal::new(egui::Id::new("terminal_dialog")).show(ctx, |ui| {
+            if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                cancelled = true;
+            }
+            ui.set_max_width(460.0);
+            ui.heading(tr("Terminal command"));
+            ui.label(trf(
+                "Run by Open terminal; {} is replaced by the ssh command.",
+                &[&terminal::PLACEHOLDER],
+            ));
+            ui.add(egui::TextEdit::multiline(template).desired_rows(2).desired_width(440.0).code_editor());
+            if !template.contains(terminal::PLACEHOLDER) {
+                ui.colored_label(tone(ui, Tone::Warning), format!("{} is missing", terminal::PLACEHOLDER));
+            }
+            ui.add_space(8.0);
+            ui.horizontal(|ui| {
+                if ui.button(tr("Cancel")).clicked() {
+                    cancelled = true;
+                }
+                if ui.button(tr("Reset to default")).clicked() {
+                    *template = terminal::default_template().to_string();
+                }
+                if ui.add_enabled(!template.trim().is_empty(), egui::Button::new(tr("Save"))).clicked() {
+                    save = true;
+                }
+            });
+        });
+
+        if save {
+            let template = self.terminal_dialog.take().unwrap_or_default().trim().to_string();
+            if let Some(settings) = &self.settings {
+                let result = if template == terminal::default_template() {
+                    settings.remove(terminal::SETTING_KEY)
+                } else {
+                    settings.set(terminal::SETTING_KEY, &template)
+                };
+                if let Err(e) = result {
+                    error!("Failed to save the terminal command: {:#}", e);
+                    self.status_message = Some(trf("The terminal command is only kept until Onigiri closes: {}", &[&format!("{:#}", e)]));
+                }
+            }
+            self.terminal_template = template;
+        } else if cancelled {
+            self.terminal_dialog = None;
+        }
+    }
+
+    fn set_show_subtitles(&mut self, show: bool) {
+        self.show_subtitles = show;
+        if let Some(settings) = &self.settings {
+            if let Err(e) = settings.set(SUBTITLE_SETTING, &show.to_string()) {
+                error!("Failed to save the server line setting: {:#}", e);
+            }
+        }
+    }
+
+    fn set_keep_running(&mut self, keep_running: bool) {
+        self.keep_running = keep_running;
+        if let Some(settings) = &self.settings {
+            if let Err(e) = settings.set(KEEP_RUNNING_SETTING, &keep_running.to_string()) {
+                error!("Failed to save the close window setting: {:#}", e);
+            }
+        }
+    }
+
+    /// Gets the window out of the way while tunnels keep running. Without a
+    /// tray icon to bring it back, it's only minimized.
+    fn hide_window(&mut self, ctx: &egui::Context) {
+        #[cfg(any(target_os = "macos", windows))]
+        if self.tray.is_some() {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Visible(false));
+            return;
+        }
+        ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(true));
+    }
+
+    /// Quits, first asking when that would stop running tunnels.
+    fn request_quit(&mut self, ctx: &egui::Context) {
+        if self.active_tunnels.is_empty() {
+            self.quit(ctx, false);
+            return;
+        }
+        match self.quit_behavior {
+            QuitBehavior::Stop => self.quit(ctx, false),
+            QuitBehavior::Keep => self.quit(ctx, true),
+            QuitBehavior::Ask => {
+                self.confirm_quit = true;
+                // Quit may come from the tray while the window is hidden
+                ctx.send_viewport_cmd(egui::ViewportCommand::Visible(true));
+                ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+            }
+        }
+    }
+
+    /// Closes the app for real; `Drop` stops the tunnels unless they are
+    /// left running.
+    fn quit(&mut self, ctx: &egui::Context, leave_running: bool) {
+        info!("Quitting{}", if leave_running { ", leaving tunnels running" } else { "" });
+        self.quitting = true;
+        self.leave_running = leave_running;
+        ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+    }
+
+    fn show_quit_dialog(&mut self, ctx: &egui::Context) {
+        let mut confirmed = None;
+        let mut cancelled = false;
+        let mut names: Vec<&str> = self.active_tunnels.values().map(|info| info.name.as_str()).collect();
+        names.sort_unstable();
+
+        egui::Modal::new(egui::Id::new("confirm_quit")).show(ctx, |ui| {
+            if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                cancelled = true;
+            }
+            ui.set_max_width(360.0);
+            ui.heading(trf("Quit {}", &[&APP_NAME]));
+            ui.label(tr("These tunnels are still running:"));
+            egui::ScrollArea::vertical().max_height(160.0).show(ui, |ui| {
+                for name in &names {
+                    ui.label(format!("• {}", name));
+                }
+            });
+            ui.add_space(8.0);
+            ui.horizontal(|ui| {
+                if ui.button(tr("Cancel")).clicked() {
+                    cancelled = true;
+                }
+                if ui.button(tr("Keep them running and quit")).clicked() {
+                    confirmed = Some(true);
+                }
+                if ui.button(tr("Stop tunnels and quit")).clicked() {
+                    confirmed = Some(false);
+                }
+            });
+        });
+
+        if let Some(leave_running) = confirmed {
+            self.confirm_quit = false;
+            self.quit(ctx, leave_running);
+        } else if cancelled {
+            self.confirm_quit = false;
+        }
+    }
+
+    fn show_empty_trash_dialog(&mut self, ctx: &egui::Context) {
+        let Some(count) = self.confirm_empty_trash else {
+            return;
+        };
+        let mut confirmed = false;
+        let mut cancelled = false;
+
+        egui::Modal::new(egui::Id::new("empty_trash")).show(ctx, |ui| {
+            if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                cancelled = true;
+            }
+            ui.set_max_width(320.0);
+            ui.heading(tr("Empty trash"));
+            ui.label(if count == 1 {
+                trf("Permanently remove {} deleted tunnel? This cannot be undone.", &[&count])
+            } else {
+                trf("Permanently remove {} deleted tunnels? This cannot be undone.", &[&count])
+            });
+            ui.add_space(8.0);
+            ui.horizontal(|ui| {
+                if ui.button(tr("Cancel")).clicked() {
+                    cancelled = true;
+                }
+                if ui.button(trf("Remove {}", &[&count])).clicked() {
+                    confirmed = true;
+                }
+            });
+        });
+
+        if confirmed {
+            self.confirm_empty_trash = None;
+            let result = self.store().and_then(|store| {
+                store
+                    .purge_deleted(None)
+                    .map_err(|e| trf("Failed to empty trash: {}", &[&format!("{:#}", e)]))
+            });
+            self.status_message = Some(match result {
+                Ok(purged) => trf("Removed {} deleted tunnels", &[&purged]),
+                Err(e) => {
+                    error!("{}", e);
+                    e
+                }
+            });
+        } else if cancelled {
+            self.confirm_empty_trash = None;
+        }
+    }
+
+    fn backup_database(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .set_file_name(storage::backup_file_name(unix_now()))
+            .add_filter("SQLite database", &["db"])
+            .save_file()
+        else {
+            return;
+        };
+
+        let result = self
+            .store()
+            .and_then(|store| store.backup(&path).map_err(|e| format!("{:#}", e)));
+        self.status_message = Some(match result {
+            Ok(()) => trf("Backed up to {}", &[&path.display()]),
+            Err(e) => {
+                error!("Failed to back up: {}", e);
+                trf("Backup failed: {}", &[&e])
+            }
+        });
+    }
+
+    fn begin_restore_backup(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("SQLite database", &["db"])
+            .pick_file()
+        else {
+            return;
+        };
+
+        match SqliteStore::check_backup(&path) {
+            Ok(count) => self.confirm_restore_backup = Some((path, count)),
+            Err(e) => {
+                error!("Not restoring {}: {:#}", path.display(), e);
+                self.status_message = Some(trf("Restore failed: {}", &[&format!("{:#}", e)]));
+            }
+        }
+    }
+
+    fn show_restore_backup_dialog(&mut self, ctx: &egui::Context) {
+        let Some((path, count)) = &self.confirm_restore_backup else {
+            return;
+        };
+        let mut confirmed = false;
+        let mut cancelled = false;
+
+        egui::Modal::new(egui::Id::new("restore_backup")).show(ctx, |ui| {
+            if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                cancelled = true;
+            }
+            ui.set_max_width(360.0);
+            ui.heading(tr("Restore from backup"));
+            ui.label(if *count == 1 {
+                trf(
+                    "Replace all tunnels with the 1 tunnel in {}? Running tunnels will be stopped.",
+                    &[&path.display()],
+                )
+            } else {
+                trf(
+                    "Replace all tunnels with the {} tunnels in {}? Running tunnels will be stopped.",
+                    &[count, &path.display()],
+                )
+            });
+            ui.add_space(8.0);
+            ui.horizontal(|ui| {
+                if ui.button(tr("Cancel")).clicked() {
+                    cancelled = true;
+                }
+                if ui.button(tr("Restore")).clicked() {
+                    confirmed = true;
+                }
+            });
+        });
+
+        if confirmed {
+            if let Some((path, _)) = self.confirm_restore_backup.take() {
+                self.restore_backup(&path);
+            }
+        } else if cancelled {
+            self.confirm_restore_backup = None;
+        }
+    }
+
+    fn restore_backup(&mut self, path: &Path) {
+        self.stop_all_tunnels();
+        let result = self
+            .store()
+            .and_then(|store| store.restore(path).map_err(|e| format!("{:#}", e)));
+        self.status_message = Some(match result {
+            Ok(()) => trf("Restored tunnels from {}", &[&path.display()]),
+            Err(e) => {
+                error!("Failed to restore {}: {}", path.display(), e);
+                trf("Restore failed: {}", &[&e])
+            }
+        });
+        self.load_workspaces();
+        self.load_tunnels();
+        self.load_templates();
+    }
+
+    fn stop_all_tunnels(&mut self) {
+        for id in self.tunnels.iter().map(|t| t.id).collect::<Vec<_>>() {
+            self.cancel_start(id);
+        }
+        let running: Vec<TunnelId> = self.active_tunnels.keys().copied().collect();
+        for id in running {
+            if let Err(e) = self.toggle_tunnel(id) {
+                error!("Failed to stop tunnel {}: {}", id, e);
+            }
+        }
+    }
+
+    /// Stores a tunnel's secret in the credential store, or clears it when
+    /// `secret` is None. The store only records the entry's reference.
+    fn set_tunnel_secret(&mut self, id: TunnelId, secret: Option<String>) -> Result<(), String> {
+        let existing = self
+            .tunnels
+            .iter()
+            .find(|t| t.id == id)
+            .and_then(|t| t.secret_ref.clone());
+        let reference = match secret {
+            Some(secret) => {
+                let reference = existing.unwrap_or_else(|| secrets::new_reference(id));
+                secrets::set(&reference, &secret)?;
+                Some(reference)
+            }
+            None => {
+                if let Some(reference) = &existing {
+                    // An entry removed outside Onigiri shouldn't block clearing
+                    if let Err(e) = secrets::clear(reference) {
+                        warn!("Failed to remove secret {}: {}", reference, e);
+                    }
+                }
+                None
+            }
+        };
+        self.store()?
+            .set_secret_ref(id, reference.clone())
+            .map_err(|e| format!("Failed to save tunnel: {:#}", e))?;
+        if let Some((_, form)) = &mut self.edit_tunnel {
+            form.secret_ref = reference;
+            form.secret_input.clear();
+        }
+        self.tunnels_dirty = true;
+        Ok(())
+    }
+
+    /// Ticks or unticks a row; with `range`, ticks every listed row from the
+    /// last one clicked to this one.
+    fn select_row(&mut self, id: TunnelId, range: bool, order: &[TunnelId]) {
+        match self.selection_anchor.filter(|_| range) {
+            Some(anchor) => self.selected.extend(selection_range(order, anchor, id)),
+            None => {
+                if !self.selected.remove(&id) {
+                    self.selected.insert(id);
+                }
+            }
+        }
+        self.selection_anchor = Some(id);
+    }
+
+    /// The selected tunnels that still exist, in list order.
+    fn selection(&self) -> Vec<TunnelId> {
+        self.tunnels
+            .iter()
+            .filter(|t| !t.deleted && self.selected.contains(&t.id))
+            .map(|t| t.id)
+            .collect()
+    }
+
+    fn apply_bulk_action(&mut self, action: BulkAction, ctx: &egui::Context) {
+        let ids = self.selection();
+        match action {
+            BulkAction::Start => self.start_many(ids.into_iter().collect(), ctx),
+            BulkAction::Stop => self.stop_many(ids.into_iter().collect()),
+            BulkAction::Delete => self.confirm_bulk_delete = Some(ids),
+            BulkAction::AddTag(tag) => self.update_many(ids, tr("Tagged"), |tunnel| {
+                if !tunnel.tags.contains(&tag) {
+                    tunnel.tags.push(tag.clone());
+                }
+            }),
+            BulkAction::MoveToGroup(group) => self.update_many(ids, tr("Moved"), |tunnel| tunnel.group = group.clone()),
+            BulkAction::Clear => {
+                self.selected.clear();
+                self.selection_anchor = None;
+            }
+        }
+    }
+
+    /// Saves `change` to each of `ids` and reports how many went through.
+    fn update_many(&mut self, ids: Vec<TunnelId>, verb: &str, change: impl Fn(&mut Tunnel)) {
+        let mut updated = 0;
+        let mut failures = Vec::new();
+        for id in ids {
+            let Some(mut tunnel) = self.tunnels.iter().find(|t| t.id == id).cloned() else {
+                continue;
+            };
+            change(&mut tunnel);
+            let name = tunnel.name.clone();
+            let result = self
+                .store()
+                .and_then(|store| store.update(tunnel).map_err(|e| format!("{:#}", e)));
+            match result {
+                Ok(()) => updated += 1,
+                Err(e) => {
+                    error!("Failed to update tunnel {}: {}", id, e);
+                    failures.push(format!("{} ({})", name, e));
+                }
+            }
+        }
+        self.tunnels_dirty = true;
+        self.status_message = Some(bulk_summary(verb, updated, &failures));
+    }
+
+    fn delete_many(&mut self, ids: Vec<TunnelId>) {
+        let mut deleted = 0;
+        let mut failures = Vec::new();
+        for id in ids {
+            let name = self.tunnels.iter().find(|t| t.id == id).map(|t| t.name.clone());
+            match self.delete_tunnel(id) {
+                Ok(()) => deleted += 1,
+                Err(e) => {
+                    error!("Failed to delete tunnel {}: {}", id, e);
+                    failures.push(format!("{} ({})", name.unwrap_or_else(|| id.to_string()), e));
+                }
+            }
+        }
+        self.selected.clear();
+        self.selection_anchor = None;
+        self.status_message = Some(bulk_summary(tr("Deleted"), deleted, &failures));
+    }
+
+    fn show_bulk_delete_dialog(&mut self, ctx: &egui::Context) {
+        let Some(ids) = &self.confirm_bulk_delete else {
+            return;
+        };
+        let names: Vec<&str> = self
+            .tunnels
+            .iter()
+            .filter(|t| ids.contains(&t.id))
+            .map(|t| t.name.as_str())
+            .collect();
+        let mut confirmed = false;
+        let mut cancelled = false;
+
+        egui::Modal::new(egui::Id::new("bulk_delete")).show(ctx, |ui| {
+            if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                cancelled = true;
+            }
+            ui.set_max_width(320.0);
+            ui.heading(tr("Delete tunnels"));
+            ui.label(if names.len() == 1 {
+                trf("Move {} tunnel to the trash?", &[&names.len()])
+            } else {
+                trf("Move {} tunnels to the trash?", &[&names.len()])
+            });
+            egui::ScrollArea::vertical().max_height(160.0).show(ui, |ui| {
+                for name in &names {
+                    ui.label(format!("• {}", name));
+                }
+            });
+            ui.add_space(8.0);
+            ui.horizontal(|ui| {
+                if ui.button(tr("Cancel")).clicked() {
+                    cancelled = true;
+                }
+                if ui.button(trf("Delete {}", &[&names.len()])).clicked() {
+                    confirmed = true;
+                }
+            });
+        });
+
+        if confirmed {
+            if let Some(ids) = self.confirm_bulk_delete.take() {
+                self.delete_many(ids);
+            }
+        } else if cancelled {
+            self.confirm_bulk_delete = None;
+        }
+    }
+
+    /// A storage failure from a tunnel action, which needs acknowledging
+    /// since the change it was making didn't happen.
+    fn show_database_error(&mut self, ctx: &egui::Context) {
+        let Some(message) = &self.database_error else {
+            return;
+        };
+        let mut dismissed = false;
+        egui::Modal::new(egui::Id::new("database_error")).show(ctx, |ui| {
+            ui.set_max_width(360.0);
+            ui.heading(tr("Couldn't save the change"));
+            ui.label(message);
+            ui.add_space(4.0);
+            ui.weak(tr("Another program may be using the tunnel database. Try again in a moment."));
+            ui.add_space(8.0);
+            dismissed = ui.button(tr("OK")).clicked() || ui.input(|i| i.key_pressed(egui::Key::Escape));
+        });
+        if dismissed {
+            self.database_error = None;
+        }
+    }
+
+    fn delete_tunnel(&mut self, id: TunnelId) -> Result<(), OnigiriError> {
+        debug!("Marking tunnel {} as deleted", id);
+        if !self.tunnels.iter().any(|t| t.id == id && !t.deleted) {
+            return Err(OnigiriError::NotFound(id));
+        }
+
+        self.stop_running(id, "Deleted");
+        self.cancel_start(id);
+
+        self.store()
+            .map_err(OnigiriError::Database)?
+            .delete(id)
+            .map_err(|e| e.context("Failed to delete tunnel"))?;
+
+        if let Some(tunnel) = self.tunnels.iter_mut().find(|t| t.id == id) {
+            tunnel.deleted = true;
+            self.list_cache.invalidate();
+            debug!("Tunnel {} marked as deleted", id);
+        }
+
+        Ok(())
+    }
+
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.track_window(ctx);
+
+        if ctx.input(|i| i.viewport().close_requested()) {
+            if self.quitting {
+                info!("Window close requested");
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                return;
+            }
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            if self.keep_running {
+                info!("Window closed, tunnels keep running in the background");
+                self.hide_window(ctx);
+            } else {
+                self.request_quit(ctx);
+            }
+        }
+
+        // SIGTERM can't wait for an answer, so asking stops the tunnels.
+        // Cleaning up now rather than in Drop covers a logout that kills
+        // the process before the window has closed.
+        if !RUNNING.load(Ordering::SeqCst) && !self.quitting {
+            info!("Terminated");
+            self.quit(ctx, self.quit_behavior == QuitBehavior::Keep);
+            self.shut_down();
+        }
+
+        let quit_shortcut = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Q);
+        if ctx.input_mut(|i| i.consume_shortcut(&quit_shortcut)) {
+            self.request_quit(ctx);
+        }
+        let logs_shortcut = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::L);
+        if ctx.input_mut(|i| i.consume_shortcut(&logs_shortcut)) {
+            self.show_logs = !self.show_logs;
+        }
+
+        // Pasting a share link onto the list, rather than into a field
+        if self.paste_dialog.is_none() && ctx.memory(|m| m.focused().is_none()) {
+            let link = ctx.input(|i| {
+                i.events.iter().find_map(|event| match event {
+                    egui::Event::Paste(text) if transfer::is_share_link(text) => Some(text.trim().to_string()),
+                    _ => None,
+                })
+            });
+            if link.is_some() {
+                self.paste_dialog = link;
+            }
+        }
+
+        // Nothing below is safe to run without usable storage
+        if let Some(message) = &self.storage_error {
+            let mut retry = false;
+            let mut dismiss = false;
+            egui::CentralPanel::default().show(ctx, |_ui| {});
+            egui::Modal::new(egui::Id::new("storage_error")).show(ctx, |ui| {
+                ui.set_max_width(360.0);
+                ui.heading(tr("Storage error"));
+                ui.label(message);
+                ui.add_space(4.0);
+                ui.label(tr("Check that the data folder exists and is writable, then retry."));
+                ui.add_space(8.0);
+                ui.horizontal(|ui| {
+                    retry = ui.button(tr("Retry")).clicked();
+                    if ui.button(tr("Open data folder")).clicked() {
+                        match storage::data_dir() {
+                            Ok(dir) => open_folder(&dir),
+                            Err(e) => error!("Failed to find the data folder: {:#}", e),
+                        }
+                    }
+                    if ui
+                        .button(tr("Continue"))
+                        .on_hover_text(tr("Use an empty tunnel list; nothing will be saved"))
+                        .clicked()
+                    {
+                        dismiss = true;
+                    }
+                    if ui.button(tr("Quit")).clicked() {
+                        ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                    }
+                });
+            });
+            if retry {
+                self.retry_storage();
+            } else if dismiss {
+                warn!("Continuing without usable storage");
+                self.storage_error = None;
+            }
+            return;
+        }
+        
+        if let Some(tunnels) = &self.store_migration {
+            let count = tunnels.len();
+            let mut choice = None;
+            egui::Modal::new(egui::Id::new("store_migration")).show(ctx, |ui| {
+                ui.set_max_width(360.0);
+                ui.heading(tr("New tunnel file"));
+                ui.label(trf(
+                    "The database has {} tunnels. Copy them into the new tunnel file?",
+                    &[&count],
+                ));
+                ui.add_space(8.0);
+                ui.horizontal(|ui| {
+                    if ui.button(trf("Copy {} tunnels", &[&count])).clicked() {
+                        choice = Some(true);
+                    }
+                    if ui.button(tr("Start empty")).clicked() {
+                        choice = Some(false);
+                    }
+                });
+            });
+            if let Some(copy) = choice {
+                if let Err(e) = self.finish_store_migration(copy) {
+                    error!("Failed to set up tunnel file: {}", e);
+                    self.status_message = Some(e);
+                }
+            }
+        }
+
+        self.update_tunnel_status(ctx);
+        self.retry_reconnects(ctx);
+        self.process_start_queue(ctx);
+        self.check_idle_tunnels(ctx);
+        self.collect_test_results();
+        self.collect_pre_start_results();
+        self.verify_started_tunnels(ctx);
+        self.collect_hook_results();
+        // "5 minutes ago" and uptimes change without any input. Threads
+        // wake the window themselves when they have news.
+        ctx.request_repaint_after(CLOCK_REPAINT_INTERVAL);
+
+        // Collect all the data we need upfront
+        #[derive(Clone)]
+        struct TunnelDisplayData {
+            id: TunnelId,
+            name: String,
+            is_active: bool,
+            state: TunnelState,
+            // When and why it last died unexpectedly, while marked
+            died: Option<(i64, String)>,
+            is_queued: bool,
+            is_expanded: bool,
+            ssh_server: String,
+            local_port: u16,
+            remote_ip: String,
+            remote_port: u16,
+            pid: Option<u32>,
+            idle_remaining: Option<Duration>,
+            idle_stopped: Option<Duration>,
+            tags: Vec<String>,
+            group: Option<String>,
+            last_started_at: Option<i64>,
+            created_at: Option<i64>,
+            updated_at: Option<i64>,
+            timestamps_approximate: bool,
+            pinned: bool,
+            notes: String,
+            color: Option<ColorLabel>,
+            is_testing: bool,
+            test_result: Option<TestOutcome>,
+            start_error: Option<String>,
+            // Another Onigiri tunnel holding this tunnel's local port
+            port_held_by: Option<(TunnelId, String)>,
+            command: String,
+            // The command with variables expanded, for tunnels that use them
+            resolved: Option<Result<String, String>>,
+            uptime: Option<Duration>,
+            started_at: Option<i64>,
+            last_check: Option<String>,
+            last_connection: Option<Duration>,
+            last_stop: Option<TunnelEvent>,
+            last_stop_known: bool,
+            restart_pending: bool,
+            last_exit: Option<String>,
+            // ssh's output, only gathered for expanded rows
+            log: Vec<String>,
+            local_address: String,
+            local_url: Option<String>,
+            // The free port picked for a tunnel saved with local port 0
+            assigned_port: Option<u16>,
+            agent_socket: Option<String>,
+            // Waiting for the pre-start hook before spawning ssh
+            preparing: bool,
+            // Why the post-start hook failed, while shown
+            hook_failure: Option<String>,
+            just_copied: bool,
+        }
+
+        let now = Instant::now();
+
+        // Only the rows' live details are gathered every frame
+        let preferences = self.list_preferences();
+        if self.list_cache.preferences.as_ref() != Some(&preferences) {
+            self.list_cache.rows = list_rows(&self.tunnels, &preferences, |id| {
+                let state = self.state(id);
+                (state.is_active(), state.is_waiting())
+            });
+            self.list_cache.preferences = Some(preferences);
+        }
+        let tunnel_data: Vec<TunnelDisplayData> = self.list_cache.rows.iter()
+            .map(|&index| &self.tunnels[index])
+            .map(|t| {
+                let state = self.state(t.id).clone();
+                let is_active = state.is_active();
+                let is_queued = state.is_waiting();
+                let is_expanded = self.expanded_tunnels.contains(&t.id);
+                let pid = if is_active {
+                    self.active_tunnels.get(&t.id).and_then(TunnelInfo::pid)
+                } else {
+                    None
+                };
+                let idle_remaining = self.active_tunnels.get(&t.id)
+                    .and_then(|info| info.idle_remaining(now));
+                let running = self.active_tunnels.get(&t.id).filter(|info| info.has_process());
+                let uptime = running.map(|info| now.duration_since(info.started));
+                // Connections are only watched for tunnels with an idle timeout
+                let last_connection = running
+                    .filter(|info| info.idle_timeout.is_some() && info.last_activity > info.started)
+                    .map(|info| now.duration_since(info.last_activity));
+                let exit = self.exits.get(&t.id);
+                let log = match (is_expanded, self.active_tunnels.get(&t.id)) {
+                    (false, _) => Vec::new(),
+                    (true, Some(info)) => info.log(),
+                    (true, None) => exit.map(|exit| exit.log.clone()).unwrap_or_default(),
+                };
+                let assigned_port = self.active_tunnels.get(&t.id)
+                    .filter(|info| info.auto_port)
+                    .map(|info| info.local_port);
+                let listening = assigned_port.map(|port| Tunnel { local_port: port, ..t.clone() });
+                let listening = listening.as_ref().unwrap_or(t);
+                let start_error = self.start_errors.get(&t.id);
+                let port_held_by = match start_error {
+                    Some(OnigiriError::Bind { holder: Some(holder), .. }) => self
+                        .active_tunnels
+                        .iter()
+                        .find(|(_, info)| info.pid() == Some(holder.pid))
+                        .map(|(id, info)| (*id, info.name.clone())),
+                    _ => None,
+                };
+                
+                TunnelDisplayData {
+                    id: t.id,
+                    name: t.name.clone(),
+                    is_active,
+                    started_at: match &state {
+                        TunnelState::Running { since, .. } => Some(*since),
+                        _ => None,
+                    },
+                    last_check: match &state {
+                        TunnelState::Running { check, .. } => check.as_ref().map(TestOutcome::describe),
+                        _ => None,
+                    },
+                    state,
+                    is_queued,
+                    is_expanded,
+                    ssh_server: t.ssh_server.clone(),
+                    local_port: t.local_port,
+                    remote_ip: t.remote_ip.clone(),
+                    remote_port: t.remote_port,
+                    pid,
+                    idle_remaining,
+                    idle_stopped: self.idle_stopped.get(&t.id).copied(),
+                    tags: t.tags.clone(),
+                    group: t.group.clone(),
+                    last_started_at: t.last_started_at,
+                    created_at: t.created_at,
+                    updated_at: t.updated_at,
+                    timestamps_approximate: t.timestamps_approximate,
+                    pinned: t.pinned,
+                    notes: t.notes.clone(),
+                    color: t.color,
+                    is_testing: self.tests_running.contains(&t.id),
+                    test_result: self.test_results.get(&t.id).cloned(),
+                    start_error: start_error.map(|e| e.to_string()),
+                    port_held_by,
+                    command: command_line(t),
+                    resolved: (is_expanded && t.has_references()).then(|| t.expanded().map(|t| command_line(&t)).map_err(|e| e.to_string())),
+                    uptime,
+                    last_connection,
+                    last_stop: if is_active { None } else { self.last_stops.get(&t.id).cloned().flatten() },
+                    last_stop_known: self.last_stops.contains_key(&t.id),
+                    restart_pending: is_active && self.restart_pending.contains(&t.id),
+                    last_exit: exit.map(|exit| exit.status.clone()),
+                    died: exit.and_then(|exit| exit.died_at.map(|at| (at, exit.reason().to_string()))),
+                    log,
+                    local_address: listening.local_address(),
+                    local_url: listening.local_url(),
+                    assigned_port,
+                    agent_socket: t.agent_socket.clone(),
+                    preparing: self.active_tunnels.get(&t.id).is_some_and(|t| t.is_preparing() && t.pre_start.is_some()),
+                    hook_failure: self.hook_failures.get(&t.id).cloned(),
+                    just_copied: self.copied.is_some_and(|(id, at)| id == t.id && now < at + COPIED_FEEDBACK),
+                }
+            })
+            .collect();
+        let unix_time = unix_now();
+
+        // Uptime and the ssh output of open details keep changing
+        if tunnel_data.iter().any(|t| t.is_expanded && t.is_active) {
+            ctx.request_repaint_after(Duration::from_secs(1));
+        }
+
+        let total = self.tunnels.iter().filter(|t| !t.deleted).count();
+        let running = self.tunnels.iter()
+            .filter(|t| !t.deleted && self.active_tunnels.contains_key(&t.id))
+            .count();
+        let hidden = total - tunnel_data.len();
+
+        // What "Start all" and "Stop all" act on: everything listed
+        let startable: Vec<TunnelId> = tunnel_data.iter()
+            .filter(|t| !t.is_active && !t.is_queued)
+            .map(|t| t.id)
+            .collect();
+        let stoppable: Vec<TunnelId> = tunnel_data.iter()
+            .filter(|t| t.is_active || t.is_queued)
+            .map(|t| t.id)
+            .collect();
+
+        // Named groups in order, then tunnels without a group
+        let (mut pinned, tunnel_data): (Vec<_>, Vec<_>) = tunnel_data.into_iter().partition(|t| t.pinned);
+        // Otherwise in the list's order, so ⬆/⬇ move pinned tunnels too
+        if self.sort_mode == SortMode::Name {
+            pinned.sort_by_key(|t| t.name.to_lowercase());
+        }
+        let mut grouped: BTreeMap<String, Vec<TunnelDisplayData>> = BTreeMap::new();
+        let mut ungrouped = Vec::new();
+        for tunnel in tunnel_data {
+            match &tunnel.group {
+                Some(group) => grouped.entry(group.clone()).or_default().push(tunnel),
+                None => ungrouped.push(tunnel),
+            }
+        }
+        // Collapsible sections: each group, or running and stopped tunnels
+        // when there are no groups. Each is (key, title, group, members),
+        // where `group` is None for the running/stopped split.
+        type Section<'a> = (String, String, Option<Option<&'a str>>, Vec<&'a TunnelDisplayData>);
+        let sections: Vec<Section> = if grouped.is_empty() {
+            let (active, inactive): (Vec<_>, Vec<_>) = ungrouped.iter().partition(|t| t.is_active || t.is_queued);
+            vec![
+                ("active".to_string(), tr("Active").to_string(), None, active),
+                ("inactive".to_string(), tr("Inactive").to_string(), None, inactive),
+            ]
+        } else {
+            grouped
+                .iter()
+                .map(|(name, members)| (format!("group:{}", name), name.clone(), Some(Some(name.as_str())), members.iter().collect()))
+                .chain(std::iter::once(("ungrouped".to_string(), tr("Ungrouped").to_string(), Some(None), ungrouped.iter().collect())))
+                .collect()
+        };
+        // The rows as listed, for shift-click ranges
+        let list_order: Vec<TunnelId> = pinned
+            .iter()
+            .chain(sections.iter().flat_map(|(_, _, _, members)| members.iter().copied()))
+            .map(|t| t.id)
+            .collect();
+
+        // Esc clears the selection unless a text field or dialog takes it
+        let nothing_focused = ctx.memory(|m| m.focused().is_none());
+        if !self.selected.is_empty()
+            && nothing_focused
+            && self.confirm_bulk_delete.is_none()
+            && ctx.input(|i| i.key_pressed(egui::Key::Escape))
+        {
+            self.selected.clear();
+            self.selection_anchor = None;
+        }
+        let selection = self.selection();
+        let group_names: BTreeSet<String> = self
+            .tunnels
+            .iter()
+            .filter(|t| !t.deleted)
+            .filter_map(|t| t.group.clone())
+            .collect();
+
+        let mut tunnel_to_pin = None;
+        let mut row_to_select = None;
+        let mut bulk_action = None;
+        let mut tunnel_to_move = None;
+        let mut tunnel_to_drop = None;
+        let manual_order = self.sort_mode == SortMode::Manual;
+        let mut tunnel_to_toggle = None;
+        let mut tunnel_to_cancel = None;
+        let mut tunnel_to_delete = None;
+        let mut tunnel_to_toggle_expand = None;
+        let mut last_stop_to_load = None;
+        let mut tunnel_to_restart = None;
+        let mut tunnel_to_edit = None;
+        let mut tunnel_to_edit_inline = None;
+        let mut death_to_dismiss = None;
+        let mut hook_failure_to_dismiss = None;
+        let mut inline_edit_done = None;
+        let inline_edit_problems = match &mut self.inline_edit {
+            Some((id, form)) => {
+                form.live_validate(ctx);
+                form.problems(&self.tunnels, &self.active_tunnels, Some(*id))
+            }
+            None => Vec::new(),
+        };
+        let mut tunnel_to_duplicate = None;
+        let mut tunnel_to_share = None;
+        let mut link_to_copy = None;
+        let mut command_to_copy = None;
+        let mut url_to_open = None;
+        let mut tunnel_to_open_terminal = None;
+        let mut address_to_copy: Option<(TunnelId, String)> = None;
+        let mut tunnel_to_show_history = None;
+        let mut tunnel_to_export_service = None;
+        let mut tunnel_to_template = None;
+        let mut tunnel_to_test = None;
+        let mut tunnel_to_stop_for = None;
+        let mut tag_to_filter = None;
+        let mut group_to_start = None;
+        let mut tunnels_to_start = None;
+        let mut tunnels_to_stop = None;
+        let mut group_to_stop = None;
+        let mut section_to_fold = None;
+        let mut tag_to_unfilter = None;
+        let mut should_import = false;
+        let mut should_export = false;
+        let mut should_empty_trash = false;
+        let mut should_quit = false;
+        let mut should_open_about = false;
+        let mut theme_to_set = None;
+        let mut density_to_set = None;
+        let mut quit_behavior_to_set = None;
+        let mut log_level_to_set = None;
+        let mut language_to_set = None;
+        let mut workspace_action = None;
+        let mut should_import_ssh_config = false;
+        let mut should_create_examples = false;
+        #[cfg(windows)]
+        let mut should_import_putty = false;
+        let mut should_backup = false;
+        let mut should_restore_backup = false;
+        let mut dismiss_status = false;
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.vertical(|ui| {
+                ui.horizontal(|ui| {
+                    ui.heading(tr("SSH Tunnel Manager"));
+                    let running_text = egui::RichText::new(trf("{} active", &[&running]));
+                    let running_text = if running > 0 { running_text.color(tone(ui, Tone::Good)) } else { running_text };
+                    let hint = if self.running_only { tr("Show all tunnels") } else { tr("Show running tunnels only") };
+                    if ui.add(egui::Label::new(running_text).sense(egui::Sense::click())).on_hover_text(hint).clicked() {
+                        self.running_only = !self.running_only;
+                    }
+                    ui.label(if total == 1 { trf("/ {} tunnel", &[&total]) } else { trf("/ {} tunnels", &[&total]) });
+                    if hidden > 0 {
+                        ui.weak(trf("(showing {})", &[&(total - hidden)]));
+                    }
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        ui.menu_button("☰", |ui| {
+                            if ui.button(tr("Import…")).clicked() {
+                                should_import = true;
+                                ui.close_menu();
+                            }
+                            if ui.button(tr("Export…")).clicked() {
+                                should_export = true;
+                                ui.close_menu();
+                            }
+                            if ui.button(tr("Paste tunnel…")).on_hover_text(tr("Add a tunnel from a share link")).clicked() {
+                                self.paste_dialog = Some(String::new());
+                                ui.close_menu();
+                            }
+                            if ui.button(tr("Import from SSH config…")).clicked() {
+                                should_import_ssh_config = true;
+                                ui.close_menu();
+                            }
+                            if ui.button(tr("Templates…")).clicked() {
+                                self.show_templates = true;
+                                ui.close_menu();
+                            }
+                            #[cfg(windows)]
+                            if ui.button(tr("Import PuTTY sessions…")).clicked() {
+                                should_import_putty = true;
+                                ui.close_menu();
+                            }
+                            ui.separator();
+                            if ui.button(tr("Empty trash…")).clicked() {
+                                should_empty_trash = true;
+                                ui.close_menu();
+                            }
+                            ui.separator();
+                            if ui.button(tr("Back up database…")).clicked() {
+                                should_backup = true;
+                                ui.close_menu();
+                            }
+                            if ui.button(tr("Restore from backup…")).clicked() {
+                                should_restore_backup = true;
+                                ui.close_menu();
+                            }
+                            ui.separator();
+                            ui.menu_button(tr("Theme"), |ui| {
+                                for theme in Theme::ALL {
+                                    if ui.radio(self.theme == theme, tr(theme.label())).clicked() {
+                                        theme_to_set = Some(theme);
+                                        ui.close_menu();
+                                    }
+                                }
+                            });
+                            ui.menu_button(tr("Density"), |ui| {
+                                for density in Density::ALL {
+                                    if ui.radio(self.density == density, tr(density.label())).clicked() {
+                                        density_to_set = Some(density);
+                                        ui.close_menu();
+                                    }
+                                }
+                            });
+                            ui.menu_button(tr("Language"), |ui| {
+                                for language in Language::ALL {
+                                    if ui.radio(i18n::language() == language, language.label()).clicked() {
+                                        language_to_set = Some(language);
+                                        ui.close_menu();
+                                    }
+                                }
+                            });
+                            let mut show_subtitles = self.show_subtitles;
+                            if ui
+                                .checkbox(&mut show_subtitles, tr("Show server under names"))
+                                .on_hover_text(tr("Not shown in the compact density"))
+                                .changed()
+                            {
+                                self.set_show_subtitles(show_subtitles);
+                            }
+                            ui.menu_button(tr("When quitting"), |ui| {
+                                for behavior in QuitBehavior::ALL {
+                                    if ui.radio(self.quit_behavior == behavior, tr(behavior.label())).clicked() {
+                                        quit_behavior_to_set = Some(behavior);
+                                        ui.close_menu();
+                                    }
+                                }
+                            });
+                            let mut keep_running = self.keep_running;
+                            if ui
+                                .checkbox(&mut keep_running, tr("Close window keeps tunnels running"))
+                                .on_hover_text(tr("Closing the window hides it; use Quit to exit"))
+                                .changed()
+                            {
+                                self.set_keep_running(keep_running);
+                            }
+                            ui.menu_button(tr("Log level"), |ui| {
+                                if logging::level_from_env() {
+                                    ui.weak(tr("Set by RUST_LOG"));
+                                } else {
+                                    for level in logging::LEVELS {
+                                        if ui.radio(logging::level() == level, tr(logging::level_label(level))).clicked() {
+                                            log_level_to_set = Some(level);
+                                            ui.close_menu();
+                                        }
+                                    }
+                                }
+                                ui.separator();
+                                if ui.button(tr("Show logs…")).clicked() {
+                                    self.show_logs = true;
+                                    ui.close_menu();
+                                }
+                                if ui.button(tr("Open log folder")).clicked() {
+                                    match storage::data_dir() {
+                                        Ok(dir) => open_folder(&logging::log_dir(&dir)),
+                                        Err(e) => error!("Failed to find the data folder: {:#}", e),
+                                    }
+                                    ui.close_menu();
+                                }
+                            });
+                            if ui.button(tr("Terminal command…")).clicked() {
+                                self.terminal_dialog = Some(self.terminal_template.clone());
+                                ui.close_menu();
+                            }
+                            if ui.button(tr("About Onigiri")).clicked() {
+                                should_open_about = true;
+                                ui.close_menu();
+                            }
+                            if ui.button(tr("Quit")).clicked() {
+                                should_quit = true;
+                                ui.close_menu();
+                            }
+                        });
+                        if ui.button(tr("Add Tunnel")).clicked() {
+                            self.show_new_tunnel_window = true;
+                        }
+                        let current = self
+                            .workspaces
+                            .iter()
+                            .find(|w| w.id == self.workspace)
+                            .map_or(tr("Default"), |w| w.name.as_str());
+                        egui::ComboBox::from_id_salt("workspace")
+                            .selected_text(current)
+                            .width(90.0)
+                            .show_ui(ui, |ui| {
+                                for workspace in &self.workspaces {
+                                    if ui.selectable_label(workspace.id == self.workspace, &workspace.name).clicked() {
+                                        workspace_action = Some(WorkspaceAction::Switch(workspace.id));
+                                    }
+                                }
+                                ui.separator();
+                                if ui.button(tr("New workspace…")).clicked() {
+                                    workspace_action = Some(WorkspaceAction::Create);
+                                }
+                                if ui.button(tr("Rename…")).clicked() {
+                                    workspace_action = Some(WorkspaceAction::Rename);
+                                }
+                                if ui
+                                    .add_enabled(self.workspace != storage::DEFAULT_WORKSPACE, egui::Button::new(tr("Delete")))
+                                    .on_disabled_hover_text(tr("The default workspace can't be deleted"))
+                                    .clicked()
+                                {
+                                    workspace_action = Some(WorkspaceAction::Delete);
+                                }
+                            });
+                    });
+                });
+
+                // Tunnels keep running after switching away from their workspace
+                let running_elsewhere = self
+                    .active_tunnels
+                    .keys()
+                    .filter(|id| !self.tunnels.iter().any(|t| t.id == **id))
+                    .count();
+                if running_elsewhere > 0 {
+                    ui.weak(trf("{} running in other workspaces", &[&running_elsewhere]));
+                }
+                if self.keep_running {
+                    ui.weak(tr("Tunnels keep running when this window is closed"));
+                }
+
+                let now = Instant::now();
+                if !self.errors.show_recent && !self.errors.recent.is_empty() && self.errors.fresh(now).next().is_none() {
+                    let count = self.errors.recent.len();
+                    let text = if count == 1 { trf("{} recent error", &[&count]) } else { trf("{} recent errors", &[&count]) };
+                    let text = egui::RichText::new(text).color(tone(ui, Tone::Bad));
+                    if ui.add(egui::Label::new(text).sense(egui::Sense::click())).on_hover_text(tr("Show them again")).clicked() {
+                        self.errors.show_recent = true;
+                    }
+                }
+
+                if let Some(message) = &self.status_message {
+                    ui.horizontal(|ui| {
+                        ui.label(message);
+                        if ui.small_button("✕").clicked() {
+                            dismiss_status = true;
+                        }
+                    });
+                }
+
+                // Search box
+                ui.horizontal(|ui| {
+                    ui.label(tr("Search:"));
+                    // Esc clears the search; either way of clearing leaves the keyboard to the list
+                    let response = ui.text_edit_singleline(&mut self.search_query);
+                    if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                        self.search_query.clear();
+                    }
+                    if !self.search_query.is_empty() {
+                        let clear = ui.small_button("✕").on_hover_text(tr("Clear search"));
+                        if clear.clicked() {
+                            self.search_query.clear();
+                            clear.surrender_focus();
+                        }
+                    }
+                    ui.checkbox(&mut self.running_only, tr("Running only"));
+                    egui::ComboBox::from_id_salt("sort_mode")
+                        .selected_text(trf("Sort: {}", &[&tr(self.sort_mode.label())]))
+                        .show_ui(ui, |ui| {
+                            for mode in SortMode::ALL {
+                                ui.selectable_value(&mut self.sort_mode, mode, tr(mode.label()));
+                            }
+                        });
+                });
+
+                if !self.tag_filter.is_empty() {
+                    ui.horizontal_wrapped(|ui| {
+                        ui.label(tr("Tags:"));
+                        for tag in &self.tag_filter {
+                            if tag_chip(ui, tag, true).clicked() {
+                                tag_to_unfilter = Some(tag.clone());
+                            }
+                        }
+                    });
+                }
+
+                // Acts on the listed tunnels, so it follows the search and filters
+                ui.horizontal(|ui| {
+                    let filtered = !self.search_query.trim().is_empty() || !self.tag_filter.is_empty() || self.running_only;
+                    let (start_all, stop_all) = if filtered {
+                        (tr("Start all matching"), tr("Stop all matching"))
+                    } else {
+                        (tr("Start all"), tr("Stop all"))
+                    };
+                    if ui.add_enabled(!startable.is_empty(), egui::Button::new(start_all)).clicked() {
+                        tunnels_to_start = Some(startable.clone());
+                    }
+                    if ui.add_enabled(!stoppable.is_empty(), egui::Button::new(stop_all)).clicked() {
+                        tunnels_to_stop = Some(stoppable.clone());
+                    }
+                    if hidden > 0 {
+                        ui.weak(if hidden == 1 {
+                            trf("{} tunnel hidden by filters", &[&hidden])
+                        } else {
+                            trf("{} tunnels hidden by filters", &[&hidden])
+                        });
+                    }
+                });
+
+                if !selection.is_empty() {
+                    ui.horizontal_wrapped(|ui| {
+                        ui.strong(trf("{} selected", &[&selection.len()]));
+                        if ui.button(tr("Start")).clicked() {
+                            bulk_action = Some(BulkAction::Start);
+                        }
+                        if ui.button(tr("Stop")).clicked() {
+                            bulk_action = Some(BulkAction::Stop);
+                        }
+                        if ui.button(tr("Delete…")).clicked() {
+                            bulk_action = Some(BulkAction::Delete);
+                        }
+                        ui.menu_button(tr("Add tag"), |ui| {
+                            let response = ui.add(
+                                egui::TextEdit::singleline(&mut self.bulk_tag)
+                                    .hint_text(tr("tag"))
+                                    .desired_width(120.0),
+                            );
+                            let entered = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                            let tag = self.bulk_tag.trim().to_string();
+                            if (ui.button(tr("Add")).clicked() || entered) && !tag.is_empty() {
+                                bulk_action = Some(BulkAction::AddTag(tag));
+                                self.bulk_tag.clear();
+                                ui.close_menu();
+                            }
+                        });
+                        ui.menu_button(tr("Move to group"), |ui| {
+                            for group in &group_names {
+                                if ui.button(group).clicked() {
+                                    bulk_action = Some(BulkAction::MoveToGroup(Some(group.clone())));
+                                    ui.close_menu();
+                                }
+                            }
+                            if ui.button(tr("No group")).clicked() {
+                                bulk_action = Some(BulkAction::MoveToGroup(None));
+                                ui.close_menu();
+                            }
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut self.bulk_group)
+                                        .hint_text(tr("new group"))
+                                        .desired_width(120.0),
+                                );
+                                let group = self.bulk_group.trim().to_string();
+                                if ui.add_enabled(!group.is_empty(), egui::Button::new(tr("Move"))).clicked() {
+                                    bulk_action = Some(BulkAction::MoveToGroup(Some(group)));
+                                    self.bulk_group.clear();
+                                    ui.close_menu();
+                                }
+                            });
+                        });
+                        if ui.button(tr("Clear selection")).on_hover_text(tr("Esc")).clicked() {
+                            bulk_action = Some(BulkAction::Clear);
+                        }
+                    });
+                }
+
+                ui.separator();
+
+                if total == 0 {
+                    ui.add_space(24.0);
+                    ui.vertical_centered(|ui| {
+                        ui.heading(tr("No tunnels yet"));
+                        ui.label(tr("Add one, or import the forwards you already have."));
+                        ui.add_space(8.0);
+                        if ui.button(tr("Add tunnel")).clicked() {
+                            self.show_new_tunnel_window = true;
+                        }
+                        if ui.button(tr("Import from SSH config…")).clicked() {
+                            should_import_ssh_config = true;
+                        }
+                        if ui.button(tr("Import from file…")).clicked() {
+                            should_import = true;
+                        }
+                        ui.add_space(8.0);
+                        if ui.small_button(tr("Create examples")).on_hover_text(tr("Add three made-up tunnels to try things out")).clicked() {
+                            should_create_examples = true;
+                        }
+                    });
+                }
+
+                if total > 0 && hidden == total {
+                    ui.add_space(16.0);
+                    ui.vertical_centered(|ui| {
+                        let search = self.search_query.trim();
+                        if search.is_empty() {
+                            ui.label(tr("No tunnels match the filters"));
+                        } else {
+                            ui.label(trf("No tunnels match '{}'", &[&search]));
+                        }
+                        if ui.link(tr("Clear filter")).clicked() {
+                            self.search_query.clear();
+                            self.tag_filter.clear();
+                            self.running_only = false;
+                        }
+                    });
+                }
+
+                // Tunnels list
+                // Narrow windows and the compact density fold the row actions into a menu
+                let dense = self.density == Density::Compact;
+                let compact_rows = dense || ui.available_width() < COMPACT_ROW_WIDTH;
+                let subtitles = self.show_subtitles && !dense;
+                let inline_edit = &mut self.inline_edit;
+                let selected = &self.selected;
+                egui::ScrollArea::vertical().auto_shrink([false, false]).show(ui, |ui| {
+                    let mut show_row = |ui: &mut egui::Ui, tunnel: &TunnelDisplayData| {
+                        let editing_inline = inline_edit.as_ref().is_some_and(|(id, _)| *id == tunnel.id);
+                        ui.vertical(|ui| {
+                            if dense {
+                                ui.spacing_mut().item_spacing.y = 1.0;
+                                ui.spacing_mut().button_padding.y = 0.0;
+                            }
+                            // Rows of tunnels that died stand out until started or dismissed
+                            let fill = if tunnel.died.is_some() {
+                                tone(ui, Tone::Warning).gamma_multiply(0.15)
+                            } else {
+                                egui::Color32::TRANSPARENT
+                            };
+                            // Rows are dragged by their handle and dropped within their own section
+                            let section = (tunnel.id, tunnel.pinned, tunnel.group.clone());
+                            let row = egui::Frame::new().fill(fill).corner_radius(4.0).show(ui, |ui| {
+                                ui.set_width(ui.available_width());
+                                ui.horizontal(|ui| {
+                                    let mut ticked = selected.contains(&tunnel.id);
+                                    if ui.checkbox(&mut ticked, "").on_hover_text(tr("Select; shift-click selects a range")).clicked() {
+                                        row_to_select = Some((tunnel.id, ui.input(|i| i.modifiers.shift)));
+                                    }
+                                    if manual_order {
+                                        ui.dnd_drag_source(egui::Id::new(("drag", tunnel.id)), section.clone(), |ui| ui.weak("↕"))
+                                            .response
+                                            .on_hover_cursor(egui::CursorIcon::Grab)
+                                            .on_hover_text(tr("Drag to reorder"));
+                                    } else {
+                                        ui.add_enabled(false, egui::Label::new("↕"))
+                                            .on_disabled_hover_text(tr("Switch to Manual sort to drag"));
+                                    }
+
+                                    // Color label bar, with its space kept so names line up
+                                    let (bar, _) = ui.allocate_exact_size(egui::vec2(4.0, 16.0), egui::Sense::hover());
+                                    if let Some(label) = tunnel.color {
+                                        ui.painter().rect_filled(bar, 2.0, label.color());
+                                    }
+
+                                    // Draw status circle, or a spinner while starting
+                                    let (color, hint) = match &tunnel.state {
+                                        TunnelState::Running { check: None, .. } => {
+                                            (tone(ui, Tone::Pending), tr("Running, checking the local port…"))
+                                        }
+                                        TunnelState::Running { check: Some(check), .. } if check.is_success() => {
+                                            (tone(ui, Tone::Good), tr("Running"))
+                                        }
+                                        TunnelState::Running { .. } => (
+                                            tone(ui, Tone::Warning),
+                                            tunnel.last_check.as_deref().unwrap_or(tr("Running but unreachable")),
+                                        ),
+                                        TunnelState::Starting if tunnel.preparing => {
+                                            (tone(ui, Tone::Good), tr("Running the pre-start command…"))
+                                        }
+                                        TunnelState::Starting => (tone(ui, Tone::Good), tr("Starting…")),
+                                        TunnelState::Queued => (ui.visuals().weak_text_color(), tr("Queued")),
+                                        TunnelState::Reconnecting { .. } => (tone(ui, Tone::Warning), tr("Reconnecting…")),
+                                        TunnelState::Failed { .. } => (tone(ui, Tone::Warning), tr("Failed")),
+                                        TunnelState::Stopped => (tone(ui, Tone::Bad), tr("Stopped")),
+                                    };
+                                    let circle_size = 8.0;
+                                    let status = if matches!(tunnel.state, TunnelState::Starting) {
+                                        ui.add(egui::Spinner::new().size(circle_size))
+                                    } else {
+                                        let (rect, response) = ui.allocate_exact_size(
+                                            egui::vec2(circle_size, circle_size),
+                                            egui::Sense::hover(),
+                                        );
+                                        ui.painter().circle_filled(rect.center(), circle_size / 2.0, color);
+                                        response
+                                    };
+                                    if status.hovered() && !tunnel.is_active && !tunnel.last_stop_known {
+                                        last_stop_to_load = Some(tunnel.id);
+                                    }
+                                    status.on_hover_ui(|ui| {
+                                        ui.label(hint);
+                                        if let Some(pid) = tunnel.pid {
+                                            ui.label(trf("PID {}", &[&pid]));
+                                        }
+                                        if let Some(uptime) = tunnel.uptime {
+                                            ui.label(trf("Up for {}", &[&format_duration(uptime)]));
+                                        }
+                                        if let TunnelState::Reconnecting { attempt, next_retry } = &tunnel.state {
+                                            let wait = next_retry.saturating_duration_since(now);
+                                            ui.label(trf("Try {} of {} in {}", &[attempt, &RECONNECT_ATTEMPTS, &format_duration(wait)]));
+                                        }
+                                        if tunnel.is_active {
+                                            return;
+                                        }
+                                        if let TunnelState::Failed { reason, at } = &tunnel.state {
+                                            ui.label(trf("Failed {}: {}", &[&format_ago(*at, unix_time), reason]));
+                                        } else if let Some(stop) = &tunnel.last_stop {
+                                            let what = if stop.kind == EventKind::Exited { tr("Exited") } else { tr("Stopped") };
+                                            let why = if stop.detail.is_empty() { String::new() } else { format!(": {}", stop.detail) };
+                                            ui.label(format!("{} {}{}", what, format_ago(stop.at, unix_time), why));
+                                        } else if tunnel.last_started_at.is_none() {
+                                            ui.weak(tr("Never started"));
+                                        }
+                                    });
+                                    ui.add_space(4.0); // Add a small gap between circle and name
+
+                                    if !dense {
+                                        if ui.add_enabled(manual_order, egui::Button::new("⬆").small()).on_hover_text(tr("Move up")).clicked() {
+                                            tunnel_to_move = Some((tunnel.id, true));
+                                        }
+                                        if ui.add_enabled(manual_order, egui::Button::new("⬇").small()).on_hover_text(tr("Move down")).clicked() {
+                                            tunnel_to_move = Some((tunnel.id, false));
+                                        }
+                                    }
+                                    let (star, hint) = if tunnel.pinned { ("★", tr("Unpin")) } else { ("☆", tr("Pin to top")) };
+                                    if ui.small_button(star).on_hover_text(hint).clicked() {
+                                        tunnel_to_pin = Some((tunnel.id, !tunnel.pinned));
+                                    }
+                                    // Buttons first, so a long name is elided instead of pushing them off
+                                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                                        let toggle_text = if tunnel.is_active || tunnel.is_queued { tr("Stop") } else { tr("Start") };
+                                        let mut toggle = |ui: &mut egui::Ui| {
+                                            let button = egui::Button::new(toggle_text).small();
+                                            if ui.add_enabled(!editing_inline, button).on_disabled_hover_text(tr("Save or cancel the edit first")).clicked() {
+                                                if tunnel.is_queued {
+                                                    tunnel_to_cancel = Some(tunnel.id);
+                                                } else {
+                                                    tunnel_to_toggle = Some(tunnel.id);
+                                                }
+                                            }
+                                        };
+                                        let info_text = if tunnel.is_expanded { tr("Hide") } else { tr("Info") };
+                                        let mut export_items = |ui: &mut egui::Ui| {
+                                            if ui.button(tr("Copy command")).clicked() {
+                                                command_to_copy = Some(tunnel.command.clone());
+                                                ui.close_menu();
+                                            }
+                                            if ui.button(tr("Copy as ssh_config")).clicked() {
+                                                tunnel_to_share = Some((tunnel.id, false));
+                                                ui.close_menu();
+                                            }
+                                            if ui.button(tr("Append ssh_config to file…")).clicked() {
+                                                tunnel_to_share = Some((tunnel.id, true));
+                                                ui.close_menu();
+                                            }
+                                            if ui.button(tr("Copy share link")).on_hover_text(tr("One line to paste into another Onigiri")).clicked() {
+                                                link_to_copy = Some(tunnel.id);
+                                                ui.close_menu();
+                                            }
+                                            if cfg!(not(windows)) && ui.button(tr("Export as service…")).clicked() {
+                                                tunnel_to_export_service = Some(tunnel.id);
+                                                ui.close_menu();
+                                            }
+                                            if ui.button(tr("Save as template…")).clicked() {
+                                                tunnel_to_template = Some(tunnel.id);
+                                                ui.close_menu();
+                                            }
+                                        };
+                                        let open_hint = if tunnel.is_active { tr("Open in the browser") } else { tr("Start the tunnel first") };
+                                        if compact_rows {
+                                            ui.menu_button("⋯", |ui| {
+                                                if let Some(url) = &tunnel.local_url {
+                                                    let open = ui.add_enabled(tunnel.is_active, egui::Button::new(tr("Open")));
+                                                    if open.on_hover_text(url).on_disabled_hover_text(open_hint).clicked() {
+                                                        url_to_open = Some(url.clone());
+                                                        ui.close_menu();
+                                                    }
+                                                }
+                                                if ui.add_enabled(!tunnel.is_testing, egui::Button::new(tr("Test"))).clicked() {
+                                                    tunnel_to_test = Some(tunnel.id);
+                                                    ui.close_menu();
+                                                }
+                                                if ui.button(tr("Edit")).clicked() {
+                                                    tunnel_to_edit = Some(tunnel.id);
+                                                    ui.close_menu();
+                                                }
+                                                if ui.button(tr("Duplicate")).clicked() {
+                                                    tunnel_to_duplicate = Some(tunnel.id);
+                                                    ui.close_menu();
+                                                }
+                                                if ui.button(info_text).clicked() {
+                                                    tunnel_to_toggle_expand = Some(tunnel.id);
+                                                    ui.close_menu();
+                                                }
+                                                if ui.button(tr("Open terminal")).clicked() {
+                                                    tunnel_to_open_terminal = Some(tunnel.id);
+                                                    ui.close_menu();
+                                                }
+                                                ui.menu_button(tr("Export"), |ui| export_items(ui));
+                                                if dense {
+                                                    if ui.add_enabled(manual_order, egui::Button::new(tr("Move up"))).clicked() {
+                                                        tunnel_to_move = Some((tunnel.id, true));
+                                                        ui.close_menu();
+                                                    }
+                                                    if ui.add_enabled(manual_order, egui::Button::new(tr("Move down"))).clicked() {
+                                                        tunnel_to_move = Some((tunnel.id, false));
+                                                        ui.close_menu();
+                                                    }
+                                                }
+                                                ui.separator();
+                                                if ui.button(tr("Delete")).clicked() {
+                                                    tunnel_to_delete = Some(tunnel.id);
+                                                    ui.close_menu();
+                                                }
+                                            });
+                                            toggle(ui);
+                                        } else {
+                                            if ui.small_button(tr("Delete")).clicked() {
+                                                tunnel_to_delete = Some(tunnel.id);
+                                            }
+                                            toggle(ui);
+                                            if let Some(url) = &tunnel.local_url {
+                                                let open = ui.add_enabled(tunnel.is_active, egui::Button::new(tr("Open")).small());
+                                                if open.on_hover_text(url).on_disabled_hover_text(open_hint).clicked() {
+                                                    url_to_open = Some(url.clone());
+                                                }
+                                            }
+                                            if ui.small_button(info_text).clicked() {
+                                                tunnel_to_toggle_expand = Some(tunnel.id);
+                                            }
+                                            if ui.small_button(tr("Duplicate")).clicked() {
+                                                tunnel_to_duplicate = Some(tunnel.id);
+                                            }
+                                            ui.menu_button(tr("Export"), |ui| export_items(ui));
+                                            if ui.small_button(tr("Edit")).clicked() {
+                                                tunnel_to_edit = Some(tunnel.id);
+                                            }
+                                            if ui.add_enabled(!tunnel.is_testing, egui::Button::new(tr("Test")).small()).clicked() {
+                                                tunnel_to_test = Some(tunnel.id);
+                                            }
+                                        }
+
+                                        ui.with_layout(egui::Layout::left_to_right(egui::Align::Center), |ui| {
+                                            // Elided names show in full on hover
+                                            ui.add(egui::Label::new(&tunnel.name).truncate().sense(egui::Sense::click()))
+                                                .context_menu(|ui| {
+                                                    if ui.button(tr("Copy command")).clicked() {
+                                                        command_to_copy = Some(tunnel.command.clone());
+                                                        ui.close_menu();
+                                                    }
+                                                    if ui.button(tr("Open terminal")).clicked() {
+                                                        tunnel_to_open_terminal = Some(tunnel.id);
+                                                        ui.close_menu();
+                                                    }
+                                                    if ui.button(tr("Copy local address")).clicked() {
+                                                        address_to_copy = Some((tunnel.id, tunnel.local_address.clone()));
+                                                        ui.close_menu();
+                                                    }
+                                                    if let Some(url) = &tunnel.local_url {
+                                                        if ui.button(tr("Copy as URL")).clicked() {
+                                                            address_to_copy = Some((tunnel.id, url.clone()));
+                                                            ui.close_menu();
+                                                        }
+                                                    }
+                                                });
+                                            if tunnel.assigned_port.is_some() {
+                                                ui.strong(format!("→ {}", tunnel.local_address));
+                                                if ui.small_button("📋").on_hover_text(tr("Copy local address")).clicked() {
+                                                    address_to_copy = Some((tunnel.id, tunnel.local_address.clone()));
+                                                }
+                                            }
+                                            for tag in &tunnel.tags {
+                                                if tag_chip(ui, tag, false).clicked() {
+                                                    tag_to_filter = Some(tag.clone());
+                                                }
+                                            }
+                                            if tunnel.restart_pending {
+                                                ui.colored_label(tone(ui, Tone::Warning), tr("restart pending"));
+                                                let restart = ui.small_button(tr("Restart"));
+                                                if restart.on_hover_text(tr("Restart with the saved settings")).clicked() {
+                                                    tunnel_to_restart = Some(tunnel.id);
+                                                }
+                                            }
+                                            // The compact density leaves the status to the dot
+                                            if dense {
+                                                return;
+                                            }
+                                            if matches!(tunnel.state, TunnelState::Reconnecting { .. }) {
+                                                ui.weak(tr("reconnecting"));
+                                            } else if tunnel.is_queued {
+                                                ui.weak(tr("queued"));
+                                            } else if let Some(timeout) = tunnel.idle_stopped {
+                                                ui.weak(trf("stopped after {} idle", &[&format_duration(timeout)]));
+                                            }
+                                        });
+                                    });
+                                });
+
+                                // Tells apart similar names without expanding the row
+                                if subtitles {
+                                    let subtitle = match (tunnel.local_port, tunnel.assigned_port) {
+                                        (0, Some(port)) => trf("{} · local port {} (picked)", &[&tunnel.ssh_server, &port]),
+                                        (0, None) => trf("{} · any free local port", &[&tunnel.ssh_server]),
+                                        (port, _) => trf("{} · local port {}", &[&tunnel.ssh_server, &port]),
+                                    };
+                                    ui.add(egui::Label::new(egui::RichText::new(subtitle).small().weak()).truncate());
+                                }
+
+                                if let Some((at, reason)) = &tunnel.died {
+                                    ui.horizontal_wrapped(|ui| {
+                                        ui.colored_label(
+                                            tone(ui, Tone::Warning),
+                                            trf("Died unexpectedly {}: {}", &[&format_ago(*at, unix_time), reason]),
+                                        );
+                                        if ui.small_button(tr("Dismiss")).clicked() {
+                                            death_to_dismiss = Some(tunnel.id);
+                                        }
+                                    });
+                                }
+
+                                if let Some(reason) = &tunnel.hook_failure {
+                                    ui.horizontal_wrapped(|ui| {
+                                        ui.colored_label(
+                                            tone(ui, Tone::Warning),
+                                            trf("Post-start hook failed: {}", &[reason]),
+                                        );
+                                        if ui.small_button(tr("Dismiss")).clicked() {
+                                            hook_failure_to_dismiss = Some(tunnel.id);
+                                        }
+                                    });
+                                }
+
+                                if tunnel.is_testing {
+                                    ui.horizontal(|ui| {
+                                        ui.spinner();
+                                        ui.weak(tr("Testing…"));
+                                    });
+                                } else if let Some(result) = &tunnel.test_result {
+                                    let color = if result.is_success() {
+                                        tone(ui, Tone::Good)
+                                    } else {
+                                        tone(ui, Tone::Bad)
+                                    };
+                                    ui.colored_label(color, result.describe());
+                                }
+
+                                if let Some(message) = &tunnel.start_error {
+                                    ui.horizontal_wrapped(|ui| {
+                                        match &tunnel.port_held_by {
+                                            Some((holder_id, holder_name)) => {
+                                                ui.colored_label(
+                                                    tone(ui, Tone::Bad),
+                                                    trf("Port {} is used by Onigiri tunnel '{}'", &[&tunnel.local_port, holder_name]),
+                                                );
+                                                if ui.small_button(trf("Stop '{}'", &[holder_name])).clicked() {
+                                                    tunnel_to_stop_for = Some((*holder_id, tunnel.id));
+                                                }
+                                            }
+                                            None => {
+                                                ui.colored_label(tone(ui, Tone::Bad), message);
+                                            }
+                                        }
+                                        if ui.small_button(tr("Copy")).clicked() {
+                                            ctx.copy_text(message.clone());
+                                        }
+                                    });
+                                }
+
+                                // Show expanded details
+                                if tunnel.is_expanded {
+                                    ui.indent("details", |ui| {
+                                        if let Some((_, form)) = inline_edit.as_mut().filter(|_| editing_inline) {
+                                            inline_edit_form(ui, form, &inline_edit_problems, &mut inline_edit_done);
+                                            ui.add_space(4.0);
+                                        }
+                                        ui.horizontal(|ui| {
+                                            if ui.small_button(tr("History")).clicked() {
+                                                tunnel_to_show_history = Some(tunnel.id);
+                                            }
+                                            let can_edit = inline_edit.is_none();
+                                            if ui.add_enabled(can_edit, egui::Button::new(tr("Edit here")).small()).clicked() {
+                                                tunnel_to_edit_inline = Some(tunnel.id);
+                                            }
+                                        });
+                                        if !tunnel.notes.is_empty() {
+                                            ui.label(&tunnel.notes);
+                                            ui.add_space(4.0);
+                                        }
+                                        if let Some(pid) = tunnel.pid {
+                                            ui.label(trf("PID: {}", &[&pid]));
+                                        }
+                                        if let Some(at) = tunnel.started_at {
+                                            ui.label(trf("Started {} ({})", &[&format_utc(at), &format_ago(at, unix_time)]));
+                                        }
+                                        if let Some(uptime) = tunnel.uptime {
+                                            ui.label(trf("Up for {}", &[&format_duration(uptime)]));
+                                        }
+                                        if let Some(check) = &tunnel.last_check {
+                                            ui.label(trf("Last check: {}", &[check]));
+                                        }
+                                        if let Some(ago) = tunnel.last_connection {
+                                            ui.label(trf("Last connection seen {} ago", &[&format_duration(ago)]));
+                                        }
+                                        if let Some(stop) = &tunnel.last_stop {
+                                            let what = if stop.kind == EventKind::Exited { tr("Exited") } else { tr("Stopped") };
+                                            let why = if stop.detail.is_empty() { String::new() } else { format!(": {}", stop.detail) };
+                                            ui.label(format!("{} {} ({}){}", what, format_utc(stop.at), format_ago(stop.at, unix_time), why));
+                                        }
+                                        if let Some(status) = &tunnel.last_exit {
+                                            ui.label(trf("Last exit: {}", &[status]));
+                                        }
+                                        ui.horizontal(|ui| {
+                                            ui.label(trf(
+                                                "Local: {} -> Remote: {}:{}",
+                                                &[&tunnel.local_address, &tunnel.remote_ip, &tunnel.remote_port],
+                                            ));
+                                            if ui.small_button("📋").on_hover_text(tr("Copy local address")).clicked() {
+                                                address_to_copy = Some((tunnel.id, tunnel.local_address.clone()));
+                                            }
+                                            if let Some(url) = &tunnel.local_url {
+                                                if ui.small_button(tr("Copy as URL")).on_hover_text(url).clicked() {
+                                                    address_to_copy = Some((tunnel.id, url.clone()));
+                                                }
+                                            }
+                                            if tunnel.just_copied {
+                                                ui.weak(tr("Copied"));
+                                            }
+                                        });
+                                        if let Some(socket) = &tunnel.agent_socket {
+                                            ui.label(trf("SSH agent: {}", &[socket]));
+                                        }
+                                        ui.horizontal(|ui| {
+                                            ui.label(egui::RichText::new(&tunnel.command).monospace().weak());
+                                            if ui.small_button(tr("Copy command")).clicked() {
+                                                command_to_copy = Some(tunnel.command.clone());
+                                            }
+                                            if ui.small_button(tr("Open terminal")).on_hover_text(tr("ssh to the server in a terminal")).clicked() {
+                                                tunnel_to_open_terminal = Some(tunnel.id);
+                                            }
+                                        });
+                                        match &tunnel.resolved {
+                                            Some(Ok(command)) => {
+                                                ui.horizontal(|ui| {
+                                                    ui.label(tr("Resolved:"));
+                                                    ui.label(egui::RichText::new(command).monospace().weak());
+                                                });
+                                            }
+                                            Some(Err(e)) => {
+                                                ui.colored_label(tone(ui, Tone::Bad), format!("{} {}", tr("Can't resolve:"), e));
+                                            }
+                                            None => {}
+                                        }
+                                        if let Some(remaining) = tunnel.idle_remaining {
+                                            ui.label(trf("Idle timeout: stops in {}", &[&format_duration(remaining)]));
+                                        }
+                                        if !tunnel.log.is_empty() {
+                                            ui.horizontal(|ui| {
+                                                ui.label(tr("ssh output:"));
+                                                if ui.small_button(tr("Copy")).clicked() {
+                                                    ctx.copy_text(tunnel.log.join("\n"));
+                                                }
+                                            });
+                                            // Follows new lines until scrolled up, and again once back at the end
+                                            egui::ScrollArea::vertical()
+                                                .id_salt(("log", tunnel.id))
+                                                .max_height(120.0)
+                                                .stick_to_bottom(true)
+                                                .show(ui, |ui| {
+                                                    for line in &tunnel.log {
+                                                        ui.label(egui::RichText::new(line).monospace().small());
+                                                    }
+                                                });
+                                        }
+                                        match tunnel.last_started_at {
+                                            Some(at) => ui.label(trf("Last used {}", &[&format_ago(at, unix_time)])),
+                                            None => ui.weak(tr("Never used")),
+                                        };
+                                        // Backfilled values only say the tunnel existed by then
+                                        let approximate = if tunnel.timestamps_approximate { tr(" (approximate)") } else { "" };
+                                        if let Some(at) = tunnel.created_at {
+                                            ui.label(trf("Created {}", &[&format_ago(at, unix_time)]) + approximate);
+                                        }
+                                        if let Some(at) = tunnel.updated_at {
+                                            let approximate = if Some(at) == tunnel.created_at { approximate } else { "" };
+                                            ui.label(trf("Updated {}", &[&format_ago(at, unix_time)]) + approximate);
+                                        }
+                                    });
+                                }
+                            }).response;
+                            let same_section = |dragged: &(TunnelId, bool, Option<String>)| {
+                                dragged.0 != tunnel.id && dragged.1 == section.1 && dragged.2 == section.2
+                            };
+                            if let Some(dragged) = row.dnd_hover_payload::<(TunnelId, bool, Option<String>)>().filter(|d| same_section(d)) {
+                                let after = ui.ctx().pointer_interact_pos().is_some_and(|pos| pos.y > row.rect.center().y);
+                                let y = if after { row.rect.bottom() } else { row.rect.top() };
+                                ui.painter().hline(row.rect.x_range(), y, ui.visuals().selection.stroke);
+                                if row.dnd_release_payload::<(TunnelId, bool, Option<String>)>().is_some() {
+                                    tunnel_to_drop = Some((dragged.0, tunnel.id, after));
+                                }
+                            }
+                            ui.add(egui::Separator::default().spacing(if dense { 2.0 } else { 6.0 }));
+                        });
+                    };
+
+                    if !pinned.is_empty() {
+                        ui.strong(tr("★ Pinned"));
+                        for tunnel in &pinned {
+                            show_row(ui, tunnel);
+                        }
+                        ui.add_space(8.0);
+                    }
+
+                    for (key, title, group, members) in &sections {
+                        if members.is_empty() {
+                            continue;
+                        }
+                        let id = ui.make_persistent_id(("section", key));
+                        let open = !self.collapsed_sections.contains(key);
+                        let mut state = egui::collapsing_header::CollapsingState::load_with_default_open(ui.ctx(), id, open);
+                        state.set_open(open);
+                        state
+                            .show_header(ui, |ui| {
+                                ui.strong(title);
+                                let running = members.iter().filter(|t| t.is_active).count();
+                                ui.weak(trf("{}/{} running", &[&running, &members.len()]));
+                                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                                    let ids = || members.iter().map(|t| t.id).collect::<Vec<_>>();
+                                    let stoppable = members.iter().any(|t| t.is_active || t.is_queued);
+                                    if ui.add_enabled(stoppable, egui::Button::new(tr("Stop All")).small()).clicked() {
+                                        match group {
+                                            Some(group) => group_to_stop = Some(group.map(str::to_string)),
+                                            None => tunnels_to_stop = Some(ids()),
+                                        }
+                                    }
+                                    let startable = members.iter().any(|t| !t.is_active && !t.is_queued);
+                                    if ui.add_enabled(startable, egui::Button::new(tr("Start All")).small()).clicked() {
+                                        match group {
+                                            Some(group) => group_to_start = Some(group.map(str::to_string)),
+                                            None => tunnels_to_start = Some(ids()),
+                                        }
+                                    }
+                                });
+                            })
+                            .body(|ui| {
+                                for tunnel in members {
+                                    show_row(ui, tunnel);
+                                }
+                            });
+                        // The toggle stored its new state; keep it in the preferences
+                        let now_open = egui::collapsing_header::CollapsingState::load(ui.ctx(), id).is_none_or(|state| state.is_open());
+                        if now_open != open {
+                            section_to_fold = Some((key.clone(), !now_open));
+                        }
+                    }
+                });
+            });
+        });
+
+        // Handle actions after UI
+        if let Some(id) = tunnel_to_toggle {
+            self.start_or_stop(id, ctx);
+        }
+
+        if let Some((key, folded)) = section_to_fold {
+            if folded {
+                self.collapsed_sections.insert(key);
+            } else {
+                self.collapsed_sections.remove(&key);
+            }
+        }
+
+        if let Some(group) = group_to_start {
+            self.start_many(self.group_members(group.as_deref()), ctx);
+        }
+
+        if let Some(group) = group_to_stop {
+            self.stop_many(self.group_members(group.as_deref()));
+        }
+
+        if let Some(ids) = tunnels_to_start {
+            self.start_many(ids, ctx);
+        }
+
+        if let Some(ids) = tunnels_to_stop {
+            self.stop_many(ids);
+        }
+
+        if let Some((id, up)) = tunnel_to_move {
+            if let Err(e) = self.move_tunnel(id, up) {
+                self.report_error(Some(id), format!("Failed to move: {}", e));
+            }
+        }
+
+        if let Some((id, range)) = row_to_select {
+            self.select_row(id, range, &list_order);
+        }
+
+        if let Some(action) = bulk_action {
+            self.apply_bulk_action(action, ctx);
+        }
+
+        if self.confirm_bulk_delete.is_some() {
+            self.show_bulk_delete_dialog(ctx);
+        }
+
+        self.show_database_error(ctx);
+
+        if let Some((id, target, after)) = tunnel_to_drop {
+            if let Err(e) = self.drop_tunnel(id, target, after) {
+                self.report_error(Some(id), format!("Failed to move: {}", e));
+            }
+        }
+
+        if let Some((id, pinned)) = tunnel_to_pin {
+            if let Err(e) = self.set_pinned(id, pinned) {
+                self.report_error(Some(id), format!("Failed to pin: {}", e));
+            }
+        }
+
+        if let Some(id) = tunnel_to_cancel {
+            if self.cancel_start(id) {
+                debug!("Removed tunnel {} from the start queue", id);
+            }
+        }
+
+        if let Some(id) = tunnel_to_delete {
+            if let Err(e) = self.delete_tunnel(id) {
+                self.report_failure(id, "Failed to delete", e);
+            }
+        }
+
+        if let Some(id) = death_to_dismiss {
+            self.dismiss_death(id);
+        }
+        if let Some(id) = hook_failure_to_dismiss {
+            self.hook_failures.remove(&id);
+        }
+
+        if let Some(id) = tunnel_to_edit_inline {
+            self.start_inline_edit(id);
+        }
+        match inline_edit_done {
+            Some(true) => self.save_inline_edit(),
+            Some(false) => self.inline_edit = None,
+            None => {}
+        }
+
+        if let Some(id) = tunnel_to_restart {
+            if let Err(e) = self.restart_tunnel(id) {
+                self.report_failure(id, "Failed to restart", e);
+            }
+        }
+
+        if let Some(id) = last_stop_to_load {
+            self.load_last_stop(id);
+        }
+
+        if let Some(id) = tunnel_to_toggle_expand {
+            if self.expanded_tunnels.contains(&id) {
+                self.expanded_tunnels.remove(&id);
+                // Hiding the row drops an edit in progress there
+                if self.inline_edit.as_ref().is_some_and(|(edited, _)| *edited == id) {
+                    self.inline_edit = None;
+                }
+            } else {
+                self.expanded_tunnels.insert(id);
+                self.load_last_stop(id);
+            }
+        }
+
+        if let Some(id) = tunnel_to_edit {
+            self.start_edit_tunnel(id);
+        }
+
+        if let Some(id) = tunnel_to_duplicate {
+            self.start_duplicate_tunnel(id);
+        }
+
+        if let Some((id, append)) = tunnel_to_share {
+            self.share_as_ssh_config(ctx, id, append);
+        }
+
+        if let Some(id) = link_to_copy {
+            self.copy_share_link(ctx, id);
+        }
+
+        if let Some(id) = tunnel_to_show_history {
+            self.open_history(id);
+        }
+
+        if self.history.is_some() {
+            self.show_history_window(ctx);
+        }
+
+        self.show_error_toasts(ctx);
+
+        if let Some(url) = url_to_open {
+            open_url(&url);
+        }
+
+        if let Some(id) = tunnel_to_open_terminal {
+            self.open_terminal(id);
+        }
+
+        if self.terminal_dialog.is_some() {
+            self.show_terminal_dialog(ctx);
+        }
+
+        if self.paste_dialog.is_some() {
+            self.show_paste_dialog(ctx);
+        }
+
+        if let Some(command) = command_to_copy {
+            ctx.copy_text(command);
+            self.status_message = Some(tr("Copied ssh command").to_string());
+        }
+
+        if let Some((id, text)) = address_to_copy {
+            ctx.copy_text(text);
+            self.copied = Some((id, Instant::now()));
+        }
+        if let Some((_, at)) = self.copied {
+            match (at + COPIED_FEEDBACK).checked_duration_since(Instant::now()) {
+                Some(left) => ctx.request_repaint_after(left),
+                None => self.copied = None,
+            }
+        }
+
+        if let Some(id) = tunnel_to_export_service {
+            self.begin_service_export(id);
+        }
+
+        if let Some(id) = tunnel_to_template {
+            self.begin_save_template(id);
+        }
+
+        if self.template_dialog.is_some() {
+            self.show_template_dialog(ctx);
+        }
+
+        if self.show_templates {
+            self.show_templates_window(ctx);
+        }
+
+        if self.show_logs {
+            self.show_log_window(ctx);
+        }
+
+        if should_open_about {
+            self.open_about();
+        }
+
+        if self.show_about {
+            self.show_about_window(ctx);
+        }
+
+        if let Some(id) = tunnel_to_test {
+            self.test_tunnel(id, ctx);
+        }
+
+        if let Some((holder_id, blocked_id)) = tunnel_to_stop_for {
+            if self.active_tunnels.contains_key(&holder_id) {
+                if let Err(e) = self.toggle_tunnel(holder_id) {
+                    self.report_error(Some(holder_id), format!("Failed to stop: {}", e));
+                }
+            }
+            self.start_errors.remove(&blocked_id);
+            self.transition(blocked_id, TunnelState::Stopped, "");
+        }
+
+        if let Some(tag) = tag_to_filter {
+            self.tag_filter.insert(tag);
+        }
+
+        if let Some(tag) = tag_to_unfilter {
+            self.tag_filter.remove(&tag);
+        }
+
+        if dismiss_status {
+            self.status_message = None;
+        }
+
+        self.save_list_preferences();
+
+        if should_export {
+            self.export_tunnels();
+        }
+
+        if should_import {
+            self.begin_import();
+        }
+
+        if self.import_preview.is_some() {
+            self.show_import_window(ctx);
+        }
+
+        if should_import_ssh_config {
+            self.begin_ssh_config_import();
+        }
+
+        if should_create_examples {
+            self.create_examples();
+        }
+
+        #[cfg(windows)]
+        if should_import_putty {
+            self.begin_putty_import();
+        }
+
+        if self.ssh_config_import.is_some() {
+            self.show_ssh_config_import_window(ctx);
+        }
+
+        match workspace_action {
+            Some(WorkspaceAction::Switch(id)) => self.switch_workspace(id),
+            Some(WorkspaceAction::Create) => {
+                self.workspace_dialog = Some(WorkspaceDialog {
+                    id: None,
+                    name: String::new(),
+                    error: None,
+                });
+            }
+            Some(WorkspaceAction::Rename) => {
+                let name = self
+                    .workspaces
+                    .iter()
+                    .find(|w| w.id == self.workspace)
+                    .map(|w| w.name.clone())
+                    .unwrap_or_default();
+                self.workspace_dialog = Some(WorkspaceDialog {
+                    id: Some(self.workspace),
+                    name,
+                    error: None,
+                });
+            }
+            Some(WorkspaceAction::Delete) => self.delete_workspace(),
+            None => {}
+        }
+
+        if self.workspace_dialog.is_some() {
+            self.show_workspace_dialog(ctx);
+        }
+
+        if should_empty_trash {
+            self.begin_empty_trash();
+        }
+
+        if self.confirm_empty_trash.is_some() {
+            self.show_empty_trash_dialog(ctx);
+        }
+
+        if should_backup {
+            self.backup_database();
+        }
+
+        if should_restore_backup {
+            self.begin_restore_backup();
+        }
+
+        if self.confirm_restore_backup.is_some() {
+            self.show_restore_backup_dialog(ctx);
+        }
+
+        if let Some(theme) = theme_to_set {
+            self.set_theme(ctx, theme);
+        }
+
+        if let Some(level) = log_level_to_set {
+            self.set_log_level(level);
+        }
+        if let Some(behavior) = quit_behavior_to_set {
+            self.set_quit_behavior(behavior);
+        }
+        if let Some(density) = density_to_set {
+            self.set_density(density);
+        }
+
+        if let Some(language) = language_to_set {
+            self.set_language(language);
+        }
+
+        if should_quit {
+            self.request_quit(ctx);
+        }
+
+        if self.confirm_quit {
+            self.show_quit_dialog(ctx);
+        }
+
+        if self.service_export.is_some() {
+            self.show_service_export_dialog(ctx);
+        }
+
+        if self.pending_restore.is_some() {
+            self.show_restore_dialog(ctx);
+        }
+
+        if self.show_new_tunnel_window {
+            self.show_new_tunnel_window(ctx);
+        }
+
+        if self.show_edit_tunnel_window {
+            self.show_edit_tunnel_window(ctx);
+        }
+    }
+
+    /// Ids of every tunnel in `group`, or of ungrouped tunnels for `None`,
+    /// regardless of the current search and tag filter.
+    fn group_members(&self, group: Option<&str>) -> Vec<TunnelId> {
+        self.tunnels
+            .iter()
+            .filter(|t| !t.deleted && t.group.as_deref() == group)
+            .map(|t| t.id)
+            .collect()
+    }
+
+    /// Every tag in use, offered as completions in the tag editor.
+    fn known_tags(&self) -> BTreeSet<String> {
+        self.tunnels
+            .iter()
+            .flat_map(|t| t.tags.iter().cloned())
+            .collect()
+    }
+
+    /// Every server in use, offered as completions for the SSH Server field.
+    fn known_servers(&self) -> BTreeSet<String> {
+        self.tunnels
+            .iter()
+            .filter(|t| !t.deleted)
+            .map(|t| t.ssh_server.trim().to_string())
+            .filter(|server| !server.is_empty())
+            .collect()
+    }
+
+    /// Adds the Add Tunnel form's ports and name as a preset, replacing one
+    /// with the same name.
+    fn save_preset(&mut self) {
+        let preset = match presets::Preset::from_form(&self.new_tunnel) {
+            Ok(preset) => preset,
+            Err(e) => {
+                self.status_message = Some(e);
+                return;
+            }
+        };
+        self.custom_presets.retain(|existing| existing.label != preset.label);
+        let label = preset.label.clone();
+        self.custom_presets.push(preset);
+        let result = match &self.settings {
+            Some(settings) => serde_json::to_string(&self.custom_presets)
+                .map_err(anyhow::Error::from)
+                .and_then(|json| settings.set(presets::SETTING_KEY, &json))
+                .map_err(|e| format!("{:#}", e)),
+            None => Err(tr("Settings are unavailable").to_string()),
+        };
+        self.status_message = Some(match result {
+            Ok(()) => trf("Saved preset '{}'", &[&label]),
+            Err(e) => {
+                error!("Failed to save presets: {}", e);
+                trf("Preset '{}' is only kept until Onigiri closes: {}", &[&label, &e])
+            }
+        });
+    }
+
+    fn show_new_tunnel_window(&mut self, ctx: &egui::Context) {
+        self.new_tunnel.live_validate(ctx);
+        let known_tags = self.known_tags();
+        let known_servers = self.known_servers();
+        let mut should_save_preset = false;
+        egui::Window::new(tr("Add New Tunnel"))
+            .default_width(300.0)
+            .resizable(false)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.vertical(|ui| {
+                    ui.horizontal(|ui| {
+                        ui.label(tr("Paste command:"));
+                        let response = ui.add(
+                            egui::TextEdit::singleline(&mut self.new_tunnel.pasted_command)
+                                .hint_text("ssh -L 8443:internal:443 deploy@bastion"),
+                        );
+                        let entered = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                        if ui.small_button(tr("Fill in")).clicked() || entered {
+                            self.new_tunnel.fill_from_command();
+                        }
+                    });
+                    match &self.new_tunnel.paste_result {
+                        Some(Err(error)) => {
+                            ui.colored_label(tone(ui, Tone::Bad), error);
+                        }
+                        Some(Ok(note)) if !note.is_empty() => {
+                            ui.colored_label(tone(ui, Tone::Warning), note);
+                        }
+                        _ => {}
+                    }
+
+                    ui.horizontal(|ui| {
+                        ui.label(tr("Preset:"));
+                        egui::ComboBox::from_id_salt("preset")
+                            .selected_text(tr("Choose…"))
+                            .show_ui(ui, |ui| {
+                                let built_in = presets::built_in();
+                                for (index, preset) in built_in.iter().chain(&self.custom_presets).enumerate() {
+                                    if index == built_in.len() {
+                                        ui.separator();
+                                    }
+                                    let text = format!("{} ({})", preset.label, preset.remote_port);
+                                    if ui.selectable_label(false, text).clicked() {
+                                        preset.apply(&mut self.new_tunnel);
+                                    }
+                                }
+                            });
+                        if ui.small_button(tr("Save as preset")).on_hover_text(tr("Save the name and ports for next time")).clicked() {
+                            should_save_preset = true;
+                        }
+                    });
+                    ui.separator();
+
+                    let blanks = self.new_tunnel.from_template;
+                    if blanks {
+                        ui.colored_label(tone(ui, Tone::Pending), tr("Fill in the highlighted fields"));
+                    }
+
+                    ui.horizontal(|ui| {
+                        ui.label(tr("Name:"));
+                        let response = form_field(ui, &mut self.new_tunnel.name, blanks);
+                        self.new_tunnel.note_edit(FormField::Name, &response);
+                    });
+                    if let Some(error) = &self.new_tunnel.name_error {
+                        ui.colored_label(tone(ui, Tone::Bad), error);
+                    }
+
+                    ui.horizontal(|ui| {
+                        ui.label(tr("SSH Server:"));
+                        let response = server_field(ui, &mut self.new_tunnel.ssh_server, &known_servers, blanks);
+                        self.new_tunnel.note_edit(FormField::SshServer, &response);
+                    });
+                    if let Some(error) = &self.new_tunnel.ssh_server_error {
+                        ui.colored_label(tone(ui, Tone::Bad), error);
+                    }
+
+                    ui.horizontal(|ui| {
+                        ui.label(tr("Local IP:"));
+                        let response = form_field(ui, &mut self.new_tunnel.local_ip, blanks);
+                        self.new_tunnel.note_edit(FormField::LocalIp, &response);
+                    });
+                    if let Some(error) = &self.new_tunnel.local_ip_error {
+                        ui.colored_label(tone(ui, Tone::Bad), error);
+                    }
+
+                    ui.horizontal(|ui| {
+                        ui.label(tr("Local Port:"));
+                        let response = form_field(ui, &mut self.new_tunnel.local_port, blanks)
+                            .on_hover_text(tr("0 picks a free port at each start"));
+                        self.new_tunnel.note_edit(FormField::LocalPort, &response);
+                    });
+                    if let Some(error) = &self.new_tunnel.local_port_error {
+                        ui.colored_label(tone(ui, Tone::Bad), error);
+                    } else {
+                        port_conflict_note(ui, &self.new_tunnel, &self.tunnels, &self.active_tunnels, None);
+                    }
+
+                    ui.horizontal(|ui| {
+                        ui.label(tr("Remote IP:"));
+                        let response = form_field(ui, &mut self.new_tunnel.remote_ip, blanks);
+                        self.new_tunnel.note_edit(FormField::RemoteIp, &response);
+                    });
+                    if let Some(error) = &self.new_tunnel.remote_ip_error {
+                        ui.colored_label(tone(ui, Tone::Bad), error);
+                    }
+
+                    ui.horizontal(|ui| {
+                        ui.label(tr("Remote Port:"));
+                        let response = form_field(ui, &mut self.new_tunnel.remote_port, blanks);
+                        self.new_tunnel.note_edit(FormField::RemotePort, &response);
+                    });
+                    if let Some(error) = &self.new_tunnel.remote_port_error {
+                        ui.colored_label(tone(ui, Tone::Bad), error);
+                    }
+
+                    ui.horizontal(|ui| {
+                        ui.label(tr("SSH options:"));
+                        let response = ui.add(egui::TextEdit::singleline(&mut self.new_tunnel.extra_args).hint_text(tr("none")))
+                            .on_hover_text(tr("More ssh options, such as -i ~/.ssh/work or -J jump@edge"));
+                        self.new_tunnel.note_edit(FormField::ExtraArgs, &response);
+                    });
+                    if let Some(error) = &self.new_tunnel.extra_args_error {
+                        ui.colored_label(tone(ui, Tone::Bad), error);
+                    }
+                    agent_socket_field(ui, &mut self.new_tunnel);
+                    pre_start_field(ui, &mut self.new_tunnel);
+                    post_start_field(ui, &mut self.new_tunnel);
+
+                    ui.horizontal(|ui| {
+                        ui.label(tr("Idle timeout (min):"));
+                        let response = ui.add(egui::TextEdit::singleline(&mut self.new_tunnel.idle_timeout).hint_text(tr("off")));
+                        self.new_tunnel.note_edit(FormField::IdleTimeout, &response);
+                    });
+                    if let Some(error) = &self.new_tunnel.idle_timeout_error {
+                        ui.colored_label(tone(ui, Tone::Bad), error);
+                    }
+
+                    ui.horizontal(|ui| {
+                        ui.label(tr("Group:"));
+                        ui.add(egui::TextEdit::singleline(&mut self.new_tunnel.group).hint_text(tr("none")));
+                    });
+
+                    tag_editor(ui, &mut self.new_tunnel, &known_tags);
+                    color_picker(ui, &mut self.new_tunnel.color);
+                    url_scheme_picker(ui, &mut self.new_tunnel.url_scheme);
+                    ui.checkbox(&mut self.new_tunnel.autostart, tr("Start when Onigiri opens"));
+
+                    ui.label(tr("Notes:"));
+                    ui.add(egui::TextEdit::multiline(&mut self.new_tunnel.notes).desired_rows(3));
+
+                    ui.add_space(8.0);
+                    if let Some(error) = &self.new_tunnel.save_error {
+                        ui.colored_label(tone(ui, Tone::Bad), error);
+                    }
+
+                    ui.horizontal(|ui| {
+                        if ui.button(tr("Cancel")).clicked() {
+                            self.show_new_tunnel_window = false;
+                            self.new_tunnel = NewTunnelForm::default();
+                        }
+
+                        let problems = self.new_tunnel.problems(&self.tunnels, &self.active_tunnels, None);
+                        let add = ui.add_enabled(problems.is_empty(), egui::Button::new(tr("Add")));
+                        let add = add.on_disabled_hover_text(problems.join("\n"));
+                        if add.clicked() && self.new_tunnel.validate_with(&self.tunnels, &self.active_tunnels, None) {
+                            self.submit_new_tunnel();
+                        }
+                    });
+                });
+            });
+
+        if should_save_preset {
+            self.save_preset();
+        }
+    }
+
+    fn show_edit_tunnel_window(&mut self, ctx: &egui::Context) {
+        let known_tags = self.known_tags();
+        let known_servers = self.known_servers();
+        let mut should_save = None;
+        let mut should_close = false;
+        let mut secret_action = None;
+
+        if let Some((id, form)) = &mut self.edit_tunnel {
+            let editing = Some(*id);
+            form.live_validate(ctx);
+            egui::Window::new(tr("Edit Tunnel"))
+                .default_width(300.0)
+                .resizable(false)
+                .collapsible(false)
+                .show(ctx, |ui| {
+                    ui.vertical(|ui| {
+                        ui.horizontal(|ui| {
+                            ui.label(tr("Name:"));
+                            let response = ui.text_edit_singleline(&mut form.name);
+                            form.note_edit(FormField::Name, &response);
+                        });
+                        if let Some(error) = &form.name_error {
+                            ui.colored_label(tone(ui, Tone::Bad), error);
+                        }
+
+                        ui.horizontal(|ui| {
+                            ui.label(tr("SSH Server:"));
+                            let response = server_field(ui, &mut form.ssh_server, &known_servers, false);
+                            form.note_edit(FormField::SshServer, &response);
+                        });
+                        if let Some(error) = &form.ssh_server_error {
+                            ui.colored_label(tone(ui, Tone::Bad), error);
+                        }
+
+                        ui.horizontal(|ui| {
+                            ui.label(tr("Local IP:"));
+                            let response = ui.text_edit_singleline(&mut form.local_ip);
+                            form.note_edit(FormField::LocalIp, &response);
+                        });
+                        if let Some(error) = &form.local_ip_error {
+                            ui.colored_label(tone(ui, Tone::Bad), error);
+                        }
+
+                        ui.horizontal(|ui| {
+                            ui.label(tr("Local Port:"));
+                            let response = ui.text_edit_singleline(&mut form.local_port)
+                                .on_hover_text(tr("0 picks a free port at each start"));
+                            form.note_edit(FormField::LocalPort, &response);
+                        });
+                        if let Some(error) = &form.local_port_error {
+                            ui.colored_label(tone(ui, Tone::Bad), error);
+                        } else {
+                            port_conflict_note(ui, form, &self.tunnels, &self.active_tunnels, editing);
+                        }
+
+                        ui.horizontal(|ui| {
+                            ui.label(tr("Remote IP:"));
+                            let response = ui.text_edit_singleline(&mut form.remote_ip);
+                            form.note_edit(FormField::RemoteIp, &response);
+                        });
+                        if let Some(error) = &form.remote_ip_error {
+                            ui.colored_label(tone(ui, Tone::Bad), error);
+                        }
+
+                        ui.horizontal(|ui| {
+                            ui.label(tr("Remote Port:"));
+                            let response = ui.text_edit_singleline(&mut form.remote_port);
+                            form.note_edit(FormField::RemotePort, &response);
+                        });
+                        if let Some(error) = &form.remote_port_error {
+                            ui.colored_label(tone(ui, Tone::Bad), error);
+                        }
+
+                        ui.horizontal(|ui| {
+                            ui.label(tr("SSH options:"));
+                            let response = ui.add(egui::TextEdit::singleline(&mut form.extra_args).hint_text(tr("none")))
+                                .on_hover_text(tr("More ssh options, such as -i ~/.ssh/work or -J jump@edge"));
+                            form.note_edit(FormField::ExtraArgs, &response);
+                        });
+                        if let Some(error) = &form.extra_args_error {
+                            ui.colored_label(tone(ui, Tone::Bad), error);
+                        }
+                        agent_socket_field(ui, form);
+                        pre_start_field(ui, form);
+                        post_start_field(ui, form);
+
+                        ui.horizontal(|ui| {
+                            ui.label(tr("Idle timeout (min):"));
+                            let response = ui.add(egui::TextEdit::singleline(&mut form.idle_timeout).hint_text(tr("off")));
+                            form.note_edit(FormField::IdleTimeout, &response);
+                        });
+                        if let Some(error) = &form.idle_timeout_error {
+                            ui.colored_label(tone(ui, Tone::Bad), error);
+                        }
+
+                        ui.horizontal(|ui| {
+                            ui.label(tr("Group:"));
+                            ui.add(egui::TextEdit::singleline(&mut form.group).hint_text(tr("none")));
+                        });
+
+                        tag_editor(ui, form, &known_tags);
+                        color_picker(ui, &mut form.color);
+                        url_scheme_picker(ui, &mut form.url_scheme);
+                        ui.checkbox(&mut form.autostart, tr("Start when Onigiri opens"));
+
+                        ui.label(tr("Notes:"));
+                        ui.add(egui::TextEdit::multiline(&mut form.notes).desired_rows(3));
+
+                        ui.horizontal(|ui| {
+                            ui.label(tr("Secret:"));
+                            if form.secret_ref.is_some() {
+                                ui.weak(tr("in system keychain"));
+                                if ui.small_button(tr("Copy")).clicked() {
+                                    secret_action = Some(SecretAction::Copy);
+                                }
+                                if ui.small_button(tr("Clear secret")).clicked() {
+                                    secret_action = Some(SecretAction::Clear);
+                                }
+                            } else {
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut form.secret_input)
+                                        .password(true)
+                                        .hint_text(tr("none"))
+                                        .desired_width(120.0),
+                                );
+                                if ui
+                                    .add_enabled(!form.secret_input.is_empty(), egui::Button::new(tr("Set secret")).small())
+                                    .clicked()
+                                {
+                                    secret_action = Some(SecretAction::Set(form.secret_input.clone()));
+                                }
+                            }
+                        });
+
+                        ui.add_space(8.0);
+                        if let Some(error) = &form.save_error {
+                            ui.colored_label(tone(ui, Tone::Bad), error);
+                        }
+
+                        if self.confirm_restart {
+                            ui.colored_label(
+                                tone(ui, Tone::Warning),
+                                tr("Saving will restart this tunnel and drop existing connections."),
+                            );
+                            ui.horizontal(|ui| {
+                                if ui.button(tr("Save and restart")).clicked() {
+                                    should_save = Some(true);
+                                }
+                                if ui.button(tr("Save without restarting")).clicked() {
+                                    should_save = Some(false);
+                                }
+                                if ui.button(tr("Cancel")).clicked() {
+                                    self.confirm_restart = false;
+                                }
+                            });
+                            return;
+                        }
+
+                        ui.horizontal(|ui| {
+                            if ui.button(tr("Cancel")).clicked() {
+                                should_close = true;
+                            }
+
+                            let problems = form.problems(&self.tunnels, &self.active_tunnels, editing);
+                            let save = ui.add_enabled(problems.is_empty(), egui::Button::new(tr("Save")));
+                            let save = save.on_disabled_hover_text(problems.join("\n"));
+                            if save.clicked() && form.validate_with(&self.tunnels, &self.active_tunnels, editing) {
+                                // Restarting drops the connections through the tunnel, so ask first
+                                if self.active_tunnels.contains_key(&*id) {
+                                    self.confirm_restart = true;
+                                } else {
+                                    should_save = Some(true);
+                                }
+                            }
+                        });
+                    });
+                });
+        }
+
+        if let (Some(action), Some((id, _))) = (secret_action, &self.edit_tunnel) {
+            let id = *id;
+            let result = match action {
+                SecretAction::Set(secret) => self.set_tunnel_secret(id, Some(secret)),
+                SecretAction::Clear => self.set_tunnel_secret(id, None),
+                SecretAction::Copy => self
+                    .tunnels
+                    .iter()
+                    .find(|t| t.id == id)
+                    .and_then(|t| t.secret_ref.as_deref())
+                    .ok_or_else(|| "No secret is set".to_string())
+                    .and_then(secrets::get)
+                    .map(|secret| ctx.copy_text(secret)),
+            };
+            if let Err(e) = result {
+                error!("Secret update for tunnel {} failed: {}", id, e);
+                self.status_message = Some(e);
+            }
+        }
+
+        if let Some(restart) = should_save {
+            if let Err(e) = self.save_edited_tunnel(restart) {
+                error!("Failed to save tunnel: {}", e);
+                self.confirm_restart = false;
+            }
+        } else if should_close {
+            self.show_edit_tunnel_window = false;
+            self.edit_tunnel = None;
+            self.confirm_restart = false;
+        }
+    }
+}
+
+/// The name, server and endpoint fields of an expanded row being edited.
+/// Sets `done` to Some(true) for Save and Some(false) for Cancel.
+/// `problems` keeps Save disabled until the form would pass.
+fn inline_edit_form(ui: &mut egui::Ui, form: &mut NewTunnelForm, problems: &[String], done: &mut Option<bool>) {
+    let mut edited = Vec::new();
+    egui::Grid::new("inline_edit").num_columns(2).show(ui, |ui| {
+        let fields = [
+            (FormField::Name, "Name:", &mut form.name, &form.name_error),
+            (FormField::SshServer, "SSH Server:", &mut form.ssh_server, &form.ssh_server_error),
+            (FormField::LocalIp, "Local IP:", &mut form.local_ip, &form.local_ip_error),
+            (FormField::LocalPort, "Local Port:", &mut form.local_port, &form.local_port_error),
+            (FormField::RemoteIp, "Remote IP:", &mut form.remote_ip, &form.remote_ip_error),
+            (FormField::RemotePort, "Remote Port:", &mut form.remote_port, &form.remote_port_error),
+        ];
+        for (field, label, value, error) in fields {
+            ui.label(label);
+            ui.vertical(|ui| {
+                edited.push((field, ui.text_edit_singleline(value)));
+                if let Some(error) = error {
+                    ui.colored_label(tone(ui, Tone::Bad), error);
+                }
+            });
+            ui.end_row();
+        }
+    });
+    for (field, response) in &edited {
+        form.note_edit(*field, response);
+    }
+    ui.horizontal(|ui| {
+        if ui.small_button(tr("Cancel")).clicked() {
+            *done = Some(false);
+        }
+        let save = ui.add_enabled(problems.is_empty(), egui::Button::new(tr("Save")).small());
+        if save.on_disabled_hover_text(problems.join("\n")).clicked() {
+            *done = Some(true);
+        }
+    });
+}
+
+/// A text field of the Add window; a template's blanks are outlined until
+/// filled in.
+fn form_field(ui: &mut egui::Ui, value: &mut String, blanks: bool) -> egui::Response {
+    let highlight = blanks && value.trim().is_empty();
+    ui.scope(|ui| {
+        let mut edit = egui::TextEdit::singleline(value);
+        if highlight {
+            let stroke = egui::Stroke::new(1.5, tone(ui, Tone::Pending));
+            let widgets = &mut ui.visuals_mut().widgets;
+            widgets.inactive.bg_stroke = stroke;
+            widgets.hovered.bg_stroke = stroke;
+            edit = edit.hint_text(egui::RichText::new("fill in").color(tone(ui, Tone::Pending)));
+        }
+        ui.add(edit)
+    })
+    .inner
+}
+
+/// The SSH Server field, with a drop-down of the servers already in use
+/// that contain what's typed. Any other value can still be entered.
+fn server_field(
+    ui: &mut egui::Ui,
+    value: &mut String,
+    known_servers: &BTreeSet<String>,
+    blanks: bool,
+) -> egui::Response {
+    let response = form_field(ui, value, blanks);
+    let popup_id = response.id.with("suggestions");
+    let typed = value.trim().to_lowercase();
+    let suggestions: Vec<&String> = known_servers
+        .iter()
+        .filter(|server| server.to_lowercase().contains(&typed) && server.as_str() != value.trim())
+        .take(8)
+        .collect();
+
+    let left_by_keyboard =
+        response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter) || i.key_pressed(egui::Key::Tab));
+    if response.has_focus() && !suggestions.is_empty() {
+        ui.memory_mut(|m| m.open_popup(popup_id));
+    } else if suggestions.is_empty() || left_by_keyboard {
+        ui.memory_mut(|m| {
+            if m.is_popup_open(popup_id) {
+                m.close_popup();
+            }
+        });
+    }
+    egui::popup_below_widget(ui, popup_id, &response, egui::PopupCloseBehavior::CloseOnClick, |ui| {
+        ui.set_min_width(response.rect.width());
+        for server in suggestions {
+            if ui.selectable_label(false, server.as_str()).clicked() {
+                *value = server.clone();
+            }
+        }
+    });
+    response
+}
+
+/// Names the tunnel already using the form's local address, in red if it is
+/// running and saving would be refused.
+fn port_conflict_note(
+    ui: &mut egui::Ui,
+    form: &NewTunnelForm,
+    tunnels: &[Tunnel],
+    running: &HashMap<TunnelId, TunnelInfo>,
+    editing: Option<TunnelId>,
+) {
+    let Some(other) = form.port_conflict(tunnels, editing) else {
+        return;
+    };
+    if running.contains_key(&other.id) {
+        ui.colored_label(tone(ui, Tone::Bad), trf("'{}' is running on this address", &[&other.name]));
+    } else {
+        ui.colored_label(
+            tone(ui, Tone::Warning),
+            trf("'{}' also uses this address; only one can run at a time", &[&other.name]),
+        );
+    }
+}
+
+/// The pre-start hook field, with how long the hook may run.
+fn pre_start_field(ui: &mut egui::Ui, form: &mut NewTunnelForm) {
+    ui.horizontal(|ui| {
+        ui.label(tr("Before start:"));
+        let response = ui.add(egui::TextEdit::singleline(&mut form.pre_start).hint_text(tr("none")))
+            .on_hover_text(tr("A shell command run before ssh, such as tsh login; the start fails if it does"));
+        form.note_edit(FormField::PreStart, &response);
+        ui.label(tr("Timeout (s):"));
+        let default = hooks::PRE_START_TIMEOUT.as_secs().to_string();
+        let response = ui.add(egui::TextEdit::singleline(&mut form.pre_start_timeout).hint_text(default).desired_width(40.0));
+        form.note_edit(FormField::PreStart, &response);
+    });
+    if let Some(error) = &form.pre_start_error {
+        ui.colored_label(tone(ui, Tone::Bad), error);
+    }
+}
+
+/// The post-start hook field.
+fn post_start_field(ui: &mut egui::Ui, form: &mut NewTunnelForm) {
+    ui.horizontal(|ui| {
+        ui.label(tr("After start:"));
+        let response = ui.add(egui::TextEdit::singleline(&mut form.post_start).hint_text(tr("none")))
+            .on_hover_text(tr("A shell command run once the tunnel is up, with ONIGIRI_LOCAL_PORT, ONIGIRI_LOCAL_IP and ONIGIRI_TUNNEL_NAME set"));
+        form.note_edit(FormField::PostStart, &response);
+    });
+    if let Some(error) = &form.post_start_error {
+        ui.colored_label(tone(ui, Tone::Bad), error);
+    }
+}
+
+/// The agent socket field, with a menu of the agents found on this machine.
+fn agent_socket_field(ui: &mut egui::Ui, form: &mut NewTunnelForm) {
+    ui.horizontal(|ui| {
+        ui.label(tr("SSH agent:"));
+        let response = ui.add(egui::TextEdit::singleline(&mut form.agent_socket).hint_text(tr("inherited")))
+            .on_hover_text(tr("The agent socket given to ssh as SSH_AUTH_SOCK"));
+        form.note_edit(FormField::AgentSocket, &response);
+        ui.menu_button("▾", |ui| {
+            let found = agent::candidates();
+            if found.is_empty() {
+                ui.weak(tr("No agents found"));
+            }
+            for (label, path) in found {
+                if ui.button(format!("{} — {}", label, path)).clicked() {
+                    form.agent_socket = path;
+                    form.touched.insert(FormField::AgentSocket);
+                    form.edited_at = Some(Instant::now());
+                    ui.close_menu();
+                }
+            }
+            ui.separator();
+            if ui.button(tr("Inherited from Onigiri")).clicked() {
+                form.agent_socket.clear();
+                ui.close_menu();
+            }
+        });
+    });
+    if let Some(error) = &form.agent_socket_error {
+        ui.colored_label(tone(ui, Tone::Bad), error);
+    }
+}
+
+/// How the Open button builds the tunnel's URL; Auto goes by the port and tags.
+fn url_scheme_picker(ui: &mut egui::Ui, scheme: &mut Option<String>) {
+    ui.horizontal(|ui| {
+        ui.label(tr("Open as:"));
+        egui::ComboBox::from_id_salt("url_scheme")
+            .selected_text(scheme.as_deref().unwrap_or(tr("Auto")))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(scheme, None, tr("Auto"));
+                for choice in ["http", "https"] {
+                    ui.selectable_value(scheme, Some(choice.to_string()), choice);
+                }
+            });
+    });
+}
+
+/// A row of swatches for the add and edit windows; ∅ clears the color.
+fn color_picker(ui: &mut egui::Ui, color: &mut Option<ColorLabel>) {
+    ui.horizontal(|ui| {
+        ui.label(tr("Color:"));
+        if ui.selectable_label(color.is_none(), "∅").on_hover_text(tr("No color")).clicked() {
+            *color = None;
+        }
+        for label in ColorLabel::ALL {
+            let selected = *color == Some(label);
+            let swatch = egui::Button::new(if selected { "✔" } else { "" })
+                .fill(label.color())
+                .min_size(egui::vec2(18.0, 18.0))
+                .corner_radius(4.0);
+            if ui.add(swatch).on_hover_text(label.key()).clicked() {
+                *color = Some(label);
+            }
+        }
+    });
+}
+
+/// A stable color per tag so the same tag looks the same in every row.
+fn tag_color(tag: &str) -> egui::Color32 {
+    let hash = tag
+        .bytes()
+        .fold(0u32, |hash, byte| hash.wrapping_mul(31).wrapping_add(byte as u32));
+    let hue = (hash % 360) as f32 / 360.0;
+    egui::epaint::Hsva::new(hue, 0.55, 0.55, 1.0).into()
+}
+
+/// A clickable colored tag; `removable` adds a ✕ to show a click removes it.
+fn tag_chip(ui: &mut egui::Ui, tag: &str, removable: bool) -> egui::Response {
+    let text = if removable {
+        format!("{} ✕", tag)
+    } else {
+        tag.to_string()
+    };
+    ui.add(
+        egui::Button::new(egui::RichText::new(text).small().color(egui::Color32::WHITE))
+            .fill(tag_color(tag))
+            .corner_radius(8.0)
+            .small(),
+    )
+}
+
+/// Tag list and input shared by the add and edit windows. Enter adds the
+/// typed tag; existing tags starting with the typed text are offered below.
+fn tag_editor(ui: &mut egui::Ui, form: &mut NewTunnelForm, known_tags: &BTreeSet<String>) {
+    let mut removed = None;
+    ui.horizontal_wrapped(|ui| {
+        ui.label(tr("Tags:"));
+        for (index, tag) in form.tags.iter().enumerate() {
+            if tag_chip(ui, tag, true).clicked() {
+                removed = Some(index);
+            }
+        }
+        let response =
+            ui.add(egui::TextEdit::singleline(&mut form.tag_input).hint_text(tr("add tag")).desired_width(80.0));
+        if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+            form.add_tag();
+            response.request_focus();
+        }
+    });
+    if let Some(index) = removed {
+        form.tags.remove(index);
+    }
+
+    let typed = form.tag_input.trim().to_lowercase();
+    if typed.is_empty() {
+        return;
+    }
+    let suggestions: Vec<&String> = known_tags
+        .iter()
+        .filter(|tag| tag.to_lowercase().starts_with(&typed) && !form.tags.contains(tag))
+        .take(5)
+        .collect();
+    if suggestions.is_empty() {
+        return;
+    }
+    ui.horizontal_wrapped(|ui| {
+        ui.weak(tr("Existing:"));
+        for tag in suggestions {
+            if tag_chip(ui, tag, false).clicked() {
+                form.tag_input = tag.clone();
+                form.add_tag();
+            }
+        }
+    });
+}
+
+impl Tunneler {
+    /// Records the running ssh processes for the next launch to take over
+    /// instead of stopping them.
+    fn leave_tunnels_running(&mut self) {
+        info!("Application shutting down, leaving {} tunnels running", self.active_tunnels.len());
+        let processes: Vec<LeftRunning> = self
+            .active_tunnels
+            .iter()
+            .filter_map(|(id, info)| {
+                info.pid().map(|pid| LeftRunning {
+                    tunnel_id: *id,
+                    pid,
+                    started_at: info.started_at,
+                    local_port: info.local_port,
+                })
+            })
+            .collect();
+        let Some(store) = self.store.as_mut() else {
+            return;
+        };
+        if let Err(e) = store.save_processes(&processes) {
+            error!("Failed to save tunnels left running: {:#}", e);
+        }
+        let now = unix_now();
+        for process in &processes {
+            if let Err(e) = store.record_event(process.tunnel_id, now, EventKind::Status, "Left running when Onigiri quit") {
+                error!("Failed to record event for tunnel {}: {:#}", process.tunnel_id, e);
+            }
+        }
+        let running: Vec<TunnelId> = processes.iter().map(|process| process.tunnel_id).collect();
+        if let Err(e) = store.save_session(&running) {
+            error!("Failed to save running tunnels: {:#}", e);
+        }
+    }
+
+    /// Stops the tunnels, or leaves them running when `leave_running` is
+    /// set, and saves what the next launch needs. Runs once, whether from a
+    /// termination signal or from Drop.
+    fn shut_down(&mut self) {
+        if std::mem::replace(&mut self.shut_down, true) {
+            return;
+        }
+        if self.leave_running {
+            self.leave_tunnels_running();
+            self.active_tunnels.clear();
+            return;
+        }
+        // Drained so that frames drawn before the window closes don't
+        // report them as exited
+        let mut stopping: Vec<TunnelInfo> = self.active_tunnels.drain().map(|(_, info)| info).collect();
+        let running: Vec<TunnelId> = stopping.iter().map(|info| info.id).collect();
+        info!("Application shutting down, stopping {} tunnels", stopping.len());
+        let started = Instant::now();
+        let killed = stop_tunnels(&mut stopping, SHUTDOWN_TIMEOUT);
+        for id in &running {
+            self.transition(*id, TunnelState::Stopped, "App closed");
+        }
+        if let Some(store) = self.store.as_mut() {
+            if let Err(e) = store.save_session(&running) {
+                error!("Failed to save running tunnels: {:#}", e);
+            }
+        }
+        info!(
+            "Stopped {} tunnels in {:.1}s, {} of them killed after ignoring SIGTERM",
+            running.len(),
+            started.elapsed().as_secs_f32(),
+            killed
+        );
+    }
+}
+
+impl Drop for Tunneler {
+    fn drop(&mut self) {
+        self.shut_down();
+        if self.window_geometry != self.saved_geometry {
+            self.save_window_geometry();
+        }
+    }
+}
+
+impl eframe::App for Tunneler {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        #[cfg(any(target_os = "macos", windows))]
+        self.handle_tray_actions(ctx);
+        self.handle_control_requests(ctx);
+        self.update(ctx, frame);
+        if std::mem::take(&mut self.tunnels_dirty) {
+            self.load_tunnels();
+        }
+        #[cfg(any(target_os = "macos", windows))]
+        self.sync_tray();
+        self.sync_badge(ctx);
+    }
+}
+
+fn main() -> Result<(), eframe::Error> {
+    let cli = Cli::parse();
+    if let Some(dir) = &cli.data_dir {
+        // The socket and the About window want a path that doesn't depend
+        // on the working directory
+        storage::set_data_dir(std::path::absolute(dir).unwrap_or_else(|_| dir.clone()));
+    }
+    if let Some(command) = cli.command.clone() {
+        // Subcommands print their own results; logs only when asked for
+        if std::env::var_os("RUST_LOG").is_none() {
+            std::env::set_var("RUST_LOG", "off");
+        }
+        pretty_env_logger::init_timed();
+        let code = match command {
+            CliCommand::Tunnels(request) => cli::run(&cli, request),
+            CliCommand::Completions { shell } => {
+                print!("{}", completions::script(shell, &mut Cli::command()));
+                0
+            }
+            CliCommand::CompleteNames => cli::print_names(&cli),
+        };
+        std::process::exit(code);
+    }
+    let log_dir = storage::data_dir().ok().map(|dir| logging::log_dir(&dir));
+    logging::init(log_dir.as_deref());
+    info!("Starting {} application", APP_NAME);
+    debug!("Window dimensions: {}x{}", WINDOW_WIDTH, WINDOW_HEIGHT);
+
+    let icon = image::load_from_memory(include_bytes!("../resources/icon.png")).unwrap().to_rgba8();
+    let (icon_width, icon_height) = icon.dimensions();
+    let icon = Arc::new(egui::IconData {
+        rgba: icon.into_raw(),
+        width: icon_width,
+        height: icon_height,
+    });
+
+    // Storage problems are reported by Tunneler::new; here they only cost
+    // the saved window position
+    let settings = match storage::database_path().and_then(|path| Settings::open(&path)) {
+        Ok(settings) => Some(settings),
+        Err(e) => {
+            error!("Failed to open settings: {:#}", e);
+            None
+        }
+    };
+    if let Some(level) = settings
+        .as_ref()
+        .and_then(|settings| settings.get(logging::SETTING_KEY).ok().flatten())
+        .and_then(|key| logging::parse_level(&key))
+    {
+        logging::set_level(level);
+    }
+    let restored = settings
+        .as_ref()
+        .and_then(|settings| settings.get(window::SETTING_KEY).ok().flatten())
+        .and_then(|value| WindowGeometry::parse(&value));
+
+    let mut viewport = eframe::egui::ViewportBuilder::default()
+        .with_inner_size([WINDOW_WIDTH, WINDOW_HEIGHT])
+        .with_min_inner_size([MIN_WINDOW_WIDTH, 300.0])
+        .with_icon(icon);
+    if let Some(geometry) = restored {
+        debug!("Restoring window geometry {:?}", geometry);
+        viewport = viewport
+            .with_position([geometry.x, geometry.y])
+            .with_inner_size([geometry.width, geometry.height]);
+    }
+    let options = eframe::NativeOptions {
+        viewport,
+        ..Default::default()
+    };
+
+    // Run the app
+    let result = eframe::run_native(
+        APP_NAME,
+        options,
+        Box::new(move |cc| {
+            #[allow(unused_mut)]
+            let mut app = Tunneler::new(&cli, settings, restored);
+            app.exit_watch.ctx = cc.egui_ctx.clone();
+            cc.egui_ctx.set_theme(app.theme.preference());
+            #[cfg(any(target_os = "macos", windows))]
+            {
+                app.tray = tray::Tray::new(&cc.egui_ctx)
+                    .map_err(|e| error!("No tray icon: {}", e))
+                    .ok();
+            }
+            let ctx = cc.egui_ctx.clone();
+            app.control = control::socket_path()
+                .map_err(|e| format!("{:#}", e))
+                .and_then(|path| {
+                    control::Server::start(&path, move || ctx.request_repaint()).map_err(|e| e.to_string())
+                })
+                .map_err(|e| warn!("Not listening for commands from the terminal: {}", e))
+                .ok();
+            // Quits through `update` so the quit setting is honored
+            let ctx = cc.egui_ctx.clone();
+            if let Err(e) = ctrlc::set_handler(move || {
+                RUNNING.store(false, Ordering::SeqCst);
+                ctx.request_repaint();
+            }) {
+                warn!("Failed to set the termination handler: {}", e);
+            }
+            Ok(Box::new(app))
+        }),
+    );
+
+    info!("Application terminated");
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tunnel(ssh_server: &str) -> Tunnel {
+        NewTunnelForm {
+            name: "Prod DB".to_string(),
+            ssh_server: ssh_server.to_string(),
+            local_ip: "127.0.0.2".to_string(),
+            local_port: "15432".to_string(),
+            remote_ip: "db.internal".to_string(),
+            remote_port: "5432".to_string(),
+            ..Default::default()
+        }
+        .to_tunnel(TunnelId(1))
+    }
+
+    #[test]
+    fn live_validation_waits_for_touched_fields() {
+        let ctx = egui::Context::default();
+        let mut form = NewTunnelForm {
+            local_port: "99999".to_string(),
+            touched: HashSet::from([FormField::LocalPort]),
+            edited_at: Some(Instant::now() - LIVE_VALIDATION_DELAY),
+            ..Default::default()
+        };
+        form.live_validate(&ctx);
+        assert!(form.local_port_error.is_some());
+        assert!(form.name_error.is_none());
+        assert!(form.edited_at.is_none());
+        assert!(!form.problems(&[], &HashMap::new(), None).is_empty());
+    }
+
+    #[test]
+    fn utc_times_read_as_dates() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_utc(1_709_993_100), "2024-03-09 14:05 UTC");
+    }
+
+    #[test]
+    fn subcommands_parse() {
+        Cli::command().debug_assert();
+        let cli = Cli::try_parse_from(["onigiri", "stop", "--all"]).unwrap();
+        let stop_all = control::Request::Stop { tunnels: Vec::new(), all: true };
+        assert_eq!(cli.command, Some(CliCommand::Tunnels(stop_all)));
+        let cli = Cli::try_parse_from(["onigiri", "__complete-names"]).unwrap();
+        assert_eq!(cli.command, Some(CliCommand::CompleteNames));
+        let cli = Cli::try_parse_from(["onigiri", "list", "--data-dir", "work"]).unwrap();
+        assert_eq!(cli.data_dir, Some(PathBuf::from("work")));
+        assert!(Cli::try_parse_from(["onigiri", "start"]).is_err());
+        assert!(Cli::try_parse_from(["onigiri"]).unwrap().command.is_none());
+    }
+
+    #[test]
+    fn selection_ranges_run_either_way() {
+        let order = [4, 1, 7, 3].map(TunnelId);
+        assert_eq!(selection_range(&order, TunnelId(1), TunnelId(3)), [1, 7, 3].map(TunnelId));
+        assert_eq!(selection_range(&order, TunnelId(3), TunnelId(4)), order);
+        // An anchor no longer listed only selects the clicked row
+        assert_eq!(selection_range(&order, TunnelId(9), TunnelId(7)), [TunnelId(7)]);
+    }
+
+    #[test]
+    fn port_conflicts_skip_the_edited_tunnel() {
+        let existing = vec![tunnel("bastion")];
+        let mut form = NewTunnelForm::from_tunnel(&existing[0]);
+        assert_eq!(form.port_conflict(&existing, None).map(|t| t.id), Some(TunnelId(1)));
+        assert!(form.port_conflict(&existing, Some(TunnelId(1))).is_none());
+
+        form.local_ip = "0.0.0.0".to_string();
+        assert!(form.port_conflict(&existing, None).is_some());
+        form.local_ip = "127.0.0.1".to_string();
+        assert!(form.port_conflict(&existing, None).is_none());
+    }
+
+    #[test]
+    fn command_keeps_local_address_and_server_port() {
+        let tunnel = tunnel("ssh://deploy@bastion:2222");
+        assert_eq!(
+            build_command(&tunnel),
+            ["ssh", "-N", "ssh://deploy@bastion:2222", "-L", "127.0.0.2:15432:db.internal:5432"]
+        );
+    }
+
+    #[test]
+    fn ipv6_forwards_are_bracketed() {
+        let mut tunnel = tunnel("bastion");
+        tunnel.local_ip = "::1".to_string();
+        tunnel.remote_ip = "2001:db8::5432".to_string();
+        assert_eq!(
+            build_command(&tunnel),
+            ["ssh", "-N", "bastion", "-L", "[::1]:15432:[2001:db8::5432]:5432"]
+        );
+    }
+
+    #[test]
+    fn servers_with_a_port_get_the_ssh_scheme() {
+        for (typed, saved) in [
+            ("Deploy@Bastion:2222", "ssh://Deploy@bastion:2222"),
+            ("10.0.0.1:22", "ssh://10.0.0.1:22"),
+            ("deploy@[2001:db8::1]:2222", "ssh://deploy@[2001:db8::1]:2222"),
+            ("ssh://bastion:2222", "ssh://bastion:2222"),
+            ("deploy@bastion", "deploy@bastion"),
+            ("::1", "::1"),
+            ("[::1]", "[::1]"),
+        ] {
+            assert_eq!(normalize_server(typed), saved, "{}", typed);
+        }
+
+        let mut form = NewTunnelForm::from_tunnel(&tunnel("bastion"));
+        form.ssh_server = "deploy@bastion:2222".to_string();
+        assert!(form.validate(), "{:?}", form.errors());
+        assert_eq!(
+            build_command(&form.to_tunnel(TunnelId(1))),
+            ["ssh", "-N", "ssh://deploy@bastion:2222", "-L", "127.0.0.2:15432:db.internal:5432"]
+        );
+
+        let environment = |value: &str| {
+            expand::expand_with(value, None, |name| (name == "BASTION").then(|| "deploy@bastion:2222".to_string()))
+        };
+        let expanded = tunnel("$BASTION").expanded_with(environment).unwrap();
+        assert_eq!(expanded.ssh_server, "ssh://deploy@bastion:2222");
+    }
+
+    #[test]
+    fn extra_options_are_expanded_word_by_word() {
+        let mut form = NewTunnelForm::from_tunnel(&tunnel("bastion"));
+        form.extra_args = "-i ~/.ssh/work -J $JUMP -o 'ProxyCommand=nc ${PROXY} %h' -i '$HOME/my key'".to_string();
+        assert!(form.validate(), "{:?}", form.errors());
+        let tunnel = form.to_tunnel(TunnelId(1));
+        assert!(tunnel.has_references());
+        let environment = |value: &str| {
+            expand::expand_with(value, Some(Path::new("/home/deploy")), |name| match name {
+                "JUMP" => Some("jump@edge".to_string()),
+                "PROXY" => Some("proxy.internal".to_string()),
+                "HOME" => Some("/home/deploy".to_string()),
+                _ => None,
+            })
+        };
+        assert_eq!(
+            build_command(&tunnel.expanded_with(environment).unwrap()),
+            [
+                "ssh",
+                "-N",
+                "-i",
+                "/home/deploy/.ssh/work",
+                "-J",
+                "jump@edge",
+                "-o",
+                "ProxyCommand=nc proxy.internal %h",
+                "-i",
+                "/home/deploy/my key",
+                "bastion",
+                "-L",
+                "127.0.0.2:15432:db.internal:5432",
+            ]
+        );
+
+        let unset = |value: &str| expand::expand_with(value, None, |_| None);
+        assert!(matches!(
+            tunnel.expanded_with(unset),
+            Err(OnigiriError::Validation { field: Some(FormField::ExtraArgs), .. })
+        ));
+        form.extra_args = "-J ${JUMP".to_string();
+        assert!(!form.validate());
+        assert!(form.extra_args_error.is_some());
+    }
+
+    #[test]
+    fn extra_options_go_before_the_server() {
+        let mut form = NewTunnelForm::from_tunnel(&tunnel("bastion"));
+        form.extra_args = "-i '~/.ssh/work key' -J jump@edge".to_string();
+        assert!(form.validate(), "{:?}", form.errors());
+        assert_eq!(
+            build_command(&form.to_tunnel(TunnelId(1))),
+            ["ssh", "-N", "-i", "~/.ssh/work key", "-J", "jump@edge", "bastion", "-L", "127.0.0.2:15432:db.internal:5432"]
+        );
+
+        for (args, error) in [("-o 'open", "quote"), ("-CfN", "-f"), ("-o\u{7}", "control")] {
+            form.extra_args = args.to_string();
+            assert!(!form.validate());
+            let message = form.extra_args_error.clone().unwrap();
+            assert!(message.contains(error), "{}: {}", args, message);
+        }
+
+        let mut form = NewTunnelForm {
+            pasted_command: "ssh -L 8443:internal:443 -i ~/.ssh/work deploy@bastion".to_string(),
+            ..Default::default()
+        };
+        form.fill_from_command();
+        assert!(form.validate(), "{:?}", form.errors());
+        assert_eq!(form.extra_args, "-i '~/.ssh/work'");
+        assert!(matches!(&form.paste_result, Some(Ok(note)) if note.contains("-i")));
+        form.pasted_command = "scp file bastion:".to_string();
+        form.fill_from_command();
+        assert!(matches!(form.paste_result, Some(Err(_))));
+        assert_eq!(form.ssh_server, "deploy@bastion");
+    }
+
+    #[test]
+    fn addresses_and_hosts_are_checked() {
+        for ip in ["127.0.0.1", "0.0.0.0", "::1", "fe80::1", "2001:db8::5432"] {
+            assert!(NewTunnelForm::validate_local_ip(ip).is_ok(), "{}", ip);
+            assert!(NewTunnelForm::validate_host(ip).is_ok(), "{}", ip);
+        }
+        for host in ["db", "db.internal", "DB-1.example.com", "example.com.", "10-0-0-1.svc"] {
+            assert!(NewTunnelForm::validate_local_ip(host).is_err(), "{}", host);
+            assert!(NewTunnelForm::validate_host(host).is_ok(), "{}", host);
+        }
+        let long_label = "a".repeat(64);
+        for junk in ["", "hello world", "256.1.1.1", "-db", "db-", "db..internal", "db_1", "db:5432", ":::1", long_label.as_str()] {
+            assert!(NewTunnelForm::validate_local_ip(junk).is_err(), "{}", junk);
+            assert!(NewTunnelForm::validate_host(junk).is_err(), "{}", junk);
+        }
+
+        for server in [
+            "bastion",
+            "deploy@bastion",
+            "deploy@bastion:2222",
+            "ssh://deploy@db1.example.com:22",
+            "work_bastion",
+            "10.0.0.1:22",
+            "::1",
+            "deploy@[2001:db8::1]:2222",
+        ] {
+            assert!(NewTunnelForm::validate_ssh_server(server).is_ok(), "{}", server);
+        }
+        for server in ["@bastion", "bastion:0", "bastion:ssh", "bad host", "[::1", "[db]:22", "[::1]x", "256.1.1.1.", "-bastion"] {
+            assert!(NewTunnelForm::validate_ssh_server(server).is_err(), "{}", server);
+        }
+
+        let mut form = NewTunnelForm {
+            name: "db".to_string(),
+            ssh_server: "deploy@bastion:99999".to_string(),
+            local_ip: "localhost".to_string(),
+            local_port: "15432".to_string(),
+            remote_ip: "db internal".to_string(),
+            remote_port: "5432".to_string(),
+            ..Default::default()
+        };
+        assert!(!form.validate());
+        assert!(form.ssh_server_error.as_deref().unwrap().contains("SSH port"));
+        assert!(form.local_ip_error.as_deref().unwrap().contains("'localhost'"));
+        assert!(form.remote_ip_error.as_deref().unwrap().contains("'db internal'"));
+        // References are only checked once expanded
+        form.ssh_server = "$BASTION".to_string();
+        form.local_ip = "${BIND}".to_string();
+        form.remote_ip = "db.internal".to_string();
+        assert!(form.validate());
+    }
+
+    #[test]
+    fn fields_are_stored_as_checked() {
+        let mut form = NewTunnelForm {
+            name: "  Prod \t  DB \n".to_string(),
+            ssh_server: " ssh://Deploy@Bastion.Example.COM:2222  ".to_string(),
+            local_ip: " 127.0.0.2 ".to_string(),
+            local_port: "15432 ".to_string(),
+            remote_ip: "DB.Internal ".to_string(),
+            remote_port: " 5432".to_string(),
+            group: " work  stuff ".to_string(),
+            notes: " VPN first\n\tthen start\u{7} ".to_string(),
+            ..Default::default()
+        };
+        assert!(form.validate(), "{:?}", form.errors());
+        let tunnel = form.to_tunnel(TunnelId(1));
+        assert_eq!(tunnel.name, "Prod DB");
+        assert_eq!(tunnel.ssh_server, "ssh://Deploy@bastion.example.com:2222");
+        assert_eq!((tunnel.local_ip.as_str(), tunnel.local_port), ("127.0.0.2", 15432));
+        assert_eq!((tunnel.remote_ip.as_str(), tunnel.remote_port), ("db.internal", 5432));
+        assert_eq!(tunnel.group.as_deref(), Some("work stuff"));
+        assert_eq!(tunnel.notes, "VPN first\n\tthen start");
+
+        for (server, remote) in [
+            ("host; rm -rf", "db.internal"),
+            ("bastion", "db.internal && curl evil"),
+            ("-oProxyCommand=sh", "db.internal"),
+            ("bastion\u{0}", "db.internal"),
+        ] {
+            form.ssh_server = server.to_string();
+            form.remote_ip = remote.to_string();
+            assert!(!form.validate(), "{} {}", server, remote);
+        }
+        form.ssh_server = "bastion".to_string();
+        form.name = "Prod\u{1b}[2J".to_string();
+        assert!(!form.validate());
+        assert!(form.name_error.as_deref().unwrap().contains("control"));
+
+        // References skip the form's checks but not the start's
+        let mut sneaky = tunnel.clone();
+        sneaky.ssh_server = "$SNEAKY_HOST".to_string();
+        let environment = |value: &str| {
+            expand::expand_with(value, None, |name| (name == "SNEAKY_HOST").then(|| "bastion; rm -rf ~".to_string()))
+        };
+        assert!(matches!(
+            sneaky.expanded_with(environment),
+            Err(OnigiriError::Validation { field: Some(FormField::SshServer), .. })
+        ));
+    }
+
+    #[test]
+    fn share_links_round_trip_without_secrets() {
+        let mut original = tunnel("db.example.com");
+        original.secret_ref = Some("onigiri/1".to_string());
+        original.pinned = true;
+        original.pre_start = "tsh login --proxy=teleport.example.com".to_string();
+        original.post_start = "open -a TablePlus".to_string();
+        let link = transfer::share_link(&original).unwrap();
+        assert!(link.starts_with("onigiri-tunnel:v1;"));
+        assert!(!link.contains(' '));
+
+        let mut form = transfer::parse_share_link(&format!("  {}\n", link)).unwrap();
+        assert!(form.validate());
+        assert_eq!(form.ssh_server, "db.example.com");
+        assert!(!form.needs_secret && !form.pinned);
+        assert!(form.pre_start.is_empty() && form.post_start.is_empty());
+
+        let newer = link.replacen("v1;", "v2;", 1);
+        assert!(transfer::parse_share_link(&newer).unwrap_err().contains("v2"));
+        assert!(transfer::parse_share_link("onigiri-tunnel:v1;!!").is_err());
+    }
+
+    #[test]
+    fn started_shown_and_exported_commands_agree() {
+        let tunnel = tunnel("deploy@bastion");
+        let command = build_command(&tunnel);
+        assert_eq!(TunnelInfo::from(tunnel.clone()).command, command);
+        assert_eq!(command_line(&tunnel), command.join(" "));
+        assert!(service::render_systemd(&tunnel).contains(&command[1..].join(" ")));
+    }
+
+    /// Runs a shell script in ssh's place, keeping the commands it was given.
+    #[cfg(unix)]
+    #[derive(Debug)]
+    struct ScriptRunner {
+        script: &'static str,
+        commands: Mutex<Vec<Vec<String>>>,
+    }
+
+    #[cfg(unix)]
+    impl ScriptRunner {
+        fn new(script: &'static str) -> Arc<Self> {
+            Arc::new(Self {
+                script,
+                commands: Mutex::new(Vec::new()),
+            })
+        }
+    }
+
+    #[cfg(unix)]
+    impl TunnelRunner for ScriptRunner {
+        fn spawn(&self, command: &[String], _agent_socket: Option<&Path>) -> std::io::Result<Child> {
+            self.commands.lock().unwrap().push(command.to_vec());
+            Command::new("sh").args(["-c", self.script]).stderr(Stdio::piped()).spawn()
+        }
+    }
+
+    #[cfg(unix)]
+    fn scripted(script: &'static str) -> TunnelInfo {
+        let mut info = TunnelInfo::from(tunnel("bastion"));
+        info.runner = ScriptRunner::new(script);
+        info
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ssh_that_exits_at_once_fails_to_start() {
+        let mut info = scripted("sleep 0.2; echo 'Permission denied (publickey).' >&2; exit 255");
+        info.start_tunnel().unwrap();
+        assert!(info.is_starting());
+        let error = info.wait_until_up().unwrap_err();
+        assert!(matches!(error, OnigiriError::Auth(line) if line == "Permission denied (publickey)."));
+        assert!(!info.is_starting());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ssh_that_keeps_running_is_up_until_stopped() {
+        let mut info = scripted("exec sleep 30");
+        // A port of our own, so parallel tests don't collide on it
+        info.local_ip = "127.0.0.1".to_string();
+        info.local_port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        info.start_tunnel().unwrap();
+        let _forward = TcpListener::bind(("127.0.0.1", info.local_port)).unwrap();
+
+        let started = Instant::now();
+        info.wait_until_up().unwrap();
+        assert!(started.elapsed() < START_GRACE);
+        assert!(!info.is_starting() && info.is_active());
+
+        info.stop_tunnel();
+        assert!(!info.is_active() && info.has_exited());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_missing_agent_socket_fails_the_start() {
+        let socket = Path::new("/tmp/onigiri-no-such-agent.sock");
+        let process = ssh_process(&build_command(&tunnel("bastion")), Some(socket));
+        let envs: Vec<_> = process.get_envs().collect();
+        assert_eq!(envs, [(std::ffi::OsStr::new("SSH_AUTH_SOCK"), Some(socket.as_os_str()))]);
+
+        let mut info = scripted("exec sleep 30");
+        info.agent_socket = Some(socket.to_path_buf());
+        match info.start_tunnel() {
+            Err(OnigiriError::Validation { field: Some(FormField::AgentSocket), message }) => {
+                assert!(message.contains("does not exist"), "{}", message)
+            }
+            other => panic!("expected a missing agent: {:?}", other),
+        }
+        assert!(!info.has_process());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn local_port_zero_picks_a_fresh_port_each_start() {
+        assert_eq!(NewTunnelForm::validate_local_port("0"), Ok(0));
+        assert!(NewTunnelForm::validate_port("0").is_err());
+
+        let mut saved = tunnel("bastion");
+        saved.local_ip = "127.0.0.1".to_string();
+        saved.local_port = 0;
+        let runner = ScriptRunner::new("exec sleep 30");
+        let mut ports = Vec::new();
+        let mut forwards = Vec::new();
+        for _ in 0..2 {
+            let mut info = TunnelInfo::from(saved.clone());
+            info.runner = runner.clone();
+            info.start_tunnel().unwrap();
+            assert_ne!(info.local_port, 0);
+            assert_eq!(info.local_address(), format!("127.0.0.1:{}", info.local_port));
+            // Held so the next start can't be given it again
+            forwards.push(TcpListener::bind(("127.0.0.1", info.local_port)).unwrap());
+            ports.push(info.local_port);
+            info.stop_tunnel();
+        }
+        assert_ne!(ports[0], ports[1]);
+        let specs: Vec<String> = runner.commands.lock().unwrap().iter()
+            .map(|command| command[command.iter().position(|arg| arg == "-L").unwrap() + 1].clone())
+            .collect();
+        assert_eq!(specs, ports.iter().map(|port| format!("127.0.0.1:{}:db.internal:5432", port)).collect::<Vec<_>>());
+        assert_eq!(saved.local_port, 0);
+    }
+
+    #[test]
+    fn taken_over_tunnels_keep_their_picked_port() {
+        let mut saved = tunnel("bastion");
+        saved.local_ip = "127.0.0.1".to_string();
+        saved.local_port = 0;
+        let mut info = TunnelInfo::from(saved.clone());
+        info.adopt(4242, unix_now(), 49_152);
+        assert_eq!(info.local_address(), "127.0.0.1:49152");
+        assert!(info.command.contains(&"127.0.0.1:49152:db.internal:5432".to_string()));
+
+        // Left running by a version that didn't save the port
+        saved.local_port = 5432;
+        let mut info = TunnelInfo::from(saved);
+        info.adopt(4242, unix_now(), 0);
+        assert_eq!(info.local_port, 5432);
+    }
+
+    #[test]
+    fn imports_keep_names_apart() {
+        let mut app = Tunneler::unopened(&Cli::parse_from(["onigiri"]), None, None);
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        store.insert(tunnel("bastion")).unwrap();
+        app.store = Some(Box::new(store));
+        app.load_tunnels();
+        let existing = app.tunnels[0].id;
+        app.active_tunnels.insert(existing, TunnelInfo::from(app.tunnels[0].clone()));
+
+        let named = |name: &str, server: &str| NewTunnelForm { name: name.to_string(), ..NewTunnelForm::from_tunnel(&tunnel(server)) };
+        let candidates = vec![
+            // Same name as the existing tunnel, twice, and a new name twice
+            ImportCandidate { form: named("Prod DB", "edge"), existing_id: Some(existing), choice: ConflictChoice::KeepBoth },
+            ImportCandidate { form: named("Prod DB", "edge2"), existing_id: Some(existing), choice: ConflictChoice::KeepBoth },
+            ImportCandidate { form: named("Cache", "edge"), existing_id: None, choice: ConflictChoice::Skip },
+            ImportCandidate { form: named("Cache", "edge2"), existing_id: None, choice: ConflictChoice::Skip },
+            ImportCandidate { form: named("Prod DB", "new-bastion"), existing_id: Some(existing), choice: ConflictChoice::Overwrite },
+        ];
+        app.import_preview = Some(ImportPreview {
+            source: PathBuf::from("tunnels.json"),
+            candidates,
+            invalid: Vec::new(),
+            templates: Vec::new(),
+        });
+        assert_eq!(app.apply_import(), Ok((5, 0)));
+        app.load_tunnels();
+        let mut names: Vec<&str> = app.tunnels.iter().map(|t| t.name.as_str()).collect();
+        names.sort_unstable();
+        assert_eq!(names, ["Cache", "Cache (2)", "Prod DB", "Prod DB (2)", "Prod DB (3)"]);
+        let overwritten = app.tunnels.iter().find(|t| t.id == existing).unwrap();
+        assert_eq!(overwritten.ssh_server, "new-bastion");
+        // Still running with the old settings
+        assert!(app.restart_pending.contains(&existing));
+
+        let taken = HashSet::from(["web".to_string(), "web (2)".to_string()]);
+        assert_eq!(unique_name("web", &taken), "web (3)");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_app_starts_tunnels_with_its_runner() {
+        let mut app = Tunneler::unopened(&Cli::parse_from(["onigiri"]), None, None);
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        store.insert(tunnel("deploy@bastion")).unwrap();
+        app.store = Some(Box::new(store));
+        app.load_tunnels();
+        let id = app.tunnels[0].id;
+        let runner = ScriptRunner::new("exec sleep 30");
+        app.runner = runner.clone();
+
+        app.toggle_tunnel(id).unwrap();
+        assert!(matches!(app.state(id), TunnelState::Starting));
+        let pid = app.active_tunnels[&id].pid().unwrap();
+        assert_eq!(runner.commands.lock().unwrap()[0], build_command(&app.tunnels[0]));
+
+        app.toggle_tunnel(id).unwrap();
+        assert!(matches!(app.state(id), TunnelState::Stopped));
+        let alive = Command::new("kill").args(["-0", &pid.to_string()]).status().unwrap();
+        assert!(!alive.success());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn stopping_ends_what_ssh_started() {
+        let pings = || {
+            let output = Command::new("tasklist").args(["/FI", "IMAGENAME eq PING.EXE", "/NH"]).output().unwrap();
+            String::from_utf8_lossy(&output.stdout).matches("PING.EXE").count()
+        };
+        let before = pings();
+        // cmd stands in for ssh, and ping for a ProxyCommand it started
+        let mut info = TunnelInfo::from(tunnel("127.0.0.1"));
+        info.command = ["cmd", "/C", "ping -n 30 127.0.0.1 > NUL"].map(String::from).to_vec();
+        info.start_tunnel().unwrap();
+        std::thread::sleep(Duration::from_millis(500));
+        assert_eq!(pings(), before + 1);
+
+        info.stop_tunnel();
+        assert!(info.has_exited());
+        assert_eq!(pings(), before);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stopping_kills_ssh_that_ignores_sigterm() {
+        let spawn = |script| {
+            let mut info = scripted(script);
+            info.start_tunnel().unwrap();
+            info
+        };
+        let mut tunnels = vec![
+            spawn("exec sleep 30"),
+            spawn("trap '' TERM; while :; do sleep 0.1; done"),
+        ];
+        // Let the shell set its trap first
+        std::thread::sleep(Duration::from_millis(200));
+
+        let started = Instant::now();
+        assert_eq!(stop_tunnels(&mut tunnels, Duration::from_millis(500)), 1);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(tunnels.iter_mut().all(TunnelInfo::has_exited));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exits_are_noticed_without_polling() {
+        let ctx = egui::Context::default();
+        let mut app = Tunneler::unopened(&Cli::parse_from(["onigiri"]), None, None);
+        let mut info = scripted("sleep 0.3; echo 'Connection refused' >&2");
+        info.exit_watch = Some(app.exit_watch.clone());
+        info.start_tunnel().unwrap();
+        app.active_tunnels.insert(TunnelId(1), info);
+        app.states.insert(TunnelId(1), TunnelState::Starting);
+
+        // Nothing is checked until the tunnel's stderr reader reports the exit
+        let id = app.exit_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(id, TunnelId(1));
+        app.update_tunnel_status(&ctx);
+        assert!(app.exiting.is_empty() && app.active_tunnels.contains_key(&id));
+
+        app.exit_watch.tx.send(id).unwrap();
+        let deadline = Instant::now() + EXIT_REAP_WAIT;
+        while app.active_tunnels.contains_key(&id) && Instant::now() < deadline {
+            app.update_tunnel_status(&ctx);
+            std::thread::sleep(Duration::from_millis(10));
         }
+        assert!(!app.active_tunnels.contains_key(&id));
+        assert!(app.start_errors[&TunnelId(1)].to_string().contains("Connection refused"));
+    }
 
-        if let Some(id) = tunnel_to_toggle_expand {
-            if self.expanded_tunnels.contains(&id) {
-                self.expanded_tunnels.remove(&id);
-            } else {
-                self.expanded_tunnels.insert(id);
+    /// A context that reports each repaint asked of it, in place of a window.
+    fn watched_context() -> (egui::Context, Receiver<Duration>) {
+        let ctx = egui::Context::default();
+        let (tx, rx) = mpsc::channel();
+        ctx.set_request_repaint_callback(move |info| {
+            let _ = tx.send(info.delay);
+        });
+        (ctx, rx)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exits_reach_the_window_without_input() {
+        let (ctx, repaints) = watched_context();
+        let mut app = Tunneler::unopened(&Cli::parse_from(["onigiri"]), None, None);
+        app.exit_watch.ctx = ctx.clone();
+        let id = TunnelId(1);
+        let mut info = scripted("sleep 0.2; echo 'Connection closed by remote host' >&2");
+        info.exit_watch = Some(app.exit_watch.clone());
+        app.launch(id, info).unwrap();
+
+        // Frames run only when asked for, with no input events, as egui
+        // would for a window nobody touches
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while app.state(id).is_active() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let delay = repaints.recv_timeout(left).expect("nothing asked for a repaint");
+            if delay >= CLOCK_REPAINT_INTERVAL {
+                continue;
             }
+            std::thread::sleep(delay);
+            let _ = ctx.run(egui::RawInput::default(), |ctx| app.update_tunnel_status(ctx));
         }
+        assert!(matches!(app.state(id), TunnelState::Failed { reason, .. } if reason.contains("Connection closed")));
+    }
 
-        if let Some(id) = tunnel_to_edit {
-            self.start_edit_tunnel(id);
+    #[cfg(unix)]
+    #[test]
+    fn failed_post_start_hooks_leave_the_tunnel_up() {
+        let mut app = Tunneler::unopened(&Cli::parse_from(["onigiri"]), None, None);
+        let id = TunnelId(1);
+        let mut info = scripted("exec sleep 30");
+        info.local_ip = "127.0.0.1".to_string();
+        info.local_port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        info.post_start = Some("echo \"no client for $ONIGIRI_LOCAL_PORT\" >&2; exit 1".to_string());
+        let port = info.local_port;
+        app.launch(id, info).unwrap();
+        let _forward = TcpListener::bind(("127.0.0.1", port)).unwrap();
+
+        let ctx = egui::Context::default();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !app.hook_failures.contains_key(&id) && Instant::now() < deadline {
+            app.verify_started_tunnels(&ctx);
+            app.collect_hook_results();
+            std::thread::sleep(Duration::from_millis(20));
         }
+        assert_eq!(app.hook_failures[&id], format!("no client for {}", port));
+        assert!(matches!(app.state(id), TunnelState::Running { .. }));
+        app.stop_all_tunnels();
+    }
 
-        if self.show_new_tunnel_window {
-            self.show_new_tunnel_window(ctx);
+    #[cfg(unix)]
+    #[test]
+    fn refused_logins_fail_the_start_without_reconnecting() {
+        let mut app = Tunneler::unopened(&Cli::parse_from(["onigiri"]), None, None);
+        let id = TunnelId(1);
+        // ssh says so well before it exits, so the probe reads it first
+        let mut info = scripted("echo 'Permission denied (publickey).' >&2; sleep 2; exit 255");
+        info.local_ip = "127.0.0.1".to_string();
+        info.local_port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        info.exit_watch = Some(app.exit_watch.clone());
+        app.launch(id, info).unwrap();
+
+        let ctx = egui::Context::default();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while matches!(app.state(id), TunnelState::Starting) && Instant::now() < deadline {
+            app.verify_started_tunnels(&ctx);
+            std::thread::sleep(Duration::from_millis(20));
         }
+        assert!(matches!(app.state(id), TunnelState::Failed { reason, .. } if reason.contains("Permission denied")));
+        assert!(matches!(&app.start_errors[&id], OnigiriError::Auth(_)));
+        assert!(!app.active_tunnels.contains_key(&id));
 
-        if self.show_edit_tunnel_window {
-            self.show_edit_tunnel_window(ctx);
+        // The stopped ssh's exit doesn't bring it back
+        let exited = app.exit_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        app.exit_watch.tx.send(exited).unwrap();
+        app.update_tunnel_status(&ctx);
+        assert!(matches!(app.state(id), TunnelState::Failed { .. }));
+        assert!(app.reconnects.is_empty());
+    }
+
+    /// Takes pre-start results until the tunnel is no longer preparing, or
+    /// 10 seconds pass.
+    #[cfg(unix)]
+    fn finish_pre_start_hook(app: &mut Tunneler, id: TunnelId) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while app.active_tunnels.get(&id).is_some_and(TunnelInfo::is_preparing) && Instant::now() < deadline {
+            app.collect_pre_start_results();
+            std::thread::sleep(Duration::from_millis(20));
         }
     }
 
-    fn show_new_tunnel_window(&mut self, ctx: &egui::Context) {
-        egui::Window::new("Add New Tunnel")
-            .fixed_size([300.0, 250.0])
-            .collapsible(false)
-            .show(ctx, |ui| {
-                ui.vertical(|ui| {
-                    ui.horizontal(|ui| {
-                        ui.label("Name:");
-                        ui.text_edit_singleline(&mut self.new_tunnel.name);
-                    });
-                    if let Some(error) = &self.new_tunnel.name_error {
-                        ui.colored_label(egui::Color32::RED, error);
-                    }
+    #[cfg(unix)]
+    #[test]
+    fn failed_pre_start_hooks_abort_the_start() {
+        let mut app = Tunneler::unopened(&Cli::parse_from(["onigiri"]), None, None);
+        let id = TunnelId(1);
+        let runner = ScriptRunner::new("exec sleep 30");
+        let mut info = scripted("exec sleep 30");
+        info.runner = runner.clone();
+        info.pre_start = Some("echo checking; echo \"no VPN profile for $ONIGIRI_TUNNEL_NAME\" >&2; exit 1".to_string());
+        app.launch(id, info).unwrap();
+        assert!(matches!(app.state(id), TunnelState::Starting));
 
-                    ui.horizontal(|ui| {
-                        ui.label("SSH Server:");
-                        ui.text_edit_singleline(&mut self.new_tunnel.ssh_server);
-                    });
-                    if let Some(error) = &self.new_tunnel.ssh_server_error {
-                        ui.colored_label(egui::Color32::RED, error);
-                    }
+        finish_pre_start_hook(&mut app, id);
+        assert!(matches!(app.state(id), TunnelState::Failed { reason, .. } if reason.ends_with("no VPN profile for Prod DB")));
+        assert!(matches!(&app.start_errors[&id], OnigiriError::PreStart(reason) if reason == "no VPN profile for Prod DB"));
+        assert!(runner.commands.lock().unwrap().is_empty());
+        assert_eq!(app.exits[&id].log, ["[pre-start] checking", "[pre-start] no VPN profile for Prod DB"]);
+    }
 
-                    ui.horizontal(|ui| {
-                        ui.label("Local IP:");
-                        ui.text_edit_singleline(&mut self.new_tunnel.local_ip);
-                    });
-                    if let Some(error) = &self.new_tunnel.local_ip_error {
-                        ui.colored_label(egui::Color32::RED, error);
-                    }
+    #[cfg(unix)]
+    #[test]
+    fn ssh_waits_for_the_pre_start_hook() {
+        let mut app = Tunneler::unopened(&Cli::parse_from(["onigiri"]), None, None);
+        let id = TunnelId(1);
+        let runner = ScriptRunner::new("exec sleep 30");
+        let mut info = scripted("exec sleep 30");
+        info.runner = runner.clone();
+        info.local_ip = "127.0.0.1".to_string();
+        info.local_port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        info.pre_start = Some("sleep 0.2; echo logged in".to_string());
+        app.launch(id, info).unwrap();
+        assert!(runner.commands.lock().unwrap().is_empty());
 
-                    ui.horizontal(|ui| {
-                        ui.label("Local Port:");
-                        ui.text_edit_singleline(&mut self.new_tunnel.local_port);
-                    });
-                    if let Some(error) = &self.new_tunnel.local_port_error {
-                        ui.colored_label(egui::Color32::RED, error);
-                    }
+        finish_pre_start_hook(&mut app, id);
+        assert!(matches!(app.state(id), TunnelState::Starting));
+        assert!(app.active_tunnels[&id].has_process());
+        assert_eq!(runner.commands.lock().unwrap().len(), 1);
+        assert_eq!(app.active_tunnels[&id].log(), ["[pre-start] logged in"]);
+        app.stop_running(id, "");
 
-                    ui.horizontal(|ui| {
-                        ui.label("Remote IP:");
-                        ui.text_edit_singleline(&mut self.new_tunnel.remote_ip);
-                    });
-                    if let Some(error) = &self.new_tunnel.remote_ip_error {
-                        ui.colored_label(egui::Color32::RED, error);
-                    }
+        // Stopping while the hook runs ends it, and ssh is never started
+        let mut info = scripted("exec sleep 30");
+        info.runner = runner.clone();
+        info.pre_start = Some("sleep 30".to_string());
+        app.launch(id, info).unwrap();
+        let started = Instant::now();
+        app.stop_running(id, "");
+        assert!(matches!(app.state(id), TunnelState::Stopped));
+        let (_, _, prepared) = app.pre_start_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(prepared.hook.unwrap().result, Err("stopped".to_string()));
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert_eq!(runner.commands.lock().unwrap().len(), 1);
+    }
 
-                    ui.horizontal(|ui| {
-                        ui.label("Remote Port:");
-                        ui.text_edit_singleline(&mut self.new_tunnel.remote_port);
-                    });
-                    if let Some(error) = &self.new_tunnel.remote_port_error {
-                        ui.colored_label(egui::Color32::RED, error);
-                    }
+    /// Finds that its server doesn't resolve once the test lets it answer.
+    #[cfg(unix)]
+    #[derive(Debug)]
+    struct UnresolvedRunner {
+        answer: Mutex<mpsc::Receiver<()>>,
+    }
 
-                    ui.add_space(8.0);
+    #[cfg(unix)]
+    impl TunnelRunner for UnresolvedRunner {
+        fn check_server(&self, server: &str, _options: &[String]) -> Result<(), OnigiriError> {
+            // Gives up eventually, so a check run on the window thread fails
+            // the test instead of hanging it
+            let _ = self.answer.lock().unwrap().recv_timeout(Duration::from_secs(10));
+            Err(OnigiriError::Unresolved(format!("{} not found", server)))
+        }
 
-                    ui.horizontal(|ui| {
-                        if ui.button("Cancel").clicked() {
-                            self.show_new_tunnel_window = false;
-                            self.new_tunnel = NewTunnelForm::default();
-                        }
+        fn checks_server(&self) -> bool {
+            true
+        }
 
-                        if ui.button("Add").clicked() {
-                            if self.new_tunnel.validate() {
-                                if let Err(e) = self.add_new_tunnel() {
-                                    error!("Failed to add tunnel: {}", e);
-                                } else {
-                                    self.show_new_tunnel_window = false;
-                                    self.new_tunnel = NewTunnelForm::default();
-                                }
-                            }
-                        }
-                    });
-                });
-            });
+        fn spawn(&self, _command: &[String], _agent_socket: Option<&Path>) -> std::io::Result<Child> {
+            unreachable!("ssh is never spawned for a server that doesn't resolve")
+        }
     }
 
-    fn show_edit_tunnel_window(&mut self, ctx: &egui::Context) {
-        let mut should_save = false;
-        let mut should_close = false;
+    #[cfg(unix)]
+    #[test]
+    fn servers_resolve_off_the_window_thread() {
+        let mut app = Tunneler::unopened(&Cli::parse_from(["onigiri"]), None, None);
+        let id = TunnelId(1);
+        let mut info = TunnelInfo::from(tunnel("bastion"));
+        let (answer, waiting) = mpsc::channel();
+        info.runner = Arc::new(UnresolvedRunner { answer: Mutex::new(waiting) });
+        // The check only answers after launch has returned
+        app.launch(id, info).unwrap();
+        assert!(matches!(app.state(id), TunnelState::Starting));
+        answer.send(()).unwrap();
 
-        if let Some((_id, form)) = &mut self.edit_tunnel {
-            egui::Window::new("Edit Tunnel")
-                .fixed_size([300.0, 250.0])
-                .collapsible(false)
-                .show(ctx, |ui| {
-                    ui.vertical(|ui| {
-                        ui.horizontal(|ui| {
-                            ui.label("Name:");
-                            ui.text_edit_singleline(&mut form.name);
-                        });
-                        if let Some(error) = &form.name_error {
-                            ui.colored_label(egui::Color32::RED, error);
-                        }
+        finish_pre_start_hook(&mut app, id);
+        assert!(matches!(app.state(id), TunnelState::Failed { reason, .. } if reason.contains("not found")));
+        assert!(matches!(app.start_errors[&id], OnigiriError::Unresolved(_)));
+        assert!(!app.active_tunnels.contains_key(&id));
+    }
 
-                        ui.horizontal(|ui| {
-                            ui.label("SSH Server:");
-                            ui.text_edit_singleline(&mut form.ssh_server);
-                        });
-                        if let Some(error) = &form.ssh_server_error {
-                            ui.colored_label(egui::Color32::RED, error);
-                        }
+    #[test]
+    fn probes_tell_a_reset_from_a_held_connection() {
+        let no_stderr = || Arc::new(Mutex::new(VecDeque::new()));
+        // What ssh does when the remote side refuses: accept, then reset
+        let resetting = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = resetting.local_addr().unwrap();
+        std::thread::spawn(move || {
+            if let Ok((stream, _)) = resetting.accept() {
+                let socket = socket2::Socket::from(stream);
+                let _ = socket.set_linger(Some(Duration::ZERO));
+            }
+        });
+        let outcome = probe_tunnel(addr, no_stderr());
+        assert!(matches!(outcome, TestOutcome::RemoteRefused(_)), "{:?}", outcome);
 
-                        ui.horizontal(|ui| {
-                            ui.label("Local IP:");
-                            ui.text_edit_singleline(&mut form.local_ip);
-                        });
-                        if let Some(error) = &form.local_ip_error {
-                            ui.colored_label(egui::Color32::RED, error);
-                        }
+        let holding = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = holding.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let held = holding.accept();
+            std::thread::sleep(Duration::from_secs(3));
+            drop(held);
+        });
+        let outcome = probe_tunnel(addr, no_stderr());
+        assert!(outcome.is_success(), "{:?}", outcome);
+    }
 
-                        ui.horizontal(|ui| {
-                            ui.label("Local Port:");
-                            ui.text_edit_singleline(&mut form.local_port);
-                        });
-                        if let Some(error) = &form.local_port_error {
-                            ui.colored_label(egui::Color32::RED, error);
-                        }
+    #[test]
+    fn idle_checks_wake_the_window() {
+        let (ctx, repaints) = watched_context();
+        let mut poller = IdlePoller::new();
+        poller.poll(vec![(TunnelId(1), std::process::id(), 1)], Instant::now(), &ctx);
+        repaints.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(poller.rx.try_recv().unwrap().len(), 1);
+    }
 
-                        ui.horizontal(|ui| {
-                            ui.label("Remote IP:");
-                            ui.text_edit_singleline(&mut form.remote_ip);
-                        });
-                        if let Some(error) = &form.remote_ip_error {
-                            ui.colored_label(egui::Color32::RED, error);
-                        }
+    #[cfg(unix)]
+    #[test]
+    fn interleaved_starts_and_stops_leak_no_processes() {
+        let mut app = Tunneler::unopened(&Cli::parse_from(["onigiri"]), None, None);
+        let ids = [TunnelId(1), TunnelId(2)];
+        let mut spawned = HashSet::new();
+        // A fixed pseudo-random mix of starts and stops on two tunnels
+        let mut seed: u32 = 42;
+        for _ in 0..60 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            let id = ids[(seed >> 16) as usize % ids.len()];
+            match (seed >> 8) % 3 {
+                0 => app.stop_running(id, ""),
+                _ => {
+                    let mut info = scripted("exec sleep 30");
+                    info.id = id;
+                    app.launch(id, info).unwrap();
+                }
+            }
+            spawned.extend(app.active_tunnels.values().filter_map(TunnelInfo::pid));
+        }
+        for id in ids {
+            app.stop_running(id, "");
+        }
 
-                        ui.horizontal(|ui| {
-                            ui.label("Remote Port:");
-                            ui.text_edit_singleline(&mut form.remote_port);
-                        });
-                        if let Some(error) = &form.remote_port_error {
-                            ui.colored_label(egui::Color32::RED, error);
-                        }
+        assert!(!spawned.is_empty());
+        let alive: Vec<u32> = spawned
+            .into_iter()
+            .filter(|pid| Command::new("kill").args(["-0", &pid.to_string()]).status().is_ok_and(|s| s.success()))
+            .collect();
+        assert_eq!(alive, Vec::<u32>::new());
+    }
 
-                        ui.add_space(8.0);
+    #[test]
+    fn bulk_start_summary_lists_failures() {
+        let mut bulk = BulkStart {
+            started: 1,
+            ..Default::default()
+        };
+        assert_eq!(bulk.summary(), "Started 1 tunnel");
+        bulk.failures.push("Prod DB (port 5432 is in use)".to_string());
+        assert_eq!(
+            bulk.summary(),
+            "Started 1 of 2 tunnels. Failed: Prod DB (port 5432 is in use)"
+        );
+    }
 
-                        ui.horizontal(|ui| {
-                            if ui.button("Cancel").clicked() {
-                                should_close = true;
-                            }
+    /// WCAG contrast ratio between two colors.
+    fn contrast(a: egui::Color32, b: egui::Color32) -> f32 {
+        let luminance = |c: egui::Color32| {
+            let channel = |v: u8| {
+                let v = v as f32 / 255.0;
+                if v <= 0.03928 {
+                    v / 12.92
+                } else {
+                    ((v + 0.055) / 1.055).powf(2.4)
+                }
+            };
+            0.2126 * channel(c.r()) + 0.7152 * channel(c.g()) + 0.0722 * channel(c.b())
+        };
+        let (la, lb) = (luminance(a), luminance(b));
+        (la.max(lb) + 0.05) / (la.min(lb) + 0.05)
+    }
 
-                            if ui.button("Save").clicked() {
-                                if form.validate() {
-                                    should_save = true;
-                                }
-                            }
-                        });
-                    });
-                });
+    #[test]
+    fn status_colors_are_readable_in_both_themes() {
+        for (visuals, dark_mode) in [(egui::Visuals::dark(), true), (egui::Visuals::light(), false)] {
+            for tone in [Tone::Good, Tone::Bad, Tone::Warning, Tone::Pending] {
+                let ratio = contrast(tone.color(dark_mode), visuals.panel_fill);
+                assert!(ratio >= 4.5, "{:?} in dark mode {}: {:.2}", tone, dark_mode, ratio);
+            }
         }
+    }
 
-        if should_save {
-            if let Err(e) = self.save_edited_tunnel() {
-                error!("Failed to save edited tunnel: {}", e);
-            }
-        } else if should_close {
-            self.show_edit_tunnel_window = false;
-            self.edit_tunnel = None;
+    #[test]
+    fn local_url_only_for_web_ports() {
+        let mut tunnel = tunnel("bastion");
+        tunnel.local_port = 8080;
+        tunnel.remote_port = 8080;
+        assert_eq!(tunnel.local_url().as_deref(), Some("http://127.0.0.2:8080"));
+        tunnel.remote_port = 443;
+        assert_eq!(tunnel.local_url().as_deref(), Some("https://127.0.0.2:8080"));
+        tunnel.local_ip = "::1".to_string();
+        assert_eq!(tunnel.local_address(), "[::1]:8080");
+        tunnel.remote_port = 5432;
+        assert_eq!(tunnel.local_url(), None);
+        tunnel.tags = vec!["HTTP".to_string()];
+        assert_eq!(tunnel.local_url().as_deref(), Some("http://[::1]:8080"));
+        tunnel.url_scheme = Some("https".to_string());
+        assert_eq!(tunnel.local_url().as_deref(), Some("https://[::1]:8080"));
+    }
+
+    #[test]
+    fn search_matches_every_field() {
+        let mut tunnel = tunnel("deploy@DB-Server");
+        tunnel.name = "Orders".to_string();
+        tunnel.remote_ip = "10.0.0.5".to_string();
+        tunnel.remote_port = 3306;
+        tunnel.tags = vec!["Prod".to_string()];
+        tunnel.notes = "Needs VPN".to_string();
+        for query in ["orders", "db-server", "10.0.0", "127.0.0.2", "3306", "prod", "vpn", "  "] {
+            assert!(tunnel.matches(query), "{}", query);
         }
+        assert!(!tunnel.matches("staging"));
     }
-}
 
-impl Drop for Tunneler {
-    fn drop(&mut self) {
-        info!("Application shutting down, cleaning up tunnel processes...");
-        for (id, tunnel) in self.active_tunnels.iter_mut() {
-            info!("Stopping tunnel {}", id);
-            tunnel.stop_tunnel();
+    #[test]
+    fn numeric_search_ranks_ports_first() {
+        let mut tunnel = tunnel("bastion");
+        tunnel.local_port = 8080;
+        tunnel.remote_port = 80;
+        assert_eq!(tunnel.search_rank("80"), Some(0));
+        assert_eq!(tunnel.search_rank("808"), Some(1));
+        tunnel.name = "web 8090".to_string();
+        assert_eq!(tunnel.search_rank("809"), Some(2));
+        assert_eq!(tunnel.search_rank("81"), None);
+    }
+
+    /// 200 tunnels on seven servers, with a search, sort and tag filter
+    /// that leave a few of them.
+    fn busy_list() -> (Vec<Tunnel>, ListPreferences) {
+        let tunnels = (0..200)
+            .map(|n| {
+                let mut tunnel = tunnel(&format!("bastion-{}", n % 7));
+                tunnel.id = TunnelId(n);
+                tunnel.name = format!("Service {}", n);
+                tunnel.local_port = 20000 + n as u16;
+                tunnel.pinned = n == 151;
+                tunnel.tags = vec![if n % 2 == 0 { "prod" } else { "staging" }.to_string()];
+                tunnel
+            })
+            .collect();
+        let preferences = ListPreferences {
+            search_query: "bastion-3".to_string(),
+            sort_mode: SortMode::Name,
+            tag_filter: BTreeSet::from(["prod".to_string()]),
+            ..Default::default()
+        };
+        (tunnels, preferences)
+    }
+
+    #[test]
+    fn listing_200_tunnels_filters_and_sorts() {
+        let (tunnels, preferences) = busy_list();
+        let status = |id: TunnelId| (id.0 % 3 == 0, false);
+
+        let rows = list_rows(&tunnels, &preferences, status);
+        let names: Vec<&str> = rows.iter().map(|&index| tunnels[index].name.as_str()).collect();
+        // Matches sorted by name, then the pinned tunnel, which doesn't match
+        assert_eq!(names.len(), 15);
+        assert_eq!(names[..3], ["Service 10", "Service 108", "Service 122"]);
+        assert_eq!(names.last(), Some(&"Service 151"));
+    }
+
+    /// Run with `cargo test --release -- --ignored`; timings from a loaded
+    /// machine or a debug build say little.
+    #[test]
+    #[ignore]
+    fn listing_200_tunnels_stays_fast() {
+        let (tunnels, preferences) = busy_list();
+        let status = |id: TunnelId| (id.0 % 3 == 0, false);
+        let started = Instant::now();
+        for _ in 0..100 {
+            list_rows(&tunnels, &preferences, status);
         }
-        info!("All tunnels stopped");
+        // A frame is 16 ms
+        assert!(started.elapsed() / 100 < Duration::from_millis(5), "{:?}", started.elapsed() / 100);
     }
-}
 
-impl eframe::App for Tunneler {
-    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
-        self.update(ctx, frame);
+    #[test]
+    fn error_toasts_keep_the_newest() {
+        let mut errors = ErrorToasts::default();
+        for n in 0..RECENT_ERRORS + 2 {
+            errors.push(n.to_string());
+        }
+        assert_eq!(errors.recent.len(), RECENT_ERRORS);
+        assert_eq!(errors.recent[0].1, (RECENT_ERRORS + 1).to_string());
+        assert_eq!(errors.fresh(Instant::now()).count(), RECENT_ERRORS);
+        assert_eq!(errors.fresh(Instant::now() + TOAST_DURATION).count(), 0);
     }
-}
 
-fn main() -> Result<(), eframe::Error> {
-    std::env::set_var("RUST_LOG","info,onigiri=debug");
-    pretty_env_logger::init_timed();
-    info!("Starting {} application", APP_NAME);
-    debug!("Window dimensions: {}x{}", WINDOW_WIDTH, WINDOW_HEIGHT);
+    /// A store whose every write fails, like a locked database.
+    struct FailingStore;
 
-    let icon = image::load_from_memory(include_bytes!("../resources/icon.png")).unwrap().to_rgba8();
-    let (icon_width, icon_height) = icon.dimensions();
-    let icon = Arc::new(egui::IconData {
-        rgba: icon.into_raw(),
-        width: icon_width,
-        height: icon_height,
-    });
+    impl TunnelStore for FailingStore {
+        fn describe(&self) -> String {
+            "failing store".to_string()
+        }
 
-    let options = eframe::NativeOptions {
-        viewport: eframe::egui::ViewportBuilder::default()
-            .with_inner_size([WINDOW_WIDTH, WINDOW_HEIGHT])
-            .with_resizable(false)
-            .with_icon(icon),
-        ..Default::default()
-    };
+        fn load_tunnels(&mut self) -> anyhow::Result<Vec<Tunnel>> {
+            Ok(Vec::new())
+        }
 
-    // Run the app
-    let result = eframe::run_native(
-        APP_NAME,
-        options,
-        Box::new(|_cc| Ok(Box::new(Tunneler::new()))),
-    );
+        fn apply(&mut self, _changes: Vec<TunnelChange>) -> anyhow::Result<()> {
+            anyhow::bail!("database is locked")
+        }
 
-    info!("Application terminated");
-    result
-}
+        fn reorder(&mut self, _ids: &[TunnelId]) -> anyhow::Result<()> {
+            anyhow::bail!("database is locked")
+        }
+    }
+
+    #[test]
+    fn autostart_tunnels_are_queued_at_launch() {
+        let mut app = Tunneler::unopened(&Cli::parse_from(["onigiri"]), None, None);
+        let mut flagged = tunnel("bastion");
+        flagged.autostart = true;
+        let mut other = tunnel("bastion");
+        other.id = TunnelId(2);
+        app.tunnels = vec![flagged, other];
+
+        app.start_autostart_tunnels();
+        assert!(app.start_queue.contains(TunnelId(1)));
+        assert!(!app.start_queue.contains(TunnelId(2)));
+    }
+
+    #[test]
+    fn quitting_follows_the_quit_setting() {
+        let ctx = egui::Context::default();
+        let mut app = Tunneler::unopened(&Cli::parse_from(["onigiri"]), None, None);
+        app.active_tunnels.insert(TunnelId(1), TunnelInfo::from(tunnel("bastion")));
+
+        app.request_quit(&ctx);
+        assert!(app.confirm_quit);
+        assert!(!app.quitting);
+
+        app.confirm_quit = false;
+        app.quit_behavior = QuitBehavior::Keep;
+        app.request_quit(&ctx);
+        assert!(app.quitting && app.leave_running);
+    }
+
+    /// A store that only remembers the sessions saved to it.
+    struct SessionStore(Arc<Mutex<Vec<Vec<TunnelId>>>>);
+
+    impl TunnelStore for SessionStore {
+        fn describe(&self) -> String {
+            "session store".to_string()
+        }
+
+        fn load_tunnels(&mut self) -> anyhow::Result<Vec<Tunnel>> {
+            Ok(Vec::new())
+        }
+
+        fn apply(&mut self, _changes: Vec<TunnelChange>) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn reorder(&mut self, _ids: &[TunnelId]) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn save_session(&mut self, running: &[TunnelId]) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(running.to_vec());
+            Ok(())
+        }
+    }
+
+    /// A store that only remembers the events recorded in it.
+    struct EventStore(Arc<Mutex<Vec<(EventKind, String)>>>);
+
+    impl TunnelStore for EventStore {
+        fn describe(&self) -> String {
+            "event store".to_string()
+        }
+
+        fn load_tunnels(&mut self) -> anyhow::Result<Vec<Tunnel>> {
+            Ok(Vec::new())
+        }
+
+        fn apply(&mut self, _changes: Vec<TunnelChange>) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn reorder(&mut self, _ids: &[TunnelId]) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn record_event(&mut self, _id: TunnelId, _at: i64, kind: EventKind, detail: &str) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push((kind, detail.to_string()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn transitions_are_recorded_and_impossible_ones_ignored() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut app = Tunneler::unopened(&Cli::parse_from(["onigiri"]), None, None);
+        app.store = Some(Box::new(EventStore(Arc::clone(&events))));
+        let id = TunnelId(1);
+
+        assert!(!app.transition(id, TunnelState::Stopped, ""));
+        assert!(app.transition(id, TunnelState::Queued, ""));
+        assert!(app.transition(id, TunnelState::Starting, ""));
+        assert!(!app.transition(id, TunnelState::Queued, ""));
+        assert!(app.transition(id, TunnelState::Running { since: 0, check: None }, ""));
+        assert!(app.transition(id, TunnelState::Stopped, "Idle for 5m"));
+        assert!(!app.transition(id, TunnelState::Stopped, ""));
+        assert!(app.states.is_empty());
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![(EventKind::Started, String::new()), (EventKind::Stopped, "Idle for 5m".to_string())]
+        );
+    }
+
+    #[test]
+    fn dead_tunnels_reconnect_until_they_give_up() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut app = Tunneler::unopened(&Cli::parse_from(["onigiri"]), None, None);
+        app.store = Some(Box::new(EventStore(Arc::clone(&events))));
+        let id = TunnelId(1);
+        let reconnecting = |attempt| TunnelState::Reconnecting { attempt, next_retry: Instant::now() };
+
+        // Only a tunnel that was seen up is started again
+        assert_eq!(app.next_reconnect(id, &TunnelState::Starting, false), None);
+        let reachable = Some(TestOutcome::Reachable(Duration::from_millis(5)));
+        let up = TunnelState::Running { since: unix_now(), check: reachable.clone() };
+        assert_eq!(app.next_reconnect(id, &up, false), Some(1));
+        let refused = Some(TestOutcome::AuthError("Permission denied (publickey).".to_string()));
+        let refused = TunnelState::Running { since: unix_now(), check: refused };
+        assert_eq!(app.next_reconnect(id, &refused, false), None);
+        assert_eq!(app.next_reconnect(id, &refused, true), Some(1));
+        let taken_over = TunnelState::Running { since: unix_now(), check: None };
+        assert_eq!(app.next_reconnect(id, &taken_over, true), Some(1));
+
+        assert!(app.transition(id, TunnelState::Starting, ""));
+        assert!(app.transition(id, up.clone(), ""));
+        assert!(app.transition(id, reconnecting(1), "Connection closed"));
+        assert!(!app.transition(id, TunnelState::Queued, ""));
+        assert!(app.transition(id, TunnelState::Starting, ""));
+        assert_eq!(app.next_reconnect(id, &TunnelState::Starting, false), Some(2));
+        assert!(app.transition(id, reconnecting(2), "Connection refused"));
+        assert!(app.transition(id, TunnelState::Starting, ""));
+        assert!(app.transition(id, reconnecting(3), "Connection refused"));
+        assert_eq!(app.next_reconnect(id, &TunnelState::Starting, false), None);
 
+        // A tunnel that stayed up a while starts its tries afresh
+        let long_up = TunnelState::Running { since: unix_now() - RECONNECT_STABLE_SECS - 1, check: reachable };
+        assert_eq!(app.next_reconnect(id, &long_up, false), Some(1));
+
+        assert!(app.cancel_start(id));
+        assert!(app.states.is_empty() && app.reconnects.is_empty());
+        assert_eq!(
+            events.lock().unwrap()[1..],
+            [
+                (EventKind::Exited, "Connection closed".to_string()),
+                (EventKind::Started, String::new()),
+                (EventKind::Status, "Reconnect 1 failed: Connection refused".to_string()),
+                (EventKind::Started, String::new()),
+                (EventKind::Status, "Reconnect 2 failed: Connection refused".to_string()),
+                (EventKind::Stopped, String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn shutting_down_after_a_signal_and_in_drop_runs_once() {
+        let sessions = Arc::new(Mutex::new(Vec::new()));
+        let mut app = Tunneler::unopened(&Cli::parse_from(["onigiri"]), None, None);
+        app.store = Some(Box::new(SessionStore(Arc::clone(&sessions))));
+        app.active_tunnels.insert(TunnelId(1), TunnelInfo::from(tunnel("bastion")));
+
+        app.shut_down();
+        assert!(app.active_tunnels.is_empty());
+        app.shut_down();
+        drop(app);
+        assert_eq!(*sessions.lock().unwrap(), vec![vec![TunnelId(1)]]);
+    }
+
+    fn app_with_failing_store() -> Tunneler {
+        let mut app = Tunneler::unopened(&Cli::parse_from(["onigiri"]), None, None);
+        app.store = Some(Box::new(FailingStore));
+        app
+    }
+
+    #[test]
+    fn failed_add_keeps_the_window_and_form() {
+        let mut app = app_with_failing_store();
+        app.show_new_tunnel_window = true;
+        app.new_tunnel = NewTunnelForm::from_tunnel(&tunnel("db.example.com"));
+        app.submit_new_tunnel();
+        assert!(app.show_new_tunnel_window);
+        assert_eq!(app.new_tunnel.name, "Prod DB");
+        assert!(app.new_tunnel.save_error.as_deref().is_some_and(|e| e.contains("locked")));
+    }
+
+    #[test]
+    fn failed_edit_keeps_the_window_and_form() {
+        let mut app = app_with_failing_store();
+        let mut form = NewTunnelForm::from_tunnel(&tunnel("db.example.com"));
+        form.name = "Renamed".to_string();
+        app.edit_tunnel = Some((TunnelId(7), form));
+        app.show_edit_tunnel_window = true;
+        assert!(app.save_edited_tunnel(true).is_err());
+        assert!(app.show_edit_tunnel_window);
+        let (id, form) = app.edit_tunnel.as_ref().unwrap();
+        assert_eq!((*id, form.name.as_str()), (TunnelId(7), "Renamed"));
+        assert!(form.save_error.is_some());
+    }
+
+    #[test]
+    fn command_line_quotes_for_the_shell() {
+        let tunnel = tunnel("it's a host");
+        assert!(command_line(&tunnel).contains(" 'it'\\''s a host' "));
+    }
+}
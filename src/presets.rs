@@ -0,0 +1,120 @@
+//! Starting points for the Add Tunnel window: the ports of common services,
+//! plus presets saved from the form.
+
+use crate::NewTunnelForm;
+use serde::{Deserialize, Serialize};
+
+/// Settings key the saved presets are stored under, as JSON.
+pub const SETTING_KEY: &str = "presets.custom";
+
+/// Fills in the ports and name of a new tunnel; the server is left alone.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Preset {
+    pub label: String,
+    pub remote_port: u16,
+    pub local_port: u16,
+    /// The tunnel name, with `{host}` replaced by the server's host.
+    pub name_template: String,
+}
+
+impl Preset {
+    fn new(label: &str, remote_port: u16) -> Self {
+        Self {
+            label: label.to_string(),
+            remote_port,
+            local_port: suggested_local_port(remote_port),
+            name_template: format!("{{host}} {}", label),
+        }
+    }
+
+    /// A preset of the form's ports and name, called after that name.
+    pub fn from_form(form: &NewTunnelForm) -> Result<Self, String> {
+        let label = form.name.trim();
+        if label.is_empty() {
+            return Err("Enter a name to save the preset under".to_string());
+        }
+        let remote_port = NewTunnelForm::validate_port(form.remote_port.trim())
+            .map_err(|e| format!("Remote port: {}", e))?;
+        let local_port = NewTunnelForm::validate_port(form.local_port.trim())
+            .unwrap_or_else(|_| suggested_local_port(remote_port));
+        Ok(Self {
+            label: label.to_string(),
+            remote_port,
+            local_port,
+            name_template: label.to_string(),
+        })
+    }
+
+    pub fn apply(&self, form: &mut NewTunnelForm) {
+        form.remote_port = self.remote_port.to_string();
+        form.local_port = self.local_port.to_string();
+        let name = self.name_template.replace("{host}", host(&form.ssh_server));
+        form.name = name.trim().to_string();
+    }
+}
+
+pub fn built_in() -> Vec<Preset> {
+    vec![
+        Preset::new("MySQL", 3306),
+        Preset::new("PostgreSQL", 5432),
+        Preset::new("Redis", 6379),
+        Preset::new("MongoDB", 27017),
+        Preset::new("HTTP", 8080),
+    ]
+}
+
+/// The remote port moved up by 10000, so it doesn't clash with the same
+/// service running locally.
+fn suggested_local_port(remote_port: u16) -> u16 {
+    remote_port
+        .checked_add(10000)
+        .unwrap_or(remote_port.saturating_add(1))
+}
+
+/// The host of a `[ssh://][user@]host[:port]` server.
+fn host(server: &str) -> &str {
+    let server = server.trim();
+    let server = server.strip_prefix("ssh://").unwrap_or(server);
+    let server = server.rsplit_once('@').map_or(server, |(_, host)| host);
+    match server.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') && port.parse::<u16>().is_ok() => host,
+        _ => server,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_fill_ports_and_name() {
+        let mut form = NewTunnelForm {
+            ssh_server: "ssh://deploy@db1.example.com:2222".to_string(),
+            ..Default::default()
+        };
+        built_in()[1].apply(&mut form);
+        assert_eq!(form.remote_port, "5432");
+        assert_eq!(form.local_port, "15432");
+        assert_eq!(form.name, "db1.example.com PostgreSQL");
+
+        form.ssh_server.clear();
+        built_in()[3].apply(&mut form);
+        assert_eq!(form.local_port, "37017");
+        assert_eq!(form.name, "MongoDB");
+    }
+
+    #[test]
+    fn presets_save_from_the_form() {
+        let mut form = NewTunnelForm {
+            name: "Grafana".to_string(),
+            remote_port: "3000".to_string(),
+            local_port: "oops".to_string(),
+            ..Default::default()
+        };
+        let preset = Preset::from_form(&form).unwrap();
+        assert_eq!((preset.remote_port, preset.local_port), (3000, 13000));
+
+        form.remote_port.clear();
+        assert!(Preset::from_form(&form).is_err());
+    }
+}
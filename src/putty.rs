@@ -0,0 +1,202 @@
+//! Importing tunnels from PuTTY's saved sessions on Windows.
+//!
+//! Sessions live under `HKCU\Software\SimonTatham\PuTTY\Sessions`, read with
+//! `reg query` like the other Windows lookups. `PortForwardings` is a comma
+//! separated list such as `L5432=db:5432,R8080=localhost:80,D1080`; each
+//! local forward becomes one tunnel named `<session> <port>`.
+
+use crate::transfer::ImportEntry;
+use crate::NewTunnelForm;
+
+pub const SESSIONS_KEY: &str = r"HKCU\Software\SimonTatham\PuTTY\Sessions";
+
+/// One saved session, with the values the importer needs.
+#[derive(Debug, Default, PartialEq)]
+struct Session {
+    name: String,
+    host: String,
+    user: String,
+    port: u16,
+    forwardings: String,
+}
+
+/// Dumps every saved session from the registry.
+#[cfg(windows)]
+pub fn read_registry() -> Result<String, String> {
+    let output = std::process::Command::new("reg")
+        .args(["query", SESSIONS_KEY, "/s"])
+        .output()
+        .map_err(|e| format!("Could not run reg: {}", e))?;
+    if !output.status.success() {
+        return Err("No PuTTY sessions found".to_string());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Turns `reg query /s` output into import entries. Sessions without
+/// forwardings are skipped.
+pub fn parse_sessions(output: &str) -> Vec<ImportEntry> {
+    let mut entries = Vec::new();
+    for (index, session) in sessions(output).into_iter().enumerate() {
+        if session.forwardings.is_empty() || session.host.is_empty() {
+            continue;
+        }
+        for forwarding in session.forwardings.split(',').filter(|f| !f.is_empty()) {
+            entries.push(forwarding_entry(&session, forwarding, index + 1));
+        }
+    }
+    entries
+}
+
+fn sessions(output: &str) -> Vec<Session> {
+    let prefix = format!("{}\\", SESSIONS_KEY.replace("HKCU", "HKEY_CURRENT_USER"));
+    let mut sessions: Vec<Session> = Vec::new();
+    for line in output.lines() {
+        if let Some(name) = line.trim().strip_prefix(&prefix) {
+            sessions.push(Session {
+                name: decode_session_name(name),
+                port: 22,
+                ..Session::default()
+            });
+            continue;
+        }
+        let Some(session) = sessions.last_mut() else {
+            continue;
+        };
+        // Value lines are `    Name    REG_TYPE    data`
+        let mut fields = line.split("    ").map(str::trim).filter(|f| !f.is_empty());
+        let (Some(name), Some(kind)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let data = fields.next().unwrap_or("");
+        match (name, kind) {
+            ("HostName", "REG_SZ") => session.host = data.to_string(),
+            ("UserName", "REG_SZ") => session.user = data.to_string(),
+            ("PortForwardings", "REG_SZ") => session.forwardings = data.to_string(),
+            ("PortNumber", "REG_DWORD") => {
+                if let Ok(port) = u16::from_str_radix(data.trim_start_matches("0x"), 16) {
+                    session.port = port;
+                }
+            }
+            _ => {}
+        }
+    }
+    sessions
+}
+
+/// PuTTY escapes session names like URLs, e.g. `My%20Server`.
+fn decode_session_name(name: &str) -> String {
+    let mut bytes = Vec::new();
+    let mut rest = name.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = (byte == b'%')
+            .then(|| tail.get(..2))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn forwarding_entry(session: &Session, forwarding: &str, position: usize) -> ImportEntry {
+    let invalid = |reason: &str| ImportEntry::Invalid {
+        position,
+        name: Some(session.name.clone()),
+        reasons: vec![reason.to_string()],
+    };
+    // A leading 4 or 6 restricts the address family
+    let forwarding = forwarding.trim_start_matches(['4', '6']);
+    let (kind, spec) = forwarding.split_at(forwarding.len().min(1));
+    match kind {
+        "L" => {}
+        "R" => return invalid("Remote forwards aren't supported"),
+        "D" => return invalid("Dynamic forwards aren't supported"),
+        _ => return invalid(&format!("Can't read forwarding {}", forwarding)),
+    }
+    let Some((listen, target)) = spec.split_once('=') else {
+        return invalid(&format!("Can't read forwarding {}", forwarding));
+    };
+    let (local_ip, local_port) = match listen.rsplit_once(':') {
+        Some((address, port)) => (address.to_string(), port),
+        None => ("127.0.0.1".to_string(), listen),
+    };
+    let (remote_ip, remote_port) = target.rsplit_once(':').unwrap_or((target, ""));
+
+    let server = match (session.user.is_empty(), session.port) {
+        (true, 22) => session.host.clone(),
+        (false, 22) => format!("{}@{}", session.user, session.host),
+        (true, port) => format!("ssh://{}:{}", session.host, port),
+        (false, port) => format!("ssh://{}@{}:{}", session.user, session.host, port),
+    };
+    let mut form = NewTunnelForm {
+        name: format!("{} {}", session.name, local_port),
+        ssh_server: server,
+        local_ip,
+        local_port: local_port.to_string(),
+        remote_ip: remote_ip.to_string(),
+        remote_port: remote_port.to_string(),
+        ..NewTunnelForm::default()
+    };
+    if form.validate() {
+        ImportEntry::Valid(Box::new(form))
+    } else {
+        ImportEntry::Invalid {
+            position,
+            name: Some(form.name.clone()),
+            reasons: form.errors(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUTPUT: &str = "\r
+HKEY_CURRENT_USER\\Software\\SimonTatham\\PuTTY\\Sessions\\Prod%20Bastion\r
+    HostName    REG_SZ    bastion.example.com\r
+    UserName    REG_SZ    deploy\r
+    PortNumber    REG_DWORD    0x8ae\r
+    PortForwardings    REG_SZ    L5432=db:5432,R8080=localhost:80,4L127.0.0.2:6379=cache:6379\r
+\r
+HKEY_CURRENT_USER\\Software\\SimonTatham\\PuTTY\\Sessions\\Plain\r
+    HostName    REG_SZ    plain.example.com\r
+    PortForwardings    REG_SZ    \r
+";
+
+    #[test]
+    fn maps_local_forwardings_to_forms() {
+        let entries = parse_sessions(OUTPUT);
+        assert_eq!(entries.len(), 3);
+
+        let ImportEntry::Valid(form) = &entries[0] else {
+            panic!("expected a valid entry: {:?}", entries[0]);
+        };
+        assert_eq!(form.name, "Prod Bastion 5432");
+        assert_eq!(form.ssh_server, "ssh://deploy@bastion.example.com:2222");
+        assert_eq!(
+            (form.local_ip.as_str(), form.local_port.as_str()),
+            ("127.0.0.1", "5432")
+        );
+        assert_eq!(
+            (form.remote_ip.as_str(), form.remote_port.as_str()),
+            ("db", "5432")
+        );
+
+        assert!(matches!(entries[1], ImportEntry::Invalid { .. }));
+        let ImportEntry::Valid(form) = &entries[2] else {
+            panic!("expected a valid entry: {:?}", entries[2]);
+        };
+        assert_eq!(form.local_ip, "127.0.0.2");
+    }
+}
@@ -0,0 +1,47 @@
+//! Sensitive per-tunnel values, kept in the OS credential store.
+//!
+//! Stores only hold a reference naming the credential entry, never the value.
+//! `keyring` picks the platform's store: the macOS Keychain, the Secret
+//! Service on Linux, and the Credential Manager on Windows.
+
+use keyring::Entry;
+use log::debug;
+
+/// Service name every entry is filed under.
+const SERVICE: &str = "onigiri";
+
+/// A new reference for a tunnel's secret. The time keeps references unique
+/// across databases that reuse tunnel ids.
+pub fn new_reference(tunnel_id: crate::TunnelId) -> String {
+    format!("tunnel-{}-{}", tunnel_id, crate::unix_now())
+}
+
+fn entry(reference: &str) -> Result<Entry, String> {
+    Entry::new(SERVICE, reference)
+        .map_err(|e| format!("Could not open credential entry {}: {}", reference, e))
+}
+
+pub fn set(reference: &str, secret: &str) -> Result<(), String> {
+    entry(reference)?
+        .set_password(secret)
+        .map_err(|e| format!("Could not store secret: {}", e))
+}
+
+pub fn get(reference: &str) -> Result<String, String> {
+    entry(reference)?.get_password().map_err(|e| match e {
+        keyring::Error::NoEntry => "The secret is no longer in the credential store".to_string(),
+        e => format!("Could not read secret: {}", e),
+    })
+}
+
+pub fn clear(reference: &str) -> Result<(), String> {
+    match entry(reference)?.delete_credential() {
+        Ok(()) => Ok(()),
+        // Already gone, which is what clearing wants
+        Err(keyring::Error::NoEntry) => {
+            debug!("Secret {} was already removed", reference);
+            Ok(())
+        }
+        Err(e) => Err(format!("Could not remove secret: {}", e)),
+    }
+}
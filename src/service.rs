@@ -0,0 +1,285 @@
+//! Running a tunnel as an OS service, so it stays up without the app.
+//!
+//! macOS gets a launchd agent and Linux a systemd user unit. Both run the
+//! same ssh arguments the app does. Files are named after the tunnel id, so
+//! exporting again after an edit replaces the old definition.
+
+use crate::Tunnel;
+use std::path::PathBuf;
+#[cfg(not(windows))]
+use std::process::Command;
+
+/// launchd labels are reverse-DNS, like the app bundle identifier.
+const LABEL_PREFIX: &str = "com.deltamaniac.onigiri.tunnel";
+/// Services start without a login shell's PATH.
+const SSH_PATH: &str = "/usr/bin/ssh";
+
+pub fn launchd_label(tunnel: &Tunnel) -> String {
+    format!("{}-{}", LABEL_PREFIX, tunnel.id)
+}
+
+pub fn systemd_unit_name(tunnel: &Tunnel) -> String {
+    format!("onigiri-tunnel-{}.service", tunnel.id)
+}
+
+/// Where this platform looks for the service file, or None where services
+/// aren't supported.
+pub fn default_path(tunnel: &Tunnel) -> Option<PathBuf> {
+    if cfg!(target_os = "macos") {
+        dirs::home_dir().map(|home| {
+            home.join("Library")
+                .join("LaunchAgents")
+                .join(format!("{}.plist", launchd_label(tunnel)))
+        })
+    } else if cfg!(unix) {
+        dirs::config_dir().map(|config| {
+            config
+                .join("systemd")
+                .join("user")
+                .join(systemd_unit_name(tunnel))
+        })
+    } else {
+        None
+    }
+}
+
+/// The service file for this platform, with the tunnel's `~` and
+/// environment variables expanded since services have no shell.
+pub fn render(tunnel: &Tunnel) -> Result<String, String> {
+    render_with(tunnel, crate::expand::expand)
+}
+
+/// `render` with `expand` in place of this process's environment.
+fn render_with(
+    tunnel: &Tunnel,
+    expand: impl Fn(&str) -> Result<String, String>,
+) -> Result<String, String> {
+    let tunnel = tunnel.expanded_with(expand).map_err(|e| e.to_string())?;
+    Ok(if cfg!(target_os = "macos") {
+        render_launchd(&tunnel)
+    } else {
+        render_systemd(&tunnel)
+    })
+}
+
+/// The app's ssh command, with ssh's full path.
+fn service_command(tunnel: &Tunnel) -> Vec<String> {
+    let mut command = crate::build_command(tunnel);
+    command[0] = SSH_PATH.to_string();
+    command
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn render_launchd(tunnel: &Tunnel) -> String {
+    let arguments: String = service_command(tunnel)
+        .into_iter()
+        .map(|arg| format!("        <string>{}</string>\n", xml_escape(&arg)))
+        .collect();
+    let environment = match &tunnel.agent_socket {
+        Some(socket) => format!(
+            "    <key>EnvironmentVariables</key>\n    <dict>\n        <key>SSH_AUTH_SOCK</key>\n        <string>{}</string>\n    </dict>\n",
+            xml_escape(socket)
+        ),
+        None => String::new(),
+    };
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<!-- Onigiri tunnel: {name} -->
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{label}</string>
+    <key>ProgramArguments</key>
+    <array>
+{arguments}    </array>
+{environment}    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <true/>
+    <key>ThrottleInterval</key>
+    <integer>10</integer>
+</dict>
+</plist>
+"#,
+        name = xml_escape(&tunnel.name),
+        label = launchd_label(tunnel),
+        arguments = arguments,
+        environment = environment,
+    )
+}
+
+/// Quotes an argument for `ExecStart` when it has spaces or quotes.
+fn systemd_quote(arg: &str) -> String {
+    if arg.is_empty() || arg.contains([' ', '"', '\\', '\'', '$', '%']) {
+        let escaped = arg
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('$', "$$")
+            .replace('%', "%%");
+        format!("\"{}\"", escaped)
+    } else {
+        arg.to_string()
+    }
+}
+
+pub fn render_systemd(tunnel: &Tunnel) -> String {
+    let command: Vec<String> = service_command(tunnel)
+        .into_iter()
+        .map(|arg| systemd_quote(&arg))
+        .collect();
+    format!(
+        "[Unit]\n\
+         Description=Onigiri tunnel: {name}\n\
+         After=network-online.target\n\
+         Wants=network-online.target\n\
+         \n\
+         [Service]\n\
+         {environment}\
+         ExecStart={command}\n\
+         Restart=on-failure\n\
+         RestartSec=10\n\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        name = tunnel.name.replace('\n', " "),
+        command = command.join(" "),
+        environment = tunnel
+            .agent_socket
+            .as_ref()
+            .map(|socket| format!(
+                "Environment={}\n",
+                systemd_quote(&format!("SSH_AUTH_SOCK={}", socket))
+            ))
+            .unwrap_or_default(),
+    )
+}
+
+/// Loads the written service and starts it, replacing an older version.
+#[cfg(target_os = "macos")]
+pub fn activate(tunnel: &Tunnel, path: &std::path::Path) -> Result<(), String> {
+    // Unloading fails harmlessly when it was never loaded
+    let _ = Command::new("launchctl").arg("unload").arg(path).output();
+    run(Command::new("launchctl").arg("load").arg("-w").arg(path))?;
+    log::info!("Loaded {}", launchd_label(tunnel));
+    Ok(())
+}
+
+/// Loads the written service and starts it, replacing an older version.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn activate(tunnel: &Tunnel, _path: &std::path::Path) -> Result<(), String> {
+    let unit = systemd_unit_name(tunnel);
+    run(Command::new("systemctl").args(["--user", "daemon-reload"]))?;
+    run(Command::new("systemctl").args(["--user", "enable", &unit]))?;
+    run(Command::new("systemctl").args(["--user", "restart", &unit]))?;
+    log::info!("Enabled and started {}", unit);
+    Ok(())
+}
+
+#[cfg(windows)]
+pub fn activate(_tunnel: &Tunnel, _path: &std::path::Path) -> Result<(), String> {
+    Err("Services aren't supported on Windows".to_string())
+}
+
+#[cfg(not(windows))]
+fn run(command: &mut Command) -> Result<(), String> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .output()
+        .map_err(|e| format!("Could not run {}: {}", program, e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tunnel() -> Tunnel {
+        crate::NewTunnelForm {
+            name: "Prod <DB>".to_string(),
+            ssh_server: "deploy@bastion".to_string(),
+            local_port: "15432".to_string(),
+            remote_ip: "db.internal".to_string(),
+            remote_port: "5432".to_string(),
+            ..Default::default()
+        }
+        .to_tunnel(crate::TunnelId(3))
+    }
+
+    #[test]
+    fn services_run_the_app_ssh_arguments() {
+        let tunnel = tunnel();
+        let exec = format!("ExecStart={}\n", service_command(&tunnel).join(" "));
+        let unit = render_systemd(&tunnel);
+        assert!(unit.contains(&exec), "{}", unit);
+        assert!(unit.contains("Restart=on-failure"));
+
+        let plist = render_launchd(&tunnel);
+        assert!(plist.contains("<string>com.deltamaniac.onigiri.tunnel-3</string>"));
+        assert!(plist.contains("Prod &lt;DB&gt;"));
+        for arg in crate::build_command(&tunnel).into_iter().skip(1) {
+            assert!(plist.contains(&format!("<string>{}</string>", arg)));
+        }
+        assert!(!unit.contains("SSH_AUTH_SOCK") && !plist.contains("SSH_AUTH_SOCK"));
+    }
+
+    #[test]
+    fn services_use_the_tunnel_agent() {
+        let mut tunnel = tunnel();
+        tunnel.agent_socket = Some("/run/user/1000/work agent.sock".to_string());
+        let unit = render_systemd(&tunnel);
+        assert!(
+            unit.contains("[Service]\nEnvironment=\"SSH_AUTH_SOCK=/run/user/1000/work agent.sock\"\nExecStart="),
+            "{}",
+            unit
+        );
+        let plist = render_launchd(&tunnel);
+        assert!(plist.contains(
+            "<key>SSH_AUTH_SOCK</key>\n        <string>/run/user/1000/work agent.sock</string>"
+        ));
+    }
+
+    #[test]
+    fn services_run_with_references_expanded() {
+        let mut tunnel = tunnel();
+        tunnel.ssh_server = "deploy@$BASTION_HOST".to_string();
+        let environment = |value: &str| {
+            crate::expand::expand_with(value, None, |name| {
+                (name == "BASTION_HOST").then(|| "bastion.example.com".to_string())
+            })
+        };
+        let service = render_with(&tunnel, environment).unwrap();
+        assert!(
+            service.contains("deploy@bastion.example.com"),
+            "{}",
+            service
+        );
+        assert!(!service.contains("BASTION_HOST"), "{}", service);
+
+        let unset = |value: &str| crate::expand::expand_with(value, None, |_| None);
+        assert!(render_with(&tunnel, unset)
+            .unwrap_err()
+            .contains("BASTION_HOST"));
+    }
+
+    #[test]
+    fn systemd_arguments_are_quoted() {
+        assert_eq!(systemd_quote("plain"), "plain");
+        assert_eq!(systemd_quote("a b"), "\"a b\"");
+        assert_eq!(systemd_quote("100%"), "\"100%%\"");
+    }
+}
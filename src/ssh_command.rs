@@ -0,0 +1,385 @@
+//! Reading a pasted ssh command, such as `ssh -N -L 8443:internal:443 -p 2222
+//! deploy@bastion` from a runbook, into the Add Tunnel form.
+//!
+//! The first `-L` forward and the server (with `-p` and `-l`) fill the form's
+//! fields. Other options ssh takes, like `-i`, `-J`, `-R` and `-D`, are kept
+//! as the tunnel's extra ssh options, and a remote command is left out.
+
+use crate::i18n::{tr, trf};
+use crate::NewTunnelForm;
+
+/// ssh's options that take a value, as in its usage message.
+const WITH_VALUE: &str = "BbcDEeFIiJLlmOoPpQRSWw";
+
+/// A pasted command read into form fields.
+#[derive(Debug, Default, PartialEq)]
+pub struct Pasted {
+    pub ssh_server: String,
+    pub local_ip: String,
+    pub local_port: String,
+    pub remote_ip: String,
+    pub remote_port: String,
+    /// Options Onigiri has no field for, passed to ssh as they are.
+    pub extra_args: Vec<String>,
+    /// A command to run on the server, which a tunnel doesn't.
+    pub dropped: Vec<String>,
+}
+
+impl Pasted {
+    /// Fills the form's server, addresses and options, and a name when it
+    /// has none yet.
+    pub fn apply(&self, form: &mut NewTunnelForm) {
+        form.ssh_server = self.ssh_server.clone();
+        form.local_ip = self.local_ip.clone();
+        form.local_port = self.local_port.clone();
+        form.remote_ip = self.remote_ip.clone();
+        form.remote_port = self.remote_port.clone();
+        form.extra_args = crate::shell_words(&self.extra_args);
+        if form.name.trim().is_empty() {
+            form.name = format!("{} {}", self.remote_ip, self.remote_port);
+        }
+    }
+}
+
+/// Reads `command`, which must start with `ssh` and have a `-L` forward and
+/// a server.
+pub fn parse(command: &str) -> Result<Pasted, String> {
+    let mut words = split(command)?.into_iter();
+    // A copied shell prompt
+    let mut program = words.next();
+    if program.as_deref() == Some("$") {
+        program = words.next();
+    }
+    match program.as_deref() {
+        None => {
+            return Err(
+                tr("Paste an ssh command, such as ssh -L 8443:internal:443 deploy@bastion")
+                    .to_string(),
+            )
+        }
+        Some("ssh" | "ssh.exe") => {}
+        Some(program) if program.ends_with("/ssh") => {}
+        Some(program) => return Err(trf("Not an ssh command: it starts with '{}'", &[&program])),
+    }
+
+    let mut pasted = Pasted::default();
+    let mut destination: Option<String> = None;
+    let mut port = None;
+    let mut login = None;
+    let mut forward = None;
+    while let Some(word) = words.next() {
+        let flags = match word.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() => flags,
+            _ if destination.is_none() => {
+                destination = Some(word);
+                continue;
+            }
+            // What follows the server is run there
+            _ => {
+                pasted.dropped.push(word);
+                pasted.dropped.extend(words.by_ref());
+                break;
+            }
+        };
+        if flags == "-" {
+            continue;
+        }
+        // Flags may be bundled, as in -fN, and a value attached, as in -p2222
+        for (at, flag) in flags.char_indices() {
+            if !WITH_VALUE.contains(flag) {
+                match flag {
+                    // Onigiri adds -N itself, and -f would leave the process it watches
+                    'N' | 'f' => {}
+                    flag => pasted.extra_args.push(format!("-{}", flag)),
+                }
+                continue;
+            }
+            let attached = &flags[at + flag.len_utf8()..];
+            let value = match attached {
+                "" => words
+                    .next()
+                    .ok_or_else(|| trf("-{} needs a value", &[&flag]))?,
+                attached => attached.to_string(),
+            };
+            match flag {
+                'L' if forward.is_none() => match parse_forward(&value) {
+                    Some(parsed) => forward = Some(parsed),
+                    None => pasted.extra_args.extend([format!("-{}", flag), value]),
+                },
+                'p' => port = Some(value),
+                'l' => login = Some(value),
+                flag => pasted.extra_args.extend([format!("-{}", flag), value]),
+            }
+            break;
+        }
+    }
+
+    let Some(destination) = destination else {
+        return Err(
+            tr("No server in the command; it needs one such as deploy@bastion").to_string(),
+        );
+    };
+    let Some((local_ip, local_port, remote_ip, remote_port)) = forward else {
+        return Err(
+            tr("No -L forward in the command; a tunnel forwards a local port, as in -L 8443:internal:443").to_string(),
+        );
+    };
+    pasted.ssh_server = server(&destination, login.as_deref(), port.as_deref());
+    pasted.local_ip = local_ip;
+    pasted.local_port = local_port;
+    pasted.remote_ip = remote_ip;
+    pasted.remote_port = remote_port;
+    Ok(pasted)
+}
+
+/// `[bind_address:]port:host:hostport`, with IPv6 addresses in brackets.
+/// None for forwards of Unix sockets, which a tunnel can't hold.
+fn parse_forward(spec: &str) -> Option<(String, String, String, String)> {
+    let fields = colon_fields(spec);
+    let (bind, port, host, host_port) = match fields.as_slice() {
+        [port, host, host_port] => ("127.0.0.1", *port, *host, *host_port),
+        [bind, port, host, host_port] => (*bind, *port, *host, *host_port),
+        _ => return None,
+    };
+    if port.parse::<u16>().is_err() || host_port.parse::<u16>().is_err() || host.is_empty() {
+        return None;
+    }
+    let bind = match bind {
+        "" | "*" => "0.0.0.0",
+        "localhost" => "127.0.0.1",
+        bind => bind,
+    };
+    Some((
+        bind.to_string(),
+        port.to_string(),
+        host.to_string(),
+        host_port.to_string(),
+    ))
+}
+
+/// Splits on colons outside of brackets, dropping the brackets.
+fn colon_fields(spec: &str) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let mut rest = spec;
+    loop {
+        if let Some(bracketed) = rest.strip_prefix('[') {
+            if let Some((inside, after)) = bracketed.split_once(']') {
+                fields.push(inside);
+                match after.strip_prefix(':') {
+                    Some(after) => rest = after,
+                    None => return fields,
+                }
+                continue;
+            }
+        }
+        match rest.split_once(':') {
+            Some((field, after)) => {
+                fields.push(field);
+                rest = after;
+            }
+            None => {
+                fields.push(rest);
+                return fields;
+            }
+        }
+    }
+}
+
+/// The server field for `destination`, with the login and port given
+/// as options when the destination doesn't have its own.
+fn server(destination: &str, login: Option<&str>, port: Option<&str>) -> String {
+    if destination.starts_with("ssh://") {
+        return destination.to_string();
+    }
+    let destination = match login {
+        Some(login) if !destination.contains('@') => format!("{}@{}", login, destination),
+        _ => destination.to_string(),
+    };
+    match port {
+        None | Some("22") => destination,
+        Some(port) => {
+            let (user, host) = match destination.rsplit_once('@') {
+                Some((user, host)) => (format!("{}@", user), host),
+                None => (String::new(), destination.as_str()),
+            };
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            if host.contains(':') {
+                format!("ssh://{}[{}]:{}", user, host, port)
+            } else {
+                format!("ssh://{}{}:{}", user, host, port)
+            }
+        }
+    }
+}
+
+/// Whether ssh options `words` set `flag`, alone or bundled as in `-fN`.
+pub fn has_flag(words: &[String], flag: char) -> bool {
+    let mut words = words.iter();
+    while let Some(word) = words.next() {
+        let Some(flags) = word
+            .strip_prefix('-')
+            .filter(|flags| !flags.starts_with('-'))
+        else {
+            continue;
+        };
+        for (at, c) in flags.char_indices() {
+            if c == flag {
+                return true;
+            }
+            if WITH_VALUE.contains(c) {
+                // The value is the rest of the word, or the next one
+                if at + c.len_utf8() == flags.len() {
+                    words.next();
+                }
+                break;
+            }
+        }
+    }
+    false
+}
+
+/// Splits a line into words as a POSIX shell would: single and double
+/// quotes, backslash escapes and backslash-newline continuations.
+pub fn split(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(tr("A ' quote isn't closed").to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some('\n') => {}
+                            Some(c) => word.extend(['\\', c]),
+                            None => return Err(tr("A \" quote isn't closed").to_string()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(tr("A \" quote isn't closed").to_string()),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n' | '\r') | None => {}
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+            },
+            c if c.is_whitespace() => words.extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runbook_commands_fill_the_form() {
+        let pasted = parse("ssh -N -L 8443:internal:443 -p 2222 deploy@bastion").unwrap();
+        assert_eq!(pasted.ssh_server, "ssh://deploy@bastion:2222");
+        assert_eq!(
+            (pasted.local_ip.as_str(), pasted.local_port.as_str()),
+            ("127.0.0.1", "8443")
+        );
+        assert_eq!(
+            (pasted.remote_ip.as_str(), pasted.remote_port.as_str()),
+            ("internal", "443")
+        );
+        assert!(pasted.extra_args.is_empty() && pasted.dropped.is_empty());
+
+        let pasted = parse("$ ssh -fNL*:5432:db.internal:5432 -l deploy -p22 bastion").unwrap();
+        assert_eq!(pasted.ssh_server, "deploy@bastion");
+        assert_eq!(pasted.local_ip, "0.0.0.0");
+
+        let pasted = parse("ssh -L [::1]:6379:[fd00::5]:6379 -p 2200 [fd00::1]").unwrap();
+        assert_eq!(pasted.local_ip, "::1");
+        assert_eq!(pasted.remote_ip, "fd00::5");
+        assert_eq!(pasted.ssh_server, "ssh://[fd00::1]:2200");
+    }
+
+    #[test]
+    fn unmapped_options_are_kept_for_ssh() {
+        let command =
+            "ssh -i ~/.ssh/work -J jump@edge \\\n  -L 8443:internal:443 -L 9000:metrics:9000 \
+            -R 9090:localhost:90 -D 1080 -C -o 'ServerAliveInterval 30' deploy@bastion sleep 60";
+        let pasted = parse(command).unwrap();
+        assert_eq!(pasted.local_port, "8443");
+        assert_eq!(
+            pasted.extra_args,
+            [
+                "-i",
+                "~/.ssh/work",
+                "-J",
+                "jump@edge",
+                "-L",
+                "9000:metrics:9000",
+                "-R",
+                "9090:localhost:90",
+                "-D",
+                "1080",
+                "-C",
+                "-o",
+                "ServerAliveInterval 30",
+            ]
+        );
+        assert_eq!(pasted.dropped, ["sleep", "60"]);
+
+        let mut form = NewTunnelForm::default();
+        pasted.apply(&mut form);
+        assert_eq!(form.name, "internal 443");
+        assert!(form.extra_args.starts_with("-i '~/.ssh/work' -J jump@edge"));
+        assert_eq!(split(&form.extra_args).unwrap(), pasted.extra_args);
+    }
+
+    #[test]
+    fn unusable_commands_say_why() {
+        assert!(parse("  ").unwrap_err().contains("Paste an ssh command"));
+        assert!(parse("scp -P 2222 file host:")
+            .unwrap_err()
+            .contains("'scp'"));
+        assert!(parse("ssh -L 8443:internal:443")
+            .unwrap_err()
+            .contains("No server"));
+        assert!(parse("ssh -D 1080 bastion")
+            .unwrap_err()
+            .contains("No -L forward"));
+        assert!(parse("ssh -L /tmp/db.sock:db:5432 bastion")
+            .unwrap_err()
+            .contains("No -L forward"));
+        assert_eq!(parse("ssh bastion -p").unwrap_err(), "-p needs a value");
+        assert!(parse("ssh -L 1:a:2 'bastion").is_err());
+    }
+
+    #[test]
+    fn bundled_flags_are_found() {
+        let words = |line: &str| split(line).unwrap();
+        assert!(has_flag(&words("-C -fN"), 'f'));
+        assert!(!has_flag(&words("-o ForwardAgent=yes -i ~/.ssh/f"), 'f'));
+        assert!(!has_flag(&words("-ofoo -J -f"), 'f'));
+    }
+
+    #[test]
+    fn words_are_split_like_a_shell() {
+        assert_eq!(
+            split(r#"a 'b c' "d \"e\" \$f" g\ h "i\j""#).unwrap(),
+            ["a", "b c", "d \"e\" $f", "g h", "i\\j"]
+        );
+        assert_eq!(split("a \\\r\n b ''").unwrap(), ["a", "b", ""]);
+        assert!(split("\"open").is_err());
+    }
+}
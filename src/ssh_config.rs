@@ -0,0 +1,403 @@
+//! Reading port forwards out of OpenSSH client config files.
+//!
+//! Only `LocalForward` maps onto an Onigiri tunnel. Remote and dynamic
+//! forwards, and forwards under host patterns or `Match` blocks, are reported
+//! so the user can see why they were left out. Tunnels use the `Host` alias
+//! as their server, so ssh still applies the rest of that block.
+//!
+//! Tunnels can also be written back out as a `Host` block to share them.
+
+use crate::Tunnel;
+use log::warn;
+use std::path::{Path, PathBuf};
+
+/// Deeper `Include` chains are assumed to be loops.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalForward {
+    pub host: String,
+    pub bind_address: Option<String>,
+    pub local_port: u16,
+    pub remote_host: String,
+    pub remote_port: u16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigForward {
+    Local(LocalForward),
+    Unsupported { host: String, reason: String },
+}
+
+/// Which block the lines being read belong to.
+#[derive(Debug, Clone)]
+enum Section {
+    Host(String),
+    /// A wildcard `Host`, a `Match`, or the global lines before any `Host`.
+    Pattern(String),
+}
+
+/// The user's config file, `~/.ssh/config`.
+pub fn default_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".ssh").join("config"))
+}
+
+/// Reads every forward in the config at `path`, following `Include`s.
+/// Relative includes are resolved against the file's directory.
+pub fn read_forwards(path: &Path) -> Result<Vec<ConfigForward>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let base = path.parent().unwrap_or(Path::new("."));
+    let mut section = Section::Pattern("(global)".to_string());
+    let mut forwards = Vec::new();
+    parse(&text, base, 0, &mut section, &mut forwards);
+    Ok(forwards)
+}
+
+fn parse(
+    text: &str,
+    base: &Path,
+    depth: usize,
+    section: &mut Section,
+    forwards: &mut Vec<ConfigForward>,
+) {
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (keyword, rest) = split_keyword(line);
+        let args = split_args(rest);
+        match keyword.to_lowercase().as_str() {
+            "host" => {
+                *section = match args.iter().find(|name| !is_pattern(name)) {
+                    Some(name) => Section::Host(name.clone()),
+                    None => Section::Pattern(format!("Host {}", args.join(" "))),
+                }
+            }
+            "match" => *section = Section::Pattern(format!("Match {}", args.join(" "))),
+            "include" => {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    warn!("Not following Include {}: nested too deeply", rest);
+                    continue;
+                }
+                for pattern in &args {
+                    for path in expand_include(pattern, base) {
+                        match std::fs::read_to_string(&path) {
+                            Ok(text) => parse(&text, base, depth + 1, section, forwards),
+                            Err(e) => warn!("Skipping Include {}: {}", path.display(), e),
+                        }
+                    }
+                }
+            }
+            "localforward" => forwards.push(match section {
+                Section::Host(host) => parse_local_forward(host, &args),
+                Section::Pattern(pattern) => ConfigForward::Unsupported {
+                    host: pattern.clone(),
+                    reason: "LocalForward under a host pattern".to_string(),
+                },
+            }),
+            kind @ ("remoteforward" | "dynamicforward") => {
+                let host = match section {
+                    Section::Host(host) | Section::Pattern(host) => host.clone(),
+                };
+                let kind = if kind == "remoteforward" {
+                    "RemoteForward"
+                } else {
+                    "DynamicForward"
+                };
+                forwards.push(ConfigForward::Unsupported {
+                    host,
+                    reason: format!("{} isn't supported", kind),
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Splits `Keyword args` or `Keyword=args`.
+fn split_keyword(line: &str) -> (&str, &str) {
+    let end = line
+        .find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(line.len());
+    let rest = line[end..].trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest).trim_start();
+    (&line[..end], rest)
+}
+
+/// Splits arguments on whitespace, keeping double-quoted ones together.
+fn split_args(rest: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in rest.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    args.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        args.push(current);
+    }
+    args
+}
+
+fn is_pattern(name: &str) -> bool {
+    name.contains(['*', '?', '!', ','])
+}
+
+/// Resolves an `Include` argument to the files it names. Wildcards are
+/// supported in the file name only, which covers the usual `config.d/*`.
+fn expand_include(pattern: &str, base: &Path) -> Vec<PathBuf> {
+    let path = match pattern.strip_prefix("~/") {
+        Some(rest) => match dirs::home_dir() {
+            Some(home) => home.join(rest),
+            None => return Vec::new(),
+        },
+        None => base.join(pattern),
+    };
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Vec::new();
+    };
+    if !name.contains(['*', '?']) {
+        return vec![path];
+    }
+    let dir = path.parent().unwrap_or(base);
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|file| file.to_str())
+                .is_some_and(|file| wildcard_match(name, file))
+        })
+        .collect();
+    // ssh reads glob matches in sorted order
+    paths.sort();
+    paths
+}
+
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    fn matches(pattern: &[char], name: &[char]) -> bool {
+        match (pattern.first(), name.first()) {
+            (None, None) => true,
+            (Some('*'), _) => {
+                matches(&pattern[1..], name) || (!name.is_empty() && matches(pattern, &name[1..]))
+            }
+            (Some('?'), Some(_)) => matches(&pattern[1..], &name[1..]),
+            (Some(p), Some(n)) if p == n => matches(&pattern[1..], &name[1..]),
+            _ => false,
+        }
+    }
+    matches(&pattern, &name)
+}
+
+/// Writes a tunnel as a `Host` block named after it. The server may be given
+/// as `[ssh://][user@]host[:port]`.
+pub fn render_host(tunnel: &Tunnel) -> String {
+    let alias: String = tunnel.name.split_whitespace().collect::<Vec<_>>().join("-");
+    let alias = if alias.is_empty() || is_pattern(&alias) {
+        format!("tunnel-{}", tunnel.id)
+    } else {
+        alias
+    };
+
+    let server = tunnel.ssh_server.trim();
+    let server = server.strip_prefix("ssh://").unwrap_or(server);
+    let (user, host) = match server.split_once('@') {
+        Some((user, host)) => (Some(user), host),
+        None => (None, server),
+    };
+    let (host, port) = match host.rsplit_once(':') {
+        Some((name, port)) if !name.contains(':') && port.parse::<u16>().is_ok() => {
+            (name, Some(port))
+        }
+        _ => (host, None),
+    };
+
+    let mut block = format!("# {}\nHost {}\n    HostName {}\n", tunnel.name, alias, host);
+    if let Some(user) = user {
+        block.push_str(&format!("    User {}\n", user));
+    }
+    if let Some(port) = port {
+        block.push_str(&format!("    Port {}\n", port));
+    }
+    block.push_str(&format!(
+        "    LocalForward {}:{} {}:{}\n",
+        bracket_ipv6(&tunnel.local_ip),
+        tunnel.local_port,
+        bracket_ipv6(&tunnel.remote_ip),
+        tunnel.remote_port
+    ));
+    block
+}
+
+fn bracket_ipv6(address: &str) -> String {
+    if address.contains(':') {
+        format!("[{}]", address)
+    } else {
+        address.to_string()
+    }
+}
+
+/// Splits `host:port`, where the host may be a bracketed IPv6 address.
+fn split_host_port(spec: &str) -> (Option<String>, &str) {
+    match spec.rsplit_once(':') {
+        Some((host, port)) => {
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            (Some(host.to_string()), port)
+        }
+        None => (None, spec),
+    }
+}
+
+fn parse_local_forward(host: &str, args: &[String]) -> ConfigForward {
+    let unsupported = |reason: String| ConfigForward::Unsupported {
+        host: host.to_string(),
+        reason,
+    };
+    let [listen, target] = args else {
+        return unsupported(format!("LocalForward {} is incomplete", args.join(" ")));
+    };
+    if listen.contains('/') || target.contains('/') {
+        return unsupported("Unix socket forwards aren't supported".to_string());
+    }
+
+    let (bind_address, local_port) = split_host_port(listen);
+    let (remote_host, remote_port) = split_host_port(target);
+    let (Ok(local_port), Some(remote_host), Ok(remote_port)) = (
+        local_port.parse::<u16>(),
+        remote_host,
+        remote_port.parse::<u16>(),
+    ) else {
+        return unsupported(format!("Can't read LocalForward {} {}", listen, target));
+    };
+    let bind_address = bind_address
+        .filter(|address| !address.is_empty())
+        .map(|address| {
+            // `*` listens on every interface
+            if address == "*" {
+                "0.0.0.0".to_string()
+            } else {
+                address
+            }
+        });
+
+    ConfigForward::Local(LocalForward {
+        host: host.to_string(),
+        bind_address,
+        local_port,
+        remote_host,
+        remote_port,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "onigiri-ssh-config-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn rendered_host_round_trips() {
+        let dir = scratch_dir("render");
+        let tunnel = crate::NewTunnelForm {
+            name: "Prod DB".to_string(),
+            ssh_server: "deploy@bastion:2222".to_string(),
+            local_port: "15432".to_string(),
+            remote_ip: "db.internal".to_string(),
+            remote_port: "5432".to_string(),
+            ..Default::default()
+        }
+        .to_tunnel(crate::TunnelId(7));
+        let block = render_host(&tunnel);
+        assert!(block.contains("Host Prod-DB\n"));
+        assert!(block.contains("    User deploy\n    Port 2222\n"));
+        std::fs::write(dir.join("config"), &block).unwrap();
+
+        let forwards = read_forwards(&dir.join("config")).unwrap();
+        assert_eq!(
+            forwards,
+            [ConfigForward::Local(LocalForward {
+                host: "Prod-DB".to_string(),
+                bind_address: Some("127.0.0.1".to_string()),
+                local_port: 15432,
+                remote_host: "db.internal".to_string(),
+                remote_port: 5432,
+            })]
+        );
+    }
+
+    #[test]
+    fn reads_forwards_and_includes() {
+        let dir = scratch_dir("includes");
+        std::fs::create_dir_all(dir.join("config.d")).unwrap();
+        std::fs::write(
+            dir.join("config"),
+            "Include config.d/*\n\
+             \n\
+             Host bastion prod-bastion\n\
+             \x20   HostName 10.0.0.1\n\
+             \x20   LocalForward 5432 db.internal:5432\n\
+             \x20   LocalForward=*:8080 [::1]:80\n\
+             \x20   DynamicForward 1080\n\
+             Host *.example.com\n\
+             \x20   LocalForward 9000 localhost:9000\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("config.d").join("work"),
+            "Host jump\n  LocalForward 127.0.0.2:6379 cache:6379\n",
+        )
+        .unwrap();
+
+        let forwards = read_forwards(&dir.join("config")).unwrap();
+        let local = |host: &str, bind: Option<&str>, port, remote: &str, remote_port| {
+            ConfigForward::Local(LocalForward {
+                host: host.to_string(),
+                bind_address: bind.map(str::to_string),
+                local_port: port,
+                remote_host: remote.to_string(),
+                remote_port,
+            })
+        };
+        assert_eq!(forwards.len(), 5);
+        assert_eq!(
+            forwards[0],
+            local("jump", Some("127.0.0.2"), 6379, "cache", 6379)
+        );
+        assert_eq!(
+            forwards[1],
+            local("bastion", None, 5432, "db.internal", 5432)
+        );
+        assert_eq!(
+            forwards[2],
+            local("bastion", Some("0.0.0.0"), 8080, "::1", 80)
+        );
+        assert!(matches!(forwards[3], ConfigForward::Unsupported { .. }));
+        assert!(
+            matches!(&forwards[4], ConfigForward::Unsupported { host, .. } if host == "Host *.example.com")
+        );
+    }
+}
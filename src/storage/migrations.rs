@@ -0,0 +1,463 @@
+//! Ordered schema migrations. SQLite's `user_version` pragma records how
+//! many of them a database has seen.
+//!
+//! Tables that belong to a tunnel must reference `tunnels(id)` with
+//! `ON DELETE CASCADE` so purging deleted tunnels also clears their rows.
+
+use anyhow::{bail, Context, Result};
+use log::info;
+use rusqlite::{Connection, Transaction};
+
+type Migration = fn(&Transaction) -> rusqlite::Result<()>;
+
+/// Every schema change in order. Append new migrations, never edit old ones.
+const MIGRATIONS: &[Migration] = &[
+    v1_create_tunnels,
+    v2_idle_timeout,
+    v3_deleted_at,
+    v4_tags,
+    v5_group,
+    v6_last_started_at,
+    v7_timestamps,
+    v8_pinned,
+    v9_resume_on_launch,
+    v10_notes,
+    v11_sort_index,
+    v12_settings,
+    v13_secret_ref,
+    v14_color,
+    v15_workspaces,
+    v16_drop_command,
+    v17_events,
+    v18_templates,
+    v19_url_scheme,
+    v20_autostart,
+    v21_active_processes,
+    v22_extra_args,
+    v23_agent_socket,
+    v24_post_start,
+    v25_pre_start,
+    v26_process_port,
+];
+
+/// Fails if the database was written by a newer version of Onigiri.
+pub fn check_schema_version(conn: &Connection) -> Result<()> {
+    check_version(schema_version(conn)?, MIGRATIONS.len())
+}
+
+/// Brings the database up to the latest schema version.
+pub fn migrate(conn: &mut Connection) -> Result<()> {
+    run_migrations(conn, MIGRATIONS)
+}
+
+pub fn schema_version(conn: &Connection) -> rusqlite::Result<u32> {
+    conn.pragma_query_value(None, "user_version", |row| row.get(0))
+}
+
+/// Runs each pending migration in its own transaction, so a failure leaves
+/// the database at the last version that applied cleanly.
+fn run_migrations(conn: &mut Connection, migrations: &[Migration]) -> Result<()> {
+    let current = schema_version(conn)?;
+    check_version(current, migrations.len())?;
+
+    for (index, migration) in migrations.iter().enumerate().skip(current as usize) {
+        let version = index as u32 + 1;
+        info!("Migrating database to schema version {}", version);
+        let tx = conn.transaction()?;
+        migration(&tx)
+            .with_context(|| format!("Migration to schema version {} failed", version))?;
+        tx.pragma_update(None, "user_version", version)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+fn check_version(current: u32, known: usize) -> Result<()> {
+    if current as usize > known {
+        bail!(
+            "The database uses schema version {}, but this version of Onigiri only understands up to {}",
+            current,
+            known
+        );
+    }
+    Ok(())
+}
+
+fn has_column(tx: &Transaction, table: &str, column: &str) -> rusqlite::Result<bool> {
+    tx.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name = ?2",
+        [table, column],
+        |row| row.get(0),
+    )
+}
+
+fn add_column(
+    tx: &Transaction,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> rusqlite::Result<()> {
+    // Builds from before migrations may already have added the column
+    if !has_column(tx, table, column)? {
+        tx.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+fn v1_create_tunnels(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS tunnels (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            command TEXT NOT NULL,
+            ssh_server TEXT NOT NULL,
+            local_ip TEXT NOT NULL,
+            local_port INTEGER NOT NULL,
+            remote_ip TEXT NOT NULL,
+            remote_port INTEGER NOT NULL,
+            active BOOLEAN NOT NULL DEFAULT 0,
+            deleted BOOLEAN NOT NULL DEFAULT 0
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn v2_idle_timeout(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "tunnels", "idle_timeout_minutes", "INTEGER")
+}
+
+fn v3_deleted_at(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "tunnels", "deleted_at", "TEXT")?;
+    // Rows deleted before this column existed start their retention period now
+    tx.execute(
+        "UPDATE tunnels SET deleted_at = CURRENT_TIMESTAMP WHERE deleted AND deleted_at IS NULL",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Tags are stored as a JSON array of strings.
+fn v4_tags(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "tunnels", "tags", "TEXT NOT NULL DEFAULT '[]'")
+}
+
+fn v5_group(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "tunnels", "group_name", "TEXT")
+}
+
+/// NULL means the tunnel has never been started.
+fn v6_last_started_at(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "tunnels", "last_started_at", "TEXT")
+}
+
+/// SQLite can't add a column with a CURRENT_TIMESTAMP default, so inserts
+/// set the times explicitly. Existing rows get the migration time and are
+/// flagged so the UI can say it's approximate.
+fn v7_timestamps(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "tunnels", "created_at", "TEXT")?;
+    add_column(tx, "tunnels", "updated_at", "TEXT")?;
+    add_column(
+        tx,
+        "tunnels",
+        "timestamps_approximate",
+        "BOOLEAN NOT NULL DEFAULT 0",
+    )?;
+    tx.execute(
+        "UPDATE tunnels SET created_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP, timestamps_approximate = 1
+         WHERE created_at IS NULL",
+        [],
+    )?;
+    Ok(())
+}
+
+fn v8_pinned(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "tunnels", "pinned", "BOOLEAN NOT NULL DEFAULT 0")
+}
+
+/// `active` tracks whether a tunnel is running right now and is cleared on
+/// exit; this keeps the set that was running at exit.
+fn v9_resume_on_launch(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(
+        tx,
+        "tunnels",
+        "resume_on_launch",
+        "BOOLEAN NOT NULL DEFAULT 0",
+    )
+}
+
+fn v10_notes(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "tunnels", "notes", "TEXT NOT NULL DEFAULT ''")
+}
+
+/// Starts out as the insertion order the list used to show.
+fn v11_sort_index(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "tunnels", "sort_index", "INTEGER NOT NULL DEFAULT 0")?;
+    tx.execute("UPDATE tunnels SET sort_index = id", [])?;
+    Ok(())
+}
+
+fn v12_settings(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Names the OS credential entry holding the tunnel's secret.
+fn v13_secret_ref(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "tunnels", "secret_ref", "TEXT")
+}
+
+/// Holds a `ColorLabel` key.
+fn v14_color(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "tunnels", "color", "TEXT")
+}
+
+/// Every existing tunnel starts out in the default workspace. SQLite can't
+/// add a REFERENCES column with a non-NULL default, so deleting a workspace
+/// moves its tunnels by hand.
+fn v15_workspaces(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS workspaces (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE
+        )",
+        [],
+    )?;
+    tx.execute(
+        "INSERT OR IGNORE INTO workspaces (id, name) VALUES (1, 'Default')",
+        [],
+    )?;
+    add_column(tx, "tunnels", "workspace_id", "INTEGER NOT NULL DEFAULT 1")
+}
+
+/// The stored command drifted from what actually runs; it's now built from
+/// the tunnel's fields when needed.
+fn v16_drop_command(tx: &Transaction) -> rusqlite::Result<()> {
+    if has_column(tx, "tunnels", "command")? {
+        tx.execute("ALTER TABLE tunnels DROP COLUMN command", [])?;
+    }
+    Ok(())
+}
+
+fn v17_events(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            tunnel_id INTEGER NOT NULL REFERENCES tunnels(id) ON DELETE CASCADE,
+            at DATETIME NOT NULL,
+            kind TEXT NOT NULL,
+            detail TEXT NOT NULL DEFAULT ''
+        )",
+        [],
+    )?;
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS events_by_tunnel ON events (tunnel_id, id)",
+        [],
+    )?;
+    Ok(())
+}
+
+fn v18_templates(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS templates (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE,
+            definition TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// "http" or "https" when the user picked the Open button's scheme.
+fn v19_url_scheme(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "tunnels", "url_scheme", "TEXT")
+}
+
+/// Tunnels started every time Onigiri opens.
+fn v20_autostart(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "tunnels", "autostart", "BOOLEAN NOT NULL DEFAULT 0")
+}
+
+/// ssh processes left running at quit, for the next launch to take back.
+fn v21_active_processes(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS active_processes (
+            tunnel_id INTEGER PRIMARY KEY REFERENCES tunnels(id) ON DELETE CASCADE,
+            pid INTEGER NOT NULL,
+            started_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// ssh options beyond the forward, such as `-i` or `-J`.
+fn v22_extra_args(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "tunnels", "extra_args", "TEXT NOT NULL DEFAULT ''")
+}
+
+/// The SSH agent socket a tunnel uses instead of the inherited one.
+fn v23_agent_socket(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "tunnels", "agent_socket", "TEXT")
+}
+
+/// A command run once a tunnel is up.
+fn v24_post_start(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "tunnels", "post_start", "TEXT NOT NULL DEFAULT ''")
+}
+
+/// A command run before ssh, and how long it may take.
+fn v25_pre_start(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "tunnels", "pre_start", "TEXT NOT NULL DEFAULT ''")?;
+    add_column(tx, "tunnels", "pre_start_timeout_secs", "INTEGER")
+}
+
+/// The local port of an ssh left running, which the system may have picked.
+fn v26_process_port(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(
+        tx,
+        "active_processes",
+        "local_port",
+        "INTEGER NOT NULL DEFAULT 0",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+    fn columns(conn: &Connection, table: &str) -> Vec<String> {
+        let mut stmt = conn
+            .prepare("SELECT name FROM pragma_table_info(?1)")
+            .unwrap();
+        stmt.query_map([table], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    /// A database as created by the first release: tunnels table, no version.
+    fn v1_database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE tunnels (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                command TEXT NOT NULL,
+                ssh_server TEXT NOT NULL,
+                local_ip TEXT NOT NULL,
+                local_port INTEGER NOT NULL,
+                remote_ip TEXT NOT NULL,
+                remote_port INTEGER NOT NULL,
+                active BOOLEAN NOT NULL DEFAULT 0,
+                deleted BOOLEAN NOT NULL DEFAULT 0
+            );
+            INSERT INTO tunnels (name, command, ssh_server, local_ip, local_port, remote_ip, remote_port)
+            VALUES ('Prod DB', 'ssh -L 5432:db:5432 bastion', 'bastion', '127.0.0.1', 5432, 'db', 5432);",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn migrates_v1_database_and_keeps_rows() {
+        let mut conn = v1_database();
+        migrate(&mut conn).unwrap();
+
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+        assert!(columns(&conn, "tunnels").contains(&"idle_timeout_minutes".to_string()));
+        assert!(!columns(&conn, "tunnels").contains(&"command".to_string()));
+        let (name, port, idle): (String, u16, Option<u32>) = conn
+            .query_row(
+                "SELECT name, local_port, idle_timeout_minutes FROM tunnels",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!((name.as_str(), port, idle), ("Prod DB", 5432, None));
+    }
+
+    #[test]
+    fn existing_rows_get_approximate_timestamps() {
+        let mut conn = v1_database();
+        migrate(&mut conn).unwrap();
+
+        let (created, updated, approximate): (Option<String>, Option<String>, bool) = conn
+            .query_row(
+                "SELECT created_at, updated_at, timestamps_approximate FROM tunnels",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert!(created.is_some());
+        assert_eq!(created, updated);
+        assert!(approximate);
+    }
+
+    #[test]
+    fn migrates_empty_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+        assert!(columns(&conn, "tunnels").contains(&"name".to_string()));
+    }
+
+    #[test]
+    fn migrating_twice_is_a_no_op() {
+        let mut conn = v1_database();
+        migrate(&mut conn).unwrap();
+        migrate(&mut conn).unwrap();
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM tunnels", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn column_added_before_migrations_is_tolerated() {
+        let mut conn = v1_database();
+        conn.execute(
+            "ALTER TABLE tunnels ADD COLUMN idle_timeout_minutes INTEGER",
+            [],
+        )
+        .unwrap();
+        migrate(&mut conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn failed_migration_rolls_back() {
+        fn broken(tx: &Transaction) -> rusqlite::Result<()> {
+            tx.execute("ALTER TABLE tunnels ADD COLUMN half_done INTEGER", [])?;
+            tx.execute("INSERT INTO no_such_table VALUES (1)", [])?;
+            Ok(())
+        }
+
+        let mut conn = v1_database();
+        let result = run_migrations(&mut conn, &[v1_create_tunnels, broken]);
+
+        assert!(result.is_err());
+        assert_eq!(schema_version(&conn).unwrap(), 1);
+        assert!(!columns(&conn, "tunnels").contains(&"half_done".to_string()));
+    }
+
+    #[test]
+    fn newer_schema_is_refused() {
+        let mut conn = v1_database();
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        assert!(migrate(&mut conn).is_err());
+    }
+}
@@ -0,0 +1,510 @@
+//! Where tunnels are kept.
+//!
+//! Tunnels live either in the SQLite database (the default) or in a TOML file
+//! meant to be kept alongside dotfiles; both implement `TunnelStore`.
+//!
+//! The data directory follows each platform's convention (Application Support
+//! on macOS, XDG data home on Linux, %APPDATA% on Windows) and can be
+//! overridden with `--data-dir` or `ONIGIRI_DATA_DIR`. Older builds always
+//! used the macOS layout, so a database found there is moved to the
+//! platform directory.
+
+mod migrations;
+mod settings;
+mod sqlite;
+mod toml_file;
+
+pub use migrations::{check_schema_version, migrate};
+pub use settings::Settings;
+pub use sqlite::SqliteStore;
+pub use toml_file::TomlStore;
+
+use crate::{Tunnel, TunnelId};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// The workspace tunnels belong to until others are created. It can't be
+/// deleted, and stores without workspace support only have this one.
+pub const DEFAULT_WORKSPACE: i32 = 1;
+
+/// Events kept per tunnel; older ones are dropped as new ones come in.
+pub const EVENTS_PER_TUNNEL: usize = 500;
+
+pub const DATA_DIR_ENV: &str = "ONIGIRI_DATA_DIR";
+pub const DB_FILE: &str = "ssh_tunnels.db";
+
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Uses `dir` as the data directory, ahead of `ONIGIRI_DATA_DIR`. Only has
+/// an effect before the directory is first used.
+pub fn set_data_dir(dir: PathBuf) {
+    if DATA_DIR.set(dir).is_err() {
+        log::warn!("The data directory was already in use; ignoring the override");
+    }
+}
+
+/// Returns the data directory, resolving it on first use.
+pub fn data_dir() -> Result<PathBuf> {
+    if let Some(dir) = DATA_DIR.get() {
+        return Ok(dir.clone());
+    }
+    let dir = resolve_data_dir(
+        std::env::consts::OS,
+        std::env::var_os(DATA_DIR_ENV),
+        dirs::data_dir(),
+    )?;
+    debug!("Using data directory {}", dir.display());
+    Ok(DATA_DIR.get_or_init(|| dir).clone())
+}
+
+/// Returns the database file path, creating its directory and moving a
+/// database from the legacy location if needed.
+pub fn database_path() -> Result<PathBuf> {
+    let dir = data_dir()?;
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create data directory {}", dir.display()))?;
+    let db_file = dir.join(DB_FILE);
+    // A directory picked on purpose starts out empty
+    let platform_dir = dirs::data_dir().map(|base| base.join(app_dir_name(std::env::consts::OS)));
+    if let (Some(home), true) = (dirs::home_dir(), platform_dir == Some(dir)) {
+        migrate_legacy_database(&legacy_data_dir(&home).join(DB_FILE), &db_file)?;
+    }
+    Ok(db_file)
+}
+
+/// A separate set of tunnels; only the active one is listed and startable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workspace {
+    pub id: i32,
+    pub name: String,
+}
+
+/// What happened to a tunnel, for its history.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    Started,
+    Stopped,
+    // The ssh process ended without being asked to
+    Exited,
+    // Anything else worth knowing, like a failed start
+    Status,
+}
+
+impl EventKind {
+    /// Stable name used when saving the event.
+    pub fn key(self) -> &'static str {
+        match self {
+            EventKind::Started => "started",
+            EventKind::Stopped => "stopped",
+            EventKind::Exited => "exited",
+            EventKind::Status => "status",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        [
+            EventKind::Started,
+            EventKind::Stopped,
+            EventKind::Exited,
+            EventKind::Status,
+        ]
+        .into_iter()
+        .find(|kind| kind.key() == key)
+    }
+}
+
+/// A tunnel's ssh that was kept running when Onigiri quit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LeftRunning {
+    pub tunnel_id: TunnelId,
+    pub pid: u32,
+    /// Unix time
+    pub started_at: i64,
+    /// The port it listens on, which differs from the tunnel's when the
+    /// system picked it. 0 when saved by a version that didn't record it.
+    #[serde(default)]
+    pub local_port: u16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TunnelEvent {
+    /// Unix time
+    pub at: i64,
+    pub kind: EventKind,
+    pub detail: String,
+}
+
+/// A saved starting point for new tunnels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pub id: i32,
+    pub name: String,
+    /// The tunnel as export JSON, with the fields to fill in left blank.
+    pub definition: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum TunnelChange {
+    /// Adds a tunnel; the store assigns its id.
+    Insert(Tunnel),
+    Update(Tunnel),
+    Delete(TunnelId),
+}
+
+/// A place tunnel definitions are loaded from and saved to.
+pub trait TunnelStore {
+    /// Human readable location, shown in errors and the UI.
+    fn describe(&self) -> String;
+
+    /// Every tunnel that hasn't been deleted.
+    fn load_tunnels(&mut self) -> Result<Vec<Tunnel>>;
+
+    /// Applies all changes or none of them.
+    fn apply(&mut self, changes: Vec<TunnelChange>) -> Result<()>;
+
+    fn get_tunnel(&mut self, id: TunnelId) -> Result<Tunnel> {
+        self.load_tunnels()?
+            .into_iter()
+            .find(|t| t.id == id)
+            .ok_or_else(|| anyhow!("No tunnel with id {}", id))
+    }
+
+    fn insert(&mut self, tunnel: Tunnel) -> Result<()> {
+        self.apply(vec![TunnelChange::Insert(tunnel)])
+    }
+
+    fn update(&mut self, tunnel: Tunnel) -> Result<()> {
+        self.apply(vec![TunnelChange::Update(tunnel)])
+    }
+
+    fn delete(&mut self, id: TunnelId) -> Result<()> {
+        self.apply(vec![TunnelChange::Delete(id)])
+    }
+
+    /// Records that a tunnel was started at unix time `at`.
+    fn record_start(&mut self, id: TunnelId, at: i64) -> Result<()> {
+        let mut tunnel = self.get_tunnel(id)?;
+        tunnel.last_started_at = Some(at);
+        self.update(tunnel)
+    }
+
+    /// Pins or unpins a tunnel without counting as an edit.
+    fn set_pinned(&mut self, id: TunnelId, pinned: bool) -> Result<()> {
+        let mut tunnel = self.get_tunnel(id)?;
+        tunnel.pinned = pinned;
+        self.update(tunnel)
+    }
+
+    /// Records which credential store entry holds the tunnel's secret.
+    fn set_secret_ref(&mut self, id: TunnelId, secret_ref: Option<String>) -> Result<()> {
+        let mut tunnel = self.get_tunnel(id)?;
+        tunnel.secret_ref = secret_ref;
+        self.update(tunnel)
+    }
+
+    /// Saves the list order; `ids` holds every tunnel in its new position.
+    /// New tunnels are always added at the end.
+    fn reorder(&mut self, ids: &[TunnelId]) -> Result<()>;
+
+    /// Records whether a tunnel is running. Stores that only hold tunnel
+    /// definitions ignore it.
+    fn set_active(&mut self, _id: TunnelId, _active: bool) -> Result<()> {
+        Ok(())
+    }
+
+    /// Marks every tunnel stopped on exit, remembering which ones were
+    /// running so the next launch can restore them.
+    fn save_session(&mut self, _running: &[TunnelId]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Remembers the ssh processes left running when Onigiri quit, replacing
+    /// any recorded before.
+    fn save_processes(&mut self, _processes: &[LeftRunning]) -> Result<()> {
+        Ok(())
+    }
+
+    /// The processes saved by `save_processes`, forgotten once read.
+    fn take_processes(&mut self) -> Result<Vec<LeftRunning>> {
+        Ok(Vec::new())
+    }
+
+    /// Tunnels that were running at the last exit, or still marked active
+    /// because the app didn't shut down cleanly.
+    fn tunnels_to_resume(&mut self) -> Result<Vec<TunnelId>> {
+        Ok(Vec::new())
+    }
+
+    /// Number of deleted tunnels still kept around. Stores that remove
+    /// tunnels outright have none.
+    fn deleted_count(&mut self) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// Permanently removes deleted tunnels, or only those deleted more than
+    /// `older_than_days` ago. Returns how many were removed.
+    fn purge_deleted(&mut self, _older_than_days: Option<u32>) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// Adds to a tunnel's history. Stores that only hold tunnel definitions
+    /// ignore it.
+    fn record_event(
+        &mut self,
+        _id: TunnelId,
+        _at: i64,
+        _kind: EventKind,
+        _detail: &str,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// A tunnel's most recent events, newest first.
+    fn events(&mut self, _id: TunnelId, _limit: usize) -> Result<Vec<TunnelEvent>> {
+        Ok(Vec::new())
+    }
+
+    fn workspaces(&mut self) -> Result<Vec<Workspace>> {
+        Ok(vec![Workspace {
+            id: DEFAULT_WORKSPACE,
+            name: "Default".to_string(),
+        }])
+    }
+
+    /// Adds a workspace and returns its id.
+    fn create_workspace(&mut self, _name: &str) -> Result<i32> {
+        bail!("Workspaces are only available with database storage")
+    }
+
+    fn rename_workspace(&mut self, _id: i32, _name: &str) -> Result<()> {
+        bail!("Workspaces are only available with database storage")
+    }
+
+    /// Deletes a workspace that has no tunnels left; its deleted tunnels
+    /// move to the default workspace's trash.
+    fn delete_workspace(&mut self, _id: i32) -> Result<()> {
+        bail!("Workspaces are only available with database storage")
+    }
+
+    fn templates(&mut self) -> Result<Vec<Template>> {
+        Ok(Vec::new())
+    }
+
+    /// Adds a template, or replaces the definition of one with that name.
+    fn save_template(&mut self, _name: &str, _definition: &str) -> Result<()> {
+        bail!("Templates are only available with database storage")
+    }
+
+    fn rename_template(&mut self, _id: i32, _name: &str) -> Result<()> {
+        bail!("Templates are only available with database storage")
+    }
+
+    fn delete_template(&mut self, _id: i32) -> Result<()> {
+        bail!("Templates are only available with database storage")
+    }
+
+    /// Writes a copy of everything the store holds to `dest`.
+    fn backup(&mut self, _dest: &Path) -> Result<()> {
+        bail!("Backups are only available with database storage")
+    }
+
+    /// Replaces everything the store holds with a copy made by `backup`.
+    fn restore(&mut self, _source: &Path) -> Result<()> {
+        bail!("Backups are only available with database storage")
+    }
+}
+
+/// Removes the credential store entries of tunnels removed for good. One
+/// that can't be removed is only left behind, so it is logged instead of
+/// failing the removal.
+fn clear_secrets(secret_refs: &[String], clear: impl Fn(&str) -> Result<(), String>) {
+    for secret_ref in secret_refs {
+        if let Err(e) = clear(secret_ref) {
+            log::warn!("Could not remove secret {}: {}", secret_ref, e);
+        }
+    }
+}
+
+/// Suggested file name for a backup taken at unix time `now` (UTC).
+pub fn backup_file_name(now: i64) -> String {
+    let (year, month, day, hour, minute, second) = utc_date_time(now);
+    format!(
+        "onigiri-backup-{:04}{:02}{:02}-{:02}{:02}{:02}.db",
+        year, month, day, hour, minute, second
+    )
+}
+
+/// Year, month, day, hour, minute and second of unix time `at` in UTC.
+pub fn utc_date_time(at: i64) -> (i64, i64, i64, i64, i64, i64) {
+    let days = at.div_euclid(86_400);
+    let seconds = at.rem_euclid(86_400);
+    // Civil date from days since 1970-01-01, after Howard Hinnant's algorithm
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+    )
+}
+
+fn resolve_data_dir(
+    os: &str,
+    env_override: Option<OsString>,
+    platform_dir: Option<PathBuf>,
+) -> Result<PathBuf> {
+    if let Some(dir) = env_override.filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+    let base = platform_dir.ok_or_else(|| anyhow!("Could not determine a data directory"))?;
+    Ok(base.join(app_dir_name(os)))
+}
+
+fn app_dir_name(os: &str) -> &'static str {
+    match os {
+        "macos" | "windows" => "Onigiri",
+        _ => "onigiri",
+    }
+}
+
+/// Where every build before platform-aware paths kept its data.
+fn legacy_data_dir(home: &Path) -> PathBuf {
+    home.join("Library")
+        .join("Application Support")
+        .join("Onigiri")
+}
+
+/// Moves a database left at the legacy path to the new one. Does nothing when
+/// both paths are the same (macOS) or the new database already exists.
+fn migrate_legacy_database(legacy: &Path, target: &Path) -> Result<bool> {
+    if legacy == target || !legacy.exists() || target.exists() {
+        return Ok(false);
+    }
+    info!(
+        "Moving database from {} to {}",
+        legacy.display(),
+        target.display()
+    );
+    if std::fs::rename(legacy, target).is_err() {
+        // rename fails across filesystems, fall back to copying
+        std::fs::copy(legacy, target)
+            .with_context(|| format!("Failed to copy database to {}", target.display()))?;
+        std::fs::remove_file(legacy).ok();
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("onigiri-storage-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn backup_name_has_utc_timestamp() {
+        assert_eq!(backup_file_name(0), "onigiri-backup-19700101-000000.db");
+        assert_eq!(
+            backup_file_name(1_709_211_909),
+            "onigiri-backup-20240229-130509.db"
+        );
+    }
+
+    #[test]
+    fn macos_uses_application_support() {
+        let dir = resolve_data_dir(
+            "macos",
+            None,
+            Some(PathBuf::from("/Users/me/Library/Application Support")),
+        )
+        .unwrap();
+        assert_eq!(
+            dir,
+            PathBuf::from("/Users/me/Library/Application Support/Onigiri")
+        );
+        assert_eq!(dir, legacy_data_dir(Path::new("/Users/me")));
+    }
+
+    #[test]
+    fn linux_uses_xdg_data_home() {
+        let dir =
+            resolve_data_dir("linux", None, Some(PathBuf::from("/home/me/.local/share"))).unwrap();
+        assert_eq!(dir, PathBuf::from("/home/me/.local/share/onigiri"));
+    }
+
+    #[test]
+    fn windows_uses_appdata() {
+        let appdata = PathBuf::from("C:\\Users\\me\\AppData\\Roaming");
+        let dir = resolve_data_dir("windows", None, Some(appdata.clone())).unwrap();
+        assert_eq!(dir, appdata.join("Onigiri"));
+    }
+
+    #[test]
+    fn env_override_wins() {
+        let dir = resolve_data_dir(
+            "linux",
+            Some(OsString::from("/tmp/onigiri-work")),
+            Some(PathBuf::from("/home/me/.local/share")),
+        )
+        .unwrap();
+        assert_eq!(dir, PathBuf::from("/tmp/onigiri-work"));
+    }
+
+    #[test]
+    fn empty_env_override_is_ignored() {
+        let dir =
+            resolve_data_dir("linux", Some(OsString::new()), Some(PathBuf::from("/data"))).unwrap();
+        assert_eq!(dir, PathBuf::from("/data/onigiri"));
+    }
+
+    #[test]
+    fn missing_platform_dir_is_an_error() {
+        assert!(resolve_data_dir("linux", None, None).is_err());
+    }
+
+    #[test]
+    fn legacy_database_is_moved() {
+        let root = scratch_dir("migrate");
+        let legacy = root.join("legacy.db");
+        let target = root.join("new.db");
+        std::fs::write(&legacy, b"tunnels").unwrap();
+
+        assert!(migrate_legacy_database(&legacy, &target).unwrap());
+        assert!(!legacy.exists());
+        assert_eq!(std::fs::read(&target).unwrap(), b"tunnels");
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn existing_database_is_not_overwritten() {
+        let root = scratch_dir("keep");
+        let legacy = root.join("legacy.db");
+        let target = root.join("new.db");
+        std::fs::write(&legacy, b"old").unwrap();
+        std::fs::write(&target, b"current").unwrap();
+
+        assert!(!migrate_legacy_database(&legacy, &target).unwrap());
+        assert_eq!(std::fs::read(&target).unwrap(), b"current");
+        assert!(!migrate_legacy_database(&target, &target).unwrap());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,65 @@
+//! App preferences such as the window position, kept as key/value pairs in
+//! the `settings` table. They live in the database whichever tunnel store is
+//! in use, and are never part of tunnel exports.
+
+use super::migrate;
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+
+pub struct Settings {
+    conn: Connection,
+}
+
+impl Settings {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut conn =
+            Connection::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        migrate(&mut conn)?;
+        Ok(Self { conn })
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        self.conn
+            .query_row("SELECT value FROM settings WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()
+            .with_context(|| format!("Failed to read setting {}", key))
+    }
+
+    pub fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.conn
+            .execute(
+                "INSERT INTO settings (key, value) VALUES (?1, ?2)
+                 ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                params![key, value],
+            )
+            .with_context(|| format!("Failed to save setting {}", key))?;
+        Ok(())
+    }
+
+    pub fn remove(&self, key: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM settings WHERE key = ?1", [key])
+            .with_context(|| format!("Failed to clear setting {}", key))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_replaced() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        let settings = Settings { conn };
+
+        assert_eq!(settings.get("window").unwrap(), None);
+        settings.set("window", "1").unwrap();
+        settings.set("window", "2").unwrap();
+        assert_eq!(settings.get("window").unwrap().as_deref(), Some("2"));
+    }
+}
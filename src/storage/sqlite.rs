@@ -0,0 +1,977 @@
+//! The default store: tunnels in the `tunnels` table of the app database.
+
+use super::{
+    check_schema_version, migrate, EventKind, LeftRunning, Template, TunnelChange, TunnelEvent,
+    TunnelStore, Workspace, DEFAULT_WORKSPACE, EVENTS_PER_TUNNEL,
+};
+use crate::{ColorLabel, Tunnel, TunnelId};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, info};
+use rusqlite::{params, Connection, DatabaseName, OpenFlags, OptionalExtension, Row};
+use std::path::{Path, PathBuf};
+
+const TUNNEL_COLUMNS: &str = "id, name, ssh_server, local_ip, local_port, remote_ip, \
+    remote_port, idle_timeout_minutes, active, deleted, tags, group_name, \
+    CAST(strftime('%s', last_started_at) AS INTEGER), \
+    CAST(strftime('%s', created_at) AS INTEGER), \
+    CAST(strftime('%s', updated_at) AS INTEGER), timestamps_approximate, pinned, notes, \
+    secret_ref, color, workspace_id, url_scheme, autostart, extra_args, agent_socket, post_start, \
+    pre_start, pre_start_timeout_secs";
+
+impl rusqlite::ToSql for TunnelId {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        self.0.to_sql()
+    }
+}
+
+impl rusqlite::types::FromSql for TunnelId {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        i64::column_result(value).map(TunnelId)
+    }
+}
+
+pub struct SqliteStore {
+    conn: Connection,
+    path: PathBuf,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        debug!("Initializing database connection");
+        let conn =
+            Connection::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        Self::from_connection(conn, path)
+    }
+
+    /// A store that lives only as long as it does, for tests.
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?, Path::new(":memory:"))
+    }
+
+    /// Prepares an already opened connection, migrating its schema.
+    fn from_connection(mut conn: Connection, path: &Path) -> Result<Self> {
+        // Lets rows in tunnel child tables cascade when a tunnel is purged
+        conn.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut conn).with_context(|| format!("Failed to prepare {}", path.display()))?;
+        Ok(Self {
+            conn,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Checks that `path` is a backup this version can restore and returns
+    /// how many tunnels it holds.
+    pub fn check_backup(path: &Path) -> Result<usize> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        check_schema_version(&conn)
+            .with_context(|| format!("{} can't be restored", path.display()))?;
+        let integrity: String = conn
+            .query_row("PRAGMA quick_check", [], |row| row.get(0))
+            .with_context(|| format!("{} is not a database", path.display()))?;
+        if integrity != "ok" {
+            bail!("{} is damaged: {}", path.display(), integrity);
+        }
+        let count: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM tunnels WHERE NOT deleted",
+                [],
+                |row| row.get(0),
+            )
+            .with_context(|| format!("{} doesn't contain Onigiri tunnels", path.display()))?;
+        Ok(count as usize)
+    }
+
+    fn tunnel_from_row(row: &Row) -> rusqlite::Result<Tunnel> {
+        Ok(Tunnel {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            ssh_server: row.get(2)?,
+            local_ip: row.get(3)?,
+            local_port: row.get(4)?,
+            remote_ip: row.get(5)?,
+            remote_port: row.get(6)?,
+            idle_timeout_minutes: row.get(7)?,
+            active: row.get(8)?,
+            deleted: row.get(9)?,
+            tags: Self::tags_from_json(row.get(10)?)?,
+            group: row.get(11)?,
+            last_started_at: row.get(12)?,
+            created_at: row.get(13)?,
+            updated_at: row.get(14)?,
+            timestamps_approximate: row.get(15)?,
+            pinned: row.get(16)?,
+            notes: row.get(17)?,
+            secret_ref: row.get(18)?,
+            // A color this version doesn't know is shown as none
+            color: row
+                .get::<_, Option<String>>(19)?
+                .as_deref()
+                .and_then(ColorLabel::from_key),
+            workspace_id: row.get(20)?,
+            url_scheme: row.get(21)?,
+            autostart: row.get(22)?,
+            extra_args: row.get(23)?,
+            agent_socket: row.get(24)?,
+            post_start: row.get(25)?,
+            pre_start: row.get(26)?,
+            pre_start_timeout_secs: row.get(27)?,
+        })
+    }
+
+    fn tags_from_json(json: String) -> rusqlite::Result<Vec<String>> {
+        serde_json::from_str(&json).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(10, rusqlite::types::Type::Text, Box::new(e))
+        })
+    }
+
+    fn tags_to_json(tags: &[String]) -> rusqlite::Result<String> {
+        serde_json::to_string(tags)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+    }
+
+    fn insert_row(conn: &Connection, tunnel: &Tunnel) -> rusqlite::Result<()> {
+        conn.execute(
+            "INSERT INTO tunnels (name, ssh_server, local_ip, local_port, remote_ip, remote_port, idle_timeout_minutes, active, deleted, tags, group_name, pinned, notes, secret_ref, color, workspace_id, url_scheme, autostart, extra_args, agent_socket, post_start, pre_start, pre_start_timeout_secs, created_at, updated_at, sort_index)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP,
+                     (SELECT COALESCE(MAX(sort_index), 0) + 1 FROM tunnels))",
+            params![
+                tunnel.name,
+                tunnel.ssh_server,
+                tunnel.local_ip,
+                tunnel.local_port,
+                tunnel.remote_ip,
+                tunnel.remote_port,
+                tunnel.idle_timeout_minutes,
+                false,
+                false,
+                Self::tags_to_json(&tunnel.tags)?,
+                tunnel.group,
+                tunnel.pinned,
+                tunnel.notes,
+                tunnel.secret_ref,
+                tunnel.color.map(ColorLabel::key),
+                tunnel.workspace_id,
+                tunnel.url_scheme,
+                tunnel.autostart,
+                tunnel.extra_args,
+                tunnel.agent_socket,
+                tunnel.post_start,
+                tunnel.pre_start,
+                tunnel.pre_start_timeout_secs,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn update_row(conn: &Connection, tunnel: &Tunnel) -> rusqlite::Result<()> {
+        conn.execute(
+            "UPDATE tunnels SET name = ?1, ssh_server = ?2, local_ip = ?3, local_port = ?4, remote_ip = ?5, remote_port = ?6, idle_timeout_minutes = ?7, tags = ?8, group_name = ?9, pinned = ?10, notes = ?11, secret_ref = ?12, color = ?13, workspace_id = ?14, url_scheme = ?15, autostart = ?16, extra_args = ?17, agent_socket = ?18, post_start = ?19, pre_start = ?20, pre_start_timeout_secs = ?21, updated_at = CURRENT_TIMESTAMP WHERE id = ?22",
+            params![
+                tunnel.name,
+                tunnel.ssh_server,
+                tunnel.local_ip,
+                tunnel.local_port,
+                tunnel.remote_ip,
+                tunnel.remote_port,
+                tunnel.idle_timeout_minutes,
+                Self::tags_to_json(&tunnel.tags)?,
+                tunnel.group,
+                tunnel.pinned,
+                tunnel.notes,
+                tunnel.secret_ref,
+                tunnel.color.map(ColorLabel::key),
+                tunnel.workspace_id,
+                tunnel.url_scheme,
+                tunnel.autostart,
+                tunnel.extra_args,
+                tunnel.agent_socket,
+                tunnel.post_start,
+                tunnel.pre_start,
+                tunnel.pre_start_timeout_secs,
+                tunnel.id,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// `purge_deleted`, removing the purged tunnels' secrets with `clear`.
+    fn purge_deleted_with(
+        &mut self,
+        older_than_days: Option<u32>,
+        clear: impl Fn(&str) -> Result<(), String>,
+    ) -> Result<usize> {
+        let (filter, cutoff) = match older_than_days {
+            None => ("deleted", None),
+            Some(days) => (
+                "deleted AND deleted_at <= datetime('now', ?1)",
+                Some(format!("-{} days", days)),
+            ),
+        };
+        let tx = self.conn.transaction()?;
+        let secret_refs = tx
+            .prepare(&format!(
+                "SELECT secret_ref FROM tunnels WHERE {} AND secret_ref IS NOT NULL",
+                filter
+            ))?
+            .query_map(rusqlite::params_from_iter(&cutoff), |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        let purged = tx.execute(
+            &format!("DELETE FROM tunnels WHERE {}", filter),
+            rusqlite::params_from_iter(&cutoff),
+        )?;
+        tx.commit()?;
+        if purged > 0 {
+            info!("Purged {} deleted tunnels", purged);
+        }
+        super::clear_secrets(&secret_refs, clear);
+        Ok(purged)
+    }
+}
+
+impl TunnelStore for SqliteStore {
+    fn describe(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn load_tunnels(&mut self) -> Result<Vec<Tunnel>> {
+        debug!("Loading tunnels from database");
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM tunnels WHERE deleted = 0 ORDER BY sort_index, id",
+            TUNNEL_COLUMNS
+        ))?;
+        let tunnels = stmt
+            .query_map([], Self::tunnel_from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(tunnels)
+    }
+
+    fn get_tunnel(&mut self, id: TunnelId) -> Result<Tunnel> {
+        self.conn
+            .query_row(
+                &format!("SELECT {} FROM tunnels WHERE id = ?", TUNNEL_COLUMNS),
+                [id],
+                Self::tunnel_from_row,
+            )
+            .optional()?
+            .ok_or_else(|| anyhow!("No tunnel with id {}", id))
+    }
+
+    fn apply(&mut self, changes: Vec<TunnelChange>) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        for change in &changes {
+            match change {
+                TunnelChange::Insert(tunnel) => Self::insert_row(&tx, tunnel)
+                    .with_context(|| format!("Failed to add tunnel '{}'", tunnel.name))?,
+                TunnelChange::Update(tunnel) => Self::update_row(&tx, tunnel)
+                    .with_context(|| format!("Failed to update tunnel '{}'", tunnel.name))?,
+                TunnelChange::Delete(id) => {
+                    tx.execute(
+                        "UPDATE tunnels SET deleted = TRUE, deleted_at = CURRENT_TIMESTAMP WHERE id = ?1",
+                        [id],
+                    )
+                        .with_context(|| format!("Failed to mark tunnel {} as deleted", id))?;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn record_start(&mut self, id: TunnelId, at: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE tunnels SET last_started_at = datetime(?2, 'unixepoch') WHERE id = ?1",
+            params![id, at],
+        )?;
+        Ok(())
+    }
+
+    fn set_pinned(&mut self, id: TunnelId, pinned: bool) -> Result<()> {
+        self.conn.execute(
+            "UPDATE tunnels SET pinned = ?2 WHERE id = ?1",
+            params![id, pinned],
+        )?;
+        Ok(())
+    }
+
+    fn reorder(&mut self, ids: &[TunnelId]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        for (index, id) in ids.iter().enumerate() {
+            tx.execute(
+                "UPDATE tunnels SET sort_index = ?2 WHERE id = ?1",
+                params![id, index as i64 + 1],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn set_active(&mut self, id: TunnelId, active: bool) -> Result<()> {
+        self.conn.execute(
+            "UPDATE tunnels SET active = ?2 WHERE id = ?1",
+            params![id, active],
+        )?;
+        Ok(())
+    }
+
+    fn save_session(&mut self, running: &[TunnelId]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute("UPDATE tunnels SET active = 0, resume_on_launch = 0", [])?;
+        for id in running {
+            tx.execute(
+                "UPDATE tunnels SET resume_on_launch = 1 WHERE id = ?1",
+                [id],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn tunnels_to_resume(&mut self) -> Result<Vec<TunnelId>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id FROM tunnels WHERE (active OR resume_on_launch) AND NOT deleted ORDER BY id",
+        )?;
+        let ids = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(ids)
+    }
+
+    fn save_processes(&mut self, processes: &[LeftRunning]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM active_processes", [])?;
+        for process in processes {
+            tx.execute(
+                "INSERT INTO active_processes (tunnel_id, pid, started_at, local_port)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    process.tunnel_id,
+                    process.pid,
+                    process.started_at,
+                    process.local_port
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn take_processes(&mut self) -> Result<Vec<LeftRunning>> {
+        let tx = self.conn.transaction()?;
+        let processes = tx
+            .prepare(
+                "SELECT tunnel_id, pid, started_at, local_port FROM active_processes
+                 ORDER BY tunnel_id",
+            )?
+            .query_map([], |row| {
+                Ok(LeftRunning {
+                    tunnel_id: row.get(0)?,
+                    pid: row.get(1)?,
+                    started_at: row.get(2)?,
+                    local_port: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        tx.execute("DELETE FROM active_processes", [])?;
+        tx.commit()?;
+        Ok(processes)
+    }
+
+    fn deleted_count(&mut self) -> Result<usize> {
+        let count: i64 =
+            self.conn
+                .query_row("SELECT COUNT(*) FROM tunnels WHERE deleted", [], |row| {
+                    row.get(0)
+                })?;
+        Ok(count as usize)
+    }
+
+    fn record_event(&mut self, id: TunnelId, at: i64, kind: EventKind, detail: &str) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT INTO events (tunnel_id, at, kind, detail) VALUES (?1, datetime(?2, 'unixepoch'), ?3, ?4)",
+            params![id, at, kind.key(), detail],
+        )?;
+        tx.execute(
+            "DELETE FROM events WHERE tunnel_id = ?1 AND id NOT IN
+                (SELECT id FROM events WHERE tunnel_id = ?1 ORDER BY id DESC LIMIT ?2)",
+            params![id, EVENTS_PER_TUNNEL as i64],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn events(&mut self, id: TunnelId, limit: usize) -> Result<Vec<TunnelEvent>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT CAST(strftime('%s', at) AS INTEGER), kind, detail FROM events
+             WHERE tunnel_id = ?1 ORDER BY id DESC LIMIT ?2",
+        )?;
+        let events = stmt
+            .query_map(params![id, limit as i64], |row| {
+                Ok((row.get(0)?, row.get::<_, String>(1)?, row.get(2)?))
+            })?
+            .filter_map(|row| match row {
+                // Kinds from a newer version are skipped
+                Ok((at, kind, detail)) => {
+                    EventKind::from_key(&kind).map(|kind| Ok(TunnelEvent { at, kind, detail }))
+                }
+                Err(e) => Some(Err(e)),
+            })
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(events)
+    }
+
+    fn workspaces(&mut self) -> Result<Vec<Workspace>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, name FROM workspaces ORDER BY id")?;
+        let workspaces = stmt
+            .query_map([], |row| {
+                Ok(Workspace {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(workspaces)
+    }
+
+    fn create_workspace(&mut self, name: &str) -> Result<i32> {
+        self.conn
+            .execute("INSERT INTO workspaces (name) VALUES (?1)", [name])
+            .with_context(|| format!("Failed to create workspace '{}'", name))?;
+        Ok(self.conn.last_insert_rowid() as i32)
+    }
+
+    fn rename_workspace(&mut self, id: i32, name: &str) -> Result<()> {
+        self.conn
+            .execute(
+                "UPDATE workspaces SET name = ?2 WHERE id = ?1",
+                params![id, name],
+            )
+            .with_context(|| format!("Failed to rename workspace to '{}'", name))?;
+        Ok(())
+    }
+
+    fn delete_workspace(&mut self, id: i32) -> Result<()> {
+        if id == DEFAULT_WORKSPACE {
+            bail!("The default workspace can't be deleted");
+        }
+        let tx = self.conn.transaction()?;
+        let count: i64 = tx.query_row(
+            "SELECT COUNT(*) FROM tunnels WHERE workspace_id = ?1 AND NOT deleted",
+            [id],
+            |row| row.get(0),
+        )?;
+        if count > 0 {
+            bail!("Move or delete the workspace's {} tunnels first", count);
+        }
+        tx.execute(
+            "UPDATE tunnels SET workspace_id = ?2 WHERE workspace_id = ?1",
+            params![id, DEFAULT_WORKSPACE],
+        )?;
+        tx.execute("DELETE FROM workspaces WHERE id = ?1", [id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn templates(&mut self) -> Result<Vec<Template>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, name, definition FROM templates ORDER BY name COLLATE NOCASE")?;
+        let templates = stmt
+            .query_map([], |row| {
+                Ok(Template {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    definition: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(templates)
+    }
+
+    fn save_template(&mut self, name: &str, definition: &str) -> Result<()> {
+        self.conn
+            .execute(
+                "INSERT INTO templates (name, definition) VALUES (?1, ?2)
+                 ON CONFLICT (name) DO UPDATE SET definition = excluded.definition",
+                params![name, definition],
+            )
+            .with_context(|| format!("Failed to save template '{}'", name))?;
+        Ok(())
+    }
+
+    fn rename_template(&mut self, id: i32, name: &str) -> Result<()> {
+        self.conn
+            .execute(
+                "UPDATE templates SET name = ?2 WHERE id = ?1",
+                params![id, name],
+            )
+            .with_context(|| format!("Failed to rename template to '{}'", name))?;
+        Ok(())
+    }
+
+    fn delete_template(&mut self, id: i32) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM templates WHERE id = ?1", [id])?;
+        Ok(())
+    }
+
+    fn backup(&mut self, dest: &Path) -> Result<()> {
+        // The backup API copies a consistent snapshot even while the app writes
+        self.conn
+            .backup(DatabaseName::Main, dest, None)
+            .with_context(|| format!("Failed to back up to {}", dest.display()))?;
+        info!("Backed up {} to {}", self.path.display(), dest.display());
+        Ok(())
+    }
+
+    fn restore(&mut self, source: &Path) -> Result<()> {
+        Self::check_backup(source)?;
+        self.conn
+            .restore(
+                DatabaseName::Main,
+                source,
+                None::<fn(rusqlite::backup::Progress)>,
+            )
+            .with_context(|| format!("Failed to restore {}", source.display()))?;
+        // Backups from older versions still need the newer columns
+        migrate(&mut self.conn)?;
+        // Nothing from the backup is running now
+        self.conn.execute("UPDATE tunnels SET active = 0", [])?;
+        info!("Restored {} from {}", self.path.display(), source.display());
+        Ok(())
+    }
+
+    fn purge_deleted(&mut self, older_than_days: Option<u32>) -> Result<usize> {
+        self.purge_deleted_with(older_than_days, crate::secrets::clear)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A store on disk, for tests that need its file.
+    fn scratch_store(name: &str) -> SqliteStore {
+        let dir =
+            std::env::temp_dir().join(format!("onigiri-sqlite-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut store = SqliteStore::open(&dir.join("tunnels.db")).unwrap();
+        add_three(&mut store);
+        store
+    }
+
+    fn memory_store() -> SqliteStore {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        add_three(&mut store);
+        store
+    }
+
+    fn add_three(store: &mut SqliteStore) {
+        let changes = [("one", 5433), ("two", 5434), ("three", 5435)]
+            .into_iter()
+            .map(|(name, port)| {
+                let form = crate::NewTunnelForm {
+                    name: name.to_string(),
+                    ssh_server: "bastion".to_string(),
+                    local_port: port.to_string(),
+                    remote_port: "5432".to_string(),
+                    ..Default::default()
+                };
+                TunnelChange::Insert(form.to_tunnel(TunnelId::default()))
+            })
+            .collect();
+        store.apply(changes).unwrap();
+    }
+
+    fn names(store: &mut SqliteStore) -> Vec<String> {
+        store
+            .load_tunnels()
+            .unwrap()
+            .into_iter()
+            .map(|t| t.name)
+            .collect()
+    }
+
+    #[test]
+    fn new_database_has_every_table() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        assert!(store.load_tunnels().unwrap().is_empty());
+        assert!(store.templates().unwrap().is_empty());
+        assert_eq!(store.workspaces().unwrap().len(), 1);
+
+        let mut stmt = store
+            .conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+            .unwrap();
+        let tables: Vec<String> = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        for table in [
+            "active_processes",
+            "events",
+            "settings",
+            "templates",
+            "tunnels",
+            "workspaces",
+        ] {
+            assert!(
+                tables.iter().any(|t| t == table),
+                "{} in {:?}",
+                table,
+                tables
+            );
+        }
+        drop(stmt);
+        // Opening it again finds nothing to migrate
+        assert!(check_schema_version(&store.conn).is_ok());
+        migrate(&mut store.conn).unwrap();
+    }
+
+    #[test]
+    fn tunnels_are_added_edited_and_deleted() {
+        let mut store = memory_store();
+        let mut two = store.load_tunnels().unwrap().remove(1);
+        assert_eq!((two.name.as_str(), two.local_port), ("two", 5434));
+        assert!(two.created_at.is_some() && !two.timestamps_approximate);
+
+        two.name = "two, renamed".to_string();
+        two.tags = vec!["db".to_string()];
+        two.color = Some(ColorLabel::Green);
+        store.update(two.clone()).unwrap();
+        let stored = store.get_tunnel(two.id).unwrap();
+        assert_eq!(stored.name, "two, renamed");
+        assert_eq!(stored.tags, ["db"]);
+        assert_eq!(stored.color, Some(ColorLabel::Green));
+
+        // Deleting only hides the tunnel until the trash is emptied
+        store.delete(two.id).unwrap();
+        assert_eq!(names(&mut store), ["one", "three"]);
+        assert!(store.get_tunnel(two.id).unwrap().deleted);
+        assert_eq!(store.deleted_count().unwrap(), 1);
+        store.purge_deleted(None).unwrap();
+        assert!(store.get_tunnel(two.id).is_err());
+
+        // One failing change leaves the others unapplied
+        let one = store.load_tunnels().unwrap().remove(0);
+        store
+            .conn
+            .execute_batch(
+                "CREATE TEMP TRIGGER refuse BEFORE INSERT ON tunnels WHEN NEW.name = 'broken'
+                 BEGIN SELECT RAISE(ABORT, 'refused'); END",
+            )
+            .unwrap();
+        let mut broken = one.clone();
+        broken.name = "broken".to_string();
+        let changes = vec![TunnelChange::Delete(one.id), TunnelChange::Insert(broken)];
+        assert!(store.apply(changes).is_err());
+        assert_eq!(names(&mut store), ["one", "three"]);
+    }
+
+    #[test]
+    fn tunnels_load_in_list_order() {
+        let mut store = memory_store();
+        let ids: Vec<TunnelId> = store.load_tunnels().unwrap().iter().map(|t| t.id).collect();
+        store.reorder(&[ids[2], ids[0], ids[1]]).unwrap();
+        assert_eq!(names(&mut store), ["three", "one", "two"]);
+
+        // New tunnels go last, whatever the order so far
+        let mut four = store.get_tunnel(ids[0]).unwrap();
+        four.name = "four".to_string();
+        store.insert(four).unwrap();
+        assert_eq!(names(&mut store), ["three", "one", "two", "four"]);
+    }
+
+    #[test]
+    fn unreadable_rows_fail_the_load() {
+        let mut store = memory_store();
+        store
+            .conn
+            .execute(
+                "UPDATE tunnels SET tags = 'not json' WHERE name = 'two'",
+                [],
+            )
+            .unwrap();
+        let error = store.load_tunnels().unwrap_err();
+        assert!(error.to_string().contains("index: 10"), "{}", error);
+    }
+
+    #[test]
+    fn older_database_is_migrated_when_opened() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE tunnels (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                command TEXT NOT NULL,
+                ssh_server TEXT NOT NULL,
+                local_ip TEXT NOT NULL,
+                local_port INTEGER NOT NULL,
+                remote_ip TEXT NOT NULL,
+                remote_port INTEGER NOT NULL,
+                active BOOLEAN NOT NULL DEFAULT 0,
+                deleted BOOLEAN NOT NULL DEFAULT 0
+            );
+            INSERT INTO tunnels (name, command, ssh_server, local_ip, local_port, remote_ip, remote_port)
+            VALUES ('Prod DB', 'ssh -L 5432:db:5432 bastion', 'bastion', '127.0.0.1', 5432, 'db', 5432);",
+        )
+        .unwrap();
+
+        let mut store = SqliteStore::from_connection(conn, Path::new(":memory:")).unwrap();
+        let tunnel = store.load_tunnels().unwrap().remove(0);
+        assert_eq!(tunnel.name, "Prod DB");
+        assert_eq!(tunnel.workspace_id, DEFAULT_WORKSPACE);
+        assert!(tunnel.tags.is_empty() && tunnel.timestamps_approximate);
+    }
+
+    #[test]
+    fn names_must_be_unique() {
+        let mut store = memory_store();
+        let work = store.create_workspace("Work").unwrap();
+        assert!(store.create_workspace("Work").is_err());
+        assert!(store.rename_workspace(work, "Default").is_err());
+        store.rename_workspace(work, "Client A").unwrap();
+
+        store.save_template("Web", "{}").unwrap();
+        store.save_template("db", "{}").unwrap();
+        let db = store.templates().unwrap()[0].id;
+        assert!(store.rename_template(db, "Web").is_err());
+
+        // Tunnels may share a name; they're told apart by id
+        let mut copy = store.load_tunnels().unwrap().remove(0);
+        copy.name = "two".to_string();
+        store.insert(copy).unwrap();
+        assert_eq!(names(&mut store), ["one", "two", "three", "two"]);
+    }
+
+    fn open_read_only(path: &Path) -> Result<SqliteStore> {
+        let conn = Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        SqliteStore::from_connection(conn, path)
+    }
+
+    #[test]
+    fn read_only_database_reports_errors() {
+        let store = scratch_store("read-only");
+        let path = store.path.clone();
+        drop(store);
+
+        let mut store = open_read_only(&path).unwrap();
+        let tunnels = store.load_tunnels().unwrap();
+        assert!(!tunnels.is_empty());
+        assert!(store.set_pinned(tunnels[0].id, true).is_err());
+        assert!(store.insert(tunnels[0].clone()).is_err());
+    }
+
+    #[test]
+    fn unusable_database_path_is_an_error() {
+        let store = scratch_store("unusable");
+        // A path below a regular file can never be created
+        let path = store.path.join("tunnels.db");
+        assert!(SqliteStore::open(&path).is_err());
+
+        let missing = store.path.with_file_name("missing.db");
+        assert!(open_read_only(&missing).is_err());
+    }
+
+    #[test]
+    fn backup_round_trip() {
+        let mut store = scratch_store("backup");
+        let backup = store.path.with_file_name("backup.db");
+        let original = store.load_tunnels().unwrap();
+        store.backup(&backup).unwrap();
+        assert_eq!(SqliteStore::check_backup(&backup).unwrap(), original.len());
+
+        store.delete(original[0].id).unwrap();
+        store.set_active(original[1].id, true).unwrap();
+        store.restore(&backup).unwrap();
+
+        let restored = store.load_tunnels().unwrap();
+        assert_eq!(restored.len(), original.len());
+        assert!(restored.iter().all(|t| !t.active));
+    }
+
+    #[test]
+    fn newer_backup_is_refused() {
+        let mut store = scratch_store("newer-backup");
+        let backup = store.path.with_file_name("backup.db");
+        store.backup(&backup).unwrap();
+        let conn = Connection::open(&backup).unwrap();
+        conn.pragma_update(None, "user_version", 999).unwrap();
+        drop(conn);
+
+        assert!(SqliteStore::check_backup(&backup).is_err());
+        assert!(store.restore(&backup).is_err());
+        assert!(!store.load_tunnels().unwrap().is_empty());
+    }
+
+    #[test]
+    fn purge_respects_retention() {
+        let mut store = memory_store();
+        let ids: Vec<TunnelId> = store.load_tunnels().unwrap().iter().map(|t| t.id).collect();
+        store.delete(ids[0]).unwrap();
+        store.delete(ids[1]).unwrap();
+        store
+            .conn
+            .execute(
+                "UPDATE tunnels SET deleted_at = datetime('now', '-40 days') WHERE id = ?1",
+                [ids[0]],
+            )
+            .unwrap();
+
+        assert_eq!(store.deleted_count().unwrap(), 2);
+        assert_eq!(store.purge_deleted(Some(30)).unwrap(), 1);
+        assert_eq!(store.deleted_count().unwrap(), 1);
+        assert_eq!(store.purge_deleted(None).unwrap(), 1);
+        assert_eq!(store.deleted_count().unwrap(), 0);
+        assert_eq!(store.load_tunnels().unwrap().len(), ids.len() - 2);
+    }
+
+    #[test]
+    fn purged_tunnels_leave_no_secrets() {
+        let mut store = memory_store();
+        let ids: Vec<TunnelId> = store.load_tunnels().unwrap().iter().map(|t| t.id).collect();
+        for (id, secret_ref) in ids.iter().zip(["onigiri/1", "onigiri/2", "onigiri/3"]) {
+            store
+                .set_secret_ref(*id, Some(secret_ref.to_string()))
+                .unwrap();
+        }
+        store.delete(ids[0]).unwrap();
+        store.delete(ids[1]).unwrap();
+        store
+            .conn
+            .execute(
+                "UPDATE tunnels SET deleted_at = datetime('now', '-40 days') WHERE id = ?1",
+                [ids[0]],
+            )
+            .unwrap();
+
+        // A stand-in for the credential store
+        let cleared = std::cell::RefCell::new(Vec::new());
+        let clear = |reference: &str| {
+            cleared.borrow_mut().push(reference.to_string());
+            Ok(())
+        };
+        assert_eq!(store.purge_deleted_with(Some(30), clear).unwrap(), 1);
+        assert_eq!(*cleared.borrow(), ["onigiri/1"]);
+        // A secret that can't be removed doesn't keep the tunnel
+        let failing = |_: &str| Err("locked".to_string());
+        assert_eq!(store.purge_deleted_with(None, failing).unwrap(), 1);
+        assert_eq!(store.purge_deleted_with(None, clear).unwrap(), 0);
+        assert_eq!(*cleared.borrow(), ["onigiri/1"]);
+    }
+
+    #[test]
+    fn session_is_remembered_across_exit() {
+        let mut store = memory_store();
+        let ids: Vec<TunnelId> = store.load_tunnels().unwrap().iter().map(|t| t.id).collect();
+        store.set_active(ids[0], true).unwrap();
+        store.set_active(ids[1], true).unwrap();
+        store.set_active(ids[1], false).unwrap();
+        // Without a clean exit the active flag alone is enough
+        assert_eq!(store.tunnels_to_resume().unwrap(), vec![ids[0]]);
+
+        store.save_session(&[ids[0], ids[2]]).unwrap();
+        let active: i64 = store
+            .conn
+            .query_row("SELECT COUNT(*) FROM tunnels WHERE active", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(active, 0);
+        assert_eq!(store.tunnels_to_resume().unwrap(), vec![ids[0], ids[2]]);
+    }
+
+    #[test]
+    fn workspace_with_tunnels_is_not_deleted() {
+        let mut store = memory_store();
+        let client = store.create_workspace("Client A").unwrap();
+        assert!(store.create_workspace("Client A").is_err());
+        let mut tunnel = store.load_tunnels().unwrap().remove(0);
+        tunnel.workspace_id = client;
+        store.update(tunnel.clone()).unwrap();
+
+        assert!(store.delete_workspace(client).is_err());
+        assert!(store.delete_workspace(DEFAULT_WORKSPACE).is_err());
+        store.delete(tunnel.id).unwrap();
+        store.delete_workspace(client).unwrap();
+
+        let names: Vec<String> = store
+            .workspaces()
+            .unwrap()
+            .into_iter()
+            .map(|w| w.name)
+            .collect();
+        assert_eq!(names, ["Default"]);
+        assert_eq!(
+            store.get_tunnel(tunnel.id).unwrap().workspace_id,
+            DEFAULT_WORKSPACE
+        );
+    }
+
+    #[test]
+    fn templates_are_saved_by_name() {
+        let mut store = memory_store();
+        store.save_template("Web", "{}").unwrap();
+        store.save_template("db", "{\"a\":1}").unwrap();
+        store.save_template("Web", "{\"b\":2}").unwrap();
+
+        let templates = store.templates().unwrap();
+        let names: Vec<&str> = templates.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["db", "Web"]);
+        assert_eq!(templates[1].definition, "{\"b\":2}");
+
+        assert!(store.rename_template(templates[1].id, "db").is_err());
+        store.rename_template(templates[1].id, "Web 2").unwrap();
+        store.delete_template(templates[0].id).unwrap();
+        assert_eq!(store.templates().unwrap()[0].name, "Web 2");
+    }
+
+    #[test]
+    fn left_running_processes_are_read_once() {
+        let mut store = memory_store();
+        let id = store.load_tunnels().unwrap()[0].id;
+        let process = LeftRunning {
+            tunnel_id: id,
+            pid: 4242,
+            started_at: 1_700_000_000,
+            local_port: 49_152,
+        };
+        store
+            .save_processes(std::slice::from_ref(&process))
+            .unwrap();
+
+        assert_eq!(store.take_processes().unwrap(), vec![process]);
+        assert!(store.take_processes().unwrap().is_empty());
+    }
+
+    #[test]
+    fn events_are_capped_per_tunnel() {
+        let mut store = memory_store();
+        let id = store.load_tunnels().unwrap()[0].id;
+        for n in 0..EVENTS_PER_TUNNEL + 5 {
+            store
+                .record_event(
+                    id,
+                    1_700_000_000 + n as i64,
+                    EventKind::Started,
+                    &n.to_string(),
+                )
+                .unwrap();
+        }
+
+        let events = store.events(id, EVENTS_PER_TUNNEL + 10).unwrap();
+        assert_eq!(events.len(), EVENTS_PER_TUNNEL);
+        assert_eq!(events[0].detail, (EVENTS_PER_TUNNEL + 4).to_string());
+        assert_eq!(events[0].at, 1_700_000_000 + EVENTS_PER_TUNNEL as i64 + 4);
+
+        store.delete(id).unwrap();
+        store.purge_deleted(None).unwrap();
+        assert!(store.events(id, 10).unwrap().is_empty());
+    }
+}
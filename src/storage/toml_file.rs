@@ -0,0 +1,570 @@
+//! Tunnels kept in a TOML file such as `~/.config/onigiri/tunnels.toml`, one
+//! `[[tunnel]]` table per tunnel.
+//!
+//! Writes go through `toml_edit`, so comments and key order in a hand-edited
+//! file survive changes made from the app. Deleting a tunnel removes its
+//! table rather than marking it deleted.
+//!
+//! Which tunnels are running isn't part of a tunnel's definition, so it goes
+//! in `tunnels.state.json` next to the file instead, keeping the TOML file
+//! free of churn for anyone tracking it in git.
+
+use super::{LeftRunning, TunnelChange, TunnelStore};
+use crate::{Tunnel, TunnelId};
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use toml_edit::{ArrayOfTables, DocumentMut, Item, Table};
+
+const HEADER: &str = "# Onigiri tunnels. Each [[tunnel]] table is one SSH tunnel.\n";
+
+#[derive(Deserialize, Default)]
+struct TunnelFile {
+    #[serde(default)]
+    tunnel: Vec<Tunnel>,
+}
+
+/// What `tunnels.state.json` holds.
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+struct SessionState {
+    /// Tunnels running right now
+    #[serde(default)]
+    active: Vec<TunnelId>,
+    /// Tunnels that were running at the last clean exit
+    #[serde(default)]
+    resume: Vec<TunnelId>,
+    #[serde(default)]
+    processes: Vec<LeftRunning>,
+}
+
+pub struct TomlStore {
+    path: PathBuf,
+}
+
+impl TomlStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// `~/.config/onigiri/tunnels.toml`, or `%APPDATA%\Onigiri\tunnels.toml`
+    /// on Windows.
+    pub fn default_path() -> Option<PathBuf> {
+        if cfg!(windows) {
+            dirs::config_dir().map(|dir| dir.join("Onigiri").join("tunnels.toml"))
+        } else {
+            dirs::home_dir().map(|home| home.join(".config").join("onigiri").join("tunnels.toml"))
+        }
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    fn read(&self) -> Result<String> {
+        if !self.exists() {
+            return Ok(HEADER.to_string());
+        }
+        std::fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read {}", self.path.display()))
+    }
+
+    fn write(&self, doc: &DocumentMut) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        // Write then rename so a crash can't leave a truncated file behind
+        let tmp = self.path.with_extension("toml.tmp");
+        std::fs::write(&tmp, doc.to_string())
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("Failed to replace {}", self.path.display()))?;
+        Ok(())
+    }
+
+    fn state_path(&self) -> PathBuf {
+        self.path.with_extension("state.json")
+    }
+
+    fn read_state(&self) -> Result<SessionState> {
+        let path = self.state_path();
+        if !path.exists() {
+            return Ok(SessionState::default());
+        }
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    fn write_state(&self, state: &SessionState) -> Result<()> {
+        let path = self.state_path();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(state)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+
+    fn parse(&self, text: &str) -> Result<DocumentMut> {
+        text.parse::<DocumentMut>()
+            .with_context(|| format!("{} is not valid TOML", self.path.display()))
+    }
+
+    /// `apply`, removing the secrets of deleted tunnels with `clear`.
+    fn apply_with(
+        &mut self,
+        changes: Vec<TunnelChange>,
+        clear: impl Fn(&str) -> Result<(), String>,
+    ) -> Result<()> {
+        let mut doc = self.parse(&self.read()?)?;
+        let tables = tunnel_tables(&mut doc)?;
+        let mut next_id = tables
+            .iter()
+            .filter_map(table_id)
+            .map(|id| id.0)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let now = crate::unix_now();
+        let mut secret_refs = Vec::new();
+
+        for change in changes {
+            match change {
+                TunnelChange::Insert(mut tunnel) => {
+                    tunnel.id = TunnelId(next_id);
+                    next_id += 1;
+                    tunnel.created_at = Some(now);
+                    tunnel.updated_at = Some(now);
+                    tables.push(to_table(&tunnel)?);
+                }
+                TunnelChange::Update(mut tunnel) => {
+                    tunnel.updated_at = Some(now);
+                    let table = tables
+                        .iter_mut()
+                        .find(|table| table_id(table) == Some(tunnel.id))
+                        .ok_or_else(|| anyhow!("No tunnel with id {}", tunnel.id))?;
+                    merge_table(table, to_table(&tunnel)?);
+                }
+                TunnelChange::Delete(id) => {
+                    // Deleting removes the table for good, so nothing can
+                    // bring the secret back
+                    secret_refs.extend(
+                        tables
+                            .iter()
+                            .filter(|table| table_id(table) == Some(id))
+                            .filter_map(|table| table.get("secret_ref").and_then(Item::as_str))
+                            .map(str::to_string),
+                    );
+                    tables.retain(|table| table_id(table) != Some(id));
+                }
+            }
+        }
+
+        self.write(&doc)?;
+        super::clear_secrets(&secret_refs, clear);
+        Ok(())
+    }
+}
+
+fn tunnel_tables(doc: &mut DocumentMut) -> Result<&mut ArrayOfTables> {
+    doc.entry("tunnel")
+        .or_insert(Item::ArrayOfTables(ArrayOfTables::new()))
+        .as_array_of_tables_mut()
+        .ok_or_else(|| anyhow!("`tunnel` must be written as [[tunnel]] tables"))
+}
+
+fn table_id(table: &Table) -> Option<TunnelId> {
+    table
+        .get("id")
+        .and_then(Item::as_integer)
+        .filter(|id| *id > 0)
+        .map(TunnelId)
+}
+
+fn to_table(tunnel: &Tunnel) -> Result<Table> {
+    let doc = toml_edit::ser::to_document(tunnel)
+        .with_context(|| format!("Failed to serialize tunnel '{}'", tunnel.name))?;
+    Ok(doc.as_table().clone())
+}
+
+/// Copies the new values into an existing table, keeping the comments and
+/// position of keys that are already there.
+fn merge_table(existing: &mut Table, updated: Table) {
+    let stale: Vec<String> = existing
+        .iter()
+        .map(|(key, _)| key.to_string())
+        .filter(|key| !updated.contains_key(key))
+        .collect();
+    for key in stale {
+        existing.remove(&key);
+    }
+
+    for (key, item) in updated {
+        match (
+            existing.get_mut(&key).and_then(Item::as_value_mut),
+            item.as_value(),
+        ) {
+            (Some(old), Some(new)) => {
+                let decor = old.decor().clone();
+                *old = new.clone();
+                *old.decor_mut() = decor;
+            }
+            _ => {
+                existing.insert(&key, item);
+            }
+        }
+    }
+}
+
+impl TunnelStore for TomlStore {
+    fn describe(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn load_tunnels(&mut self) -> Result<Vec<Tunnel>> {
+        debug!("Loading tunnels from {}", self.path.display());
+        let text = self.read()?;
+        let file: TunnelFile = toml_edit::de::from_str(&text)
+            .with_context(|| format!("Failed to parse {}", self.path.display()))?;
+        let mut tunnels = file.tunnel;
+
+        // Hand-written entries may lack an id, or copy one from another entry.
+        // Give those fresh ids and save them so later edits can find them.
+        let mut seen = HashSet::new();
+        let mut next_id = tunnels.iter().map(|t| t.id.0).max().unwrap_or(0).max(0) + 1;
+        let mut assigned = Vec::new();
+        for (index, tunnel) in tunnels.iter_mut().enumerate() {
+            if tunnel.id.0 <= 0 || !seen.insert(tunnel.id) {
+                tunnel.id = TunnelId(next_id);
+                next_id += 1;
+                assigned.push((index, tunnel.id));
+            }
+        }
+        if !assigned.is_empty() {
+            info!(
+                "Assigning ids to {} tunnels in {}",
+                assigned.len(),
+                self.path.display()
+            );
+            let mut doc = self.parse(&text)?;
+            let tables = tunnel_tables(&mut doc)?;
+            for (index, id) in assigned {
+                if let Some(table) = tables.get_mut(index) {
+                    table.insert("id", toml_edit::value(id.0));
+                }
+            }
+            self.write(&doc)?;
+        }
+
+        Ok(tunnels)
+    }
+
+    fn apply(&mut self, changes: Vec<TunnelChange>) -> Result<()> {
+        self.apply_with(changes, crate::secrets::clear)
+    }
+
+    /// Only touches `last_started_at`, so starting a tunnel doesn't count as
+    /// editing it.
+    fn record_start(&mut self, id: TunnelId, at: i64) -> Result<()> {
+        let mut doc = self.parse(&self.read()?)?;
+        let table = tunnel_tables(&mut doc)?
+            .iter_mut()
+            .find(|table| table_id(table) == Some(id))
+            .ok_or_else(|| anyhow!("No tunnel with id {}", id))?;
+        table.insert("last_started_at", toml_edit::value(at));
+        self.write(&doc)
+    }
+
+    /// The file order is the list order, so this rearranges the tables.
+    fn reorder(&mut self, ids: &[TunnelId]) -> Result<()> {
+        let mut doc = self.parse(&self.read()?)?;
+        let tables = tunnel_tables(&mut doc)?;
+        let mut ordered = ArrayOfTables::new();
+        for id in ids {
+            if let Some(table) = tables.iter().find(|table| table_id(table) == Some(*id)) {
+                ordered.push(table.clone());
+            }
+        }
+        // Keep anything the caller didn't list rather than dropping it
+        for table in tables.iter() {
+            if !table_id(table).is_some_and(|id| ids.contains(&id)) {
+                ordered.push(table.clone());
+            }
+        }
+        // toml_edit writes tables in order of their recorded position, so
+        // hand the existing positions out in the new order
+        let mut positions: Vec<usize> = tables.iter().filter_map(Table::position).collect();
+        positions.sort_unstable();
+        if positions.len() == ordered.len() {
+            for (table, position) in ordered.iter_mut().zip(positions) {
+                table.set_position(position);
+            }
+        }
+        *tables = ordered;
+        self.write(&doc)
+    }
+
+    fn set_pinned(&mut self, id: TunnelId, pinned: bool) -> Result<()> {
+        let mut doc = self.parse(&self.read()?)?;
+        let table = tunnel_tables(&mut doc)?
+            .iter_mut()
+            .find(|table| table_id(table) == Some(id))
+            .ok_or_else(|| anyhow!("No tunnel with id {}", id))?;
+        if pinned {
+            table.insert("pinned", toml_edit::value(true));
+        } else {
+            table.remove("pinned");
+        }
+        self.write(&doc)
+    }
+
+    fn set_active(&mut self, id: TunnelId, active: bool) -> Result<()> {
+        let mut state = self.read_state()?;
+        state.active.retain(|other| *other != id);
+        if active {
+            state.active.push(id);
+        }
+        self.write_state(&state)
+    }
+
+    fn save_session(&mut self, running: &[TunnelId]) -> Result<()> {
+        let mut state = self.read_state()?;
+        state.active.clear();
+        state.resume = running.to_vec();
+        self.write_state(&state)
+    }
+
+    /// Skips ids whose tables were removed from the file since.
+    fn tunnels_to_resume(&mut self) -> Result<Vec<TunnelId>> {
+        let state = self.read_state()?;
+        let known: HashSet<TunnelId> = self.load_tunnels()?.iter().map(|t| t.id).collect();
+        let mut ids: Vec<TunnelId> = state
+            .active
+            .into_iter()
+            .chain(state.resume)
+            .filter(|id| known.contains(id))
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        Ok(ids)
+    }
+
+    fn save_processes(&mut self, processes: &[LeftRunning]) -> Result<()> {
+        let mut state = self.read_state()?;
+        state.processes = processes.to_vec();
+        self.write_state(&state)
+    }
+
+    fn take_processes(&mut self) -> Result<Vec<LeftRunning>> {
+        let mut state = self.read_state()?;
+        let processes = std::mem::take(&mut state.processes);
+        if !processes.is_empty() {
+            self.write_state(&state)?;
+        }
+        Ok(processes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_store(name: &str) -> TomlStore {
+        let dir =
+            std::env::temp_dir().join(format!("onigiri-toml-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        TomlStore::new(dir.join("tunnels.toml"))
+    }
+
+    fn tunnel(name: &str, port: u16) -> Tunnel {
+        Tunnel {
+            id: TunnelId::default(),
+            name: name.to_string(),
+            ssh_server: "bastion".to_string(),
+            local_ip: "127.0.0.1".to_string(),
+            local_port: port,
+            remote_ip: "db".to_string(),
+            remote_port: 5432,
+            idle_timeout_minutes: None,
+            tags: Vec::new(),
+            group: None,
+            last_started_at: None,
+            created_at: None,
+            updated_at: None,
+            timestamps_approximate: false,
+            pinned: false,
+            notes: String::new(),
+            secret_ref: None,
+            color: None,
+            url_scheme: None,
+            autostart: false,
+            extra_args: String::new(),
+            agent_socket: None,
+            pre_start: String::new(),
+            pre_start_timeout_secs: None,
+            post_start: String::new(),
+            workspace_id: crate::storage::DEFAULT_WORKSPACE,
+            active: false,
+            deleted: false,
+        }
+    }
+
+    #[test]
+    fn insert_update_delete_round_trip() {
+        let mut store = scratch_store("crud");
+        store
+            .apply(vec![
+                TunnelChange::Insert(tunnel("one", 5433)),
+                TunnelChange::Insert(tunnel("two", 5434)),
+            ])
+            .unwrap();
+
+        let loaded = store.load_tunnels().unwrap();
+        assert_eq!(
+            loaded.iter().map(|t| t.id).collect::<Vec<_>>(),
+            vec![TunnelId(1), TunnelId(2)]
+        );
+
+        let mut changed = loaded[0].clone();
+        changed.local_port = 6000;
+        changed.idle_timeout_minutes = Some(30);
+        store.update(changed).unwrap();
+        store.delete(TunnelId(2)).unwrap();
+
+        let loaded = store.load_tunnels().unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].local_port, 6000);
+        assert_eq!(loaded[0].idle_timeout_minutes, Some(30));
+    }
+
+    #[test]
+    fn deleted_tunnels_leave_no_secrets() {
+        let mut store = scratch_store("secrets");
+        let mut kept = tunnel("one", 5433);
+        kept.secret_ref = Some("onigiri/1".to_string());
+        let mut deleted = tunnel("two", 5434);
+        deleted.secret_ref = Some("onigiri/2".to_string());
+        store
+            .apply(vec![
+                TunnelChange::Insert(kept),
+                TunnelChange::Insert(deleted),
+            ])
+            .unwrap();
+
+        // A stand-in for the credential store
+        let cleared = std::cell::RefCell::new(Vec::new());
+        let clear = |reference: &str| {
+            cleared.borrow_mut().push(reference.to_string());
+            Ok(())
+        };
+        store
+            .apply_with(vec![TunnelChange::Delete(TunnelId(2))], clear)
+            .unwrap();
+        assert_eq!(*cleared.borrow(), ["onigiri/2"]);
+        let loaded = store.load_tunnels().unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].secret_ref.as_deref(), Some("onigiri/1"));
+    }
+
+    #[test]
+    fn comments_survive_updates() {
+        let mut store = scratch_store("comments");
+        std::fs::create_dir_all(store.path.parent().unwrap()).unwrap();
+        std::fs::write(
+            &store.path,
+            "# my tunnels\n\n[[tunnel]]\n# staging database\nname = \"db\"\nssh_server = \"bastion\"\nlocal_ip = \"127.0.0.1\"\nlocal_port = 5433 # not 5432\nremote_ip = \"db\"\nremote_port = 5432\n",
+        )
+        .unwrap();
+
+        let mut loaded = store.load_tunnels().unwrap();
+        assert_eq!(loaded[0].id, TunnelId(1));
+        loaded[0].local_port = 5500;
+        store.update(loaded.remove(0)).unwrap();
+
+        let text = std::fs::read_to_string(&store.path).unwrap();
+        assert!(text.contains("# my tunnels"));
+        assert!(text.contains("# staging database"));
+        assert!(text.contains("local_port = 5500 # not 5432"));
+    }
+
+    #[test]
+    fn duplicate_ids_are_reassigned() {
+        let mut store = scratch_store("dupes");
+        std::fs::create_dir_all(store.path.parent().unwrap()).unwrap();
+        let entry = "[[tunnel]]\nid = 4\nname = \"x\"\nssh_server = \"s\"\nlocal_ip = \"127.0.0.1\"\nlocal_port = 1\nremote_ip = \"r\"\nremote_port = 2\n";
+        std::fs::write(&store.path, format!("{}{}", entry, entry)).unwrap();
+
+        let ids: Vec<TunnelId> = store.load_tunnels().unwrap().iter().map(|t| t.id).collect();
+        assert_eq!(ids, vec![TunnelId(4), TunnelId(5)]);
+        let ids: Vec<TunnelId> = store.load_tunnels().unwrap().iter().map(|t| t.id).collect();
+        assert_eq!(ids, vec![TunnelId(4), TunnelId(5)]);
+    }
+
+    #[test]
+    fn reorder_moves_tables() {
+        let mut store = scratch_store("reorder");
+        store
+            .apply(vec![
+                TunnelChange::Insert(tunnel("one", 1)),
+                TunnelChange::Insert(tunnel("two", 2)),
+                TunnelChange::Insert(tunnel("three", 3)),
+            ])
+            .unwrap();
+
+        store.reorder(&[TunnelId(3), TunnelId(1)]).unwrap();
+
+        let names: Vec<String> = store
+            .load_tunnels()
+            .unwrap()
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
+        assert_eq!(names, vec!["three", "one", "two"]);
+    }
+
+    #[test]
+    fn running_tunnels_are_remembered_beside_the_file() {
+        let mut store = scratch_store("session");
+        store
+            .apply(vec![
+                TunnelChange::Insert(tunnel("one", 1)),
+                TunnelChange::Insert(tunnel("two", 2)),
+                TunnelChange::Insert(tunnel("three", 3)),
+            ])
+            .unwrap();
+        store.set_active(TunnelId(1), true).unwrap();
+        store.set_active(TunnelId(2), true).unwrap();
+        store.set_active(TunnelId(2), false).unwrap();
+        assert_eq!(store.tunnels_to_resume().unwrap(), vec![TunnelId(1)]);
+
+        store.save_session(&[TunnelId(1), TunnelId(3)]).unwrap();
+        assert_eq!(
+            store.tunnels_to_resume().unwrap(),
+            vec![TunnelId(1), TunnelId(3)]
+        );
+        store.delete(TunnelId(3)).unwrap();
+        assert_eq!(store.tunnels_to_resume().unwrap(), vec![TunnelId(1)]);
+
+        let process = LeftRunning {
+            tunnel_id: TunnelId(1),
+            pid: 4242,
+            started_at: 1_700_000_000,
+            local_port: 49_152,
+        };
+        store
+            .save_processes(std::slice::from_ref(&process))
+            .unwrap();
+        assert_eq!(store.take_processes().unwrap(), vec![process]);
+        assert!(store.take_processes().unwrap().is_empty());
+
+        let text = std::fs::read_to_string(&store.path).unwrap();
+        assert!(!text.contains("active"));
+    }
+}
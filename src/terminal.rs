@@ -0,0 +1,82 @@
+//! Opening a shell on a tunnel's server in the platform terminal.
+//!
+//! The terminal is started through a command template, so iTerm2, kitty and
+//! the like can be used instead of the platform default.
+
+use crate::Tunnel;
+use std::process::Command;
+
+/// Settings key of the user's template, when it differs from the default.
+pub const SETTING_KEY: &str = "terminal.command";
+/// Replaced by the ssh command line in a template.
+pub const PLACEHOLDER: &str = "{command}";
+
+pub fn default_template() -> &'static str {
+    if cfg!(target_os = "macos") {
+        r#"osascript -e 'tell application "Terminal" to do script "{command}"' -e 'tell application "Terminal" to activate'"#
+    } else if cfg!(windows) {
+        "wt.exe {command}"
+    } else {
+        "x-terminal-emulator -e {command}"
+    }
+}
+
+/// The tunnel's ssh command without the forward, for an interactive login.
+pub fn login_command(tunnel: &Tunnel) -> Vec<String> {
+    let mut args = crate::build_command(tunnel).into_iter();
+    let mut command = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-N" => {}
+            "-L" => {
+                args.next();
+            }
+            _ => command.push(arg),
+        }
+    }
+    command
+}
+
+/// The shell command that opens the terminal.
+pub fn render(template: &str, tunnel: &Tunnel) -> String {
+    template.replace(PLACEHOLDER, &crate::shell_words(&login_command(tunnel)))
+}
+
+pub fn open(template: &str, tunnel: &Tunnel) -> Result<(), String> {
+    let line = render(template, tunnel);
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C");
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c");
+        command
+    };
+    command
+        .arg(&line)
+        .spawn()
+        .map(|_| ())
+        .map_err(|e| format!("Failed to run '{}': {}", line, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn login_drops_the_forward() {
+        let tunnel = crate::NewTunnelForm {
+            ssh_server: "ssh://deploy@bastion:2222".to_string(),
+            local_port: "15432".to_string(),
+            remote_port: "5432".to_string(),
+            ..Default::default()
+        }
+        .to_tunnel(crate::TunnelId(1));
+        assert_eq!(login_command(&tunnel), ["ssh", "ssh://deploy@bastion:2222"]);
+        assert_eq!(
+            render("kitty {command}", &tunnel),
+            "kitty ssh ssh://deploy@bastion:2222"
+        );
+    }
+}
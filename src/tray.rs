@@ -0,0 +1,246 @@
+//! The menu bar (macOS) and notification area (Windows) icon.
+//!
+//! Its menu lists the workspace's tunnels with a check mark on running ones,
+//! plus window and bulk actions. Clicks come back as `TrayAction`s for
+//! `Tunneler` to apply like the matching buttons. The icon gets a green dot
+//! while any tunnel runs.
+
+use crate::TunnelId;
+#[cfg(any(target_os = "macos", windows))]
+use std::sync::mpsc::{self, Receiver};
+#[cfg(any(target_os = "macos", windows))]
+use tray_icon::menu::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+#[cfg(any(target_os = "macos", windows))]
+use tray_icon::{Icon, TrayIcon, TrayIconBuilder};
+
+/// Tray icons are drawn small; the app icon is scaled down to this.
+#[cfg(any(target_os = "macos", windows))]
+const ICON_SIZE: u32 = 32;
+
+/// A tunnel as the tray menu shows it.
+#[cfg(any(target_os = "macos", windows))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrayEntry {
+    pub id: TunnelId,
+    pub name: String,
+    pub active: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrayAction {
+    Toggle(TunnelId),
+    ShowWindow,
+    StartAll,
+    StopAll,
+    Quit,
+}
+
+const TUNNEL_PREFIX: &str = "tunnel:";
+
+fn tunnel_item_id(id: TunnelId) -> String {
+    format!("{}{}", TUNNEL_PREFIX, id)
+}
+
+fn action_item_id(action: TrayAction) -> &'static str {
+    match action {
+        TrayAction::Toggle(_) => TUNNEL_PREFIX,
+        TrayAction::ShowWindow => "show",
+        TrayAction::StartAll => "start-all",
+        TrayAction::StopAll => "stop-all",
+        TrayAction::Quit => "quit",
+    }
+}
+
+/// The action behind a clicked menu item id.
+fn parse_item_id(id: &str) -> Option<TrayAction> {
+    if let Some(tunnel) = id.strip_prefix(TUNNEL_PREFIX) {
+        return tunnel.parse().ok().map(TrayAction::Toggle);
+    }
+    [
+        TrayAction::ShowWindow,
+        TrayAction::StartAll,
+        TrayAction::StopAll,
+        TrayAction::Quit,
+    ]
+    .into_iter()
+    .find(|action| action_item_id(*action) == id)
+}
+
+/// Paints a green dot in the bottom right corner of an RGBA image.
+fn add_badge(rgba: &mut [u8], width: u32, height: u32) {
+    let radius = (width.min(height) / 4) as i64;
+    let (cx, cy) = (width as i64 - radius - 1, height as i64 - radius - 1);
+    for y in 0..height as i64 {
+        for x in 0..width as i64 {
+            if (x - cx).pow(2) + (y - cy).pow(2) <= radius.pow(2) {
+                let pixel = ((y * width as i64 + x) * 4) as usize;
+                rgba[pixel..pixel + 4].copy_from_slice(&[50, 205, 50, 255]);
+            }
+        }
+    }
+}
+
+#[cfg(any(target_os = "macos", windows))]
+pub struct Tray {
+    icon: TrayIcon,
+    idle_icon: Icon,
+    active_icon: Icon,
+    // What the menu currently shows; None forces a rebuild
+    shown: Option<Vec<TrayEntry>>,
+    events: Receiver<MenuEvent>,
+}
+
+#[cfg(any(target_os = "macos", windows))]
+impl Tray {
+    pub fn new(ctx: &egui::Context) -> Result<Self, String> {
+        let image = image::load_from_memory(include_bytes!("../resources/icon.png"))
+            .map_err(|e| format!("Failed to load tray icon: {}", e))?
+            .resize(ICON_SIZE, ICON_SIZE, image::imageops::FilterType::Lanczos3)
+            .to_rgba8();
+        let (width, height) = image.dimensions();
+        let idle = image.into_raw();
+        let mut active = idle.clone();
+        add_badge(&mut active, width, height);
+        let idle_icon = Icon::from_rgba(idle, width, height).map_err(|e| e.to_string())?;
+        let active_icon = Icon::from_rgba(active, width, height).map_err(|e| e.to_string())?;
+
+        let icon = TrayIconBuilder::new()
+            .with_tooltip(crate::APP_NAME)
+            .with_icon(idle_icon.clone())
+            .with_menu(Box::new(Menu::new()))
+            .build()
+            .map_err(|e| format!("Failed to create tray icon: {}", e))?;
+
+        // Menu clicks have to wake the UI, which may be idle or hidden
+        let (tx, events) = mpsc::channel();
+        let ctx = ctx.clone();
+        MenuEvent::set_event_handler(Some(move |event| {
+            let _ = tx.send(event);
+            ctx.request_repaint();
+        }));
+
+        Ok(Self {
+            icon,
+            idle_icon,
+            active_icon,
+            shown: None,
+            events,
+        })
+    }
+
+    /// Menu items clicked since the last call.
+    pub fn actions(&mut self) -> Vec<TrayAction> {
+        let actions: Vec<TrayAction> = self
+            .events
+            .try_iter()
+            .filter_map(|event| parse_item_id(&event.id.0))
+            .collect();
+        if !actions.is_empty() {
+            // Check items flip themselves when clicked, even if the toggle
+            // then fails, so always redraw from the real state
+            self.shown = None;
+        }
+        actions
+    }
+
+    /// Rebuilds the menu and icon when the tunnels or their state changed.
+    pub fn sync(&mut self, entries: Vec<TrayEntry>) {
+        if self.shown.as_ref() == Some(&entries) {
+            return;
+        }
+        let any_active = entries.iter().any(|entry| entry.active);
+        match build_menu(&entries) {
+            Ok(menu) => self.icon.set_menu(Some(Box::new(menu))),
+            Err(e) => log::error!("Failed to build tray menu: {}", e),
+        }
+        let icon = if any_active {
+            &self.active_icon
+        } else {
+            &self.idle_icon
+        };
+        if let Err(e) = self.icon.set_icon(Some(icon.clone())) {
+            log::error!("Failed to update tray icon: {}", e);
+        }
+        let running = entries.iter().filter(|entry| entry.active).count();
+        let tooltip = match running {
+            0 => crate::APP_NAME.to_string(),
+            1 => format!("{}: 1 tunnel running", crate::APP_NAME),
+            n => format!("{}: {} tunnels running", crate::APP_NAME, n),
+        };
+        if let Err(e) = self.icon.set_tooltip(Some(tooltip)) {
+            log::error!("Failed to update tray tooltip: {}", e);
+        }
+        self.shown = Some(entries);
+    }
+}
+
+#[cfg(any(target_os = "macos", windows))]
+fn build_menu(entries: &[TrayEntry]) -> tray_icon::menu::Result<Menu> {
+    let menu = Menu::new();
+    for entry in entries {
+        menu.append(&CheckMenuItem::with_id(
+            tunnel_item_id(entry.id),
+            // `&` marks a mnemonic; `&&` shows one
+            entry.name.replace('&', "&&"),
+            true,
+            entry.active,
+            None,
+        ))?;
+    }
+    if entries.is_empty() {
+        menu.append(&MenuItem::new("No tunnels", false, None))?;
+    }
+    menu.append(&PredefinedMenuItem::separator())?;
+    for (action, label) in [
+        (TrayAction::ShowWindow, "Show window"),
+        (TrayAction::StartAll, "Start all"),
+        (TrayAction::StopAll, "Stop all"),
+    ] {
+        menu.append(&MenuItem::with_id(
+            action_item_id(action),
+            label,
+            true,
+            None,
+        ))?;
+    }
+    menu.append(&PredefinedMenuItem::separator())?;
+    menu.append(&MenuItem::with_id(
+        action_item_id(TrayAction::Quit),
+        "Quit",
+        true,
+        None,
+    ))?;
+    Ok(menu)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn menu_ids_map_back_to_actions() {
+        assert_eq!(
+            parse_item_id(&tunnel_item_id(TunnelId(42))),
+            Some(TrayAction::Toggle(TunnelId(42)))
+        );
+        for action in [
+            TrayAction::ShowWindow,
+            TrayAction::StartAll,
+            TrayAction::StopAll,
+            TrayAction::Quit,
+        ] {
+            assert_eq!(parse_item_id(action_item_id(action)), Some(action));
+        }
+        assert_eq!(parse_item_id("tunnel:x"), None);
+        assert_eq!(parse_item_id("other"), None);
+    }
+
+    #[test]
+    fn badge_is_drawn_in_the_corner() {
+        let mut rgba = vec![0; 16 * 16 * 4];
+        add_badge(&mut rgba, 16, 16);
+        let pixel = |x: usize, y: usize| &rgba[(y * 16 + x) * 4..(y * 16 + x) * 4 + 4];
+        assert_eq!(pixel(12, 12), [50, 205, 50, 255]);
+        assert_eq!(pixel(2, 2), [0, 0, 0, 0]);
+    }
+}
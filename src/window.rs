@@ -0,0 +1,165 @@
+//! Saving and restoring the main window's position and size.
+
+use eframe::egui::{Pos2, Vec2, ViewportInfo};
+
+/// Settings key the geometry is stored under.
+pub const SETTING_KEY: &str = "window_geometry";
+
+/// Where a window that ended up on no monitor is moved to.
+const RESCUE_POSITION: f32 = 40.0;
+const MIN_SIZE: f32 = 200.0;
+
+/// Outer position and inner size of the window, in logical points.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowGeometry {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl WindowGeometry {
+    /// Parses the `x,y,width,height` form written by `to_setting`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let parts: Vec<f32> = value
+            .split(',')
+            .map(|part| part.trim().parse().ok())
+            .collect::<Option<_>>()?;
+        let [x, y, width, height] = parts[..] else {
+            return None;
+        };
+        let valid =
+            parts.iter().all(|value| value.is_finite()) && width >= MIN_SIZE && height >= MIN_SIZE;
+        valid.then_some(Self {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    pub fn to_setting(self) -> String {
+        format!("{},{},{},{}", self.x, self.y, self.width, self.height)
+    }
+
+    /// None when the platform doesn't report the window position (Wayland).
+    pub fn from_viewport(info: &ViewportInfo) -> Option<Self> {
+        let outer = info.outer_rect?;
+        let inner = info.inner_rect?;
+        Some(Self {
+            x: outer.min.x,
+            y: outer.min.y,
+            width: inner.width(),
+            height: inner.height(),
+        })
+    }
+
+    /// Corrects a restored geometry once the window is shown. `monitor` is
+    /// the size of the monitor the window landed on, or `None` when it is on
+    /// none at all, for example because the one it was saved on is gone.
+    ///
+    /// With the monitor's top-left corner in `origin` the window is moved
+    /// inside it. egui doesn't report that corner, so without it a window
+    /// that can't be on a monitor of that size at (0, 0) is centred on one.
+    pub fn fit_to_monitor(self, monitor: Option<Vec2>, origin: Option<Pos2>) -> Self {
+        let Some(size) = monitor else {
+            return Self {
+                x: RESCUE_POSITION,
+                y: RESCUE_POSITION,
+                ..self
+            };
+        };
+        let width = self.width.min(size.x);
+        let height = self.height.min(size.y);
+        let (x, y) = match origin {
+            Some(origin) => (
+                self.x.clamp(origin.x, origin.x + size.x - width),
+                self.y.clamp(origin.y, origin.y + size.y - height),
+            ),
+            None => {
+                let inside = (0.0..=size.x - width).contains(&self.x)
+                    && (0.0..=size.y - height).contains(&self.y);
+                if inside {
+                    (self.x, self.y)
+                } else {
+                    ((size.x - width) / 2.0, (size.y - height) / 2.0)
+                }
+            }
+        };
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn setting_round_trips() {
+        let geometry = WindowGeometry {
+            x: -1200.0,
+            y: 80.5,
+            width: 420.0,
+            height: 640.0,
+        };
+        assert_eq!(
+            WindowGeometry::parse(&geometry.to_setting()),
+            Some(geometry)
+        );
+    }
+
+    #[test]
+    fn bad_settings_are_ignored() {
+        assert_eq!(WindowGeometry::parse(""), None);
+        assert_eq!(WindowGeometry::parse("1,2,3"), None);
+        assert_eq!(WindowGeometry::parse("0,0,10,10"), None);
+        assert_eq!(WindowGeometry::parse("0,0,NaN,400"), None);
+    }
+
+    #[test]
+    fn window_off_every_monitor_is_moved_back() {
+        let geometry = WindowGeometry {
+            x: 3000.0,
+            y: 200.0,
+            width: 400.0,
+            height: 500.0,
+        };
+        let fitted = geometry.fit_to_monitor(None, None);
+        assert_eq!((fitted.x, fitted.y), (RESCUE_POSITION, RESCUE_POSITION));
+
+        // Past the right edge of a 1280 wide monitor to the left of the main one
+        let monitor = Some(Vec2::new(1280.0, 400.0));
+        let fitted = geometry.fit_to_monitor(monitor, Some(Pos2::new(-1280.0, 0.0)));
+        assert_eq!(
+            (fitted.x, fitted.y, fitted.width, fitted.height),
+            (-400.0, 0.0, 400.0, 400.0)
+        );
+
+        let fitted = geometry.fit_to_monitor(monitor, None);
+        assert_eq!(
+            (fitted.x, fitted.y, fitted.width, fitted.height),
+            (440.0, 0.0, 400.0, 400.0)
+        );
+    }
+
+    #[test]
+    fn window_on_its_monitor_stays_put() {
+        let geometry = WindowGeometry {
+            x: 100.0,
+            y: 50.0,
+            width: 400.0,
+            height: 300.0,
+        };
+        let monitor = Some(Vec2::new(1280.0, 800.0));
+        assert_eq!(geometry.fit_to_monitor(monitor, None), geometry);
+        assert_eq!(
+            geometry.fit_to_monitor(monitor, Some(Pos2::new(0.0, 0.0))),
+            geometry
+        );
+    }
+}